@@ -0,0 +1,21 @@
+#![no_main]
+
+use aspirin_eats::http::HttpRequest;
+use libfuzzer_sys::fuzz_target;
+
+// Anything that parses should survive being written back out and parsed again
+fuzz_target!(|data: &str| {
+    let Ok(request) = data.parse::<HttpRequest>() else {
+        return;
+    };
+    let _ = (request.route(), request.query("mode"));
+
+    let reparsed: HttpRequest = request
+        .to_string()
+        .parse()
+        .expect("a parsed request should reparse");
+    assert_eq!(reparsed.method, request.method);
+    assert_eq!(reparsed.path, request.path);
+    assert_eq!(reparsed.body, request.body);
+    assert_eq!(reparsed.to_string(), request.to_string());
+});
@@ -0,0 +1,16 @@
+#![no_main]
+
+use aspirin_eats::http::{read_request_with_limits, RequestLimits};
+use libfuzzer_sys::fuzz_target;
+
+// Small limits, so the fuzzer can reach them
+const LIMITS: RequestLimits = RequestLimits {
+    max_header_bytes: 256,
+    max_body_bytes: 256,
+};
+
+fuzz_target!(|data: &[u8]| {
+    if let Ok(request) = read_request_with_limits(data, LIMITS) {
+        assert!(request.body.map_or(0, |body| body.len()) <= LIMITS.max_body_bytes);
+    }
+});
@@ -0,0 +1,1348 @@
+use std::io::{Read, Write};
+use std::net::SocketAddr;
+use std::str::FromStr;
+use std::time::Instant;
+
+use chrono::{DateTime, Local, NaiveDate, Utc};
+
+use crate::db::{normalize_name, AspirinEatsDb, BatchMode, StockChange, StockLevel};
+use crate::drivers::{ClaimRequest, DriverRequest, ShiftRequest};
+use crate::error::AspirinEatsError;
+use crate::food::*;
+use crate::http::{
+    allow_header, http_date, if_match, read_request_with_limits, CorsPolicy, HttpRequest,
+    HttpResponse, RequestLimits,
+};
+use crate::logging::{new_request_id, AccessLog, AccessLogEntry, REQUEST_ID_HEADER};
+use crate::metrics::{InstrumentedStore, Metrics, METRICS_CONTENT_TYPE, UNMATCHED_ROUTE};
+use crate::openapi;
+use crate::pricing::Pricing;
+use crate::reports::{ReportGrouping, SalesQuery};
+use crate::schedule::SchedulePolicy;
+use crate::store::{OrderStore, Store};
+use crate::ui::UiPolicy;
+
+/// `Content-Type` of every JSON response
+const JSON_CONTENT_TYPE: &str = "application/json";
+
+/// The orders API served by the origin server, keeping everything in `S`
+pub struct Api<S = AspirinEatsDb> {
+    store: S,
+    pricing: Pricing,
+    access_log: Option<AccessLog>,
+    metrics: Metrics,
+    limits: RequestLimits,
+    cors: CorsPolicy,
+    schedule: SchedulePolicy,
+    ui: UiPolicy,
+}
+
+impl<S: Store> Api<S> {
+    pub fn new(store: S, pricing: Pricing) -> Self {
+        Api {
+            store,
+            pricing,
+            access_log: None,
+            metrics: Metrics::new("origin"),
+            limits: RequestLimits::default(),
+            cors: CorsPolicy::default(),
+            schedule: SchedulePolicy::default(),
+            ui: UiPolicy::default(),
+        }
+    }
+
+    /// Let browsers call the API from the pages of other sites, as `cors` allows
+    pub fn with_cors(mut self, cors: CorsPolicy) -> Self {
+        self.cors = cors;
+        self
+    }
+
+    /// Refuse requests bigger than `limits`
+    pub fn with_limits(mut self, limits: RequestLimits) -> Self {
+        self.limits = limits;
+        self
+    }
+
+    /// Only take orders ahead of time for when `schedule` says we're open
+    pub fn with_schedule(mut self, schedule: SchedulePolicy) -> Self {
+        self.schedule = schedule;
+        self
+    }
+
+    /// Serve the web UI under `/ui/` as `ui` says
+    pub fn with_ui(mut self, ui: UiPolicy) -> Self {
+        self.ui = ui;
+        self
+    }
+
+    /// Metrics recorded by `serve_connection`, as served on `GET /metrics`
+    pub fn metrics(&self) -> &Metrics {
+        &self.metrics
+    }
+
+    /// Log every request served through `serve_connection`
+    pub fn with_access_log(mut self, access_log: AccessLog) -> Self {
+        self.access_log = Some(access_log);
+        self
+    }
+
+    /// Read a request from a connection, handle it, and write back the response. The request's
+    /// `X-Request-Id` (or a new one, if the client didn't send one) is echoed in the response
+    pub fn serve_connection<C: Read + Write>(
+        &self,
+        mut stream: C,
+        client: Option<SocketAddr>,
+    ) -> Result<(), AspirinEatsError> {
+        let start = Instant::now();
+        let _in_flight = self.metrics.track_connection();
+        let (request, response) = match read_request_with_limits(&mut stream, self.limits) {
+            Ok(request) => {
+                let response = self.handle(&request);
+                (Some(request), response)
+            }
+            Err(err) => (None, err.into()),
+        };
+        let request_id = request
+            .as_ref()
+            .and_then(|request| request.header(REQUEST_ID_HEADER))
+            .map_or_else(new_request_id, str::to_string);
+        let response = response.with_header(REQUEST_ID_HEADER, &request_id);
+        let raw = response.to_string();
+        stream.write_all(raw.as_bytes())?;
+        stream.flush()?;
+
+        let route = request.as_ref().map_or(UNMATCHED_ROUTE, route_template);
+        self.metrics
+            .record_request(route, response.status_code(), start.elapsed());
+        if let Some(access_log) = &self.access_log {
+            access_log.record(&AccessLogEntry {
+                time: Utc::now(),
+                service: access_log.service().to_string(),
+                request_id: Some(request_id),
+                client,
+                method: request
+                    .as_ref()
+                    .and_then(|r| r.method.clone())
+                    .unwrap_or_else(|| "-".to_string()),
+                path: request
+                    .as_ref()
+                    .and_then(|r| r.path.clone())
+                    .unwrap_or_else(|| "-".to_string()),
+                status: response.status_code(),
+                bytes: raw.len(),
+                latency: start.elapsed(),
+            });
+        }
+        Ok(())
+    }
+
+    /// Handle a single request, turning any error into the matching error response. `HEAD` is
+    /// answered like `GET` without the body, and `OPTIONS` with the methods the path allows
+    pub fn handle(&self, request: &HttpRequest) -> HttpResponse {
+        let response = match request.method.as_deref() {
+            Some("HEAD") => {
+                let mut get = request.clone();
+                get.method = Some("GET".to_string());
+                self.route(&get)
+                    .unwrap_or_else(HttpResponse::from)
+                    .conditional(request)
+                    .without_body()
+            }
+            Some("OPTIONS") => match allowed_methods(&segments(request)) {
+                Some(methods) => HttpResponse::new(204, "No Content", "")
+                    .with_header("Allow", &allow_header(methods)),
+                None => AspirinEatsError::NotFound.into(),
+            },
+            Some("GET") => self
+                .route(request)
+                .unwrap_or_else(HttpResponse::from)
+                .conditional(request),
+            _ => self.route(request).unwrap_or_else(HttpResponse::from),
+        };
+        self.cors.apply(request, response)
+    }
+
+    fn route(&self, request: &HttpRequest) -> Result<HttpResponse, AspirinEatsError> {
+        let method = request
+            .method
+            .as_deref()
+            .ok_or(AspirinEatsError::InvalidRequest)?;
+        let segments = segments(request);
+
+        let store = InstrumentedStore::new(&self.store, &self.metrics);
+
+        match (method, segments.as_slice()) {
+            ("GET", []) => Ok(HttpResponse::new(200, "OK", "Welcome to Aspirin Eats!")),
+            ("GET", ["metrics"]) => Ok(HttpResponse::new(200, "OK", &self.metrics.render())
+                .with_header("Content-Type", METRICS_CONTENT_TYPE)),
+            ("GET", ["openapi.json"]) => Ok(HttpResponse::new(200, "OK", openapi::document())
+                .with_header("Content-Type", JSON_CONTENT_TYPE)),
+            ("GET", ["menu"]) => json(&self.pricing.menu()),
+            ("GET", ["ui", ..]) => self.ui.serve(request.route()),
+
+            ("GET", ["orders"]) => {
+                let response = list_orders(&store)?;
+                self.with_orders_last_modified(response)
+            }
+            ("POST", ["orders"]) => add_order(&store, &self.pricing, &self.schedule, request),
+            ("POST", ["orders", "batch"]) => self.add_orders(request),
+            ("DELETE", ["orders"]) => reset_orders(&store),
+            ("GET", ["orders", id]) => {
+                let id = parse_id(id)?;
+                let response = get_order(&store, id)?;
+                self.with_order_etag(response, id)
+            }
+            ("PUT", ["orders", id, "items"]) => self.replace_items(parse_id(id)?, request),
+            ("DELETE", ["orders", id]) => remove_order(&store, parse_id(id)?),
+
+            ("GET", ["customers"]) => {
+                json(&self.time_db("get_all_customers", |store| store.get_all_customers())?)
+            }
+            ("GET", ["customers", id]) => {
+                let id = parse_id(id)?;
+                json(
+                    &self
+                        .time_db("get_customer", |store| store.get_customer(id))?
+                        .ok_or(AspirinEatsError::NotFound)?,
+                )
+            }
+            ("GET", ["customers", id, "orders"]) => {
+                let id = parse_id(id)?;
+                self.time_db("get_customer", |store| store.get_customer(id))?
+                    .ok_or(AspirinEatsError::NotFound)?;
+                json(&self.time_db("get_customer_orders", |store| store.get_customer_orders(id))?)
+            }
+
+            ("GET", ["reports", "sales"]) => self.sales_report(request),
+
+            ("GET", ["inventory"]) => {
+                json(&self.time_db("get_inventory", |store| store.get_inventory())?)
+            }
+            ("PUT", ["inventory"]) => {
+                let levels: Vec<StockLevel> = serde_json::from_str(body(request)?)?;
+                self.time_db("set_stock", |store| store.set_stock(&levels))?;
+                json(&self.time_db("get_inventory", |store| store.get_inventory())?)
+            }
+            ("POST", ["inventory"]) => {
+                let changes: Vec<StockChange> = serde_json::from_str(body(request)?)?;
+                self.time_db("adjust_stock", |store| store.adjust_stock(&changes))?;
+                json(&self.time_db("get_inventory", |store| store.get_inventory())?)
+            }
+
+            ("GET", ["drivers"]) => {
+                json(&self.time_db("get_all_drivers", |store| store.get_all_drivers())?)
+            }
+            ("POST", ["drivers"]) => {
+                let driver: DriverRequest = serde_json::from_str(body(request)?)?;
+                if normalize_name(&driver.name).is_empty() {
+                    return Err(AspirinEatsError::InvalidRequest);
+                }
+                let id = self.time_db("add_driver", |store| store.add_driver(&driver.name))?;
+                let driver = self
+                    .time_db("get_driver", |store| store.get_driver(id))?
+                    .ok_or(AspirinEatsError::NotFound)?;
+                created(&driver)
+            }
+            ("GET", ["drivers", id]) => {
+                let id = parse_id(id)?;
+                json(
+                    &self
+                        .time_db("get_driver", |store| store.get_driver(id))?
+                        .ok_or(AspirinEatsError::NotFound)?,
+                )
+            }
+            ("POST", ["drivers", id, "shift"]) => {
+                let id = parse_id(id)?;
+                let shift: ShiftRequest = match request.body.as_deref() {
+                    Some(body) if !body.trim().is_empty() => serde_json::from_str(body)?,
+                    _ => ShiftRequest::default(),
+                };
+                if shift.location.is_some_and(|location| !location.is_valid()) {
+                    return Err(AspirinEatsError::InvalidRequest);
+                }
+                json(&self.time_db("start_shift", |store| store.start_shift(id, shift.location))?)
+            }
+            ("DELETE", ["drivers", id, "shift"]) => {
+                let id = parse_id(id)?;
+                json(&self.time_db("end_shift", |store| store.end_shift(id))?)
+            }
+            ("POST", ["drivers", id, "claim"]) => {
+                let id = parse_id(id)?;
+                let claim: ClaimRequest = serde_json::from_str(body(request)?)?;
+                json(&self.time_db("claim_delivery", |store| {
+                    store.claim_delivery(id, claim.order)
+                })?)
+            }
+            ("POST", ["drivers", id, "complete"]) => {
+                let id = parse_id(id)?;
+                json(&self.time_db("complete_delivery", |store| store.complete_delivery(id))?)
+            }
+
+            (_, segments) => match allowed_methods(segments) {
+                Some(methods) => Ok(HttpResponse::from(AspirinEatsError::MethodNotAllowed)
+                    .with_header("Allow", &allow_header(methods))),
+                None => Err(AspirinEatsError::NotFound),
+            },
+        }
+    }
+}
+
+/// The path of a request, split into its segments
+fn segments(request: &HttpRequest) -> Vec<&str> {
+    request
+        .route()
+        .split('/')
+        .filter(|segment| !segment.is_empty())
+        .collect()
+}
+
+/// Every path `Api::route` handles, with the methods it handles there. `{id}` stands for any
+/// single segment and `*` for any number of them, and the first route that matches a path is
+/// the one it's labelled with in metrics
+const ROUTES: &[(&str, &[&str])] = &[
+    ("/", &["GET"]),
+    ("/metrics", &["GET"]),
+    ("/openapi.json", &["GET"]),
+    ("/menu", &["GET"]),
+    ("/ui/*", &["GET"]),
+    ("/orders", &["GET", "POST", "DELETE"]),
+    ("/orders/batch", &["POST"]),
+    ("/orders/{id}", &["GET", "DELETE"]),
+    ("/orders/{id}/items", &["PUT"]),
+    ("/customers", &["GET"]),
+    ("/customers/{id}", &["GET"]),
+    ("/customers/{id}/orders", &["GET"]),
+    ("/reports/sales", &["GET"]),
+    ("/inventory", &["GET", "PUT", "POST"]),
+    ("/drivers", &["GET", "POST"]),
+    ("/drivers/{id}", &["GET"]),
+    ("/drivers/{id}/shift", &["POST", "DELETE"]),
+    ("/drivers/{id}/claim", &["POST"]),
+    ("/drivers/{id}/complete", &["POST"]),
+];
+
+/// The route in [`ROUTES`] a path matches, if any
+fn find_route(segments: &[&str]) -> Option<&'static (&'static str, &'static [&'static str])> {
+    ROUTES.iter().find(|(template, _)| {
+        let mut parts = template.split('/').filter(|part| !part.is_empty());
+        let mut segments = segments.iter();
+        loop {
+            match (parts.next(), segments.next()) {
+                (Some("*"), _) | (None, None) => return true,
+                (Some("{id}"), Some(_)) => {}
+                (Some(part), Some(segment)) if part == *segment => {}
+                _ => return false,
+            }
+        }
+    })
+}
+
+/// Methods `Api::route` handles for a path, or None if there is no such path
+fn allowed_methods(segments: &[&str]) -> Option<&'static [&'static str]> {
+    find_route(segments).map(|(_, methods)| *methods)
+}
+
+/// The template of the route a request is for (e.g. `/orders/{id}`), to label its metrics with.
+/// Paths the API doesn't serve are all [`UNMATCHED_ROUTE`], so they can't add time series
+pub fn route_template(request: &HttpRequest) -> &'static str {
+    find_route(&segments(request)).map_or(UNMATCHED_ROUTE, |(template, _)| template)
+}
+
+/// `GET /orders`
+pub fn list_orders<S: OrderStore>(store: &S) -> Result<HttpResponse, AspirinEatsError> {
+    json(&store.get_all_orders()?)
+}
+
+/// `GET /orders/{id}`
+pub fn get_order<S: OrderStore>(store: &S, id: i64) -> Result<HttpResponse, AspirinEatsError> {
+    json(&store.get_order(id)?.ok_or(AspirinEatsError::NotFound)?)
+}
+
+/// `POST /orders`, responding with the newly created order
+pub fn add_order<S: OrderStore>(
+    store: &S,
+    pricing: &Pricing,
+    schedule: &SchedulePolicy,
+    request: &HttpRequest,
+) -> Result<HttpResponse, AspirinEatsError> {
+    let order_request = OrderRequest::from_str(body(request)?)?;
+    let order = new_order(pricing, schedule, order_request, &Local::now())?;
+    let id = store.add_order(order)?;
+    let order = store.get_order(id)?.ok_or(AspirinEatsError::NotFound)?;
+    created(&order)
+}
+
+/// Check a request for a new order and price it as of `now`
+fn new_order(
+    pricing: &Pricing,
+    schedule: &SchedulePolicy,
+    order_request: OrderRequest,
+    now: &DateTime<Local>,
+) -> Result<Order, AspirinEatsError> {
+    if normalize_name(&order_request.customer).is_empty()
+        || order_request.food.is_empty()
+        || order_request
+            .delivery
+            .as_ref()
+            .is_some_and(|address| !address.is_valid())
+    {
+        return Err(AspirinEatsError::InvalidRequest);
+    }
+    if let Some(scheduled_for) = &order_request.scheduled_for {
+        schedule.check(scheduled_for, now)?;
+    }
+    pricing.order(order_request, now.date_naive())
+}
+
+/// `DELETE /orders/{id}`
+pub fn remove_order<S: OrderStore>(store: &S, id: i64) -> Result<HttpResponse, AspirinEatsError> {
+    store.get_order(id)?.ok_or(AspirinEatsError::NotFound)?;
+    store.remove_order(id)?;
+    Ok(HttpResponse::new(200, "OK", &format!("Order {id} removed")))
+}
+
+/// `DELETE /orders`
+pub fn reset_orders<S: OrderStore>(store: &S) -> Result<HttpResponse, AspirinEatsError> {
+    store.reset_orders()?;
+    Ok(HttpResponse::new(200, "OK", "All orders removed"))
+}
+
+impl<S: Store> Api<S> {
+    /// `POST /orders/batch?mode=all_or_nothing|best_effort`, placing every order in one
+    /// transaction. Orders that can't be priced never reach the database, and in
+    /// all-or-nothing mode they stop the rest from being placed too
+    fn add_orders(&self, request: &HttpRequest) -> Result<HttpResponse, AspirinEatsError> {
+        let mode: BatchMode = request
+            .query("mode")
+            .map(|mode| mode.parse())
+            .transpose()?
+            .unwrap_or_default();
+        let order_requests: Vec<OrderRequest> = serde_json::from_str(body(request)?)?;
+        if order_requests.is_empty() {
+            return Err(AspirinEatsError::InvalidRequest);
+        }
+
+        let now = Local::now();
+        let mut failures = Vec::new();
+        let mut orders = Vec::new();
+        for (index, order_request) in order_requests.into_iter().enumerate() {
+            match new_order(&self.pricing, &self.schedule, order_request, &now) {
+                Ok(order) => orders.push((index, order)),
+                Err(err) => failures.push(batch_failure(index, err)),
+            }
+        }
+
+        let mut created = Vec::new();
+        if failures.is_empty() || mode == BatchMode::BestEffort {
+            let (indexes, orders): (Vec<usize>, Vec<Order>) = orders.into_iter().unzip();
+            let store = InstrumentedStore::new(&self.store, &self.metrics);
+            let outcome = store.add_orders(orders, mode)?;
+            for (index, result) in indexes.into_iter().zip(outcome.results) {
+                match result {
+                    Ok(id) if outcome.committed => created.push(id),
+                    Ok(_) => {}
+                    Err(err) => failures.push(batch_failure(index, err)),
+                }
+            }
+        }
+        failures.sort_by_key(|failure| failure.index);
+
+        let report = BatchReport { created, failures };
+        match report.created.is_empty() {
+            true => json_with_status(422, "Unprocessable Content", &report),
+            false => json_with_status(201, "Created", &report),
+        }
+    }
+
+    /// `GET /reports/sales?from=&to=&group_by=day|item|status&format=json|csv`
+    fn sales_report(&self, request: &HttpRequest) -> Result<HttpResponse, AspirinEatsError> {
+        let query = SalesQuery {
+            from: request
+                .query("from")
+                .as_deref()
+                .map(parse_date)
+                .transpose()?,
+            to: request.query("to").as_deref().map(parse_date).transpose()?,
+            group_by: request
+                .query("group_by")
+                .as_deref()
+                .unwrap_or("day")
+                .parse::<ReportGrouping>()?,
+        };
+        let report = self.time_db("sales_report", |store| store.sales_report(&query))?;
+
+        match request.query("format").as_deref() {
+            None | Some("json") => json(&report),
+            Some("csv") => Ok(HttpResponse::new(200, "OK", &report.to_csv())
+                .with_header("Content-Type", "text/csv")),
+            Some(_) => Err(AspirinEatsError::InvalidRequest),
+        }
+    }
+}
+
+impl<S: Store> Api<S> {
+    /// `PUT /orders/{id}/items`, replacing the food of a pending order and pricing it again.
+    /// `If-Match` has to carry the ETag the order was fetched with, so that changes made to it
+    /// since then aren't lost
+    fn replace_items(
+        &self,
+        id: i64,
+        request: &HttpRequest,
+    ) -> Result<HttpResponse, AspirinEatsError> {
+        let items = ItemsRequest::from_str(body(request)?)?;
+        if items.food.is_empty() {
+            return Err(AspirinEatsError::InvalidRequest);
+        }
+        let version = self
+            .time_db("get_order_version", |store| store.get_order_version(id))?
+            .ok_or(AspirinEatsError::NotFound)?;
+        let etag = request
+            .header("If-Match")
+            .ok_or(AspirinEatsError::PreconditionRequired)?;
+        if !if_match(etag, &order_etag(version)) {
+            return Err(AspirinEatsError::PreconditionFailed);
+        }
+
+        let order = self
+            .time_db("get_order", |store| store.get_order(id))?
+            .ok_or(AspirinEatsError::NotFound)?;
+        let breakdown = self.pricing.price_delivered(
+            &items.food,
+            items.coupon.as_deref(),
+            order.delivery.as_ref(),
+            Local::now().date_naive(),
+        )?;
+        InstrumentedStore::new(&self.store, &self.metrics).replace_items(
+            id,
+            version,
+            &items.food,
+            &breakdown,
+        )?;
+        let order = self
+            .time_db("get_order", |store| store.get_order(id))?
+            .ok_or(AspirinEatsError::NotFound)?;
+        self.with_order_etag(json(&order)?, id)
+    }
+
+    /// Tag a response about an order with the order's current version
+    fn with_order_etag(
+        &self,
+        response: HttpResponse,
+        id: i64,
+    ) -> Result<HttpResponse, AspirinEatsError> {
+        let version = self
+            .time_db("get_order_version", |store| store.get_order_version(id))?
+            .ok_or(AspirinEatsError::NotFound)?;
+        Ok(response.with_header("ETag", &order_etag(version)))
+    }
+
+    /// Add a `Last-Modified` header saying when any order last changed, so pollers can ask
+    /// whether anything has since
+    fn with_orders_last_modified(
+        &self,
+        response: HttpResponse,
+    ) -> Result<HttpResponse, AspirinEatsError> {
+        let modified =
+            self.time_db("orders_last_modified", |store| store.orders_last_modified())?;
+        Ok(match modified {
+            Some(modified) => response.with_header("Last-Modified", &http_date(modified)),
+            None => response,
+        })
+    }
+
+    /// Run a query against the store, recording how long it took
+    fn time_db<T>(
+        &self,
+        operation: &str,
+        query: impl FnOnce(&S) -> Result<T, AspirinEatsError>,
+    ) -> Result<T, AspirinEatsError> {
+        self.metrics.time_db(operation, || query(&self.store))
+    }
+}
+
+/// Report why an order in a batch couldn't be placed, with the status it would have had alone
+fn batch_failure(index: usize, err: AspirinEatsError) -> BatchFailure {
+    let message = err.to_string();
+    BatchFailure {
+        index,
+        status: HttpResponse::from(err).status_code(),
+        message,
+    }
+}
+
+fn parse_date(date: &str) -> Result<NaiveDate, AspirinEatsError> {
+    NaiveDate::parse_from_str(date, "%Y-%m-%d").map_err(|_| AspirinEatsError::InvalidRequest)
+}
+
+/// Strong entity tag for version `version` of an order
+fn order_etag(version: i64) -> String {
+    format!("\"{version}\"")
+}
+
+fn parse_id(id: &str) -> Result<i64, AspirinEatsError> {
+    id.parse().map_err(|_| AspirinEatsError::InvalidRequest)
+}
+
+fn body(request: &HttpRequest) -> Result<&str, AspirinEatsError> {
+    request
+        .body
+        .as_deref()
+        .ok_or(AspirinEatsError::InvalidRequest)
+}
+
+/// 200 OK with `value` as a JSON body
+fn json<T: serde::Serialize>(value: &T) -> Result<HttpResponse, AspirinEatsError> {
+    json_with_status(200, "OK", value)
+}
+
+/// 201 Created with the new `value` as a JSON body
+fn created<T: serde::Serialize>(value: &T) -> Result<HttpResponse, AspirinEatsError> {
+    json_with_status(201, "Created", value)
+}
+
+fn json_with_status<T: serde::Serialize>(
+    status_code: u16,
+    status_text: &str,
+    value: &T,
+) -> Result<HttpResponse, AspirinEatsError> {
+    Ok(
+        HttpResponse::new(status_code, status_text, &serde_json::to_string(value)?)
+            .with_header("Content-Type", JSON_CONTENT_TYPE),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::{TimeDelta, TimeZone};
+
+    use super::*;
+    use crate::drivers::Driver;
+    use crate::logging::{LogFormat, SharedBuffer};
+    use crate::pricing::Menu;
+    use crate::store::InMemoryStore;
+
+    const ORDER: &str = r#"{"customer":"Amit","food":[{"Burger":{"bun":"Plain","patty":"Beef","toppings":["Lettuce","Tomato","Bacon"]}},"Fries"]}"#;
+
+    fn get_test_api() -> Api<InMemoryStore> {
+        Api::new(InMemoryStore::new(), Pricing::default())
+    }
+
+    fn send(api: &Api<InMemoryStore>, method: &str, path: &str, body: &str) -> HttpResponse {
+        let request = format!("{method} {path} HTTP/1.1\r\nHost: localhost\r\n\r\n{body}");
+        api.handle(&request.parse().unwrap())
+    }
+
+    #[test]
+    fn test_welcome() {
+        let api = get_test_api();
+        let response = send(&api, "GET", "/", "");
+        assert_eq!(response.status_code(), 200);
+        assert_eq!(response.body(), "Welcome to Aspirin Eats!");
+    }
+
+    #[test]
+    fn test_add_and_get_order() {
+        let api = get_test_api();
+        let response = send(&api, "POST", "/orders", ORDER);
+        assert_eq!(response.status_code(), 201);
+        let order = Order::from_str(response.body()).unwrap();
+        assert_eq!(order.id, Some(1));
+        assert_eq!(order.total, 15.0);
+
+        let response = send(&api, "GET", "/orders/1", "");
+        assert_eq!(Order::from_str(response.body()).unwrap(), order);
+
+        let response = send(&api, "GET", "/orders", "");
+        let orders: Vec<Order> = serde_json::from_str(response.body()).unwrap();
+        assert_eq!(orders, vec![order]);
+    }
+
+    #[test]
+    fn test_json_content_type() {
+        let api = get_test_api();
+        let batch = format!(r#"[{ORDER}]"#);
+        for (method, path, body, status) in [
+            ("POST", "/orders", ORDER, 201),
+            ("GET", "/orders/1", "", 200),
+            ("GET", "/orders", "", 200),
+            ("POST", "/orders/batch", batch.as_str(), 201),
+            (
+                "POST",
+                "/orders/batch",
+                r#"[{"customer":"","food":[]}]"#,
+                422,
+            ),
+            ("POST", "/drivers", r#"{"name":"Sam"}"#, 201),
+            ("GET", "/customers", "", 200),
+            ("GET", "/menu", "", 200),
+        ] {
+            let response = send(&api, method, path, body);
+            assert_eq!(response.status_code(), status, "{method} {path}");
+            assert_eq!(
+                response.header("Content-Type"),
+                Some("application/json"),
+                "{method} {path}"
+            );
+            serde_json::from_str::<serde_json::Value>(response.body()).unwrap();
+        }
+
+        let response = send(&api, "GET", "/menu", "");
+        assert_eq!(
+            serde_json::from_str::<Menu>(response.body()).unwrap(),
+            Pricing::default().menu()
+        );
+    }
+
+    #[test]
+    fn test_order_handlers_without_sqlite() {
+        let store = InMemoryStore::new();
+        let request = format!(
+            "POST /orders HTTP/1.1\r\nContent-Length: {}\r\n\r\n{ORDER}",
+            ORDER.len()
+        )
+        .parse()
+        .unwrap();
+
+        let response = add_order(
+            &store,
+            &Pricing::default(),
+            &SchedulePolicy::default(),
+            &request,
+        )
+        .unwrap();
+        assert_eq!(response.status_code(), 201);
+        assert_eq!(get_order(&store, 1).unwrap().body(), response.body());
+        assert!(matches!(
+            get_order(&store, 2),
+            Err(AspirinEatsError::NotFound)
+        ));
+
+        remove_order(&store, 1).unwrap();
+        assert_eq!(list_orders(&store).unwrap().body(), "[]");
+        assert!(matches!(
+            remove_order(&store, 1),
+            Err(AspirinEatsError::NotFound)
+        ));
+    }
+
+    #[test]
+    fn test_scheduled_orders() {
+        let api = get_test_api();
+        let order_at = |days: i64, hour: u32| {
+            let date = Local::now().date_naive() + TimeDelta::days(days);
+            let time = Local
+                .from_local_datetime(&date.and_hms_opt(hour, 0, 0).unwrap())
+                .earliest()
+                .unwrap();
+            let order = r#"{"customer":"Amit","food":["Fries"],"scheduled_for":"TIME"}"#;
+            order.replace("TIME", &time.to_rfc3339())
+        };
+
+        let response = send(&api, "POST", "/orders", &order_at(1, 18));
+        assert_eq!(response.status_code(), 201);
+        let order: Order = serde_json::from_str(response.body()).unwrap();
+        assert_eq!(order.status, OrderStatus::Scheduled);
+        assert_eq!(order.scheduled_for.unwrap().time().to_string(), "18:00:00");
+
+        let response = send(&api, "POST", "/orders", &order_at(-1, 18));
+        assert_eq!(response.status_code(), 422);
+        assert!(response.body().ends_with("has already passed"));
+        let response = send(&api, "POST", "/orders", &order_at(1, 3));
+        assert_eq!(response.status_code(), 422);
+        assert_eq!(
+            response.body(),
+            "Can't schedule order: we're only open from 11:00 to 22:00"
+        );
+        assert_eq!(api.store.get_all_orders().unwrap().len(), 1);
+    }
+
+    #[test]
+    fn test_batch_orders() {
+        let api = get_test_api();
+        let batch = format!(r#"[{ORDER},{{"customer":" ","food":["Fries"]}},{ORDER}]"#);
+
+        let response = send(&api, "POST", "/orders/batch", &batch);
+        assert_eq!(response.status_code(), 422);
+        let report: BatchReport = serde_json::from_str(response.body()).unwrap();
+        assert_eq!(
+            report,
+            BatchReport {
+                created: vec![],
+                failures: vec![BatchFailure {
+                    index: 1,
+                    status: 400,
+                    message: "Invalid Request".to_string()
+                }],
+            }
+        );
+        assert_eq!(api.store.get_all_orders().unwrap().len(), 0);
+
+        let response = send(&api, "POST", "/orders/batch?mode=best_effort", &batch);
+        assert_eq!(response.status_code(), 201);
+        let report: BatchReport = serde_json::from_str(response.body()).unwrap();
+        assert_eq!(report.created, vec![1, 2]);
+        assert_eq!(report.failures.len(), 1);
+
+        let response = send(&api, "POST", "/orders/batch", &format!("[{ORDER}]"));
+        assert_eq!(response.status_code(), 201);
+        assert_eq!(response.body(), r#"{"created":[3],"failures":[]}"#);
+
+        for (path, body) in [
+            ("/orders/batch", "[]"),
+            ("/orders/batch", ORDER),
+            ("/orders/batch?mode=most", batch.as_str()),
+        ] {
+            assert_eq!(send(&api, "POST", path, body).status_code(), 400);
+        }
+    }
+
+    #[test]
+    fn test_remove_orders() {
+        let api = get_test_api();
+        send(&api, "POST", "/orders", ORDER);
+        send(&api, "POST", "/orders", ORDER);
+
+        assert_eq!(send(&api, "DELETE", "/orders/1", "").status_code(), 200);
+        assert_eq!(send(&api, "GET", "/orders/1", "").status_code(), 404);
+        assert_eq!(send(&api, "DELETE", "/orders/1", "").status_code(), 404);
+
+        assert_eq!(send(&api, "DELETE", "/orders", "").status_code(), 200);
+        assert_eq!(send(&api, "GET", "/orders", "").body(), "[]");
+    }
+
+    #[test]
+    fn test_inventory() {
+        let api = get_test_api();
+        let response = send(
+            &api,
+            "PUT",
+            "/inventory",
+            r#"[{"ingredient":{"Patty":"Beef"},"quantity":1},{"ingredient":"Fries","quantity":5}]"#,
+        );
+        assert_eq!(response.status_code(), 200);
+
+        assert_eq!(send(&api, "POST", "/orders", ORDER).status_code(), 201);
+        let response = send(&api, "POST", "/orders", ORDER);
+        assert_eq!(response.status_code(), 409);
+        assert_eq!(response.body(), "Out of stock: Beef patty");
+
+        let response = send(
+            &api,
+            "POST",
+            "/inventory",
+            r#"[{"ingredient":{"Patty":"Beef"},"change":4}]"#,
+        );
+        let levels: Vec<StockLevel> = serde_json::from_str(response.body()).unwrap();
+        assert_eq!(
+            levels,
+            vec![
+                StockLevel {
+                    ingredient: Ingredient::Fries,
+                    quantity: 4,
+                },
+                StockLevel {
+                    ingredient: Ingredient::Patty(Patty::Beef),
+                    quantity: 4,
+                },
+            ]
+        );
+
+        assert_eq!(send(&api, "DELETE", "/orders/1", "").status_code(), 200);
+        let response = send(&api, "GET", "/inventory", "");
+        assert!(response
+            .body()
+            .contains(r#"{"ingredient":"Fries","quantity":5}"#));
+        assert_eq!(send(&api, "PUT", "/inventory", "nope").status_code(), 400);
+    }
+
+    fn send_with_header(
+        api: &Api<InMemoryStore>,
+        method: &str,
+        path: &str,
+        header: (&str, &str),
+        body: &str,
+    ) -> HttpResponse {
+        let (name, value) = header;
+        let request =
+            format!("{method} {path} HTTP/1.1\r\nHost: localhost\r\n{name}: {value}\r\n\r\n{body}");
+        api.handle(&request.parse().unwrap())
+    }
+
+    #[test]
+    fn test_replace_items() {
+        let api = get_test_api();
+        let items = r#"{"food":["Fries","Fries"]}"#;
+        assert_eq!(send(&api, "POST", "/orders", ORDER).status_code(), 201);
+        let response = send(&api, "GET", "/orders/1", "");
+        assert_eq!(response.header("ETag"), Some("\"1\""));
+
+        let response = send(&api, "PUT", "/orders/1/items", items);
+        assert_eq!(response.status_code(), 428);
+        let response =
+            send_with_header(&api, "PUT", "/orders/1/items", ("If-Match", "\"2\""), items);
+        assert_eq!(response.status_code(), 412);
+
+        let response =
+            send_with_header(&api, "PUT", "/orders/1/items", ("If-Match", "\"1\""), items);
+        assert_eq!(response.status_code(), 200);
+        assert_eq!(response.header("ETag"), Some("\"2\""));
+        let order: Order = serde_json::from_str(response.body()).unwrap();
+        assert_eq!(order.id, Some(1));
+        assert_eq!(order.food, vec![MenuItem::Fries, MenuItem::Fries]);
+        assert_eq!(order.total, 10.0);
+
+        // The first edit moved the order on, so repeating it is refused
+        let response =
+            send_with_header(&api, "PUT", "/orders/1/items", ("If-Match", "\"1\""), items);
+        assert_eq!(response.status_code(), 412);
+
+        let mut order = api.store.get_order(1).unwrap().unwrap();
+        order.status = OrderStatus::Preparing;
+        api.store.update_order(&order).unwrap();
+        let response = send_with_header(&api, "PUT", "/orders/1/items", ("If-Match", "*"), items);
+        assert_eq!(response.status_code(), 409);
+        assert_eq!(
+            response.body(),
+            "Order 1 is Preparing, so it can't be changed any more"
+        );
+
+        let response = send_with_header(
+            &api,
+            "PUT",
+            "/orders/1/items",
+            ("If-Match", "*"),
+            r#"{"food":[]}"#,
+        );
+        assert_eq!(response.status_code(), 400);
+        let response = send_with_header(&api, "PUT", "/orders/2/items", ("If-Match", "*"), items);
+        assert_eq!(response.status_code(), 404);
+    }
+
+    #[test]
+    fn test_conditional_get() {
+        let api = get_test_api();
+        assert_eq!(
+            send(&api, "GET", "/orders", "").header("Last-Modified"),
+            None
+        );
+        assert_eq!(send(&api, "POST", "/orders", ORDER).status_code(), 201);
+
+        let response = send(&api, "GET", "/orders", "");
+        let etag = response.header("ETag").unwrap().to_string();
+        let modified = response.header("Last-Modified").unwrap().to_string();
+
+        let response = send_with_header(&api, "GET", "/orders", ("If-None-Match", &etag), "");
+        assert_eq!(response.status_code(), 304);
+        assert_eq!(response.body(), "");
+        assert_eq!(response.header("ETag"), Some(etag.as_str()));
+        let response = send_with_header(&api, "HEAD", "/orders", ("If-None-Match", &etag), "");
+        assert_eq!(response.status_code(), 304);
+        let response =
+            send_with_header(&api, "GET", "/orders", ("If-Modified-Since", &modified), "");
+        assert_eq!(response.status_code(), 304);
+
+        // A new order changes the list
+        assert_eq!(send(&api, "POST", "/orders", ORDER).status_code(), 201);
+        let response = send_with_header(&api, "GET", "/orders", ("If-None-Match", &etag), "");
+        assert_eq!(response.status_code(), 200);
+        assert_ne!(response.header("ETag"), Some(etag.as_str()));
+
+        // A single order is tagged with its version
+        let response = send_with_header(&api, "GET", "/orders/1", ("If-None-Match", "\"1\""), "");
+        assert_eq!(response.status_code(), 304);
+        let response = send_with_header(&api, "GET", "/orders/1", ("If-None-Match", "\"0\""), "");
+        assert_eq!(response.status_code(), 200);
+    }
+
+    #[test]
+    fn test_ui() {
+        let api = get_test_api().with_ui(UiPolicy {
+            dir: concat!(env!("CARGO_MANIFEST_DIR"), "/ui").into(),
+            ..UiPolicy::default()
+        });
+
+        let response = send(&api, "GET", "/ui/", "");
+        assert_eq!(response.status_code(), 200);
+        assert!(response.body().contains(r#"data-page="menu""#));
+        assert_eq!(response.header("Cache-Control"), Some("no-cache"));
+        let etag = response.header("ETag").unwrap().to_string();
+        let response = send_with_header(&api, "GET", "/ui/", ("If-None-Match", &etag), "");
+        assert_eq!(response.status_code(), 304);
+
+        // Every page's links, stylesheets and scripts are there to be served
+        for page in ["index.html", "order.html", "status.html"] {
+            let response = send(&api, "GET", &format!("/ui/{page}"), "");
+            assert_eq!(response.status_code(), 200, "{page}");
+            for link in response.body().split(['"']).filter(|attribute| {
+                [".html", ".css", ".js"]
+                    .iter()
+                    .any(|extension| attribute.ends_with(extension))
+            }) {
+                let response = send(&api, "HEAD", &format!("/ui/{link}"), "");
+                assert_eq!(response.status_code(), 200, "{page} links to {link}");
+            }
+        }
+        assert!(send(&api, "GET", "/ui/app.js", "")
+            .body()
+            .contains("\"/orders\""));
+
+        assert_eq!(send(&api, "GET", "/ui", "").status_code(), 301);
+        assert_eq!(
+            send(&api, "GET", "/ui/../Cargo.toml", "").status_code(),
+            404
+        );
+        let response = send(&api, "POST", "/ui/", "");
+        assert_eq!(response.status_code(), 405);
+        assert_eq!(response.header("Allow"), Some("GET, HEAD, OPTIONS"));
+
+        let api = api.with_ui(UiPolicy {
+            enabled: false,
+            ..UiPolicy::default()
+        });
+        assert_eq!(send(&api, "GET", "/ui/", "").status_code(), 404);
+    }
+
+    #[test]
+    fn test_drivers() {
+        let api = get_test_api();
+        let response = send(&api, "POST", "/drivers", r#"{"name":"Sam"}"#);
+        assert_eq!(response.status_code(), 201);
+        assert_eq!(response.body(), r#"{"id":1,"name":"Sam","on_shift":false}"#);
+        assert_eq!(
+            send(&api, "POST", "/drivers", r#"{"name":" "}"#).status_code(),
+            400
+        );
+
+        let response = send(
+            &api,
+            "POST",
+            "/drivers/1/shift",
+            r#"{"location":{"lat":42.0,"lon":-71.0}}"#,
+        );
+        assert_eq!(response.status_code(), 200);
+        let driver: Driver = serde_json::from_str(response.body()).unwrap();
+        assert!(driver.on_shift);
+
+        // Not out of the kitchen yet
+        assert_eq!(send(&api, "POST", "/orders", ORDER).status_code(), 201);
+        let response = send(&api, "POST", "/drivers/1/claim", r#"{"order":1}"#);
+        assert_eq!(response.status_code(), 409);
+        assert_eq!(response.body(), "Order 1 isn't ready for a driver");
+
+        let mut order = api.store.get_order(1).unwrap().unwrap();
+        order.status = OrderStatus::Transporting;
+        api.store.update_order(&order).unwrap();
+        let response = send(&api, "POST", "/drivers/1/claim", r#"{"order":1}"#);
+        assert_eq!(response.status_code(), 200);
+        let order: Order = serde_json::from_str(response.body()).unwrap();
+        assert_eq!(order.driver, Some(1));
+
+        assert_eq!(
+            send(&api, "DELETE", "/drivers/1/shift", "").status_code(),
+            409
+        );
+        let response = send(&api, "POST", "/drivers/1/complete", "");
+        let order: Order = serde_json::from_str(response.body()).unwrap();
+        assert_eq!(order.status, OrderStatus::Completed);
+        assert_eq!(
+            send(&api, "DELETE", "/drivers/1/shift", "").status_code(),
+            200
+        );
+
+        let response = send(&api, "GET", "/drivers", "");
+        assert_eq!(
+            response.body(),
+            r#"[{"id":1,"name":"Sam","on_shift":false,"location":{"lat":42.0,"lon":-71.0}}]"#
+        );
+        assert_eq!(send(&api, "GET", "/drivers/2", "").status_code(), 404);
+        assert_eq!(
+            send(&api, "POST", "/drivers/2/shift", "").status_code(),
+            404
+        );
+        assert_eq!(send(&api, "PUT", "/drivers/1/claim", "").status_code(), 405);
+    }
+
+    #[test]
+    fn test_errors() {
+        let api = get_test_api();
+        assert_eq!(send(&api, "POST", "/orders", "not json").status_code(), 400);
+        assert_eq!(send(&api, "GET", "/orders/abc", "").status_code(), 400);
+        assert_eq!(send(&api, "PUT", "/orders", "").status_code(), 405);
+        assert_eq!(send(&api, "GET", "/burgers", "").status_code(), 404);
+
+        let response = send(&api, "PUT", "/orders/1", "");
+        assert_eq!(response.header("Allow"), Some("GET, DELETE, HEAD, OPTIONS"));
+    }
+
+    #[test]
+    fn test_head() {
+        let api = get_test_api();
+        send(&api, "POST", "/orders", ORDER);
+        let get = send(&api, "GET", "/orders/1", "");
+
+        let head = send(&api, "HEAD", "/orders/1", "");
+        assert_eq!(head.status_code(), 200);
+        assert_eq!(head.body(), "");
+        assert_eq!(
+            head.header("Content-Length"),
+            Some(get.body().len().to_string().as_str())
+        );
+        assert_eq!(send(&api, "HEAD", "/orders/9", "").status_code(), 404);
+        assert_eq!(send(&api, "HEAD", "/orders/9", "").body(), "");
+    }
+
+    #[test]
+    fn test_options() {
+        let api = get_test_api();
+        let response = send(&api, "OPTIONS", "/orders", "");
+        assert_eq!(response.status_code(), 204);
+        assert_eq!(
+            response.header("Allow"),
+            Some("GET, POST, DELETE, HEAD, OPTIONS")
+        );
+        assert_eq!(send(&api, "OPTIONS", "/burgers", "").status_code(), 404);
+
+        // Every method we say is allowed is actually handled
+        for path in [
+            "/",
+            "/metrics",
+            "/orders",
+            "/orders/1",
+            "/customers",
+            "/customers/1",
+            "/customers/1/orders",
+            "/reports/sales",
+        ] {
+            let allow = send(&api, "OPTIONS", path, "")
+                .header("Allow")
+                .unwrap()
+                .to_string();
+            for method in allow.split(", ") {
+                assert_ne!(
+                    send(&api, method, path, "").status_code(),
+                    405,
+                    "{method} {path}"
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn test_cors_preflight() {
+        let api = get_test_api().with_cors(CorsPolicy {
+            allowed_origins: vec!["https://eats.example.com".to_string()],
+            ..CorsPolicy::default()
+        });
+        let request = "OPTIONS /orders HTTP/1.1\r\nOrigin: https://eats.example.com\r\n\
+                       Access-Control-Request-Method: POST\r\n\r\n";
+        let response = api.handle(&request.parse().unwrap());
+
+        assert_eq!(response.status_code(), 204);
+        assert_eq!(
+            response.header("Access-Control-Allow-Origin"),
+            Some("https://eats.example.com")
+        );
+        assert_eq!(
+            response.header("Access-Control-Allow-Methods"),
+            Some("GET, POST, DELETE")
+        );
+
+        let request = "GET /orders HTTP/1.1\r\nOrigin: https://eats.example.com\r\n\r\n";
+        let response = api.handle(&request.parse().unwrap());
+        assert_eq!(response.body(), "[]");
+        assert_eq!(
+            response.header("Access-Control-Allow-Origin"),
+            Some("https://eats.example.com")
+        );
+    }
+
+    #[test]
+    fn test_customer_orders() {
+        let api = get_test_api();
+        send(&api, "POST", "/orders", ORDER);
+        send(&api, "POST", "/orders", &ORDER.replace("Amit", " amit "));
+        send(&api, "POST", "/orders", &ORDER.replace("Amit", "Bea"));
+
+        let customers: Vec<Customer> =
+            serde_json::from_str(send(&api, "GET", "/customers", "").body()).unwrap();
+        assert_eq!(customers.len(), 2);
+
+        let response = send(&api, "GET", "/customers/1/orders", "");
+        let orders: Vec<Order> = serde_json::from_str(response.body()).unwrap();
+        assert_eq!(
+            orders.iter().map(|o| o.id.unwrap()).collect::<Vec<_>>(),
+            vec![1, 2]
+        );
+        assert_eq!(
+            send(&api, "GET", "/customers/9/orders", "").status_code(),
+            404
+        );
+    }
+
+    #[test]
+    fn test_sales_report() {
+        let api = get_test_api();
+        send(&api, "POST", "/orders", ORDER);
+        send(&api, "POST", "/orders", ORDER);
+
+        let response = send(&api, "GET", "/reports/sales?group_by=item", "");
+        assert_eq!(response.status_code(), 200);
+        let report: crate::reports::SalesReport = serde_json::from_str(response.body()).unwrap();
+        assert_eq!(report.summary.orders, 2);
+        assert_eq!(report.summary.revenue, 30.0);
+        assert_eq!(report.groups.len(), 2);
+
+        let response = send(&api, "GET", "/reports/sales?group_by=status&format=csv", "");
+        assert_eq!(response.header("Content-Type"), Some("text/csv"));
+        assert_eq!(
+            response.body(),
+            "key,orders,items,revenue,average_order_value\nPending,2,4,30.00,15.00\n"
+        );
+
+        let response = send(&api, "GET", "/reports/sales?from=2099-01-01", "");
+        let report: crate::reports::SalesReport = serde_json::from_str(response.body()).unwrap();
+        assert_eq!(report.summary.orders, 0);
+
+        assert_eq!(
+            send(&api, "GET", "/reports/sales?group_by=week", "").status_code(),
+            400
+        );
+        assert_eq!(
+            send(&api, "GET", "/reports/sales?from=yesterday", "").status_code(),
+            400
+        );
+    }
+
+    #[test]
+    fn test_serve_connection() {
+        let api = get_test_api();
+        let mut stream =
+            std::io::Cursor::new(b"GET / HTTP/1.1\r\nX-Request-Id: abc\r\n\r\n".to_vec());
+        api.serve_connection(&mut stream, None).unwrap();
+        let written = String::from_utf8(stream.into_inner()).unwrap();
+        let body = "Welcome to Aspirin Eats!";
+        assert!(written.ends_with(&format!(
+            "HTTP/1.1 200 OK\r\nETag: {}\r\nX-Request-Id: abc\r\n\r\n{body}",
+            crate::http::content_etag(body)
+        )));
+    }
+
+    #[test]
+    fn test_metrics() {
+        let api = get_test_api();
+        for request in [
+            format!(
+                "POST /orders HTTP/1.1\r\nContent-Length: {}\r\n\r\n{ORDER}",
+                ORDER.len()
+            ),
+            "GET /orders/1 HTTP/1.1\r\n\r\n".to_string(),
+            "GET /orders/2 HTTP/1.1\r\n\r\n".to_string(),
+            "GET /customers/1/orders HTTP/1.1\r\n\r\n".to_string(),
+        ] {
+            let mut stream = std::io::Cursor::new(request.into_bytes());
+            api.serve_connection(&mut stream, None).unwrap();
+        }
+
+        let response = send(&api, "GET", "/metrics", "");
+        assert_eq!(response.status_code(), 200);
+        assert_eq!(response.header("Content-Type"), Some(METRICS_CONTENT_TYPE));
+        let body = response.body();
+        for line in [
+            "aspirin_eats_http_requests_total{service=\"origin\",route=\"/orders\",status=\"201\"} 1",
+            "aspirin_eats_http_requests_total{service=\"origin\",route=\"/orders/{id}\",status=\"200\"} 1",
+            "aspirin_eats_http_requests_total{service=\"origin\",route=\"/orders/{id}\",status=\"404\"} 1",
+            "aspirin_eats_http_request_duration_seconds_count{service=\"origin\",route=\"/orders/{id}\"} 2",
+            "aspirin_eats_db_query_duration_seconds_count{service=\"origin\",operation=\"add_order\"} 1",
+            "aspirin_eats_db_query_duration_seconds_count{service=\"origin\",operation=\"get_order\"} 3",
+            "aspirin_eats_db_query_duration_seconds_count{service=\"origin\",operation=\"get_customer_orders\"} 1",
+            "aspirin_eats_in_flight_connections{service=\"origin\"} 0",
+        ] {
+            assert!(body.contains(&format!("{line}\n")), "missing {line}");
+        }
+    }
+
+    #[test]
+    fn test_route_template() {
+        let template = |path: &str| {
+            let request: HttpRequest = format!("GET {path} HTTP/1.1\r\n\r\n").parse().unwrap();
+            route_template(&request)
+        };
+        assert_eq!(template("/"), "/");
+        assert_eq!(template("/orders"), "/orders");
+        assert_eq!(template("/orders/batch"), "/orders/batch");
+        assert_eq!(template("/orders/42"), "/orders/{id}");
+        assert_eq!(
+            template("/customers/7/orders?x=1"),
+            "/customers/{id}/orders"
+        );
+        assert_eq!(template("/ui"), "/ui/*");
+        assert_eq!(template("/ui/css/app.css"), "/ui/*");
+        assert_eq!(template("/orders/42/cancel"), UNMATCHED_ROUTE);
+        assert_eq!(template("/wp-admin.php"), UNMATCHED_ROUTE);
+    }
+
+    #[test]
+    fn test_metrics_routes_are_bounded() {
+        let api = get_test_api();
+        for _ in 0..100 {
+            let random = uuid::Uuid::new_v4();
+            for path in [
+                format!("/{random}"),
+                format!("/orders/{random}"),
+                format!("/orders/1/{random}"),
+                format!("/ui/{random}.js"),
+                format!("/{random}/orders?{random}"),
+            ] {
+                let request = format!("GET {path} HTTP/1.1\r\n\r\n");
+                let mut stream = std::io::Cursor::new(request.into_bytes());
+                api.serve_connection(&mut stream, None).unwrap();
+            }
+        }
+
+        let body = api.metrics().render();
+        let routes: std::collections::BTreeSet<&str> = body
+            .lines()
+            .filter(|line| line.starts_with("aspirin_eats_http_requests_total{"))
+            .filter_map(|line| line.split("route=\"").nth(1)?.split('"').next())
+            .collect();
+        assert_eq!(
+            routes.into_iter().collect::<Vec<_>>(),
+            vec!["/orders/{id}", "/ui/*", UNMATCHED_ROUTE]
+        );
+    }
+
+    #[test]
+    fn test_serve_connection_access_log() {
+        let buffer = SharedBuffer::default();
+        let api = get_test_api().with_access_log(AccessLog::with_writer(
+            "origin",
+            LogFormat::Json,
+            buffer.clone(),
+        ));
+        let client = "10.0.0.1:4000".parse().ok();
+
+        let mut stream = std::io::Cursor::new(
+            b"GET /orders/7 HTTP/1.1\r\nX-Request-Id: trace-me\r\n\r\n".to_vec(),
+        );
+        api.serve_connection(&mut stream, client).unwrap();
+        let mut stream = std::io::Cursor::new(b"garbage\r\n\r\n".to_vec());
+        api.serve_connection(&mut stream, client).unwrap();
+
+        let lines = buffer.lines();
+        let first: serde_json::Value = serde_json::from_str(&lines[0]).unwrap();
+        assert_eq!(first["service"], "origin");
+        assert_eq!(first["request_id"], "trace-me");
+        assert_eq!(first["client"], "10.0.0.1:4000");
+        assert_eq!(first["method"], "GET");
+        assert_eq!(first["path"], "/orders/7");
+        assert_eq!(first["status"], 404);
+        assert!(first["bytes"].as_u64().unwrap() > 0);
+
+        let second: serde_json::Value = serde_json::from_str(&lines[1]).unwrap();
+        assert_eq!(second["status"], 400);
+        assert_eq!(second["method"], "-");
+        assert!(second["request_id"]
+            .as_str()
+            .is_some_and(|id| !id.is_empty()));
+    }
+}
@@ -0,0 +1,96 @@
+use std::env;
+use std::io::{self, Read};
+
+use aspirin_eats::client::Client;
+use aspirin_eats::config::{CliArgs, Config};
+use aspirin_eats::error::AspirinEatsError;
+use aspirin_eats::food::OrderRequest;
+use aspirin_eats::tls;
+
+/// Shorthands for settings that are commonly changed on the command line
+const FLAGS: &[(&str, &str)] = &[("--addr", "client.addr"), ("--tls-ca", "client.tls_ca")];
+
+fn main() {
+    let args = env::args().collect::<Vec<String>>();
+    let (cli, config) = CliArgs::parse(&args[1..], FLAGS)
+        .and_then(|cli| {
+            let config = Config::load(&cli, &env::vars().collect::<Vec<_>>())?;
+            Ok((cli, config))
+        })
+        .unwrap_or_else(|err| {
+            eprintln!("{err}");
+            std::process::exit(2);
+        });
+    let client = client(&config).unwrap_or_else(|err| {
+        eprintln!("{err}");
+        std::process::exit(2);
+    });
+
+    let result = match cli
+        .positional
+        .iter()
+        .map(String::as_str)
+        .collect::<Vec<_>>()[..]
+    {
+        ["list"] => client.list_orders().map(|orders| {
+            for order in orders {
+                println!("{order}");
+            }
+        }),
+        ["get", id] => parse_id(id)
+            .and_then(|id| client.get_order(id))
+            .map(|order| println!("{order}")),
+        ["place", order] => read_order(order)
+            .and_then(|order| client.place_order(&order))
+            .map(|order| println!("{order}")),
+        ["cancel", id] => parse_id(id)
+            .and_then(|id| client.cancel_order(id))
+            .map(|_| eprintln!("Cancelled order {id}")),
+        ["reset"] => client
+            .reset_orders()
+            .map(|_| eprintln!("Removed all orders")),
+        _ => {
+            eprintln!("Usage: {} [options] list", args[0]);
+            eprintln!("       {} [options] get <id>", args[0]);
+            eprintln!("       {} [options] place <order-json | ->", args[0]);
+            eprintln!("       {} [options] cancel <id>", args[0]);
+            eprintln!("       {} [options] reset", args[0]);
+            eprintln!();
+            eprintln!("Options:");
+            eprintln!("  --config <file>        TOML config file (or ASPIRIN_EATS_CONFIG)");
+            eprintln!("  --addr <host:port>     server to talk to, by default the proxy");
+            eprintln!(
+                "  --tls-ca <file>        PEM certificate to trust, for a proxy serving HTTPS"
+            );
+            eprintln!("  --set <key>=<value>    any other setting, e.g. timeouts.read_secs=10");
+            std::process::exit(2);
+        }
+    };
+    if let Err(err) = result {
+        eprintln!("{err}");
+        std::process::exit(1);
+    }
+}
+
+/// Client for the configured server, speaking HTTPS if given a certificate to trust
+fn client(config: &Config) -> Result<Client, AspirinEatsError> {
+    let client = Client::new(&config.client.addr).with_timeout(config.timeouts.read());
+    match &config.client.tls_ca {
+        Some(ca_path) => Ok(client.with_tls(tls::load_client_config(ca_path)?)),
+        None => Ok(client),
+    }
+}
+
+fn parse_id(id: &str) -> Result<i64, AspirinEatsError> {
+    id.parse().map_err(|_| AspirinEatsError::InvalidRequest)
+}
+
+/// Parse an order given as JSON on the command line, or read from stdin if it's `-`
+fn read_order(order: &str) -> Result<OrderRequest, AspirinEatsError> {
+    if order != "-" {
+        return Ok(order.parse()?);
+    }
+    let mut order = String::new();
+    io::stdin().read_to_string(&mut order)?;
+    Ok(order.parse()?)
+}
@@ -1,9 +1,213 @@
-use aspirin_eats::db::AspirinEatsDb;
+use std::env;
+use std::fs::File;
+use std::io::{self, BufReader, BufWriter};
+use std::net::TcpListener;
 
-/// Change this path to match where you want to store the database file
-const DB_PATH: &str =
-    "/home/amit/Documents/code/aspirin/dev-aspirin/assignments/05-networking/aspirin_eats.db";
+use chrono::Local;
+
+use aspirin_eats::api::Api;
+use aspirin_eats::config::{CliArgs, Config};
+use aspirin_eats::db::{AspirinEatsDb, ImportMode};
+use aspirin_eats::kitchen::{Kitchen, SystemClock};
+use aspirin_eats::logging::{AccessLog, LogLevel};
+
+/// Shorthands for settings that are commonly changed on the command line
+const FLAGS: &[(&str, &str)] = &[
+    ("--bind", "origin.bind"),
+    ("--db", "origin.db_path"),
+    ("--log-level", "log.level"),
+    ("--log-format", "log.format"),
+];
 
 fn main() {
-    let db = AspirinEatsDb::from_path(DB_PATH).expect("Failed to open database");
+    let args = env::args().collect::<Vec<String>>();
+    let (cli, config) = CliArgs::parse(&args[1..], FLAGS)
+        .and_then(|cli| {
+            let config = Config::load(&cli, &env::vars().collect::<Vec<_>>())?;
+            Ok((cli, config))
+        })
+        .unwrap_or_else(|err| {
+            eprintln!("{err}");
+            std::process::exit(2);
+        });
+    if config.log.level >= LogLevel::Debug {
+        eprintln!("{config:#?}");
+    }
+    let db = open_db(&config);
+
+    match cli
+        .positional
+        .iter()
+        .map(String::as_str)
+        .collect::<Vec<_>>()[..]
+    {
+        [] | ["serve"] => serve(db, &config),
+        ["export"] => export(&db, io::stdout().lock()),
+        ["export", path] => export(&db, File::create(path).expect("Failed to create file")),
+        ["import", path] => import(&db, path, ImportMode::Commit),
+        ["import", path, "--dry-run"] | ["import", "--dry-run", path] => {
+            import(&db, path, ImportMode::DryRun)
+        }
+        _ => {
+            eprintln!("Usage: {} [options] [serve]", args[0]);
+            eprintln!("       {} [options] export [<file>]", args[0]);
+            eprintln!("       {} [options] import <file> [--dry-run]", args[0]);
+            eprintln!();
+            eprintln!("Options:");
+            eprintln!("  --config <file>        TOML config file (or ASPIRIN_EATS_CONFIG)");
+            eprintln!("  --bind <host:port>     address to listen on");
+            eprintln!("  --db <file>            SQLite database file");
+            eprintln!("  --log-level <level>    error, warn, info or debug");
+            eprintln!("  --log-format <format>  human or json");
+            eprintln!("  --set <key>=<value>    any other setting, e.g. timeouts.read_secs=10");
+            std::process::exit(2);
+        }
+    }
+}
+
+fn serve(db: AspirinEatsDb, config: &Config) {
+    let mut api = Api::new(db, config.pricing())
+        .with_limits(config.limits)
+        .with_cors(config.cors.clone())
+        .with_schedule(config.schedule.clone())
+        .with_ui(config.ui.clone());
+    if config.log.level >= LogLevel::Info {
+        api = api.with_access_log(AccessLog::new("origin", config.log.format));
+    }
+    if config.log.level >= LogLevel::Warn {
+        for coupon in config.pricing.expired(Local::now().date_naive()) {
+            eprintln!(
+                "Warning: coupon {:?} expired on {}, so it can't be redeemed",
+                coupon.code, coupon.expires
+            );
+        }
+    }
+
+    let listener = TcpListener::bind(&config.origin.bind).unwrap_or_else(|err| {
+        eprintln!("Failed to bind to {}: {err}", config.origin.bind);
+        std::process::exit(1);
+    });
+    if config.kitchen.stations > 0 {
+        run_kitchen(config);
+    }
+    if config.dispatch.enabled {
+        run_dispatcher(config);
+    }
+    run_scheduler(config);
+    for stream in listener.incoming() {
+        let result = stream.map_err(Into::into).and_then(|stream| {
+            stream.set_read_timeout(Some(config.timeouts.read()))?;
+            stream.set_write_timeout(Some(config.timeouts.write()))?;
+            let client = stream.peer_addr().ok();
+            api.serve_connection(stream, client)
+        });
+        if let Err(err) = result {
+            eprintln!("Error handling connection: {err}");
+        }
+    }
+}
+
+/// Open another connection to the database, for work done in the background
+fn open_db(config: &Config) -> AspirinEatsDb {
+    AspirinEatsDb::from_path(&config.origin.db_path).unwrap_or_else(|err| {
+        eprintln!("Failed to open {}: {err}", config.origin.db_path.display());
+        std::process::exit(1);
+    })
+}
+
+/// Move orders through the kitchen in the background, on a connection of its own
+fn run_kitchen(config: &Config) {
+    let db = open_db(config);
+    let policy = config.kitchen.clone();
+    let log_changes = config.log.level >= LogLevel::Debug;
+    std::thread::spawn(move || {
+        let tick = policy.tick();
+        let mut kitchen = Kitchen::new(SystemClock::new(), policy);
+        loop {
+            match kitchen.tick(&db) {
+                Ok(changes) if log_changes => {
+                    for change in changes {
+                        eprintln!("Order {} is now {:?}", change.order_id, change.status);
+                    }
+                }
+                Ok(_) => {}
+                Err(err) => eprintln!("Error running the kitchen: {err}"),
+            }
+            std::thread::sleep(tick);
+        }
+    });
+}
+
+/// Hand ready orders to drivers in the background, on a connection of its own
+fn run_dispatcher(config: &Config) {
+    let db = open_db(config);
+    let restaurant = config.delivery.restaurant;
+    let tick = config.dispatch.tick();
+    let log_assignments = config.log.level >= LogLevel::Debug;
+    std::thread::spawn(move || loop {
+        match db.dispatch(&restaurant) {
+            Ok(assignments) if log_assignments => {
+                for assignment in assignments {
+                    eprintln!(
+                        "Order {} is going out with driver {}",
+                        assignment.order_id, assignment.driver_id
+                    );
+                }
+            }
+            Ok(_) => {}
+            Err(err) => eprintln!("Error dispatching orders: {err}"),
+        }
+        std::thread::sleep(tick);
+    });
+}
+
+/// Send scheduled orders to the kitchen shortly before they're wanted, on a connection of its own
+fn run_scheduler(config: &Config) {
+    let db = open_db(config);
+    let policy = config.schedule.clone();
+    let log_releases = config.log.level >= LogLevel::Debug;
+    std::thread::spawn(move || loop {
+        match db.release_scheduled(policy.release_until(Local::now())) {
+            Ok(released) if log_releases => {
+                for id in released {
+                    eprintln!("Order {id} has gone to the kitchen");
+                }
+            }
+            Ok(_) => {}
+            Err(err) => eprintln!("Error releasing scheduled orders: {err}"),
+        }
+        std::thread::sleep(policy.tick());
+    });
+}
+
+fn export<W: io::Write>(db: &AspirinEatsDb, writer: W) {
+    let count = db
+        .export_orders(BufWriter::new(writer))
+        .expect("Failed to export orders");
+    eprintln!("Exported {count} orders");
+}
+
+fn import(db: &AspirinEatsDb, path: &str, mode: ImportMode) {
+    let file = File::open(path).expect("Failed to open file");
+    let report = db
+        .import_orders(BufReader::new(file), mode)
+        .expect("Failed to import orders");
+
+    for failure in &report.failures {
+        eprintln!("line {}: {}", failure.line, failure.message);
+    }
+    match (report.committed, mode) {
+        (true, _) => eprintln!("Imported {} orders", report.imported),
+        (false, ImportMode::DryRun) if report.failures.is_empty() => {
+            eprintln!("Dry run: {} orders would be imported", report.imported)
+        }
+        (false, _) => {
+            eprintln!(
+                "Nothing imported: {} of {} orders failed validation",
+                report.failures.len(),
+                report.imported + report.failures.len()
+            );
+            std::process::exit(1);
+        }
+    }
 }
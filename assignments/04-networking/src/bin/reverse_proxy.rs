@@ -1,12 +1,85 @@
 use std::env;
+use std::net::TcpListener;
+
+use aspirin_eats::config::{CliArgs, Config};
+use aspirin_eats::error::AspirinEatsError;
+use aspirin_eats::logging::{AccessLog, LogLevel};
+use aspirin_eats::proxy::Proxy;
+use aspirin_eats::tls;
+
+/// Shorthands for settings that are commonly changed on the command line
+const FLAGS: &[(&str, &str)] = &[
+    ("--bind", "proxy.bind"),
+    ("--origin", "proxy.origin"),
+    ("--tls-cert", "proxy.tls_cert"),
+    ("--tls-key", "proxy.tls_key"),
+    ("--log-level", "log.level"),
+    ("--log-format", "log.format"),
+];
 
 fn main() {
     let args = env::args().collect::<Vec<String>>();
-    if args.len() < 3 {
-        eprintln!("Usage: {} <proxy-from> <proxy-to>", args[0]);
+    let config = load_config(&args).unwrap_or_else(|err| {
+        eprintln!("{err}");
+        eprintln!();
+        eprintln!("Usage: {} [options] [<proxy-from> <proxy-to>]", args[0]);
+        eprintln!();
+        eprintln!("Options:");
+        eprintln!("  --config <file>        TOML config file (or ASPIRIN_EATS_CONFIG)");
+        eprintln!("  --bind <host:port>     address to listen on (same as <proxy-from>)");
+        eprintln!("  --origin <host:port>   origin server to forward to (same as <proxy-to>)");
+        eprintln!("  --tls-cert <file>      PEM certificate chain, to serve HTTPS");
+        eprintln!("  --tls-key <file>       PEM private key for --tls-cert");
+        eprintln!("  --log-level <level>    error, warn, info or debug");
+        eprintln!("  --log-format <format>  human or json");
+        eprintln!("  --set <key>=<value>    any other setting, e.g. timeouts.read_secs=10");
         std::process::exit(2);
+    });
+    if config.log.level >= LogLevel::Debug {
+        eprintln!("{config:#?}");
+    }
+
+    let mut proxy = Proxy::new(&config.proxy.origin)
+        .with_limits(config.limits)
+        .with_compression(config.compression)
+        .with_upstream(config.upstream);
+    if config.log.level >= LogLevel::Info {
+        proxy = proxy.with_access_log(AccessLog::new("proxy", config.log.format));
     }
+    if let (Some(cert), Some(key)) = (&config.proxy.tls_cert, &config.proxy.tls_key) {
+        let tls_config = tls::load_server_config(cert, key).unwrap_or_else(|err| {
+            eprintln!("Failed to load TLS certificate: {err}");
+            std::process::exit(1);
+        });
+        proxy = proxy.with_tls(tls_config);
+    }
+
+    let listener = TcpListener::bind(&config.proxy.bind).unwrap_or_else(|err| {
+        eprintln!("Failed to bind to {}: {err}", config.proxy.bind);
+        std::process::exit(1);
+    });
+    proxy.serve(&listener, config.proxy.workers, &config.timeouts, |err| {
+        eprintln!("Error proxying connection: {err}")
+    });
+}
 
-    let proxy_addr = &args[1];
-    let origin_addr = &args[2];
+/// Load the config, still accepting the addresses as positional arguments like we used to
+fn load_config(args: &[String]) -> Result<Config, AspirinEatsError> {
+    let mut cli = CliArgs::parse(&args[1..], FLAGS)?;
+    match &cli.positional[..] {
+        [] => {}
+        [from, to] => {
+            cli.overrides
+                .insert(0, ("proxy.origin".to_string(), to.clone()));
+            cli.overrides
+                .insert(0, ("proxy.bind".to_string(), from.clone()));
+        }
+        _ => {
+            return Err(AspirinEatsError::Config(format!(
+                "unexpected arguments {:?}",
+                cli.positional
+            )))
+        }
+    }
+    Config::load(&cli, &env::vars().collect::<Vec<_>>())
 }
@@ -0,0 +1,267 @@
+use std::io::{Read, Write};
+use std::net::TcpStream;
+use std::str::FromStr;
+use std::sync::Arc;
+use std::time::Duration;
+
+use serde::de::DeserializeOwned;
+
+use crate::error::AspirinEatsError;
+use crate::food::{Order, OrderRequest};
+use crate::http::{HttpRequest, HttpResponse};
+use crate::tls;
+
+/// Client for the orders API, spoken to through either the origin or the proxy. Requests go over
+/// plain HTTP unless given a TLS config with [`Client::with_tls`]
+pub struct Client {
+    addr: String,
+    timeout: Option<Duration>,
+    tls: Option<Arc<rustls::ClientConfig>>,
+}
+
+impl Client {
+    /// Client for the server listening on `addr`, e.g. `127.0.0.1:8000`
+    pub fn new(addr: &str) -> Self {
+        Client {
+            addr: addr.to_string(),
+            timeout: None,
+            tls: None,
+        }
+    }
+
+    /// Give up on requests the server takes longer than `timeout` to answer
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = Some(timeout);
+        self
+    }
+
+    /// Speak HTTPS, verifying the server's certificate with `config`
+    pub fn with_tls(mut self, config: Arc<rustls::ClientConfig>) -> Self {
+        self.tls = Some(config);
+        self
+    }
+
+    /// Get every order
+    pub fn list_orders(&self) -> Result<Vec<Order>, AspirinEatsError> {
+        json(self.send("GET", "/orders", None)?)
+    }
+
+    /// Get a single order by its ID
+    pub fn get_order(&self, id: i64) -> Result<Order, AspirinEatsError> {
+        json(self.send("GET", &format!("/orders/{id}"), None)?)
+    }
+
+    /// Place a new order, returning it as the server stored it
+    pub fn place_order(&self, order: &OrderRequest) -> Result<Order, AspirinEatsError> {
+        let body = serde_json::to_string(order)?;
+        json(self.send("POST", "/orders", Some(body))?)
+    }
+
+    /// Cancel an order, removing it
+    pub fn cancel_order(&self, id: i64) -> Result<(), AspirinEatsError> {
+        self.send("DELETE", &format!("/orders/{id}"), None)?;
+        Ok(())
+    }
+
+    /// Remove every order
+    pub fn reset_orders(&self) -> Result<(), AspirinEatsError> {
+        self.send("DELETE", "/orders", None)?;
+        Ok(())
+    }
+
+    /// Send a request on a new connection and read back the response, turning error statuses
+    /// into [`AspirinEatsError::Response`]
+    pub fn send(
+        &self,
+        method: &str,
+        path: &str,
+        body: Option<String>,
+    ) -> Result<HttpResponse, AspirinEatsError> {
+        let mut request = HttpRequest {
+            method: Some(method.to_string()),
+            path: Some(path.to_string()),
+            headers: vec![("Host".to_string(), self.addr.clone())],
+            body,
+        };
+        if request.body.is_some() {
+            request.set_header("Content-Type", "application/json");
+        }
+
+        let stream = TcpStream::connect(&self.addr)?;
+        stream.set_read_timeout(self.timeout)?;
+        stream.set_write_timeout(self.timeout)?;
+        let response = match &self.tls {
+            Some(config) => exchange(&request, tls::connect(config, self.host(), stream)?)?,
+            None => exchange(&request, stream)?,
+        };
+        match response.status_code() {
+            200..=299 => Ok(response),
+            status => Err(AspirinEatsError::Response {
+                status,
+                message: response.body().to_string(),
+            }),
+        }
+    }
+
+    /// Host part of the address, which the server's certificate must be for
+    fn host(&self) -> &str {
+        let host = self
+            .addr
+            .rsplit_once(':')
+            .map_or(&*self.addr, |(host, _)| host);
+        host.trim_start_matches('[').trim_end_matches(']')
+    }
+}
+
+/// Send `request` over `stream` and read back the server's response, which ends when the server
+/// closes the connection
+pub fn exchange<S: Read + Write>(
+    request: &HttpRequest,
+    mut stream: S,
+) -> Result<HttpResponse, AspirinEatsError> {
+    stream.write_all(request.to_string().as_bytes())?;
+    stream.flush()?;
+
+    let mut response = Vec::new();
+    stream.read_to_end(&mut response)?;
+    let response = String::from_utf8(response).map_err(|_| AspirinEatsError::InvalidResponse)?;
+    HttpResponse::from_str(&response)
+}
+
+fn json<T: DeserializeOwned>(response: HttpResponse) -> Result<T, AspirinEatsError> {
+    Ok(serde_json::from_str(response.body())?)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::TcpListener;
+
+    use super::*;
+    use crate::api::Api;
+    use crate::db::AspirinEatsDb;
+    use crate::food::{Bun, Burger, MenuItem, OrderStatus, Patty, Topping};
+    use crate::pricing::Pricing;
+    use crate::proxy::Proxy;
+
+    /// Start an origin on an ephemeral port that serves `connections` connections
+    fn spawn_api(connections: usize) -> (String, std::thread::JoinHandle<()>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap().to_string();
+        let handle = std::thread::spawn(move || {
+            let api = Api::new(AspirinEatsDb::in_memory().unwrap(), Pricing::default());
+            for stream in listener.incoming().take(connections) {
+                api.serve_connection(stream.unwrap(), None).unwrap();
+            }
+        });
+        (addr, handle)
+    }
+
+    fn get_test_order_request() -> OrderRequest {
+        OrderRequest {
+            customer: "Amit".to_string(),
+            food: vec![
+                MenuItem::Burger(Burger::new(
+                    Bun::Plain,
+                    Patty::Beef,
+                    vec![Topping::Lettuce, Topping::Tomato, Topping::Bacon],
+                )),
+                MenuItem::Fries,
+            ],
+            coupon: None,
+            delivery: None,
+            scheduled_for: None,
+        }
+    }
+
+    #[test]
+    fn test_orders() {
+        let (addr, server) = spawn_api(8);
+        let client = Client::new(&addr).with_timeout(Duration::from_secs(5));
+
+        let order = client.place_order(&get_test_order_request()).unwrap();
+        assert_eq!(order.id, Some(1));
+        assert_eq!(order.status, OrderStatus::Pending);
+        assert_eq!(order.total, 15.0);
+
+        assert_eq!(client.get_order(1).unwrap(), order);
+        assert_eq!(client.list_orders().unwrap(), vec![order]);
+
+        client.cancel_order(1).unwrap();
+        assert!(matches!(
+            client.get_order(1),
+            Err(AspirinEatsError::Response { status: 404, .. })
+        ));
+
+        client.place_order(&get_test_order_request()).unwrap();
+        client.reset_orders().unwrap();
+        assert_eq!(client.list_orders().unwrap(), vec![]);
+        server.join().unwrap();
+    }
+
+    #[test]
+    fn test_tls() {
+        let cert = tls::TestCert::generate();
+        let (origin_addr, origin) = spawn_api(1);
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let config = tls::load_server_config(&cert.cert_path, &cert.key_path).unwrap();
+        let proxy = std::thread::spawn(move || {
+            let proxy = Proxy::new(&origin_addr).with_tls(config);
+            let (stream, _) = listener.accept().unwrap();
+            proxy.serve_stream(stream).unwrap();
+        });
+
+        let client = Client::new(&format!("localhost:{port}"))
+            .with_timeout(Duration::from_secs(5))
+            .with_tls(cert.client_config());
+        assert_eq!(client.list_orders().unwrap(), vec![]);
+        proxy.join().unwrap();
+        origin.join().unwrap();
+
+        // The certificate is only for localhost, so it's refused at 127.0.0.1
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap().to_string();
+        let config = tls::load_server_config(&cert.cert_path, &cert.key_path).unwrap();
+        let server = std::thread::spawn(move || {
+            let (stream, _) = listener.accept().unwrap();
+            assert!(tls::accept(&config, stream).is_err());
+        });
+        assert!(matches!(
+            Client::new(&addr)
+                .with_tls(cert.client_config())
+                .list_orders(),
+            Err(AspirinEatsError::Tls(_) | AspirinEatsError::Io(_))
+        ));
+        server.join().unwrap();
+    }
+
+    #[test]
+    fn test_error_response() {
+        let (addr, server) = spawn_api(1);
+        let client = Client::new(&addr);
+
+        let err = client.send("PUT", "/orders", None).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Server responded with 405: Method not allowed"
+        );
+        server.join().unwrap();
+    }
+
+    #[test]
+    fn test_invalid_response() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap().to_string();
+        let server = std::thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            crate::http::read_request(&mut stream).unwrap();
+            stream.write_all(b"not http").unwrap();
+        });
+
+        assert!(matches!(
+            Client::new(&addr).list_orders(),
+            Err(AspirinEatsError::InvalidResponse)
+        ));
+        server.join().unwrap();
+    }
+}
@@ -0,0 +1,359 @@
+use std::io::Write;
+
+use flate2::write::{GzEncoder, ZlibEncoder};
+use serde::Deserialize;
+
+use crate::http::HttpRequest;
+
+/// Content encodings we can compress responses with
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum Encoding {
+    Gzip,
+
+    /// zlib-wrapped DEFLATE, which is what HTTP means by `deflate`
+    Deflate,
+}
+
+impl Encoding {
+    /// Name of the encoding in `Accept-Encoding` and `Content-Encoding`
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Encoding::Gzip => "gzip",
+            Encoding::Deflate => "deflate",
+        }
+    }
+
+    /// Compress `body` with this encoding
+    pub fn compress(&self, body: &[u8]) -> std::io::Result<Vec<u8>> {
+        match self {
+            Encoding::Gzip => {
+                let mut encoder = GzEncoder::new(Vec::new(), flate2::Compression::default());
+                encoder.write_all(body)?;
+                encoder.finish()
+            }
+            Encoding::Deflate => {
+                let mut encoder = ZlibEncoder::new(Vec::new(), flate2::Compression::default());
+                encoder.write_all(body)?;
+                encoder.finish()
+            }
+        }
+    }
+}
+
+/// Pick the encoding the client likes best from an `Accept-Encoding` header, preferring gzip
+/// when it likes both the same. `*` only speaks for encodings the header doesn't name, so one
+/// refused with `q=0` stays refused. None means the body should be sent as is
+pub fn negotiate(accept_encoding: &str) -> Option<Encoding> {
+    let (mut gzip, mut deflate, mut any) = (None, None, None);
+    for item in accept_encoding.split(',') {
+        let mut params = item.split(';').map(str::trim);
+        let name = params.next().unwrap_or_default().to_ascii_lowercase();
+        let quality = params
+            .find_map(|param| param.strip_prefix("q="))
+            .map_or(Some(1.0), |q| q.trim().parse::<f32>().ok());
+        let Some(quality) = quality else {
+            continue;
+        };
+        let named = match name.as_str() {
+            "gzip" | "x-gzip" => &mut gzip,
+            "deflate" => &mut deflate,
+            "*" => &mut any,
+            _ => continue,
+        };
+        *named = Some(named.map_or(quality, |named: f32| named.max(quality)));
+    }
+
+    let mut best: Option<(Encoding, f32)> = None;
+    for (encoding, quality) in [(Encoding::Gzip, gzip), (Encoding::Deflate, deflate)] {
+        let Some(quality) = quality.or(any).filter(|&q| q > 0.0) else {
+            continue;
+        };
+        if best.is_none_or(|(_, best_quality)| quality > best_quality) {
+            best = Some((encoding, quality));
+        }
+    }
+    best.map(|(encoding, _)| encoding)
+}
+
+/// When responses get compressed
+#[derive(Deserialize, Debug, PartialEq, Clone, Copy)]
+#[serde(default, deny_unknown_fields)]
+pub struct CompressionPolicy {
+    pub enabled: bool,
+
+    /// Bodies smaller than this are sent as is, since compressing them saves next to nothing
+    pub min_size_bytes: usize,
+}
+
+impl Default for CompressionPolicy {
+    fn default() -> Self {
+        CompressionPolicy {
+            enabled: true,
+            min_size_bytes: 1024,
+        }
+    }
+}
+
+impl CompressionPolicy {
+    /// Compress a raw HTTP response to `request` if the client accepts it and it's worth it,
+    /// setting `Content-Encoding`, `Content-Length` and `Vary` and tagging it with
+    /// [`encoded_etag`]. Responses that already have a
+    /// `Content-Encoding`, or aren't worth compressing, are returned untouched
+    pub fn apply(&self, request: &HttpRequest, response: Vec<u8>) -> Vec<u8> {
+        if !self.enabled || request.method.as_deref() == Some("HEAD") {
+            return response;
+        }
+        let Some(split) = response.windows(4).position(|window| window == b"\r\n\r\n") else {
+            return response;
+        };
+        let Ok(head) = std::str::from_utf8(&response[..split]) else {
+            return response;
+        };
+        let body = &response[split + 4..];
+
+        let mut lines = head.split("\r\n");
+        let status_line = lines.next().unwrap_or_default();
+        let headers: Vec<(&str, &str)> = lines
+            .filter_map(|line| line.split_once(':'))
+            .map(|(name, value)| (name.trim(), value.trim()))
+            .collect();
+        let header = |name: &str| {
+            headers
+                .iter()
+                .find(|(header, _)| header.eq_ignore_ascii_case(name))
+                .map(|(_, value)| *value)
+        };
+
+        if header("Content-Encoding").is_some()
+            || body.len() < self.min_size_bytes
+            || header("Content-Type").is_some_and(is_compressed_type)
+        {
+            return response;
+        }
+        let encoding = request.header("Accept-Encoding").and_then(negotiate);
+        let compressed = encoding.and_then(|encoding| {
+            let compressed = encoding.compress(body).ok()?;
+            (compressed.len() < body.len()).then_some((encoding, compressed))
+        });
+
+        let mut rewritten = format!("{status_line}\r\n");
+        for (name, value) in &headers {
+            match &compressed {
+                Some(_) if name.eq_ignore_ascii_case("Content-Length") => {}
+                // The compressed body is a different representation, so needs its own tag
+                Some((encoding, _)) if name.eq_ignore_ascii_case("ETag") => {
+                    let etag = encoded_etag(value, *encoding);
+                    rewritten.push_str(&format!("{name}: {etag}\r\n"));
+                }
+                _ => rewritten.push_str(&format!("{name}: {value}\r\n")),
+            }
+        }
+        rewritten.push_str("Vary: Accept-Encoding\r\n");
+        let body = match compressed {
+            Some((encoding, compressed)) => {
+                rewritten.push_str(&format!(
+                    "Content-Encoding: {}\r\nContent-Length: {}\r\n",
+                    encoding.as_str(),
+                    compressed.len()
+                ));
+                compressed
+            }
+            None => body.to_vec(),
+        };
+        rewritten.push_str("\r\n");
+
+        let mut rewritten = rewritten.into_bytes();
+        rewritten.extend_from_slice(&body);
+        rewritten
+    }
+}
+
+/// Entity tag for a body compressed with `encoding`, made by adding the encoding's name to the
+/// tag of the uncompressed body, e.g. `"abc"` becomes `"abc-gzip"`
+pub fn encoded_etag(etag: &str, encoding: Encoding) -> String {
+    match etag.strip_suffix('"') {
+        Some(opaque) => format!("{opaque}-{}\"", encoding.as_str()),
+        None => etag.to_string(),
+    }
+}
+
+/// Turn the tags of compressed bodies in an `If-None-Match` or `If-Match` header back into the
+/// tags the origin gave the uncompressed bodies, so that it can compare them
+pub fn strip_encoded_etags(header: &str) -> String {
+    header
+        .split(',')
+        .map(|tag| {
+            let tag = tag.trim();
+            [Encoding::Gzip, Encoding::Deflate]
+                .iter()
+                .find_map(|encoding| {
+                    let opaque = tag.strip_suffix(&format!("-{}\"", encoding.as_str()))?;
+                    Some(format!("{opaque}\""))
+                })
+                .unwrap_or_else(|| tag.to_string())
+        })
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+/// Whether a content type is already compressed, so compressing it again would be wasted work
+fn is_compressed_type(content_type: &str) -> bool {
+    let content_type = content_type.to_ascii_lowercase();
+    [
+        "image/",
+        "video/",
+        "audio/",
+        "application/gzip",
+        "application/zip",
+    ]
+    .iter()
+    .any(|prefix| content_type.starts_with(prefix))
+        && !content_type.starts_with("image/svg")
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Read;
+
+    use flate2::read::{GzDecoder, ZlibDecoder};
+
+    use super::*;
+
+    fn get_test_request(accept_encoding: &str) -> HttpRequest {
+        format!("GET /orders HTTP/1.1\r\nAccept-Encoding: {accept_encoding}\r\n\r\n")
+            .parse()
+            .unwrap()
+    }
+
+    fn get_test_response(body: &str) -> Vec<u8> {
+        format!("HTTP/1.1 200 OK\r\nX-Request-Id: abc\r\n\r\n{body}").into_bytes()
+    }
+
+    /// Split a raw response into its head and body
+    fn split(response: &[u8]) -> (String, Vec<u8>) {
+        let split = response.windows(4).position(|w| w == b"\r\n\r\n").unwrap();
+        (
+            String::from_utf8(response[..split].to_vec()).unwrap(),
+            response[split + 4..].to_vec(),
+        )
+    }
+
+    #[test]
+    fn test_negotiate() {
+        assert_eq!(negotiate("gzip, deflate, br"), Some(Encoding::Gzip));
+        assert_eq!(negotiate("deflate, gzip"), Some(Encoding::Gzip));
+        assert_eq!(
+            negotiate("deflate;q=1.0, gzip;q=0.5"),
+            Some(Encoding::Deflate)
+        );
+        assert_eq!(negotiate("gzip;q=0, deflate"), Some(Encoding::Deflate));
+        assert_eq!(negotiate("*"), Some(Encoding::Gzip));
+        assert_eq!(negotiate("gzip;q=0, *"), Some(Encoding::Deflate));
+        assert_eq!(negotiate("*, gzip;q=0, deflate;q=0"), None);
+        assert_eq!(negotiate("*;q=0.5, deflate;q=0.8"), Some(Encoding::Deflate));
+        assert_eq!(negotiate("br, identity"), None);
+        assert_eq!(negotiate(""), None);
+    }
+
+    #[test]
+    fn test_compress_gzip() {
+        let body = "[{\"customer\":\"Amit\"}]".repeat(100);
+        let response =
+            CompressionPolicy::default().apply(&get_test_request("gzip"), get_test_response(&body));
+
+        let (head, compressed) = split(&response);
+        assert!(head.contains("\r\nX-Request-Id: abc\r\n"));
+        assert!(head.contains("\r\nVary: Accept-Encoding\r\n"));
+        assert!(head.contains("\r\nContent-Encoding: gzip\r\n"));
+        assert!(head.contains(&format!("\r\nContent-Length: {}", compressed.len())));
+
+        let mut decompressed = String::new();
+        GzDecoder::new(&compressed[..])
+            .read_to_string(&mut decompressed)
+            .unwrap();
+        assert_eq!(decompressed, body);
+    }
+
+    #[test]
+    fn test_compressed_etag() {
+        let body = "a".repeat(2000);
+        let response = format!("HTTP/1.1 200 OK\r\nETag: \"abc\"\r\n\r\n{body}").into_bytes();
+        let response = CompressionPolicy::default().apply(&get_test_request("gzip"), response);
+        let (head, _) = split(&response);
+        assert!(head.contains("\r\nETag: \"abc-gzip\"\r\n"));
+
+        assert_eq!(
+            encoded_etag("W/\"abc\"", Encoding::Deflate),
+            "W/\"abc-deflate\""
+        );
+        assert_eq!(
+            strip_encoded_etags("\"abc-gzip\",W/\"def-deflate\", \"ghi\""),
+            "\"abc\", W/\"def\", \"ghi\""
+        );
+        assert_eq!(strip_encoded_etags("*"), "*");
+    }
+
+    #[test]
+    fn test_compress_deflate() {
+        let body = "a".repeat(2000);
+        let response = CompressionPolicy::default()
+            .apply(&get_test_request("deflate"), get_test_response(&body));
+
+        let (head, compressed) = split(&response);
+        assert!(head.contains("\r\nContent-Encoding: deflate\r\n"));
+        let mut decompressed = String::new();
+        ZlibDecoder::new(&compressed[..])
+            .read_to_string(&mut decompressed)
+            .unwrap();
+        assert_eq!(decompressed, body);
+    }
+
+    #[test]
+    fn test_left_untouched() {
+        let policy = CompressionPolicy::default();
+        let body = "a".repeat(2000);
+
+        // Small bodies aren't worth it
+        let small = get_test_response("[]");
+        assert_eq!(
+            policy.apply(&get_test_request("gzip"), small.clone()),
+            small
+        );
+
+        // Already compressed by the origin
+        let encoded = format!("HTTP/1.1 200 OK\r\nContent-Encoding: br\r\n\r\n{body}").into_bytes();
+        assert_eq!(
+            policy.apply(&get_test_request("gzip"), encoded.clone()),
+            encoded
+        );
+        let image =
+            format!("HTTP/1.1 200 OK\r\nContent-Type: image/png\r\n\r\n{body}").into_bytes();
+        assert_eq!(
+            policy.apply(&get_test_request("gzip"), image.clone()),
+            image
+        );
+
+        // Turned off
+        let disabled = CompressionPolicy {
+            enabled: false,
+            ..policy
+        };
+        let response = get_test_response(&body);
+        assert_eq!(
+            disabled.apply(&get_test_request("gzip"), response.clone()),
+            response
+        );
+    }
+
+    #[test]
+    fn test_not_accepted_still_varies() {
+        let body = "a".repeat(2000);
+        let response =
+            CompressionPolicy::default().apply(&get_test_request("br"), get_test_response(&body));
+
+        let (head, uncompressed) = split(&response);
+        assert!(head.contains("\r\nVary: Accept-Encoding"));
+        assert!(!head.contains("Content-Encoding"));
+        assert_eq!(uncompressed, body.as_bytes());
+    }
+}
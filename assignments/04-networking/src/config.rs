@@ -0,0 +1,771 @@
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::time::Duration;
+
+use serde::Deserialize;
+
+use crate::compression::CompressionPolicy;
+use crate::delivery::DeliveryPolicy;
+use crate::drivers::DispatchPolicy;
+use crate::error::AspirinEatsError;
+use crate::http::{CorsPolicy, RequestLimits};
+use crate::kitchen::KitchenPolicy;
+use crate::logging::{LogFormat, LogLevel};
+use crate::pricing::{Pricing, PricingPolicy};
+use crate::schedule::SchedulePolicy;
+use crate::ui::UiPolicy;
+use crate::upstream::UpstreamPolicy;
+
+/// Prefix of the environment variables that override settings, e.g. `ASPIRIN_EATS_ORIGIN_BIND`
+/// for `origin.bind`
+pub const ENV_PREFIX: &str = "ASPIRIN_EATS_";
+
+/// Environment variable naming the config file, if `--config` isn't given
+pub const CONFIG_ENV_VAR: &str = "ASPIRIN_EATS_CONFIG";
+
+/// Settings for both binaries. Each setting comes from, in order of precedence: a command line
+/// flag, an environment variable, the TOML config file, and finally the default
+#[derive(Deserialize, Debug, PartialEq, Clone, Default)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    pub origin: OriginConfig,
+    pub proxy: ProxyConfig,
+    pub client: ClientConfig,
+    pub timeouts: TimeoutConfig,
+    pub limits: RequestLimits,
+    pub cors: CorsPolicy,
+    pub compression: CompressionPolicy,
+    pub upstream: UpstreamPolicy,
+    pub kitchen: KitchenPolicy,
+    pub pricing: PricingPolicy,
+    pub delivery: DeliveryPolicy,
+    pub dispatch: DispatchPolicy,
+    pub schedule: SchedulePolicy,
+    pub ui: UiPolicy,
+    pub log: LogConfig,
+}
+
+/// The `[origin]` section
+#[derive(Deserialize, Debug, PartialEq, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub struct OriginConfig {
+    /// Address the origin server listens on
+    pub bind: String,
+
+    /// SQLite database file. Created if it doesn't exist
+    pub db_path: PathBuf,
+}
+
+impl Default for OriginConfig {
+    fn default() -> Self {
+        OriginConfig {
+            bind: "127.0.0.1:8080".to_string(),
+            db_path: PathBuf::from("aspirin_eats.db"),
+        }
+    }
+}
+
+/// The `[proxy]` section
+#[derive(Deserialize, Debug, PartialEq, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub struct ProxyConfig {
+    /// Address the reverse proxy listens on
+    pub bind: String,
+
+    /// Address of the origin server to forward requests to
+    pub origin: String,
+
+    /// PEM certificate chain. TLS is turned on when this and `tls_key` are set
+    pub tls_cert: Option<PathBuf>,
+
+    /// PEM private key for `tls_cert`
+    pub tls_key: Option<PathBuf>,
+
+    /// How many connections are served at once. Each one waits on the origin, retries included,
+    /// without holding up the others
+    pub workers: usize,
+}
+
+impl Default for ProxyConfig {
+    fn default() -> Self {
+        ProxyConfig {
+            bind: "127.0.0.1:8000".to_string(),
+            origin: "127.0.0.1:8080".to_string(),
+            tls_cert: None,
+            tls_key: None,
+            workers: 8,
+        }
+    }
+}
+
+/// The `[client]` section, for the `eats` command line client
+#[derive(Deserialize, Debug, PartialEq, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub struct ClientConfig {
+    /// Address of the server to talk to, by default the proxy
+    pub addr: String,
+
+    /// PEM certificate to trust. The client speaks HTTPS when this is set, and plain HTTP
+    /// otherwise
+    pub tls_ca: Option<PathBuf>,
+}
+
+impl Default for ClientConfig {
+    fn default() -> Self {
+        ClientConfig {
+            addr: "127.0.0.1:8000".to_string(),
+            tls_ca: None,
+        }
+    }
+}
+
+/// The `[timeouts]` section, applied to every client connection
+#[derive(Deserialize, Debug, PartialEq, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub struct TimeoutConfig {
+    /// How long to wait for a client to send its request
+    pub read_secs: u64,
+
+    /// How long to wait for a client to accept our response
+    pub write_secs: u64,
+}
+
+impl Default for TimeoutConfig {
+    fn default() -> Self {
+        TimeoutConfig {
+            read_secs: 30,
+            write_secs: 30,
+        }
+    }
+}
+
+impl TimeoutConfig {
+    pub fn read(&self) -> Duration {
+        Duration::from_secs(self.read_secs)
+    }
+
+    pub fn write(&self) -> Duration {
+        Duration::from_secs(self.write_secs)
+    }
+}
+
+/// The `[log]` section
+#[derive(Deserialize, Debug, PartialEq, Clone, Default)]
+#[serde(default, deny_unknown_fields)]
+pub struct LogConfig {
+    pub level: LogLevel,
+    pub format: LogFormat,
+}
+
+impl Config {
+    /// Parse a TOML config. Settings that are left out keep their defaults
+    pub fn from_toml(toml: &str) -> Result<Self, AspirinEatsError> {
+        toml::from_str(toml).map_err(|err| AspirinEatsError::Config(err.to_string()))
+    }
+
+    /// Read a TOML config file
+    pub fn from_file(path: &Path) -> Result<Self, AspirinEatsError> {
+        let toml = std::fs::read_to_string(path)
+            .map_err(|err| AspirinEatsError::Config(format!("{}: {err}", path.display())))?;
+        Self::from_toml(&toml).map_err(|err| match err {
+            AspirinEatsError::Config(message) => {
+                AspirinEatsError::Config(format!("{}: {message}", path.display()))
+            }
+            err => err,
+        })
+    }
+
+    /// Load the config for a binary: the file from `--config` or `ASPIRIN_EATS_CONFIG` (if any),
+    /// then `ASPIRIN_EATS_*` variables from `env`, then the command line overrides. The result is
+    /// validated before it's returned
+    pub fn load(args: &CliArgs, env: &[(String, String)]) -> Result<Self, AspirinEatsError> {
+        let path = args.config.clone().or_else(|| {
+            env.iter()
+                .find(|(name, _)| name == CONFIG_ENV_VAR)
+                .map(|(_, value)| PathBuf::from(value))
+        });
+        let mut config = match path {
+            Some(path) => Self::from_file(&path)?,
+            None => Self::default(),
+        };
+        config.apply_env(env)?;
+        for (key, value) in &args.overrides {
+            config.set(key, value)?;
+        }
+        config.validate()?;
+        Ok(config)
+    }
+
+    /// Override settings from `ASPIRIN_EATS_<SECTION>_<SETTING>` environment variables. Other
+    /// variables are ignored
+    pub fn apply_env(&mut self, env: &[(String, String)]) -> Result<(), AspirinEatsError> {
+        for (name, value) in env {
+            let Some(setting) = name.strip_prefix(ENV_PREFIX) else {
+                continue;
+            };
+            if name == CONFIG_ENV_VAR {
+                continue;
+            }
+            let key = setting.to_ascii_lowercase().replacen('_', ".", 1);
+            self.try_set(&key, value)
+                .map_err(|message| AspirinEatsError::Config(format!("{name}: {message}")))?;
+        }
+        Ok(())
+    }
+
+    /// Set a single setting by its key, e.g. `origin.bind`
+    pub fn set(&mut self, key: &str, value: &str) -> Result<(), AspirinEatsError> {
+        self.try_set(key, value)
+            .map_err(|message| AspirinEatsError::Config(format!("{key}: {message}")))
+    }
+
+    fn try_set(&mut self, key: &str, value: &str) -> Result<(), String> {
+        match key {
+            "origin.bind" => self.origin.bind = value.to_string(),
+            "origin.db_path" => self.origin.db_path = PathBuf::from(value),
+            "proxy.bind" => self.proxy.bind = value.to_string(),
+            "proxy.origin" => self.proxy.origin = value.to_string(),
+            "proxy.tls_cert" => self.proxy.tls_cert = Some(PathBuf::from(value)),
+            "proxy.tls_key" => self.proxy.tls_key = Some(PathBuf::from(value)),
+            "proxy.workers" => self.proxy.workers = parse(value)?,
+            "client.addr" => self.client.addr = value.to_string(),
+            "client.tls_ca" => self.client.tls_ca = Some(PathBuf::from(value)),
+            "timeouts.read_secs" => self.timeouts.read_secs = parse(value)?,
+            "timeouts.write_secs" => self.timeouts.write_secs = parse(value)?,
+            "limits.max_header_bytes" => self.limits.max_header_bytes = parse(value)?,
+            "limits.max_body_bytes" => self.limits.max_body_bytes = parse(value)?,
+            "cors.allowed_origins" => self.cors.allowed_origins = parse_list(value),
+            "cors.allowed_methods" => self.cors.allowed_methods = parse_list(value),
+            "cors.allowed_headers" => self.cors.allowed_headers = parse_list(value),
+            "cors.max_age_secs" => self.cors.max_age_secs = parse(value)?,
+            "compression.enabled" => self.compression.enabled = parse(value)?,
+            "compression.min_size_bytes" => self.compression.min_size_bytes = parse(value)?,
+            "upstream.connect_timeout_ms" => self.upstream.connect_timeout_ms = parse(value)?,
+            "upstream.read_timeout_ms" => self.upstream.read_timeout_ms = parse(value)?,
+            "upstream.max_retries" => self.upstream.max_retries = parse(value)?,
+            "upstream.retry_backoff_ms" => self.upstream.retry_backoff_ms = parse(value)?,
+            "upstream.failure_threshold" => self.upstream.failure_threshold = parse(value)?,
+            "upstream.open_ms" => self.upstream.open_ms = parse(value)?,
+            "kitchen.stations" => self.kitchen.stations = parse(value)?,
+            "kitchen.tick_ms" => self.kitchen.tick_ms = parse(value)?,
+            "kitchen.burger_secs" => self.kitchen.burger_secs = parse(value)?,
+            "kitchen.topping_secs" => self.kitchen.topping_secs = parse(value)?,
+            "kitchen.fries_secs" => self.kitchen.fries_secs = parse(value)?,
+            "kitchen.drink_secs" => self.kitchen.drink_secs = parse(value)?,
+            "pricing.tax_rate" => self.pricing.tax_rate = parse(value)?,
+            "pricing.coupons" => {
+                self.pricing.coupons = parse_list(value)
+                    .iter()
+                    .map(|coupon| coupon.parse())
+                    .collect::<Result<_, _>>()?
+            }
+            "delivery.base_fee" => self.delivery.base_fee = parse(value)?,
+            "delivery.fee_per_km" => self.delivery.fee_per_km = parse(value)?,
+            "dispatch.enabled" => self.dispatch.enabled = parse(value)?,
+            "dispatch.tick_ms" => self.dispatch.tick_ms = parse(value)?,
+            "schedule.opens" => self.schedule.opens = parse(value)?,
+            "schedule.closes" => self.schedule.closes = parse(value)?,
+            "schedule.release_mins" => self.schedule.release_mins = parse(value)?,
+            "schedule.tick_ms" => self.schedule.tick_ms = parse(value)?,
+            "ui.enabled" => self.ui.enabled = parse(value)?,
+            "ui.dir" => self.ui.dir = PathBuf::from(value),
+            "ui.max_age_secs" => self.ui.max_age_secs = parse(value)?,
+            "log.level" => self.log.level = parse(value)?,
+            "log.format" => self.log.format = parse(value)?,
+            _ => return Err("unknown setting".to_string()),
+        }
+        Ok(())
+    }
+
+    /// Pricing for the origin: the standard menu and deals, with the configured tax, coupons and
+    /// delivery charges
+    pub fn pricing(&self) -> Pricing {
+        Pricing {
+            tax_rate: self.pricing.tax_rate,
+            coupons: self.pricing.coupons.clone(),
+            delivery: self.delivery.clone(),
+            ..Pricing::default()
+        }
+    }
+
+    /// Check that every setting makes sense, reporting all the problems at once
+    pub fn validate(&self) -> Result<(), AspirinEatsError> {
+        let mut problems = Vec::new();
+
+        for (key, addr) in [
+            ("origin.bind", &self.origin.bind),
+            ("proxy.bind", &self.proxy.bind),
+            ("proxy.origin", &self.proxy.origin),
+            ("client.addr", &self.client.addr),
+        ] {
+            if !is_host_port(addr) {
+                problems.push(format!("{key}: expected host:port, got {addr:?}"));
+            }
+        }
+        if self.proxy.bind == self.proxy.origin {
+            problems.push(
+                "proxy.bind and proxy.origin are the same, so the proxy would forward to itself"
+                    .to_string(),
+            );
+        }
+        if self.origin.db_path.as_os_str().is_empty() {
+            problems.push("origin.db_path: must not be empty".to_string());
+        }
+        if self.ui.enabled && self.ui.dir.as_os_str().is_empty() {
+            problems.push("ui.dir: must not be empty".to_string());
+        }
+        if self.proxy.tls_cert.is_some() != self.proxy.tls_key.is_some() {
+            problems.push("proxy.tls_cert and proxy.tls_key must be set together".to_string());
+        }
+        for (key, value) in [
+            ("proxy.workers", self.proxy.workers),
+            ("timeouts.read_secs", self.timeouts.read_secs as usize),
+            ("timeouts.write_secs", self.timeouts.write_secs as usize),
+            ("limits.max_header_bytes", self.limits.max_header_bytes),
+            ("limits.max_body_bytes", self.limits.max_body_bytes),
+            (
+                "upstream.connect_timeout_ms",
+                self.upstream.connect_timeout_ms as usize,
+            ),
+            (
+                "upstream.read_timeout_ms",
+                self.upstream.read_timeout_ms as usize,
+            ),
+            (
+                "upstream.failure_threshold",
+                self.upstream.failure_threshold as usize,
+            ),
+            ("kitchen.tick_ms", self.kitchen.tick_ms as usize),
+            ("dispatch.tick_ms", self.dispatch.tick_ms as usize),
+            ("schedule.tick_ms", self.schedule.tick_ms as usize),
+        ] {
+            if value == 0 {
+                problems.push(format!("{key}: must be greater than 0"));
+            }
+        }
+
+        for origin in &self.cors.allowed_origins {
+            if origin != "*" && !origin.starts_with("http://") && !origin.starts_with("https://") {
+                problems.push(format!(
+                    "cors.allowed_origins: expected \"*\" or a URL like https://example.com, got {origin:?}"
+                ));
+            }
+        }
+        for method in &self.cors.allowed_methods {
+            if method.is_empty() || !method.chars().all(|c| c.is_ascii_uppercase()) {
+                problems.push(format!(
+                    "cors.allowed_methods: expected a method like GET, got {method:?}"
+                ));
+            }
+        }
+        problems.extend(self.pricing.problems());
+        problems.extend(self.delivery.problems());
+
+        match problems.is_empty() {
+            true => Ok(()),
+            false => Err(AspirinEatsError::Config(problems.join("; "))),
+        }
+    }
+}
+
+fn parse<T: FromStr>(value: &str) -> Result<T, String> {
+    value
+        .trim()
+        .parse()
+        .map_err(|_| format!("invalid value {value:?}"))
+}
+
+/// Parse a comma-separated list, as lists are given in environment variables and flags
+fn parse_list(value: &str) -> Vec<String> {
+    value
+        .split(',')
+        .map(str::trim)
+        .filter(|item| !item.is_empty())
+        .map(str::to_string)
+        .collect()
+}
+
+fn is_host_port(addr: &str) -> bool {
+    match addr.rsplit_once(':') {
+        Some((host, port)) => !host.is_empty() && port.parse::<u16>().is_ok(),
+        None => false,
+    }
+}
+
+/// Command line arguments understood by both binaries
+#[derive(Debug, PartialEq, Clone, Default)]
+pub struct CliArgs {
+    /// Config file given with `--config`
+    pub config: Option<PathBuf>,
+
+    /// Settings given on the command line, as (key, value)
+    pub overrides: Vec<(String, String)>,
+
+    /// Every argument that isn't one of ours, e.g. subcommands
+    pub positional: Vec<String>,
+}
+
+impl CliArgs {
+    /// Parse `args` (without the program name). `--config <file>` picks the config file,
+    /// `--set <key>=<value>` overrides any setting, and each `(flag, key)` in `flags` is a
+    /// shorthand for `--set <key>=<value>`. Flags can also be written as `--flag=value`
+    pub fn parse(args: &[String], flags: &[(&str, &str)]) -> Result<Self, AspirinEatsError> {
+        let mut cli = CliArgs::default();
+        let mut args = args.iter();
+        while let Some(arg) = args.next() {
+            let (flag, inline_value) = match arg.split_once('=') {
+                Some((flag, value)) if flag.starts_with("--") => (flag, Some(value.to_string())),
+                _ => (arg.as_str(), None),
+            };
+            let key = flags
+                .iter()
+                .find(|(name, _)| *name == flag)
+                .map(|(_, key)| *key);
+            if flag != "--config" && flag != "--set" && key.is_none() {
+                cli.positional.push(arg.clone());
+                continue;
+            }
+
+            let value = inline_value
+                .or_else(|| args.next().cloned())
+                .ok_or_else(|| AspirinEatsError::Config(format!("{flag} needs a value")))?;
+            match (flag, key) {
+                ("--config", _) => cli.config = Some(PathBuf::from(value)),
+                ("--set", _) => {
+                    let (key, value) = value.split_once('=').ok_or_else(|| {
+                        AspirinEatsError::Config(format!(
+                            "--set expects <key>=<value>, got {value:?}"
+                        ))
+                    })?;
+                    cli.overrides.push((key.to_string(), value.to_string()));
+                }
+                (_, Some(key)) => cli.overrides.push((key.to_string(), value)),
+                (_, None) => unreachable!("only known flags take values"),
+            }
+        }
+        Ok(cli)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::NaiveDate;
+
+    use super::*;
+    use crate::delivery::DeliveryZone;
+    use crate::food::MenuItem;
+    use crate::pricing::{Coupon, CouponKind};
+
+    fn args(args: &[&str]) -> Vec<String> {
+        args.iter().map(|arg| arg.to_string()).collect()
+    }
+
+    fn env(vars: &[(&str, &str)]) -> Vec<(String, String)> {
+        vars.iter()
+            .map(|(name, value)| (name.to_string(), value.to_string()))
+            .collect()
+    }
+
+    #[test]
+    fn test_defaults_are_valid() {
+        let config = Config::default();
+        config.validate().unwrap();
+        assert_eq!(config.origin.bind, "127.0.0.1:8080");
+        assert_eq!(config.proxy.origin, config.origin.bind);
+        assert_eq!(config.client.addr, config.proxy.bind);
+        assert_eq!(config.log.level, LogLevel::Info);
+    }
+
+    #[test]
+    fn test_from_toml() {
+        let config = Config::from_toml(
+            r#"
+            [origin]
+            db_path = "/var/lib/aspirin_eats.db"
+
+            [limits]
+            max_body_bytes = 1024
+
+            [log]
+            level = "warn"
+            format = "json"
+            "#,
+        )
+        .unwrap();
+
+        assert_eq!(config.origin.db_path, Path::new("/var/lib/aspirin_eats.db"));
+        assert_eq!(config.origin.bind, "127.0.0.1:8080");
+        assert_eq!(config.limits.max_body_bytes, 1024);
+        assert_eq!(config.limits.max_header_bytes, 8 * 1024);
+        assert_eq!(config.log.level, LogLevel::Warn);
+        assert_eq!(config.log.format, LogFormat::Json);
+    }
+
+    #[test]
+    fn test_example_config_matches_defaults() {
+        let config = Config::from_toml(include_str!("../aspirin_eats.example.toml")).unwrap();
+        assert_eq!(config, Config::default());
+    }
+
+    #[test]
+    fn test_from_toml_rejects_unknown_settings() {
+        let err = Config::from_toml("[origin]\nport = 80\n").unwrap_err();
+        assert!(err.to_string().contains("unknown field `port`"));
+        let err = Config::from_toml("[timeouts]\nread_secs = \"soon\"\n").unwrap_err();
+        assert!(err.to_string().contains("read_secs"));
+    }
+
+    #[test]
+    fn test_load_precedence() {
+        let path = std::env::temp_dir().join(format!("aspirin-eats-{}.toml", uuid::Uuid::new_v4()));
+        std::fs::write(
+            &path,
+            "[origin]\nbind = \"0.0.0.0:1\"\ndb_path = \"file.db\"\n[log]\nlevel = \"debug\"\n",
+        )
+        .unwrap();
+
+        let cli = CliArgs::parse(
+            &args(&["--config", path.to_str().unwrap(), "--bind", "0.0.0.0:3"]),
+            &[("--bind", "origin.bind")],
+        )
+        .unwrap();
+        let config = Config::load(
+            &cli,
+            &env(&[
+                ("ASPIRIN_EATS_ORIGIN_BIND", "0.0.0.0:2"),
+                ("ASPIRIN_EATS_ORIGIN_DB_PATH", "env.db"),
+                ("ASPIRIN_EATS_UPSTREAM_MAX_RETRIES", "0"),
+                ("ASPIRIN_EATS_KITCHEN_STATIONS", "4"),
+                ("ASPIRIN_EATS_SCHEDULE_CLOSES", "01:30"),
+                ("HOME", "/root"),
+            ]),
+        )
+        .unwrap();
+        std::fs::remove_file(path).unwrap();
+
+        assert_eq!(config.origin.bind, "0.0.0.0:3");
+        assert_eq!(config.origin.db_path, Path::new("env.db"));
+        assert_eq!(config.log.level, LogLevel::Debug);
+        assert_eq!(config.timeouts.read_secs, 30);
+        assert_eq!(config.upstream.max_retries, 0);
+        assert_eq!(config.kitchen.stations, 4);
+        assert_eq!(config.schedule.closes.to_string(), "01:30:00");
+    }
+
+    #[test]
+    fn test_load_errors_name_their_source() {
+        let err = Config::load(
+            &CliArgs::default(),
+            &env(&[("ASPIRIN_EATS_TIMEOUTS_READ_SECS", "ten")]),
+        )
+        .unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Invalid configuration: ASPIRIN_EATS_TIMEOUTS_READ_SECS: invalid value \"ten\""
+        );
+
+        let err =
+            Config::load(&CliArgs::default(), &env(&[("ASPIRIN_EATS_COLOR", "red")])).unwrap_err();
+        assert!(err
+            .to_string()
+            .contains("ASPIRIN_EATS_COLOR: unknown setting"));
+
+        let cli = CliArgs {
+            config: Some(PathBuf::from("/nonexistent/aspirin_eats.toml")),
+            ..CliArgs::default()
+        };
+        let err = Config::load(&cli, &[]).unwrap_err();
+        assert!(err.to_string().contains("/nonexistent/aspirin_eats.toml"));
+    }
+
+    #[test]
+    fn test_validate() {
+        let mut config = Config::default();
+        config.set("origin.bind", "localhost").unwrap();
+        config.set("proxy.tls_cert", "cert.pem").unwrap();
+        config.set("client.addr", "proxy").unwrap();
+        config.set("limits.max_body_bytes", "0").unwrap();
+        config.set("dispatch.tick_ms", "0").unwrap();
+        config.set("proxy.workers", "0").unwrap();
+        config.set("ui.dir", "").unwrap();
+
+        let message = config.validate().unwrap_err().to_string();
+        assert!(message.contains("origin.bind: expected host:port, got \"localhost\""));
+        assert!(message.contains("proxy.tls_cert and proxy.tls_key must be set together"));
+        assert!(message.contains("client.addr: expected host:port, got \"proxy\""));
+        assert!(message.contains("limits.max_body_bytes: must be greater than 0"));
+        assert!(message.contains("dispatch.tick_ms: must be greater than 0"));
+        assert!(message.contains("proxy.workers: must be greater than 0"));
+        assert!(message.contains("ui.dir: must not be empty"));
+
+        let mut config = Config::default();
+        config
+            .set(
+                "cors.allowed_origins",
+                "https://eats.example.com, eats.example.com",
+            )
+            .unwrap();
+        config.set("cors.allowed_methods", "GET,post").unwrap();
+        assert_eq!(
+            config.cors.allowed_origins,
+            vec!["https://eats.example.com", "eats.example.com"]
+        );
+        let message = config.validate().unwrap_err().to_string();
+        assert!(message.contains("got \"eats.example.com\""));
+        assert!(message.contains("cors.allowed_methods: expected a method like GET, got \"post\""));
+
+        assert!(config.set("log.level", "chatty").is_err());
+        assert!(config.set("origin.port", "80").is_err());
+    }
+
+    #[test]
+    fn test_delivery_zones() {
+        let config = Config::from_toml(
+            r#"
+            [delivery]
+            fee_per_km = 1.0
+
+            [[delivery.zones]]
+            shape = "radius"
+            name = "Campus"
+            km = 1.5
+
+            [[delivery.zones]]
+            shape = "polygon"
+            name = "Downtown"
+            points = [
+                { lat = 42.36, lon = -71.06 },
+                { lat = 42.36, lon = -71.05 },
+                { lat = 42.35, lon = -71.05 },
+            ]
+            "#,
+        )
+        .unwrap();
+        assert_eq!(config.delivery.base_fee, 2.0);
+        assert_eq!(config.delivery.fee_per_km, 1.0);
+        assert_eq!(
+            config
+                .delivery
+                .zones
+                .iter()
+                .map(DeliveryZone::name)
+                .collect::<Vec<_>>(),
+            vec!["Campus", "Downtown"]
+        );
+
+        let err = Config::from_toml("[[delivery.zones]]\nshape = \"circle\"\nname = \"x\"");
+        assert!(err.is_err());
+
+        let mut config = Config::default();
+        config.set("delivery.base_fee", "-1").unwrap();
+        let message = config.validate().unwrap_err().to_string();
+        assert!(message.contains("delivery.base_fee: must not be negative"));
+    }
+
+    #[test]
+    fn test_pricing() {
+        let config = Config::from_toml(
+            r#"
+            [pricing]
+            tax_rate = 0.0625
+            coupons = [
+                { code = "SAVE10", kind = { Percent = 10.0 }, expires = "2999-12-31" },
+            ]
+
+            [delivery]
+            base_fee = 3.0
+            "#,
+        )
+        .unwrap();
+        config.validate().unwrap();
+        let pricing = config.pricing();
+        assert_eq!(pricing.tax_rate, 0.0625);
+        assert_eq!(pricing.coupons[0].kind, CouponKind::Percent(10.0));
+        assert_eq!(pricing.delivery.base_fee, 3.0);
+        assert_eq!(pricing.combos, Pricing::default().combos);
+
+        let mut config = Config::default();
+        config
+            .apply_env(&env(&[(
+                "ASPIRIN_EATS_PRICING_COUPONS",
+                "SAVE10:10%:2999-12-31, FIVE:5:2999-12-31",
+            )]))
+            .unwrap();
+        assert_eq!(
+            config.pricing.coupons[1],
+            Coupon {
+                code: "FIVE".to_string(),
+                kind: CouponKind::Fixed(5.0),
+                expires: NaiveDate::from_ymd_opt(2999, 12, 31).unwrap(),
+            }
+        );
+        let err = config.set("pricing.coupons", "FIVE:5").unwrap_err();
+        assert!(err.to_string().contains("expected CODE:AMOUNT:YYYY-MM-DD"));
+
+        config.set("pricing.tax_rate", "1.5").unwrap();
+        config
+            .set(
+                "pricing.coupons",
+                "OLD:5:2000-01-01,HALF:150%:2999-12-31,half:1:2999-12-31",
+            )
+            .unwrap();
+        let message = config.validate().unwrap_err().to_string();
+        assert!(message.contains("pricing.tax_rate: must be between 0 and 1"));
+        assert!(message.contains("\"HALF\" must take off between 0 and 100%"));
+        assert!(message.contains("\"half\" is listed twice"));
+        assert!(!message.contains("OLD"));
+    }
+
+    #[test]
+    fn test_expired_coupons_are_valid() {
+        let config = Config::from_toml(
+            r#"
+            [[pricing.coupons]]
+            code = "OLD"
+            kind = { Fixed = 5.0 }
+            expires = "2000-01-01"
+            "#,
+        )
+        .unwrap();
+        config.validate().unwrap();
+        let today = NaiveDate::from_ymd_opt(2026, 1, 1).unwrap();
+        assert_eq!(
+            config.pricing.expired(today),
+            vec![&config.pricing.coupons[0]]
+        );
+        assert!(config
+            .pricing()
+            .price(&[MenuItem::Fries], Some("OLD"), today)
+            .is_err());
+    }
+
+    #[test]
+    fn test_cli_args() {
+        let cli = CliArgs::parse(
+            &args(&[
+                "import",
+                "orders.jsonl",
+                "--dry-run",
+                "--db=test.db",
+                "--set",
+                "log.format=json",
+            ]),
+            &[("--db", "origin.db_path")],
+        )
+        .unwrap();
+
+        assert_eq!(
+            cli.positional,
+            args(&["import", "orders.jsonl", "--dry-run"])
+        );
+        assert_eq!(
+            cli.overrides,
+            vec![
+                ("origin.db_path".to_string(), "test.db".to_string()),
+                ("log.format".to_string(), "json".to_string()),
+            ]
+        );
+        assert!(CliArgs::parse(&args(&["--config"]), &[]).is_err());
+        assert!(CliArgs::parse(&args(&["--set", "log.format"]), &[]).is_err());
+    }
+}
@@ -1,9 +1,58 @@
+use std::io::{BufRead, Write};
 use std::path::Path;
 use std::str::FromStr;
 
-use rusqlite::{Connection, Result};
+use chrono::{DateTime, Local, NaiveDateTime, TimeZone, Utc};
+use rusqlite::{params, Connection, OptionalExtension, Result, Row};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
 
+use crate::delivery::Location;
+use crate::drivers::{assign, Assignment, Driver};
+use crate::error::AspirinEatsError;
 use crate::food::*;
+use crate::pricing::PriceBreakdown;
+use crate::reports::*;
+
+/// Select every column needed by `order_from_row`, naming the customer by their canonical name,
+/// followed by `created_at`
+const SELECT_ORDERS: &str = "SELECT o.id, COALESCE(c.name, o.customer), o.food, o.status, o.total,
+    o.breakdown, o.delivery, o.driver_id, o.scheduled_for, o.created_at
+    FROM orders o LEFT JOIN customers c ON c.id = o.customer_id";
+
+/// Select every column needed by `driver_from_row`, working out which order each driver is
+/// delivering from the orders they have that are still out
+const SELECT_DRIVERS: &str = "SELECT d.id, d.name, d.on_shift, d.lat, d.lon,
+    (SELECT o.id FROM orders o WHERE o.driver_id = d.id AND o.status = '\"Transporting\"'
+    ORDER BY o.id LIMIT 1) FROM drivers d";
+
+/// Format of the `created_at` column, which SQLite's date functions understand
+const DATETIME_FORMAT: &str = "%Y-%m-%d %H:%M:%S";
+
+/// Restrict a report to orders placed between the dates bound to `?1` and `?2` (either may be
+/// NULL to leave that end open)
+const REPORT_FILTER: &str = "(?1 IS NULL OR date(o.created_at) >= ?1)
+    AND (?2 IS NULL OR date(o.created_at) <= ?2)";
+
+/// Write a time as the restaurant's local time, like `created_at`, so times compare as text
+fn format_local(time: &DateTime<Local>) -> String {
+    time.naive_local().format(DATETIME_FORMAT).to_string()
+}
+
+/// Read back a time written by [`format_local`]
+fn parse_local(time: &str) -> Option<DateTime<Local>> {
+    let time = NaiveDateTime::parse_from_str(time, DATETIME_FORMAT).ok()?;
+    Local.from_local_datetime(&time).earliest()
+}
+
+/// Leave cancelled orders out of sales figures
+const NOT_CANCELLED: &str = "o.status != '\"Cancelled\"'";
+
+/// Trim a customer's or driver's name and collapse any runs of whitespace, so that "Amit" and
+/// " amit " can be matched up (the customers table compares names case-insensitively)
+pub fn normalize_name(name: &str) -> String {
+    name.split_whitespace().collect::<Vec<&str>>().join(" ")
+}
 
 pub struct AspirinEatsDb {
     conn: Connection,
@@ -33,6 +82,15 @@ impl AspirinEatsDb {
     }
 
     fn create_table(&self) -> Result<()> {
+        self.conn.execute("PRAGMA foreign_keys = ON", [])?;
+        self.conn.execute(
+            "CREATE TABLE IF NOT EXISTS customers (
+            id          INTEGER NOT NULL,
+            name        TEXT NOT NULL UNIQUE COLLATE NOCASE,
+            PRIMARY KEY(id AUTOINCREMENT)
+        )",
+            [],
+        )?;
         self.conn.execute(
             "CREATE TABLE IF NOT EXISTS orders (
             id	        INTEGER NOT NULL,
@@ -40,25 +98,223 @@ impl AspirinEatsDb {
             food        TEXT NOT NULL,
             status	    TEXT NOT NULL,
             total       REAL NOT NULL,
+            breakdown   TEXT,
+            delivery    TEXT,
+            driver_id   INTEGER,
+            version     INTEGER NOT NULL DEFAULT 1,
+            scheduled_for TEXT,
+            customer_id INTEGER REFERENCES customers(id),
+            created_at  TEXT,
             PRIMARY KEY(id AUTOINCREMENT)
         )",
             [], // no params for this query
         )?;
+        self.conn.execute(
+            "CREATE TABLE IF NOT EXISTS inventory (
+            ingredient  TEXT NOT NULL,
+            quantity    INTEGER NOT NULL CHECK (quantity >= 0),
+            PRIMARY KEY(ingredient)
+        )",
+            [],
+        )?;
+        self.conn.execute(
+            "CREATE TABLE IF NOT EXISTS drivers (
+            id          INTEGER NOT NULL,
+            name        TEXT NOT NULL,
+            on_shift    INTEGER NOT NULL DEFAULT 0,
+            lat         REAL,
+            lon         REAL,
+            PRIMARY KEY(id AUTOINCREMENT)
+        )",
+            [],
+        )?;
+        self.conn.execute(
+            "CREATE TABLE IF NOT EXISTS last_modified (
+            resource    TEXT NOT NULL,
+            at          TEXT NOT NULL,
+            PRIMARY KEY(resource)
+        )",
+            [],
+        )?;
+        // Triggers rather than code in each method, so that every write counts, whichever
+        // connection makes it
+        for event in ["INSERT", "UPDATE", "DELETE"] {
+            self.conn.execute(
+                &format!(
+                    "CREATE TRIGGER IF NOT EXISTS orders_modified_on_{} AFTER {event} ON orders
+                    BEGIN
+                        INSERT OR REPLACE INTO last_modified (resource, at)
+                        VALUES ('orders', datetime('now'));
+                    END",
+                    event.to_lowercase()
+                ),
+                [],
+            )?;
+        }
+        self.add_column_if_missing("orders", "breakdown", "TEXT")?;
+        self.add_column_if_missing("orders", "customer_id", "INTEGER REFERENCES customers(id)")?;
+        self.add_column_if_missing("orders", "created_at", "TEXT")?;
+        self.add_column_if_missing("orders", "delivery", "TEXT")?;
+        self.add_column_if_missing("orders", "driver_id", "INTEGER")?;
+        self.add_column_if_missing("orders", "version", "INTEGER NOT NULL DEFAULT 1")?;
+        self.add_column_if_missing("orders", "scheduled_for", "TEXT")?;
+        self.migrate_customers()?;
         Ok(())
     }
+
+    /// Bring tables created by older versions up to date by adding a column they don't have yet
+    fn add_column_if_missing(&self, table: &str, column: &str, definition: &str) -> Result<()> {
+        let mut stmt = self.conn.prepare(&format!("PRAGMA table_info({table})"))?;
+        let columns = stmt
+            .query_map([], |row| row.get::<_, String>(1))?
+            .collect::<Result<Vec<String>>>()?;
+
+        if !columns.iter().any(|name| name == column) {
+            self.conn.execute(
+                &format!("ALTER TABLE {table} ADD COLUMN {column} {definition}"),
+                [],
+            )?;
+        }
+        Ok(())
+    }
+
+    /// Link orders from older databases, which only stored the customer's name, to rows in the
+    /// customers table. Names that differ only in case or whitespace become the same customer
+    fn migrate_customers(&self) -> Result<()> {
+        let tx = self.conn.unchecked_transaction()?;
+        let names = {
+            let mut stmt =
+                tx.prepare("SELECT DISTINCT customer FROM orders WHERE customer_id IS NULL")?;
+            let names = stmt
+                .query_map([], |row| row.get::<_, String>(0))?
+                .collect::<Result<Vec<String>>>()?;
+            names
+        };
+
+        for name in names {
+            let customer_id = self.get_or_create_customer(&name)?;
+            tx.execute(
+                "UPDATE orders SET customer_id = ?1 WHERE customer_id IS NULL AND customer = ?2",
+                (customer_id, &name),
+            )?;
+        }
+        tx.commit()
+    }
+
+    /// Build an Order from a row selected with [`SELECT_ORDERS`]
+    fn order_from_row(row: &Row) -> Result<Order> {
+        Ok(Order {
+            id: row.get(0)?,
+            customer: row.get(1)?,
+            food: {
+                let food_str: String = row.get(2)?;
+                serde_json::from_str(&food_str).expect("db should contain valid json")
+            },
+            status: {
+                let status: String = row.get(3)?;
+                OrderStatus::from_str(&status).expect("db should contain valid status")
+            },
+            total: row.get(4)?,
+            breakdown: {
+                let breakdown: Option<String> = row.get(5)?;
+                breakdown.map(|b| serde_json::from_str(&b).expect("db should contain valid json"))
+            },
+            delivery: {
+                let delivery: Option<String> = row.get(6)?;
+                delivery.map(|d| serde_json::from_str(&d).expect("db should contain valid json"))
+            },
+            driver: row.get(7)?,
+            scheduled_for: {
+                let scheduled_for: Option<String> = row.get(8)?;
+                scheduled_for.map(|s| parse_local(&s).expect("db should contain valid times"))
+            },
+        })
+    }
 }
 
 impl AspirinEatsDb {
-    /// Insert a new Order into the database
-    pub fn add_order(&self, order: Order) -> Result<i64> {
+    /// Insert a new Order into the database, linking it to its customer and taking its
+    /// ingredients out of stock
+    pub fn add_order(&self, order: Order) -> Result<i64, AspirinEatsError> {
+        self.add_order_at(order, Local::now().naive_local())
+    }
+
+    /// Insert a new Order into the database as if it had been placed at `created_at`. Fails
+    /// without adding the order if any of its ingredients are out of stock
+    pub fn add_order_at(
+        &self,
+        order: Order,
+        created_at: NaiveDateTime,
+    ) -> Result<i64, AspirinEatsError> {
+        let tx = self.conn.unchecked_transaction()?;
+        self.take_stock(&order.food)?;
+        let id = self.insert_order(None, order, created_at)?;
+        tx.commit()?;
+        Ok(id)
+    }
+
+    /// Insert a batch of orders in a single transaction, reporting what happened to each of them
+    /// in the order they were given. Each order takes its own stock, so one that fails leaves
+    /// the others as they were. In [`BatchMode::AllOrNothing`], any failure rolls the whole
+    /// batch back
+    pub fn add_orders(
+        &self,
+        orders: Vec<Order>,
+        mode: BatchMode,
+    ) -> Result<BatchOutcome, AspirinEatsError> {
+        let tx = self.conn.unchecked_transaction()?;
+        let created_at = Local::now().naive_local();
+        let mut results = Vec::with_capacity(orders.len());
+        for order in orders {
+            self.conn.execute_batch("SAVEPOINT batch_order")?;
+            let result = self
+                .take_stock(&order.food)
+                .and_then(|_| Ok(self.insert_order(None, order, created_at)?));
+            match result {
+                Ok(_) => self.conn.execute_batch("RELEASE batch_order")?,
+                Err(_) => self
+                    .conn
+                    .execute_batch("ROLLBACK TO batch_order; RELEASE batch_order")?,
+            }
+            results.push(result);
+        }
+
+        let committed = mode == BatchMode::BestEffort || results.iter().all(Result::is_ok);
+        if committed {
+            tx.commit()?;
+        }
+        Ok(BatchOutcome { results, committed })
+    }
+
+    /// Insert an Order with the given ID, or the next free ID if `id` is None
+    fn insert_order(
+        &self,
+        id: Option<i64>,
+        order: Order,
+        created_at: NaiveDateTime,
+    ) -> Result<i64> {
+        let customer_id = self.get_or_create_customer(&order.customer)?;
         self.conn.execute(
-            "INSERT INTO orders (customer, food, status, total) VALUES (?1, ?2, ?3, ?4)",
-            [
-                order.customer,
+            "INSERT INTO orders (id, customer, food, status, total, breakdown, delivery, driver_id,
+            scheduled_for, customer_id, created_at)
+            VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11)",
+            (
+                id,
+                normalize_name(&order.customer),
                 serde_json::to_string(&order.food).expect("Failed to serialize food"),
                 serde_json::to_string(&order.status).expect("Failed to serialize status"),
-                order.total.to_string(),
-            ],
+                order.total,
+                order
+                    .breakdown
+                    .map(|b| serde_json::to_string(&b).expect("Failed to serialize breakdown")),
+                order
+                    .delivery
+                    .map(|d| serde_json::to_string(&d).expect("Failed to serialize address")),
+                order.driver,
+                order.scheduled_for.as_ref().map(format_local),
+                customer_id,
+                created_at.format(DATETIME_FORMAT).to_string(),
+            ),
         )?;
         Ok(self.conn.last_insert_rowid())
     }
@@ -67,36 +323,168 @@ impl AspirinEatsDb {
     pub fn get_order(&self, id: i64) -> Result<Option<Order>> {
         let mut stmt = self
             .conn
-            .prepare("SELECT customer, food, status, total FROM orders WHERE id = ?1")?;
+            .prepare(&format!("{SELECT_ORDERS} WHERE o.id = ?1"))?;
         let mut rows = stmt.query([&id])?;
 
         if let Some(row) = rows.next()? {
-            Ok(Some(Order {
-                id: Some(id),
-                customer: row.get(0)?,
-                food: {
-                    let food_str: String = row.get(1)?;
-                    serde_json::from_str(&food_str).expect("db should contain valid json")
-                },
-                status: {
-                    let status: String = row.get(2)?;
-                    OrderStatus::from_str(&status).expect("db should contain valid status")
-                },
-                total: row.get(3)?,
-            }))
+            Ok(Some(Self::order_from_row(row)?))
         } else {
             Ok(None)
         }
     }
 
-    /// Remove an order by ID from the database
+    /// Replace the customer, food, status, total, breakdown, delivery address, driver and
+    /// scheduled time of the order with `order.id`, moving it on to its next version. Cancelling
+    /// an order the kitchen hasn't started restocks its ingredients. Returns false if there is no
+    /// such order
+    pub fn update_order(&self, order: &Order) -> Result<bool> {
+        let Some(id) = order.id else {
+            return Ok(false);
+        };
+        let tx = self.conn.unchecked_transaction()?;
+        if let Some(old) = self.get_order(id)? {
+            if old.status.is_waiting() && order.status == OrderStatus::Cancelled {
+                self.restock(&old.food)?;
+            }
+        }
+        let customer_id = self.get_or_create_customer(&order.customer)?;
+        let updated = self.conn.execute(
+            "UPDATE orders SET customer = ?2, food = ?3, status = ?4, total = ?5, breakdown = ?6,
+            delivery = ?7, driver_id = ?8, scheduled_for = ?9, customer_id = ?10,
+            version = version + 1 WHERE id = ?1",
+            (
+                id,
+                normalize_name(&order.customer),
+                serde_json::to_string(&order.food).expect("Failed to serialize food"),
+                serde_json::to_string(&order.status).expect("Failed to serialize status"),
+                order.total,
+                order
+                    .breakdown
+                    .as_ref()
+                    .map(|b| serde_json::to_string(b).expect("Failed to serialize breakdown")),
+                order
+                    .delivery
+                    .as_ref()
+                    .map(|d| serde_json::to_string(d).expect("Failed to serialize address")),
+                order.driver,
+                order.scheduled_for.as_ref().map(format_local),
+                customer_id,
+            ),
+        )?;
+        tx.commit()?;
+        Ok(updated > 0)
+    }
+
+    /// Move the order with `id` from status `from` to `to`, onto its next version, as long as
+    /// its food is still `food`. It's a single step, so two writers can't both make the same
+    /// change, and nobody acts on food that has since been replaced. Cancelling an order the
+    /// kitchen hasn't started restocks its ingredients. Returns false, changing nothing, if there
+    /// is no such order, or its status isn't `from` or its food isn't `food`
+    pub fn set_status(
+        &self,
+        id: i64,
+        from: OrderStatus,
+        to: OrderStatus,
+        food: &[MenuItem],
+    ) -> Result<bool> {
+        let tx = self.conn.unchecked_transaction()?;
+        let updated = self.conn.execute(
+            "UPDATE orders SET status = ?3, version = version + 1
+            WHERE id = ?1 AND status = ?2 AND food = ?4",
+            (
+                id,
+                serde_json::to_string(&from).expect("Failed to serialize status"),
+                serde_json::to_string(&to).expect("Failed to serialize status"),
+                serde_json::to_string(food).expect("Failed to serialize food"),
+            ),
+        )?;
+        if updated > 0 && from.is_waiting() && to == OrderStatus::Cancelled {
+            if let Some(order) = self.get_order(id)? {
+                self.restock(&order.food)?;
+            }
+        }
+        tx.commit()?;
+        Ok(updated > 0)
+    }
+
+    /// Get the version of an order, which goes up by one every time the order changes
+    pub fn get_order_version(&self, id: i64) -> Result<Option<i64>> {
+        self.conn
+            .query_row("SELECT version FROM orders WHERE id = ?1", [&id], |row| {
+                row.get(0)
+            })
+            .optional()
+    }
+
+    /// When any order was last added, changed or removed, to the second. None if none ever has
+    pub fn orders_last_modified(&self) -> Result<Option<DateTime<Utc>>> {
+        let at: Option<String> = self
+            .conn
+            .query_row(
+                "SELECT at FROM last_modified WHERE resource = 'orders'",
+                [],
+                |row| row.get(0),
+            )
+            .optional()?;
+        Ok(at
+            .and_then(|at| NaiveDateTime::parse_from_str(&at, DATETIME_FORMAT).ok())
+            .map(|at| at.and_utc()))
+    }
+
+    /// Replace the food of an order the kitchen hasn't started, priced at `breakdown`, as long as
+    /// it's still at `version`. The old food's ingredients go back into stock and the new food's
+    /// are taken, failing without changing anything if there isn't enough. Returns the order's new
+    /// version
+    pub fn replace_items(
+        &self,
+        id: i64,
+        version: i64,
+        food: &[MenuItem],
+        breakdown: &PriceBreakdown,
+    ) -> Result<i64, AspirinEatsError> {
+        let tx = self.conn.unchecked_transaction()?;
+        let order = self.get_order(id)?.ok_or(AspirinEatsError::NotFound)?;
+        if !order.status.is_waiting() {
+            return Err(AspirinEatsError::Conflict(format!(
+                "Order {id} is {:?}, so it can't be changed any more",
+                order.status
+            )));
+        }
+        self.restock(&order.food)?;
+        self.take_stock(food)?;
+        let updated = self.conn.execute(
+            "UPDATE orders SET food = ?3, total = ?4, breakdown = ?5, version = version + 1
+            WHERE id = ?1 AND version = ?2",
+            (
+                id,
+                version,
+                serde_json::to_string(food).expect("Failed to serialize food"),
+                breakdown.total,
+                serde_json::to_string(breakdown).expect("Failed to serialize breakdown"),
+            ),
+        )?;
+        if updated == 0 {
+            return Err(AspirinEatsError::PreconditionFailed);
+        }
+        tx.commit()?;
+        Ok(version + 1)
+    }
+
+    /// Remove an order by ID from the database. If it's still waiting for the kitchen, nothing
+    /// has been made yet, so its ingredients go back into stock
     pub fn remove_order(&self, id: i64) -> Result<()> {
+        let tx = self.conn.unchecked_transaction()?;
+        if let Some(order) = self.get_order(id)? {
+            if order.status.is_waiting() {
+                self.restock(&order.food)?;
+            }
+        }
         self.conn
             .execute("DELETE FROM orders WHERE id = ?1", [&id])?;
-        Ok(())
+        tx.commit()
     }
 
-    /// Remove all orders from the database
+    /// Remove all orders from the database. Stock is left as it is
     pub fn reset_orders(&self) -> Result<()> {
         self.conn.execute("DELETE FROM orders", [])?;
         self.conn.execute(
@@ -106,35 +494,671 @@ impl AspirinEatsDb {
         Ok(())
     }
 
+    /// Send scheduled orders wanted at or before `until` to the kitchen by making them Pending,
+    /// returning their IDs, soonest first
+    pub fn release_scheduled(&self, until: DateTime<Local>) -> Result<Vec<i64>> {
+        let tx = self.conn.unchecked_transaction()?;
+        let scheduled = serde_json::to_string(&OrderStatus::Scheduled).expect("valid status");
+        let ids = {
+            let mut stmt = tx.prepare(
+                "SELECT id FROM orders WHERE status = ?1 AND scheduled_for <= ?2
+                ORDER BY scheduled_for, id",
+            )?;
+            let ids = stmt
+                .query_map((&scheduled, format_local(&until)), |row| row.get(0))?
+                .collect::<Result<Vec<i64>>>()?;
+            ids
+        };
+        for id in &ids {
+            tx.execute(
+                "UPDATE orders SET status = ?2, version = version + 1 WHERE id = ?1",
+                (
+                    id,
+                    serde_json::to_string(&OrderStatus::Pending).expect("valid status"),
+                ),
+            )?;
+        }
+        tx.commit()?;
+        Ok(ids)
+    }
+
     /// Get all orders from the database
     pub fn get_all_orders(&self) -> Result<Vec<Order>> {
         let mut stmt = self
             .conn
-            .prepare("SELECT id, customer, food, status, total FROM orders")?;
+            .prepare(&format!("{SELECT_ORDERS} ORDER BY o.id"))?;
+
+        let order_iter = stmt.query_map([], Self::order_from_row)?;
 
-        let order_iter = stmt.query_map([], |row| {
-            Ok(Order {
+        Ok(order_iter.map(Result::unwrap).collect())
+    }
+}
+
+impl AspirinEatsDb {
+    /// Get the ID of the customer with the given name, creating them if they don't exist yet.
+    /// Existing customers are looked up first, because even an insert that does nothing uses up
+    /// an ID
+    pub fn get_or_create_customer(&self, name: &str) -> Result<i64> {
+        let name = normalize_name(name);
+        let find = || {
+            self.conn
+                .query_row("SELECT id FROM customers WHERE name = ?1", [&name], |row| {
+                    row.get(0)
+                })
+        };
+        if let Some(id) = find().optional()? {
+            return Ok(id);
+        }
+        self.conn.execute(
+            "INSERT INTO customers (name) VALUES (?1) ON CONFLICT(name) DO NOTHING",
+            [&name],
+        )?;
+        find()
+    }
+
+    /// Get a customer by ID from the database
+    pub fn get_customer(&self, id: i64) -> Result<Option<Customer>> {
+        let mut stmt = self
+            .conn
+            .prepare("SELECT id, name FROM customers WHERE id = ?1")?;
+        let mut rows = stmt.query([&id])?;
+
+        if let Some(row) = rows.next()? {
+            Ok(Some(Customer {
                 id: row.get(0)?,
-                customer: row.get(1)?,
-                food: {
-                    let food_str: String = row.get(2)?;
-                    serde_json::from_str(&food_str).expect("db should contain valid json")
-                },
-                status: {
-                    let status: String = row.get(3)?;
-                    OrderStatus::from_str(&status).expect("db should contain valid status")
-                },
-                total: row.get(4)?,
+                name: row.get(1)?,
+            }))
+        } else {
+            Ok(None)
+        }
+    }
+
+    /// Get all customers from the database
+    pub fn get_all_customers(&self) -> Result<Vec<Customer>> {
+        let mut stmt = self.conn.prepare("SELECT id, name FROM customers")?;
+
+        let customer_iter = stmt.query_map([], |row| {
+            Ok(Customer {
+                id: row.get(0)?,
+                name: row.get(1)?,
             })
         })?;
 
-        Ok(order_iter.map(Result::unwrap).collect())
+        customer_iter.collect()
+    }
+
+    /// Get all of a customer's orders, oldest first
+    pub fn get_customer_orders(&self, customer_id: i64) -> Result<Vec<Order>> {
+        let mut stmt = self.conn.prepare(&format!(
+            "{SELECT_ORDERS} WHERE o.customer_id = ?1 ORDER BY o.id"
+        ))?;
+
+        let order_iter = stmt.query_map([&customer_id], Self::order_from_row)?;
+
+        order_iter.collect()
+    }
+}
+
+impl AspirinEatsDb {
+    /// Aggregate sales over the orders matching `query`
+    pub fn sales_report(&self, query: &SalesQuery) -> Result<SalesReport> {
+        let range = (
+            query.from.map(|date| date.to_string()),
+            query.to.map(|date| date.to_string()),
+        );
+
+        let summary = self.conn.query_row(
+            &format!(
+                "SELECT COUNT(*), COALESCE(SUM(json_array_length(o.food)), 0),
+                COALESCE(SUM(o.total), 0) FROM orders o WHERE {REPORT_FILTER} AND {NOT_CANCELLED}"
+            ),
+            params![range.0, range.1],
+            |row| Ok(SalesSummary::new(row.get(0)?, row.get(1)?, row.get(2)?)),
+        )?;
+
+        let groups_sql = match query.group_by {
+            ReportGrouping::Day => format!(
+                "SELECT COALESCE(date(o.created_at), 'unknown') AS key, COUNT(*),
+                SUM(json_array_length(o.food)), SUM(o.total) FROM orders o
+                WHERE {REPORT_FILTER} AND {NOT_CANCELLED} GROUP BY key ORDER BY key"
+            ),
+            ReportGrouping::Status => format!(
+                "SELECT json_extract(o.status, '$') AS key, COUNT(*),
+                SUM(json_array_length(o.food)), SUM(o.total) FROM orders o
+                WHERE {REPORT_FILTER} GROUP BY key ORDER BY key"
+            ),
+            // Line items in the breakdown are in the same order as the food, so the item's
+            // position in one finds its price in the other
+            ReportGrouping::Item => format!(
+                "SELECT CASE WHEN f.type = 'object' THEN 'Burger' ELSE f.value END AS key,
+                COUNT(DISTINCT o.id), COUNT(*),
+                SUM(COALESCE(json_extract(o.breakdown, '$.lines[' || f.key || '].price'), 0))
+                FROM orders o, json_each(o.food) f
+                WHERE {REPORT_FILTER} AND {NOT_CANCELLED} GROUP BY key ORDER BY key"
+            ),
+        };
+        let mut stmt = self.conn.prepare(&groups_sql)?;
+        let groups = stmt
+            .query_map(params![range.0, range.1], |row| {
+                Ok(SalesGroup {
+                    key: row.get(0)?,
+                    sales: SalesSummary::new(row.get(1)?, row.get(2)?, row.get(3)?),
+                })
+            })?
+            .collect::<Result<Vec<SalesGroup>>>()?;
+
+        let popular_toppings = self.tally(
+            &format!(
+                "SELECT t.value, COUNT(*) FROM orders o, json_each(o.food) f,
+                json_each(o.food, f.fullkey || '.Burger.toppings') t
+                WHERE {REPORT_FILTER} AND {NOT_CANCELLED} GROUP BY t.value"
+            ),
+            &range,
+        )?;
+        let patty_mix = self.tally(
+            &format!(
+                "SELECT json_extract(o.food, f.fullkey || '.Burger.patty') AS patty, COUNT(*)
+                FROM orders o, json_each(o.food) f
+                WHERE {REPORT_FILTER} AND {NOT_CANCELLED} AND f.type = 'object' GROUP BY patty"
+            ),
+            &range,
+        )?;
+
+        Ok(SalesReport {
+            from: query.from,
+            to: query.to,
+            group_by: query.group_by,
+            summary,
+            groups,
+            popular_toppings,
+            patty_mix,
+        })
+    }
+
+    /// Run a `SELECT name, count ... GROUP BY name` query, most common first
+    fn tally(&self, sql: &str, range: &(Option<String>, Option<String>)) -> Result<Vec<Tally>> {
+        let mut stmt = self.conn.prepare(&format!("{sql} ORDER BY 2 DESC, 1"))?;
+        let tally = stmt
+            .query_map(params![range.0, range.1], |row| {
+                Ok(Tally {
+                    name: row.get(0)?,
+                    count: row.get(1)?,
+                })
+            })?
+            .collect();
+        tally
+    }
+}
+
+/// How much of an ingredient is in stock
+#[derive(Serialize, Deserialize, JsonSchema, Debug, PartialEq, Clone)]
+pub struct StockLevel {
+    pub ingredient: Ingredient,
+    pub quantity: i64,
+}
+
+/// A change to how much of an ingredient is in stock, e.g. +20 for a delivery
+#[derive(Serialize, Deserialize, JsonSchema, Debug, PartialEq, Clone)]
+pub struct StockChange {
+    pub ingredient: Ingredient,
+    pub change: i64,
+}
+
+/// Key of an ingredient in the inventory table
+pub(crate) fn ingredient_key(ingredient: &Ingredient) -> String {
+    serde_json::to_string(ingredient).expect("Failed to serialize ingredient")
+}
+
+/// How many of each ingredient `food` uses, in the order they first appear
+pub(crate) fn ingredient_counts(food: &[MenuItem]) -> Vec<(Ingredient, i64)> {
+    let mut counts: Vec<(Ingredient, i64)> = Vec::new();
+    for ingredient in food.iter().flat_map(MenuItem::ingredients) {
+        match counts
+            .iter_mut()
+            .find(|(counted, _)| *counted == ingredient)
+        {
+            Some((_, count)) => *count += 1,
+            None => counts.push((ingredient, 1)),
+        }
+    }
+    counts
+}
+
+impl AspirinEatsDb {
+    /// Stock of every tracked ingredient. Ingredients that aren't tracked never run out
+    pub fn get_inventory(&self) -> Result<Vec<StockLevel>> {
+        let mut stmt = self
+            .conn
+            .prepare("SELECT ingredient, quantity FROM inventory ORDER BY ingredient")?;
+        let levels = stmt.query_map([], |row| {
+            let ingredient: String = row.get(0)?;
+            Ok(StockLevel {
+                ingredient: serde_json::from_str(&ingredient)
+                    .expect("db should contain valid json"),
+                quantity: row.get(1)?,
+            })
+        })?;
+        levels.collect()
+    }
+
+    /// Set how much of each ingredient is in stock, starting to track any that weren't yet
+    pub fn set_stock(&self, levels: &[StockLevel]) -> Result<(), AspirinEatsError> {
+        if levels.iter().any(|level| level.quantity < 0) {
+            return Err(AspirinEatsError::InvalidRequest);
+        }
+        let tx = self.conn.unchecked_transaction()?;
+        for level in levels {
+            self.conn.execute(
+                "INSERT INTO inventory (ingredient, quantity) VALUES (?1, ?2)
+                ON CONFLICT(ingredient) DO UPDATE SET quantity = excluded.quantity",
+                (ingredient_key(&level.ingredient), level.quantity),
+            )?;
+        }
+        tx.commit()?;
+        Ok(())
+    }
+
+    /// Add to (or take from) the stock of each ingredient, starting from zero for any that
+    /// weren't tracked yet. Fails without changing anything if stock would go below zero
+    pub fn adjust_stock(&self, changes: &[StockChange]) -> Result<(), AspirinEatsError> {
+        let tx = self.conn.unchecked_transaction()?;
+        for change in changes {
+            let key = ingredient_key(&change.ingredient);
+            let quantity: i64 = self
+                .conn
+                .query_row(
+                    "SELECT quantity FROM inventory WHERE ingredient = ?1",
+                    [&key],
+                    |row| row.get(0),
+                )
+                .optional()?
+                .unwrap_or_default();
+            if quantity + change.change < 0 {
+                return Err(AspirinEatsError::OutOfStock(change.ingredient.clone()));
+            }
+            self.conn.execute(
+                "INSERT INTO inventory (ingredient, quantity) VALUES (?1, ?2)
+                ON CONFLICT(ingredient) DO UPDATE SET quantity = excluded.quantity",
+                (key, quantity + change.change),
+            )?;
+        }
+        tx.commit()?;
+        Ok(())
+    }
+
+    /// Take the ingredients for `food` out of stock, failing on the first one there isn't
+    /// enough of. Should be run in a transaction, so nothing is taken if that happens
+    fn take_stock(&self, food: &[MenuItem]) -> Result<(), AspirinEatsError> {
+        for (ingredient, needed) in ingredient_counts(food) {
+            let updated = self.conn.execute(
+                "UPDATE inventory SET quantity = quantity - ?2
+                WHERE ingredient = ?1 AND quantity >= ?2",
+                (ingredient_key(&ingredient), needed),
+            )?;
+            let tracked = self
+                .conn
+                .query_row(
+                    "SELECT 1 FROM inventory WHERE ingredient = ?1",
+                    [ingredient_key(&ingredient)],
+                    |_| Ok(()),
+                )
+                .optional()?
+                .is_some();
+            if updated == 0 && tracked {
+                return Err(AspirinEatsError::OutOfStock(ingredient));
+            }
+        }
+        Ok(())
+    }
+
+    /// Put the ingredients for `food` back into stock
+    fn restock(&self, food: &[MenuItem]) -> Result<()> {
+        for (ingredient, count) in ingredient_counts(food) {
+            self.conn.execute(
+                "UPDATE inventory SET quantity = quantity + ?2 WHERE ingredient = ?1",
+                (ingredient_key(&ingredient), count),
+            )?;
+        }
+        Ok(())
+    }
+}
+
+impl AspirinEatsDb {
+    /// Sign up a new driver, who starts off shift. Returns their ID
+    pub fn add_driver(&self, name: &str) -> Result<i64> {
+        self.conn.execute(
+            "INSERT INTO drivers (name) VALUES (?1)",
+            [normalize_name(name)],
+        )?;
+        Ok(self.conn.last_insert_rowid())
+    }
+
+    /// Get a driver by ID from the database
+    pub fn get_driver(&self, id: i64) -> Result<Option<Driver>> {
+        self.conn
+            .query_row(
+                &format!("{SELECT_DRIVERS} WHERE d.id = ?1"),
+                [&id],
+                Self::driver_from_row,
+            )
+            .optional()
+    }
+
+    /// Get all drivers from the database, in the order they signed up
+    pub fn get_all_drivers(&self) -> Result<Vec<Driver>> {
+        let mut stmt = self
+            .conn
+            .prepare(&format!("{SELECT_DRIVERS} ORDER BY d.id"))?;
+        let drivers = stmt.query_map([], Self::driver_from_row)?;
+        drivers.collect()
+    }
+
+    /// Put a driver on shift, moving them to `location` if they say where they are
+    pub fn start_shift(
+        &self,
+        id: i64,
+        location: Option<Location>,
+    ) -> Result<Driver, AspirinEatsError> {
+        let tx = self.conn.unchecked_transaction()?;
+        self.get_driver(id)?.ok_or(AspirinEatsError::NotFound)?;
+        self.conn
+            .execute("UPDATE drivers SET on_shift = 1 WHERE id = ?1", [&id])?;
+        if let Some(location) = location {
+            self.move_driver(id, &location)?;
+        }
+        tx.commit()?;
+        self.get_driver(id)?.ok_or(AspirinEatsError::NotFound)
+    }
+
+    /// Take a driver off shift. Fails if they're still out with an order
+    pub fn end_shift(&self, id: i64) -> Result<Driver, AspirinEatsError> {
+        let tx = self.conn.unchecked_transaction()?;
+        let driver = self.get_driver(id)?.ok_or(AspirinEatsError::NotFound)?;
+        if let Some(order_id) = driver.delivering {
+            return Err(AspirinEatsError::Conflict(format!(
+                "Driver {id} is still delivering order {order_id}"
+            )));
+        }
+        self.conn
+            .execute("UPDATE drivers SET on_shift = 0 WHERE id = ?1", [&id])?;
+        tx.commit()?;
+        self.get_driver(id)?.ok_or(AspirinEatsError::NotFound)
+    }
+
+    /// Give an order to a driver to deliver. The driver has to be on shift and not already out
+    /// with an order, and the order has to be ready: out of the kitchen, with no driver yet
+    pub fn claim_delivery(&self, driver_id: i64, order_id: i64) -> Result<Order, AspirinEatsError> {
+        let tx = self.conn.unchecked_transaction()?;
+        let driver = self
+            .get_driver(driver_id)?
+            .ok_or(AspirinEatsError::NotFound)?;
+        if !driver.on_shift {
+            return Err(AspirinEatsError::Conflict(format!(
+                "Driver {driver_id} is off shift"
+            )));
+        }
+        if let Some(delivering) = driver.delivering {
+            return Err(AspirinEatsError::Conflict(format!(
+                "Driver {driver_id} is already delivering order {delivering}"
+            )));
+        }
+        self.get_order(order_id)?
+            .ok_or(AspirinEatsError::NotFound)?;
+        if !self.give_order(order_id, driver_id)? {
+            return Err(AspirinEatsError::Conflict(format!(
+                "Order {order_id} isn't ready for a driver"
+            )));
+        }
+        tx.commit()?;
+        self.get_order(order_id)?.ok_or(AspirinEatsError::NotFound)
+    }
+
+    /// Mark a driver's delivery as done, completing the order and leaving the driver at the
+    /// address it went to
+    pub fn complete_delivery(&self, driver_id: i64) -> Result<Order, AspirinEatsError> {
+        let tx = self.conn.unchecked_transaction()?;
+        let driver = self
+            .get_driver(driver_id)?
+            .ok_or(AspirinEatsError::NotFound)?;
+        let order_id = driver.delivering.ok_or_else(|| {
+            AspirinEatsError::Conflict(format!("Driver {driver_id} isn't delivering anything"))
+        })?;
+        self.conn.execute(
+            "UPDATE orders SET status = ?2, version = version + 1 WHERE id = ?1",
+            (
+                order_id,
+                serde_json::to_string(&OrderStatus::Completed).expect("Failed to serialize status"),
+            ),
+        )?;
+        let order = self
+            .get_order(order_id)?
+            .ok_or(AspirinEatsError::NotFound)?;
+        if let Some(address) = &order.delivery {
+            self.move_driver(driver_id, &address.location)?;
+        }
+        tx.commit()?;
+        Ok(order)
+    }
+
+    /// IDs of the orders that are ready for a driver, oldest first
+    pub fn ready_orders(&self) -> Result<Vec<i64>> {
+        let mut stmt = self.conn.prepare(
+            "SELECT id FROM orders WHERE status = '\"Transporting\"' AND driver_id IS NULL
+            ORDER BY id",
+        )?;
+        let ids = stmt.query_map([], |row| row.get(0))?;
+        ids.collect()
+    }
+
+    /// Hand as many ready orders as there are free drivers to the drivers nearest the
+    /// restaurant at `restaurant`, oldest orders first. Returns who got what
+    pub fn dispatch(&self, restaurant: &Location) -> Result<Vec<Assignment>> {
+        let tx = self.conn.unchecked_transaction()?;
+        let assignments = assign(&self.ready_orders()?, &self.get_all_drivers()?, restaurant);
+        for assignment in &assignments {
+            self.give_order(assignment.order_id, assignment.driver_id)?;
+        }
+        tx.commit()?;
+        Ok(assignments)
+    }
+
+    /// Set the driver of an order if it's ready for one. Returns false if it isn't
+    fn give_order(&self, order_id: i64, driver_id: i64) -> Result<bool> {
+        let updated = self.conn.execute(
+            "UPDATE orders SET driver_id = ?2, version = version + 1
+            WHERE id = ?1 AND status = '\"Transporting\"' AND driver_id IS NULL",
+            (order_id, driver_id),
+        )?;
+        Ok(updated > 0)
+    }
+
+    fn move_driver(&self, id: i64, location: &Location) -> Result<()> {
+        self.conn.execute(
+            "UPDATE drivers SET lat = ?2, lon = ?3 WHERE id = ?1",
+            (id, location.lat, location.lon),
+        )?;
+        Ok(())
+    }
+
+    /// Build a Driver from a row selected with [`SELECT_DRIVERS`]
+    fn driver_from_row(row: &Row) -> Result<Driver> {
+        let lat: Option<f64> = row.get(3)?;
+        let lon: Option<f64> = row.get(4)?;
+        Ok(Driver {
+            id: row.get(0)?,
+            name: row.get(1)?,
+            on_shift: row.get(2)?,
+            location: lat.zip(lon).map(|(lat, lon)| Location { lat, lon }),
+            delivering: row.get(5)?,
+        })
+    }
+}
+
+/// One line of an orders export: an Order, plus when it was placed
+#[derive(Serialize, Deserialize)]
+struct ExportedOrder {
+    #[serde(flatten)]
+    order: Order,
+
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    created_at: Option<NaiveDateTime>,
+}
+
+/// What to do with a batch of orders when some of them can't be added
+#[derive(Debug, PartialEq, Clone, Copy, Default)]
+pub enum BatchMode {
+    /// Add none of the orders unless all of them can be added
+    #[default]
+    AllOrNothing,
+
+    /// Add every order that can be, and report the rest
+    BestEffort,
+}
+
+impl FromStr for BatchMode {
+    type Err = AspirinEatsError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "all_or_nothing" => Ok(BatchMode::AllOrNothing),
+            "best_effort" => Ok(BatchMode::BestEffort),
+            _ => Err(AspirinEatsError::InvalidRequest),
+        }
+    }
+}
+
+/// What happened to each order in a batch
+#[derive(Debug)]
+pub struct BatchOutcome {
+    /// The new order's ID, or why it couldn't be added, for each order in the batch. IDs only
+    /// last if the batch was committed
+    pub results: Vec<Result<i64, AspirinEatsError>>,
+    pub committed: bool,
+}
+
+/// Whether an import should be kept or only checked
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum ImportMode {
+    /// Import the orders if every line is valid
+    Commit,
+
+    /// Validate every line and report what would happen, without changing the database
+    DryRun,
+}
+
+/// A line of an import that could not be imported
+#[derive(Serialize, Debug, PartialEq, Clone)]
+pub struct ImportFailure {
+    /// Line number in the input, starting at 1
+    pub line: usize,
+    pub message: String,
+}
+
+/// Outcome of an import. Nothing is imported unless `failures` is empty and the import was not
+/// a dry run
+#[derive(Serialize, Debug, PartialEq, Clone)]
+pub struct ImportReport {
+    /// Number of orders that were (or, for a dry run, would have been) imported
+    pub imported: usize,
+    pub failures: Vec<ImportFailure>,
+    pub committed: bool,
+}
+
+impl AspirinEatsDb {
+    /// Write every order to `writer` as JSON Lines, oldest first. Returns the number of orders
+    pub fn export_orders<W: Write>(&self, mut writer: W) -> Result<usize, AspirinEatsError> {
+        let mut stmt = self
+            .conn
+            .prepare(&format!("{SELECT_ORDERS} ORDER BY o.id"))?;
+        let mut rows = stmt.query([])?;
+
+        let mut count = 0;
+        while let Some(row) = rows.next()? {
+            let created_at: Option<String> = row.get(9)?;
+            let exported = ExportedOrder {
+                order: Self::order_from_row(row)?,
+                created_at: created_at
+                    .and_then(|c| NaiveDateTime::parse_from_str(&c, DATETIME_FORMAT).ok()),
+            };
+            serde_json::to_writer(&mut writer, &exported)?;
+            writer.write_all(b"\n")?;
+            count += 1;
+        }
+        writer.flush()?;
+        Ok(count)
+    }
+
+    /// Read JSON Lines orders (as written by `export_orders`) from `reader` and add them in a
+    /// single transaction. Orders keep their IDs if they have one. If any line fails, or this
+    /// is a dry run, the transaction is rolled back and nothing is imported
+    pub fn import_orders<R: BufRead>(
+        &self,
+        reader: R,
+        mode: ImportMode,
+    ) -> Result<ImportReport, AspirinEatsError> {
+        let tx = self.conn.unchecked_transaction()?;
+        let mut report = ImportReport {
+            imported: 0,
+            failures: Vec::new(),
+            committed: false,
+        };
+
+        for (index, line) in reader.lines().enumerate() {
+            let line = line?;
+            if line.trim().is_empty() {
+                continue;
+            }
+            match self.import_line(&line) {
+                Ok(()) => report.imported += 1,
+                Err(message) => report.failures.push(ImportFailure {
+                    line: index + 1,
+                    message,
+                }),
+            }
+        }
+
+        if report.failures.is_empty() && mode == ImportMode::Commit {
+            tx.commit()?;
+            report.committed = true;
+        }
+        Ok(report)
+    }
+
+    /// Validate and insert a single line of an import, describing what was wrong if it fails
+    fn import_line(&self, line: &str) -> Result<(), String> {
+        let ExportedOrder { order, created_at } =
+            serde_json::from_str(line).map_err(|err| format!("invalid order: {err}"))?;
+
+        if normalize_name(&order.customer).is_empty() {
+            return Err("customer is empty".to_string());
+        }
+        if order.food.is_empty() {
+            return Err("order has no food".to_string());
+        }
+        if !order.total.is_finite() || order.total < 0.0 {
+            return Err(format!("invalid total {}", order.total));
+        }
+        if order.id.is_some_and(|id| id <= 0) {
+            return Err(format!("invalid id {}", order.id.unwrap_or_default()));
+        }
+
+        let id = order.id;
+        let created_at = created_at.unwrap_or_else(|| Local::now().naive_local());
+        self.insert_order(id, order, created_at)
+            .map_err(|err| match id {
+                Some(id) => format!("could not add order {id}: {err}"),
+                None => format!("could not add order: {err}"),
+            })?;
+        Ok(())
     }
 }
 
 #[cfg(test)]
 mod tests {
+    use chrono::NaiveDate;
+
     use super::*;
+    use crate::pricing::Pricing;
 
     fn get_test_order() -> Order {
         Order {
@@ -143,6 +1167,10 @@ mod tests {
             food: vec![MenuItem::Fries, MenuItem::Drink],
             status: OrderStatus::Pending,
             total: 8.0,
+            breakdown: None,
+            delivery: None,
+            driver: None,
+            scheduled_for: None,
         }
     }
 
@@ -182,6 +1210,284 @@ mod tests {
         assert_eq!(got, None);
     }
 
+    #[test]
+    fn test_breakdown_round_trip() {
+        let db = AspirinEatsDb::in_memory().unwrap();
+        let mut order = Pricing::default()
+            .order(
+                OrderRequest {
+                    customer: "Amit".to_string(),
+                    food: vec![MenuItem::Fries, MenuItem::Drink],
+                    coupon: None,
+                    delivery: None,
+                    scheduled_for: None,
+                },
+                Local::now().date_naive(),
+            )
+            .unwrap();
+
+        order.id = Some(db.add_order(order.clone()).unwrap());
+
+        let got = db.get_order(order.id.unwrap()).unwrap().unwrap();
+        assert!(got.breakdown.is_some());
+        assert_eq!(got, order);
+    }
+
+    #[test]
+    fn test_customers_are_deduplicated() {
+        let db = AspirinEatsDb::in_memory().unwrap();
+        let mut order = get_test_order();
+        db.add_order(order.clone()).unwrap();
+        order.customer = " amit ".to_string();
+        db.add_order(order.clone()).unwrap();
+        order.customer = "Bea".to_string();
+        db.add_order(order).unwrap();
+
+        let customers = db.get_all_customers().unwrap();
+        assert_eq!(customers.len(), 2);
+
+        let amit = customers.iter().find(|c| c.name == "Amit").unwrap();
+        let orders = db.get_customer_orders(amit.id.unwrap()).unwrap();
+        assert_eq!(orders.len(), 2);
+        assert!(orders.iter().all(|o| o.customer == "Amit"));
+    }
+
+    #[test]
+    fn test_migrate_customers_from_old_database() {
+        let path = std::env::temp_dir().join(format!("aspirin-eats-{}.db", uuid::Uuid::new_v4()));
+        {
+            let conn = Connection::open(&path).unwrap();
+            conn.execute(
+                "CREATE TABLE orders (
+                id	        INTEGER NOT NULL,
+                customer	TEXT NOT NULL,
+                food        TEXT NOT NULL,
+                status	    TEXT NOT NULL,
+                total       REAL NOT NULL,
+                PRIMARY KEY(id AUTOINCREMENT)
+            )",
+                [],
+            )
+            .unwrap();
+            for customer in ["Amit", "amit ", "Bea"] {
+                conn.execute(
+                    "INSERT INTO orders (customer, food, status, total)
+                    VALUES (?1, '[\"Fries\"]', '\"Pending\"', 5.0)",
+                    [customer],
+                )
+                .unwrap();
+            }
+        }
+
+        let db = AspirinEatsDb::from_path(&path).unwrap();
+        let customers = db.get_all_customers().unwrap();
+        assert_eq!(customers.len(), 2);
+        assert_eq!(
+            db.get_customer_orders(customers[0].id.unwrap())
+                .unwrap()
+                .len(),
+            2
+        );
+        assert_eq!(db.get_order(2).unwrap().unwrap().customer, "Amit");
+
+        drop(db);
+        std::fs::remove_file(path).unwrap();
+    }
+
+    fn burger(patty: Patty, toppings: Vec<Topping>) -> MenuItem {
+        MenuItem::Burger(Burger::new(Bun::Plain, patty, toppings))
+    }
+
+    fn add_priced_order(db: &AspirinEatsDb, food: Vec<MenuItem>, placed: &str) -> i64 {
+        let placed = NaiveDateTime::parse_from_str(placed, DATETIME_FORMAT).unwrap();
+        let order = Pricing::default()
+            .order(
+                OrderRequest {
+                    customer: "Amit".to_string(),
+                    food,
+                    coupon: None,
+                    delivery: None,
+                    scheduled_for: None,
+                },
+                placed.date(),
+            )
+            .unwrap();
+        db.add_order_at(order, placed).unwrap()
+    }
+
+    fn get_report_db() -> AspirinEatsDb {
+        let db = AspirinEatsDb::in_memory().unwrap();
+        add_priced_order(
+            &db,
+            vec![
+                burger(Patty::Beef, vec![Topping::Cheese, Topping::Bacon]),
+                MenuItem::Fries,
+                MenuItem::Drink,
+            ],
+            "2024-03-01 12:00:00",
+        );
+        add_priced_order(
+            &db,
+            vec![burger(Patty::Veggie, vec![Topping::Cheese])],
+            "2024-03-01 18:30:00",
+        );
+        add_priced_order(&db, vec![MenuItem::Fries], "2024-03-02 09:00:00");
+        let cancelled = add_priced_order(
+            &db,
+            vec![burger(Patty::Beef, vec![Topping::Onion])],
+            "2024-03-02 10:00:00",
+        );
+        db.conn
+            .execute(
+                "UPDATE orders SET status = '\"Cancelled\"' WHERE id = ?1",
+                [cancelled],
+            )
+            .unwrap();
+        db
+    }
+
+    #[test]
+    fn test_sales_report_by_day() {
+        let db = get_report_db();
+        let report = db
+            .sales_report(&SalesQuery {
+                from: None,
+                to: None,
+                group_by: ReportGrouping::Day,
+            })
+            .unwrap();
+
+        // 19 - 2 (meal) + 7 + 5, leaving out the cancelled order
+        assert_eq!(report.summary.orders, 3);
+        assert_eq!(report.summary.items, 5);
+        assert_eq!(report.summary.revenue, 29.0);
+        assert_eq!(report.summary.average_order_value, 9.67);
+
+        let keys: Vec<&str> = report.groups.iter().map(|g| g.key.as_str()).collect();
+        assert_eq!(keys, vec!["2024-03-01", "2024-03-02"]);
+        assert_eq!(report.groups[0].sales.revenue, 24.0);
+        assert_eq!(report.groups[1].sales.orders, 1);
+
+        assert_eq!(
+            report.popular_toppings,
+            vec![
+                Tally {
+                    name: "Cheese".to_string(),
+                    count: 2
+                },
+                Tally {
+                    name: "Bacon".to_string(),
+                    count: 1
+                },
+            ]
+        );
+        assert_eq!(report.patty_mix.len(), 2);
+    }
+
+    #[test]
+    fn test_sales_report_by_item_and_status_in_range() {
+        let db = get_report_db();
+        let from = NaiveDate::from_ymd_opt(2024, 3, 2);
+
+        let report = db
+            .sales_report(&SalesQuery {
+                from: None,
+                to: NaiveDate::from_ymd_opt(2024, 3, 1),
+                group_by: ReportGrouping::Item,
+            })
+            .unwrap();
+        let items: Vec<(&str, i64, f64)> = report
+            .groups
+            .iter()
+            .map(|g| (g.key.as_str(), g.sales.items, g.sales.revenue))
+            .collect();
+        assert_eq!(
+            items,
+            vec![("Burger", 2, 18.0), ("Drink", 1, 3.0), ("Fries", 1, 5.0)]
+        );
+
+        let report = db
+            .sales_report(&SalesQuery {
+                from,
+                to: None,
+                group_by: ReportGrouping::Status,
+            })
+            .unwrap();
+        let statuses: Vec<&str> = report.groups.iter().map(|g| g.key.as_str()).collect();
+        assert_eq!(statuses, vec!["Cancelled", "Pending"]);
+        assert_eq!(report.summary.orders, 1);
+    }
+
+    #[test]
+    fn test_export_import_round_trip() {
+        let db = get_report_db();
+        let mut exported = Vec::new();
+        assert_eq!(db.export_orders(&mut exported).unwrap(), 4);
+        assert_eq!(
+            String::from_utf8(exported.clone()).unwrap().lines().count(),
+            4
+        );
+
+        let copy = AspirinEatsDb::in_memory().unwrap();
+        let report = copy
+            .import_orders(exported.as_slice(), ImportMode::Commit)
+            .unwrap();
+        assert_eq!(report.imported, 4);
+        assert!(report.committed);
+        assert_eq!(copy.get_all_orders().unwrap(), db.get_all_orders().unwrap());
+
+        let query = SalesQuery {
+            from: None,
+            to: None,
+            group_by: ReportGrouping::Day,
+        };
+        assert_eq!(
+            copy.sales_report(&query).unwrap(),
+            db.sales_report(&query).unwrap()
+        );
+    }
+
+    #[test]
+    fn test_import_is_all_or_nothing() {
+        let db = AspirinEatsDb::in_memory().unwrap();
+        db.add_order(get_test_order()).unwrap();
+
+        let input = [
+            r#"{"id":5,"customer":"Bea","food":["Fries"],"status":"Pending","total":5.0}"#,
+            r#"{"id":1,"customer":"Bea","food":["Fries"],"status":"Pending","total":5.0}"#,
+            "",
+            r#"{"customer":" ","food":["Fries"],"status":"Pending","total":5.0}"#,
+            r#"{"customer":"Bea","food":[],"status":"Pending","total":5.0}"#,
+            "not json",
+        ]
+        .join("\n");
+
+        let report = db
+            .import_orders(input.as_bytes(), ImportMode::Commit)
+            .unwrap();
+        assert!(!report.committed);
+        assert_eq!(report.imported, 1);
+        assert_eq!(
+            report.failures.iter().map(|f| f.line).collect::<Vec<_>>(),
+            vec![2, 4, 5, 6]
+        );
+        assert_eq!(db.get_all_orders().unwrap().len(), 1);
+    }
+
+    #[test]
+    fn test_import_dry_run() {
+        let db = AspirinEatsDb::in_memory().unwrap();
+        let input = r#"{"customer":"Bea","food":["Fries"],"status":"Pending","total":5.0}"#;
+
+        let report = db
+            .import_orders(input.as_bytes(), ImportMode::DryRun)
+            .unwrap();
+        assert_eq!(report.imported, 1);
+        assert!(report.failures.is_empty());
+        assert!(!report.committed);
+        assert!(db.get_all_orders().unwrap().is_empty());
+    }
+
     #[test]
     fn test_reset_orders() {
         let db = AspirinEatsDb::in_memory().unwrap();
@@ -193,4 +1499,424 @@ mod tests {
         let orders = db.get_all_orders().unwrap();
         assert_eq!(orders.len(), 0);
     }
+
+    #[test]
+    fn test_orders_last_modified() {
+        let db = AspirinEatsDb::in_memory().unwrap();
+        assert_eq!(db.orders_last_modified().unwrap(), None);
+
+        let before = Utc::now() - chrono::Duration::seconds(1);
+        let id = db.add_order(get_test_order()).unwrap();
+        let added = db.orders_last_modified().unwrap().unwrap();
+        assert!(added >= before && added <= Utc::now(), "{added}");
+
+        // Changes count as much as new orders do
+        db.conn
+            .execute("UPDATE last_modified SET at = '2000-01-01 00:00:00'", [])
+            .unwrap();
+        db.remove_order(id).unwrap();
+        assert!(db.orders_last_modified().unwrap().unwrap() >= before);
+    }
+
+    #[test]
+    fn test_release_scheduled() {
+        let db = AspirinEatsDb::in_memory().unwrap();
+        let at = |hour| Local.with_ymd_and_hms(2024, 3, 10, hour, 0, 0).unwrap();
+        let schedule = |hour| Order {
+            status: OrderStatus::Scheduled,
+            scheduled_for: Some(at(hour)),
+            ..get_test_order()
+        };
+        let evening = db.add_order(schedule(19)).unwrap();
+        let lunch = db.add_order(schedule(12)).unwrap();
+        let now = db.add_order(get_test_order()).unwrap();
+        assert_eq!(
+            db.get_order(lunch).unwrap().unwrap().scheduled_for,
+            Some(at(12))
+        );
+
+        assert!(db.release_scheduled(at(11)).unwrap().is_empty());
+        assert_eq!(db.release_scheduled(at(12)).unwrap(), vec![lunch]);
+        assert!(db.release_scheduled(at(12)).unwrap().is_empty());
+        assert_eq!(db.get_order_version(lunch).unwrap(), Some(2));
+        assert_eq!(db.get_order_version(now).unwrap(), Some(1));
+
+        let statuses: Vec<OrderStatus> = db
+            .get_all_orders()
+            .unwrap()
+            .into_iter()
+            .map(|order| order.status)
+            .collect();
+        assert_eq!(
+            statuses,
+            vec![
+                OrderStatus::Scheduled,
+                OrderStatus::Pending,
+                OrderStatus::Pending
+            ]
+        );
+        assert_eq!(db.release_scheduled(at(23)).unwrap(), vec![evening]);
+    }
+
+    fn stock(db: &AspirinEatsDb, ingredient: Ingredient) -> Option<i64> {
+        db.get_inventory()
+            .unwrap()
+            .into_iter()
+            .find(|level| level.ingredient == ingredient)
+            .map(|level| level.quantity)
+    }
+
+    fn get_inventory_db() -> AspirinEatsDb {
+        let db = AspirinEatsDb::in_memory().unwrap();
+        db.set_stock(&[
+            StockLevel {
+                ingredient: Ingredient::Patty(Patty::Beef),
+                quantity: 3,
+            },
+            StockLevel {
+                ingredient: Ingredient::Topping(Topping::Bacon),
+                quantity: 2,
+            },
+        ])
+        .unwrap();
+        db
+    }
+
+    #[test]
+    fn test_add_order_takes_stock() {
+        let db = get_inventory_db();
+        let mut order = get_test_order();
+        order.food = vec![
+            burger(Patty::Beef, vec![Topping::Bacon]),
+            burger(Patty::Beef, vec![Topping::Lettuce]),
+        ];
+
+        db.add_order(order).unwrap();
+
+        assert_eq!(stock(&db, Ingredient::Patty(Patty::Beef)), Some(1));
+        assert_eq!(stock(&db, Ingredient::Topping(Topping::Bacon)), Some(1));
+        // Not tracked, so never runs out
+        assert_eq!(stock(&db, Ingredient::Topping(Topping::Lettuce)), None);
+    }
+
+    #[test]
+    fn test_add_orders() {
+        let order = |food| Order {
+            food,
+            ..get_test_order()
+        };
+        let bacon = || burger(Patty::Beef, vec![Topping::Bacon]);
+        // The second order runs out of bacon after taking its patties
+        let batch = || {
+            vec![
+                order(vec![bacon()]),
+                order(vec![bacon(), bacon()]),
+                order(vec![burger(Patty::Beef, vec![])]),
+            ]
+        };
+
+        let db = get_inventory_db();
+        let outcome = db.add_orders(batch(), BatchMode::BestEffort).unwrap();
+        assert!(outcome.committed);
+        assert_eq!(outcome.results[0].as_ref().unwrap(), &1);
+        assert!(matches!(
+            outcome.results[1],
+            Err(AspirinEatsError::OutOfStock(Ingredient::Topping(
+                Topping::Bacon
+            )))
+        ));
+        assert_eq!(outcome.results[2].as_ref().unwrap(), &2);
+        assert_eq!(db.get_all_orders().unwrap().len(), 2);
+        assert_eq!(stock(&db, Ingredient::Patty(Patty::Beef)), Some(1));
+        assert_eq!(stock(&db, Ingredient::Topping(Topping::Bacon)), Some(1));
+
+        let db = get_inventory_db();
+        let outcome = db.add_orders(batch(), BatchMode::AllOrNothing).unwrap();
+        assert!(!outcome.committed);
+        assert!(outcome.results[1].is_err());
+        assert_eq!(db.get_all_orders().unwrap().len(), 0);
+        assert_eq!(stock(&db, Ingredient::Patty(Patty::Beef)), Some(3));
+        assert_eq!(stock(&db, Ingredient::Topping(Topping::Bacon)), Some(2));
+
+        let outcome = db
+            .add_orders(batch()[..1].to_vec(), BatchMode::AllOrNothing)
+            .unwrap();
+        assert!(outcome.committed);
+        assert_eq!(db.get_all_orders().unwrap().len(), 1);
+    }
+
+    #[test]
+    fn test_out_of_stock_adds_nothing() {
+        let db = get_inventory_db();
+        let mut order = get_test_order();
+        order.food = vec![
+            burger(Patty::Beef, vec![Topping::Bacon]),
+            burger(Patty::Beef, vec![Topping::Bacon, Topping::Bacon]),
+        ];
+
+        let err = db.add_order(order).unwrap_err();
+
+        assert_eq!(err.to_string(), "Out of stock: Bacon");
+        assert_eq!(db.get_all_orders().unwrap(), vec![]);
+        // The patties taken before bacon ran out are put back
+        assert_eq!(stock(&db, Ingredient::Patty(Patty::Beef)), Some(3));
+        assert_eq!(stock(&db, Ingredient::Topping(Topping::Bacon)), Some(2));
+    }
+
+    #[test]
+    fn test_cancelling_restocks() {
+        let db = get_inventory_db();
+        let mut order = get_test_order();
+        order.food = vec![burger(Patty::Beef, vec![])];
+        // Scheduled orders have taken their ingredients too
+        let removed = db
+            .add_order(Order {
+                status: OrderStatus::Scheduled,
+                ..order.clone()
+            })
+            .unwrap();
+        let cancelled = db.add_order(order.clone()).unwrap();
+        let completed = db.add_order(order).unwrap();
+        assert_eq!(stock(&db, Ingredient::Patty(Patty::Beef)), Some(0));
+
+        db.remove_order(removed).unwrap();
+        assert_eq!(stock(&db, Ingredient::Patty(Patty::Beef)), Some(1));
+
+        let mut order = db.get_order(cancelled).unwrap().unwrap();
+        order.status = OrderStatus::Cancelled;
+        db.update_order(&order).unwrap();
+        // Cancelling it again, or removing it once cancelled, doesn't restock twice
+        db.update_order(&order).unwrap();
+        db.remove_order(cancelled).unwrap();
+        assert_eq!(stock(&db, Ingredient::Patty(Patty::Beef)), Some(2));
+
+        // Once it's been made, there's nothing to put back
+        let mut order = db.get_order(completed).unwrap().unwrap();
+        order.status = OrderStatus::Completed;
+        db.update_order(&order).unwrap();
+        db.remove_order(completed).unwrap();
+        assert_eq!(stock(&db, Ingredient::Patty(Patty::Beef)), Some(2));
+    }
+
+    #[test]
+    fn test_adjust_stock() {
+        let db = get_inventory_db();
+        db.adjust_stock(&[
+            StockChange {
+                ingredient: Ingredient::Patty(Patty::Beef),
+                change: -3,
+            },
+            StockChange {
+                ingredient: Ingredient::Fries,
+                change: 10,
+            },
+        ])
+        .unwrap();
+        assert_eq!(stock(&db, Ingredient::Patty(Patty::Beef)), Some(0));
+        assert_eq!(stock(&db, Ingredient::Fries), Some(10));
+
+        let err = db
+            .adjust_stock(&[
+                StockChange {
+                    ingredient: Ingredient::Fries,
+                    change: -5,
+                },
+                StockChange {
+                    ingredient: Ingredient::Bun(Bun::GlutenFree),
+                    change: -1,
+                },
+            ])
+            .unwrap_err();
+        assert_eq!(err.to_string(), "Out of stock: GlutenFree bun");
+        assert_eq!(stock(&db, Ingredient::Fries), Some(10));
+
+        assert!(matches!(
+            db.set_stock(&[StockLevel {
+                ingredient: Ingredient::Drink,
+                quantity: -1,
+            }]),
+            Err(AspirinEatsError::InvalidRequest)
+        ));
+    }
+
+    /// Add an order delivered to `location`, and send it out of the kitchen
+    fn add_ready_order(db: &AspirinEatsDb, location: Location) -> i64 {
+        let id = db
+            .add_order(Order {
+                delivery: Some(crate::delivery::Address {
+                    street: "1 Main St".to_string(),
+                    city: "Springfield".to_string(),
+                    postcode: "12345".to_string(),
+                    location,
+                }),
+                ..get_test_order()
+            })
+            .unwrap();
+        let mut order = db.get_order(id).unwrap().unwrap();
+        order.status = OrderStatus::Transporting;
+        db.update_order(&order).unwrap();
+        id
+    }
+
+    fn conflict(result: Result<impl std::fmt::Debug, AspirinEatsError>) -> String {
+        match result {
+            Err(AspirinEatsError::Conflict(message)) => message,
+            other => panic!("expected a conflict, got {other:?}"),
+        }
+    }
+
+    #[test]
+    fn test_delivery_lifecycle() {
+        let db = AspirinEatsDb::in_memory().unwrap();
+        let driver = db.add_driver(" Sam ").unwrap();
+        let home = Location { lat: 1.0, lon: 2.0 };
+        let order = add_ready_order(&db, home);
+        let pending = db.add_order(get_test_order()).unwrap();
+
+        assert_eq!(
+            conflict(db.claim_delivery(driver, order)),
+            "Driver 1 is off shift"
+        );
+        let got = db.start_shift(driver, None).unwrap();
+        assert_eq!(got.name, "Sam");
+        assert!(got.is_free());
+        assert_eq!(
+            conflict(db.claim_delivery(driver, pending)),
+            "Order 2 isn't ready for a driver"
+        );
+        assert!(matches!(
+            db.claim_delivery(driver, 99),
+            Err(AspirinEatsError::NotFound)
+        ));
+
+        let got = db.claim_delivery(driver, order).unwrap();
+        assert_eq!(got.driver, Some(driver));
+        assert_eq!(got.status, OrderStatus::Transporting);
+        assert_eq!(
+            db.get_driver(driver).unwrap().unwrap().delivering,
+            Some(order)
+        );
+        assert_eq!(
+            conflict(db.end_shift(driver)),
+            "Driver 1 is still delivering order 1"
+        );
+
+        let got = db.complete_delivery(driver).unwrap();
+        assert_eq!(got.status, OrderStatus::Completed);
+        assert_eq!(got.driver, Some(driver));
+        let got = db.get_driver(driver).unwrap().unwrap();
+        assert_eq!(got.delivering, None);
+        assert_eq!(got.location, Some(home));
+        assert_eq!(
+            conflict(db.complete_delivery(driver)),
+            "Driver 1 isn't delivering anything"
+        );
+
+        assert!(!db.end_shift(driver).unwrap().on_shift);
+        assert!(matches!(db.end_shift(99), Err(AspirinEatsError::NotFound)));
+    }
+
+    #[test]
+    fn test_dispatch() {
+        let db = AspirinEatsDb::in_memory().unwrap();
+        let restaurant = Location { lat: 0.0, lon: 0.0 };
+        let far = db.add_driver("Far").unwrap();
+        let near = db.add_driver("Near").unwrap();
+        let off_shift = db.add_driver("Off").unwrap();
+        db.start_shift(far, Some(Location { lat: 0.0, lon: 0.5 }))
+            .unwrap();
+        db.start_shift(near, Some(Location { lat: 0.0, lon: 0.1 }))
+            .unwrap();
+
+        let first = add_ready_order(&db, restaurant);
+        db.add_order(get_test_order()).unwrap();
+        let second = add_ready_order(&db, restaurant);
+        let third = add_ready_order(&db, restaurant);
+        assert_eq!(db.ready_orders().unwrap(), vec![first, second, third]);
+
+        let assignments = db.dispatch(&restaurant).unwrap();
+        assert_eq!(
+            assignments,
+            vec![
+                Assignment {
+                    order_id: first,
+                    driver_id: near
+                },
+                Assignment {
+                    order_id: second,
+                    driver_id: far
+                },
+            ]
+        );
+        assert_eq!(db.get_order(second).unwrap().unwrap().driver, Some(far));
+        assert_eq!(db.get_driver(off_shift).unwrap().unwrap().delivering, None);
+
+        // Nobody's free until a delivery is done
+        assert_eq!(db.dispatch(&restaurant).unwrap(), vec![]);
+        db.complete_delivery(far).unwrap();
+        assert_eq!(db.dispatch(&restaurant).unwrap()[0].order_id, third);
+        assert!(db.ready_orders().unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_replace_items() {
+        let db = AspirinEatsDb::in_memory().unwrap();
+        db.set_stock(&[
+            StockLevel {
+                ingredient: Ingredient::Fries,
+                quantity: 2,
+            },
+            StockLevel {
+                ingredient: Ingredient::Drink,
+                quantity: 1,
+            },
+        ])
+        .unwrap();
+        let id = db.add_order(get_test_order()).unwrap();
+        assert_eq!(db.get_order_version(id).unwrap(), Some(1));
+
+        let food = [MenuItem::Fries, MenuItem::Fries];
+        let breakdown = Pricing::default()
+            .price(&food, None, NaiveDate::from_ymd_opt(2024, 1, 1).unwrap())
+            .unwrap();
+        assert_eq!(db.replace_items(id, 1, &food, &breakdown).unwrap(), 2);
+        let order = db.get_order(id).unwrap().unwrap();
+        assert_eq!(order.food, food);
+        assert_eq!(order.total, 10.0);
+        // The drink went back, and both portions of fries were taken
+        let stock = |ingredient: Ingredient| {
+            db.get_inventory()
+                .unwrap()
+                .into_iter()
+                .find(|level| level.ingredient == ingredient)
+                .unwrap()
+                .quantity
+        };
+        assert_eq!(stock(Ingredient::Fries), 0);
+        assert_eq!(stock(Ingredient::Drink), 1);
+
+        // A stale version, and more fries than there are, both change nothing
+        assert!(matches!(
+            db.replace_items(id, 1, &food, &breakdown),
+            Err(AspirinEatsError::PreconditionFailed)
+        ));
+        let more = [MenuItem::Fries, MenuItem::Fries, MenuItem::Fries];
+        assert!(matches!(
+            db.replace_items(id, 2, &more, &breakdown),
+            Err(AspirinEatsError::OutOfStock(Ingredient::Fries))
+        ));
+        assert_eq!(stock(Ingredient::Fries), 0);
+        assert_eq!(db.get_order_version(id).unwrap(), Some(2));
+
+        // Any other change moves the version on too
+        let mut order = db.get_order(id).unwrap().unwrap();
+        order.status = OrderStatus::Preparing;
+        db.update_order(&order).unwrap();
+        assert_eq!(db.get_order_version(id).unwrap(), Some(3));
+        assert!(matches!(
+            db.replace_items(id, 3, &food, &breakdown),
+            Err(AspirinEatsError::Conflict(_))
+        ));
+        assert_eq!(db.get_order_version(99).unwrap(), None);
+    }
 }
@@ -0,0 +1,308 @@
+use std::fmt;
+
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+
+use crate::error::AspirinEatsError;
+
+/// Mean radius of the Earth, for distances between coordinates
+const EARTH_RADIUS_KM: f64 = 6371.0;
+
+/// A point on the map, in degrees
+#[derive(Serialize, Deserialize, JsonSchema, Debug, PartialEq, Clone, Copy)]
+#[serde(deny_unknown_fields)]
+pub struct Location {
+    pub lat: f64,
+    pub lon: f64,
+}
+
+impl Location {
+    /// Distance to `other` in kilometres, as the crow flies
+    pub fn distance_km(&self, other: &Location) -> f64 {
+        let (lat1, lat2) = (self.lat.to_radians(), other.lat.to_radians());
+        let dlat = lat2 - lat1;
+        let dlon = (other.lon - self.lon).to_radians();
+        let a = (dlat / 2.0).sin().powi(2) + lat1.cos() * lat2.cos() * (dlon / 2.0).sin().powi(2);
+        2.0 * EARTH_RADIUS_KM * a.sqrt().asin()
+    }
+
+    /// Whether this is a real place: a latitude within ±90 and a longitude within ±180
+    pub fn is_valid(&self) -> bool {
+        (-90.0..=90.0).contains(&self.lat) && (-180.0..=180.0).contains(&self.lon)
+    }
+}
+
+/// Where an order is delivered to
+#[derive(Serialize, Deserialize, JsonSchema, Debug, PartialEq, Clone)]
+pub struct Address {
+    pub street: String,
+    pub city: String,
+    pub postcode: String,
+
+    /// Where the address is, which decides whether we deliver there and what it costs
+    pub location: Location,
+}
+
+impl Address {
+    /// Whether every part of the address is filled in
+    pub fn is_valid(&self) -> bool {
+        [&self.street, &self.city, &self.postcode]
+            .iter()
+            .all(|part| !part.trim().is_empty())
+            && self.location.is_valid()
+    }
+}
+
+impl fmt::Display for Address {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}, {} {}", self.street, self.city, self.postcode)
+    }
+}
+
+/// An area we deliver to
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone)]
+#[serde(tag = "shape", rename_all = "snake_case", deny_unknown_fields)]
+pub enum DeliveryZone {
+    /// Everywhere within `km` of the restaurant
+    Radius { name: String, km: f64 },
+
+    /// Everywhere inside the polygon with these corners, in order
+    Polygon { name: String, points: Vec<Location> },
+}
+
+impl DeliveryZone {
+    pub fn name(&self) -> &str {
+        match self {
+            DeliveryZone::Radius { name, .. } | DeliveryZone::Polygon { name, .. } => name,
+        }
+    }
+
+    /// Whether `location` is in this zone, for a restaurant at `restaurant`
+    pub fn contains(&self, restaurant: &Location, location: &Location) -> bool {
+        match self {
+            DeliveryZone::Radius { km, .. } => restaurant.distance_km(location) <= *km,
+            DeliveryZone::Polygon { points, .. } => polygon_contains(points, location),
+        }
+    }
+}
+
+/// Whether `location` is inside the polygon with corners `points`, by counting how many of its
+/// edges a line heading east from `location` crosses. Zones are small enough to treat latitude
+/// and longitude as flat
+fn polygon_contains(points: &[Location], location: &Location) -> bool {
+    let mut inside = false;
+    for (i, a) in points.iter().enumerate() {
+        let b = &points[(i + 1) % points.len()];
+        if (a.lat > location.lat) != (b.lat > location.lat) {
+            let crossing = a.lon + (location.lat - a.lat) / (b.lat - a.lat) * (b.lon - a.lon);
+            if location.lon < crossing {
+                inside = !inside;
+            }
+        }
+    }
+    inside
+}
+
+/// Where we deliver to, and what it costs
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub struct DeliveryPolicy {
+    /// Where deliveries leave from
+    pub restaurant: Location,
+
+    /// Areas we deliver to. An address has to be in at least one of them
+    pub zones: Vec<DeliveryZone>,
+
+    /// Charged for every delivery
+    pub base_fee: f64,
+
+    /// Charged for every kilometre between the restaurant and the address
+    pub fee_per_km: f64,
+}
+
+impl Default for DeliveryPolicy {
+    /// Anywhere within 5km of the restaurant, for $2 plus $0.50 a kilometre
+    fn default() -> Self {
+        DeliveryPolicy {
+            restaurant: Location {
+                lat: 42.2929,
+                lon: -71.2615,
+            },
+            zones: vec![DeliveryZone::Radius {
+                name: "Nearby".to_string(),
+                km: 5.0,
+            }],
+            base_fee: 2.0,
+            fee_per_km: 0.5,
+        }
+    }
+}
+
+impl DeliveryPolicy {
+    /// The zone `address` is in, or [`AspirinEatsError::OutsideDeliveryArea`] if it's in none
+    pub fn zone(&self, address: &Address) -> Result<&DeliveryZone, AspirinEatsError> {
+        self.zones
+            .iter()
+            .find(|zone| zone.contains(&self.restaurant, &address.location))
+            .ok_or_else(|| AspirinEatsError::OutsideDeliveryArea(address.to_string()))
+    }
+
+    /// What delivering to `address` costs, before rounding to the cent. Fails if we don't
+    /// deliver there
+    pub fn fee(&self, address: &Address) -> Result<f64, AspirinEatsError> {
+        self.zone(address)?;
+        Ok(self.base_fee + self.fee_per_km * self.restaurant.distance_km(&address.location))
+    }
+
+    /// Problems with the policy, for config validation
+    pub fn problems(&self) -> Vec<String> {
+        let mut problems = Vec::new();
+        if !self.restaurant.is_valid() {
+            problems.push("delivery.restaurant: not a valid location".to_string());
+        }
+        for (key, fee) in [
+            ("delivery.base_fee", self.base_fee),
+            ("delivery.fee_per_km", self.fee_per_km),
+        ] {
+            if !fee.is_finite() || fee < 0.0 {
+                problems.push(format!("{key}: must not be negative"));
+            }
+        }
+        for zone in &self.zones {
+            let name = zone.name();
+            match zone {
+                DeliveryZone::Radius { km, .. } if !km.is_finite() || *km <= 0.0 => {
+                    problems.push(format!("delivery.zones: {name:?} must have a positive km"));
+                }
+                DeliveryZone::Polygon { points, .. } if points.len() < 3 => {
+                    problems.push(format!("delivery.zones: {name:?} needs at least 3 points"));
+                }
+                DeliveryZone::Polygon { points, .. } if !points.iter().all(Location::is_valid) => {
+                    problems.push(format!("delivery.zones: {name:?} has an invalid point"));
+                }
+                _ => {}
+            }
+        }
+        problems
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn location(lat: f64, lon: f64) -> Location {
+        Location { lat, lon }
+    }
+
+    fn address(lat: f64, lon: f64) -> Address {
+        Address {
+            street: "1000 Olin Way".to_string(),
+            city: "Needham".to_string(),
+            postcode: "02492".to_string(),
+            location: location(lat, lon),
+        }
+    }
+
+    fn get_test_policy() -> DeliveryPolicy {
+        DeliveryPolicy {
+            restaurant: location(0.0, 0.0),
+            zones: vec![
+                DeliveryZone::Radius {
+                    name: "Nearby".to_string(),
+                    km: 10.0,
+                },
+                // A square to the north east, a little over 1° on each side
+                DeliveryZone::Polygon {
+                    name: "Uptown".to_string(),
+                    points: vec![
+                        location(0.5, 0.5),
+                        location(0.5, 1.6),
+                        location(1.6, 1.6),
+                        location(1.6, 0.5),
+                    ],
+                },
+            ],
+            base_fee: 2.0,
+            fee_per_km: 0.1,
+        }
+    }
+
+    #[test]
+    fn test_distance() {
+        // A degree of longitude at the equator
+        let distance = location(0.0, 0.0).distance_km(&location(0.0, 1.0));
+        assert!((distance - 111.19).abs() < 0.01, "{distance}");
+        assert_eq!(
+            location(42.0, -71.0).distance_km(&location(42.0, -71.0)),
+            0.0
+        );
+    }
+
+    #[test]
+    fn test_zones() {
+        let policy = get_test_policy();
+        assert_eq!(policy.zone(&address(0.0, 0.05)).unwrap().name(), "Nearby");
+        assert_eq!(policy.zone(&address(1.0, 1.0)).unwrap().name(), "Uptown");
+
+        // Between the two, and on the far side of the restaurant
+        for (lat, lon) in [(0.3, 0.3), (-1.0, -1.0), (1.0, 2.0)] {
+            let err = policy.zone(&address(lat, lon)).unwrap_err();
+            assert!(matches!(err, AspirinEatsError::OutsideDeliveryArea(_)));
+        }
+    }
+
+    #[test]
+    fn test_fee() {
+        let policy = get_test_policy();
+        assert_eq!(policy.fee(&address(0.0, 0.0)).unwrap(), 2.0);
+
+        // A degree of longitude is 111.19km
+        let fee = policy.fee(&address(0.0, 0.05)).unwrap();
+        assert!((fee - (2.0 + 0.556)).abs() < 0.001, "{fee}");
+
+        assert_eq!(
+            policy.fee(&address(-1.0, -1.0)).unwrap_err().to_string(),
+            "Outside delivery area: 1000 Olin Way, Needham 02492"
+        );
+    }
+
+    #[test]
+    fn test_address_is_valid() {
+        assert!(address(42.0, -71.0).is_valid());
+        assert!(!address(91.0, -71.0).is_valid());
+        assert!(!Address {
+            street: " ".to_string(),
+            ..address(42.0, -71.0)
+        }
+        .is_valid());
+    }
+
+    #[test]
+    fn test_problems() {
+        assert_eq!(DeliveryPolicy::default().problems(), Vec::<String>::new());
+
+        let policy = DeliveryPolicy {
+            base_fee: -1.0,
+            zones: vec![
+                DeliveryZone::Radius {
+                    name: "Nowhere".to_string(),
+                    km: 0.0,
+                },
+                DeliveryZone::Polygon {
+                    name: "Line".to_string(),
+                    points: vec![location(0.0, 0.0), location(1.0, 1.0)],
+                },
+            ],
+            ..get_test_policy()
+        };
+        assert_eq!(
+            policy.problems(),
+            vec![
+                "delivery.base_fee: must not be negative",
+                "delivery.zones: \"Nowhere\" must have a positive km",
+                "delivery.zones: \"Line\" needs at least 3 points",
+            ]
+        );
+    }
+}
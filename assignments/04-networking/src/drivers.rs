@@ -0,0 +1,170 @@
+use std::time::Duration;
+
+use display_json::DisplayAsJson;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+
+use crate::delivery::Location;
+
+/// Someone who delivers orders
+#[derive(Serialize, Deserialize, JsonSchema, DisplayAsJson, Debug, PartialEq, Clone)]
+pub struct Driver {
+    /// Driver ID (unique). Generated by the SQL database
+    pub id: Option<i64>,
+
+    pub name: String,
+
+    /// Whether the driver is working, and so can be given deliveries
+    pub on_shift: bool,
+
+    /// Where the driver last said they were, or delivered an order to
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub location: Option<Location>,
+
+    /// ID of the order the driver is delivering, if any
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub delivering: Option<i64>,
+}
+
+impl Driver {
+    /// Whether the driver can be given a delivery
+    pub fn is_free(&self) -> bool {
+        self.on_shift && self.delivering.is_none()
+    }
+}
+
+/// Body of `POST /drivers`
+#[derive(Serialize, Deserialize, JsonSchema, Debug, PartialEq, Clone)]
+pub struct DriverRequest {
+    pub name: String,
+}
+
+/// Body of `POST /drivers/{id}/shift`, which may be left out
+#[derive(Serialize, Deserialize, JsonSchema, Debug, PartialEq, Clone, Default)]
+pub struct ShiftRequest {
+    /// Where the driver is starting from
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub location: Option<Location>,
+}
+
+/// Body of `POST /drivers/{id}/claim`
+#[derive(Serialize, Deserialize, JsonSchema, Debug, PartialEq, Clone)]
+pub struct ClaimRequest {
+    /// ID of the order to deliver
+    pub order: i64,
+}
+
+/// An order handed to a driver by the dispatcher
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone)]
+pub struct Assignment {
+    pub order_id: i64,
+    pub driver_id: i64,
+}
+
+/// Match orders that are ready to go (oldest first) with free drivers. Each order goes to the
+/// free driver nearest `restaurant`, where it's picked up from. Drivers who haven't said where
+/// they are come last, and ties go to the driver who signed up first
+pub fn assign(ready: &[i64], drivers: &[Driver], restaurant: &Location) -> Vec<Assignment> {
+    let mut free: Vec<(f64, i64)> = drivers
+        .iter()
+        .filter(|driver| driver.is_free())
+        .filter_map(|driver| {
+            let distance = driver
+                .location
+                .map_or(f64::INFINITY, |location| location.distance_km(restaurant));
+            Some((distance, driver.id?))
+        })
+        .collect();
+    free.sort_by(|a, b| a.0.total_cmp(&b.0).then(a.1.cmp(&b.1)));
+
+    ready
+        .iter()
+        .zip(free)
+        .map(|(order_id, (_, driver_id))| Assignment {
+            order_id: *order_id,
+            driver_id,
+        })
+        .collect()
+}
+
+/// How often the dispatcher hands ready orders to drivers
+#[derive(Deserialize, Debug, PartialEq, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub struct DispatchPolicy {
+    /// Whether orders are handed out automatically. Drivers can always claim them themselves
+    pub enabled: bool,
+
+    pub tick_ms: u64,
+}
+
+impl Default for DispatchPolicy {
+    fn default() -> Self {
+        DispatchPolicy {
+            enabled: true,
+            tick_ms: 1000,
+        }
+    }
+}
+
+impl DispatchPolicy {
+    pub fn tick(&self) -> Duration {
+        Duration::from_millis(self.tick_ms)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn driver(id: i64, location: Option<(f64, f64)>) -> Driver {
+        Driver {
+            id: Some(id),
+            name: format!("Driver {id}"),
+            on_shift: true,
+            location: location.map(|(lat, lon)| Location { lat, lon }),
+            delivering: None,
+        }
+    }
+
+    fn assignment(order_id: i64, driver_id: i64) -> Assignment {
+        Assignment {
+            order_id,
+            driver_id,
+        }
+    }
+
+    #[test]
+    fn test_nearest_free_driver_gets_the_oldest_order() {
+        let restaurant = Location { lat: 0.0, lon: 0.0 };
+        let drivers = vec![
+            driver(1, Some((0.0, 0.2))),
+            driver(2, None),
+            driver(3, Some((0.0, 0.1))),
+            Driver {
+                on_shift: false,
+                ..driver(4, Some((0.0, 0.0)))
+            },
+            Driver {
+                delivering: Some(9),
+                ..driver(5, Some((0.0, 0.0)))
+            },
+            driver(6, Some((0.15, 0.0))),
+        ];
+
+        assert_eq!(
+            assign(&[3, 7, 8, 10], &drivers, &restaurant),
+            vec![
+                assignment(3, 3),
+                assignment(7, 6),
+                assignment(8, 1),
+                assignment(10, 2)
+            ]
+        );
+        assert_eq!(
+            assign(&[3], &drivers[..2], &restaurant),
+            vec![assignment(3, 1)]
+        );
+        assert_eq!(assign(&[], &drivers, &restaurant), vec![]);
+        assert_eq!(assign(&[3], &drivers[3..5], &restaurant), vec![]);
+    }
+}
@@ -25,4 +25,58 @@ pub enum AspirinEatsError {
     /// Error when request is for an HTTP method not supported on that path
     #[error("Method not allowed")]
     MethodNotAllowed,
+
+    /// Error when a request's headers or body are bigger than the server allows
+    #[error("Request too large")]
+    RequestTooLarge,
+
+    /// Error when an order asks for a coupon that is unknown or has expired
+    #[error("Invalid coupon: {0}")]
+    InvalidCoupon(String),
+
+    /// Error setting up TLS or during a TLS handshake
+    #[error("TLS error: {0}")]
+    Tls(#[from] rustls::Error),
+
+    /// Error when an order needs more of an ingredient than is in stock
+    #[error("Out of stock: {}", .0.name())]
+    OutOfStock(crate::food::Ingredient),
+
+    /// Error when an order is to be delivered to an address outside every delivery zone
+    #[error("Outside delivery area: {0}")]
+    OutsideDeliveryArea(String),
+
+    /// Error when an order is scheduled for a time that has passed or when we're closed
+    #[error("Can't schedule order: {0}")]
+    InvalidSchedule(String),
+
+    /// Error when a request's `If-Match` doesn't match what it would change, because it has
+    /// changed since the client fetched it
+    #[error("Precondition failed: it has changed since it was fetched")]
+    PreconditionFailed,
+
+    /// Error when a request that changes something doesn't say which version it expects to change
+    #[error("Precondition required: send If-Match with the ETag it was fetched with")]
+    PreconditionRequired,
+
+    /// Error when a request can't be done in the state something is in, e.g. claiming a
+    /// delivery for a driver who's off shift
+    #[error("{0}")]
+    Conflict(String),
+
+    /// Error loading or validating a server's configuration
+    #[error("Invalid configuration: {0}")]
+    Config(String),
+
+    /// Error when a server sends back something that isn't an HTTP Response
+    #[error("Invalid Response")]
+    InvalidResponse,
+
+    /// Error when a server answers a request with an error status
+    #[error("Server responded with {status}: {message}")]
+    Response { status: u16, message: String },
+
+    /// Error forwarding a request to the origin server
+    #[error("Upstream error: {0}")]
+    Upstream(#[from] crate::upstream::UpstreamError),
 }
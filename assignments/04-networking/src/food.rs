@@ -1,8 +1,15 @@
+use chrono::{DateTime, Local};
 use display_json::{DisplayAsJson, FromStrAsJson};
+use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
 
+use crate::delivery::Address;
+use crate::pricing::{PriceBreakdown, Pricing};
+
 /// Struct that represents an order
-#[derive(Serialize, Deserialize, DisplayAsJson, FromStrAsJson, Debug, PartialEq, Clone)]
+#[derive(
+    Serialize, Deserialize, JsonSchema, DisplayAsJson, FromStrAsJson, Debug, PartialEq, Clone,
+)]
 pub struct Order {
     /// Order ID (unique). Should be generated by the SQL database
     pub id: Option<i64>,
@@ -18,35 +25,114 @@ pub struct Order {
 
     /// Total price of the order
     pub total: f64,
+
+    /// Itemized breakdown of how the total was reached
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub breakdown: Option<PriceBreakdown>,
+
+    /// Where to deliver the order, or None if it's being picked up
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub delivery: Option<Address>,
+
+    /// ID of the driver taking the order out, once one has it
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub driver: Option<i64>,
+
+    /// When the customer wants the order ready, if they placed it ahead of time
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub scheduled_for: Option<DateTime<Local>>,
 }
 
 /// Struct that represents an incoming order request to be added to the database. Separate from the
 /// Order struct because many of the fields will be generated for new orders
-#[derive(Deserialize, FromStrAsJson)]
+#[derive(Serialize, Deserialize, JsonSchema, DisplayAsJson, FromStrAsJson)]
 pub struct OrderRequest {
     /// Customer Name
     pub customer: String,
 
     /// Vec of all the food items in the order
     pub food: Vec<MenuItem>,
+
+    /// Coupon code to redeem, if any
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub coupon: Option<String>,
+
+    /// Where to deliver the order, or None if it's being picked up
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub delivery: Option<Address>,
+
+    /// When the order should be ready, or None for as soon as possible. Has to be in the future
+    /// and while we're open
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub scheduled_for: Option<DateTime<Local>>,
+}
+
+/// Struct that represents a request to replace the food in an order that hasn't been started yet
+#[derive(Serialize, Deserialize, JsonSchema, DisplayAsJson, FromStrAsJson)]
+pub struct ItemsRequest {
+    /// Vec of all the food items the order should have
+    pub food: Vec<MenuItem>,
+
+    /// Coupon code to redeem on the new total, if any
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub coupon: Option<String>,
+}
+
+/// Response to `POST /orders/batch`
+#[derive(Serialize, Deserialize, JsonSchema, DisplayAsJson, Debug, PartialEq, Clone)]
+pub struct BatchReport {
+    /// IDs of the orders that were placed, in the order they were given
+    pub created: Vec<i64>,
+
+    /// Orders that couldn't be placed
+    pub failures: Vec<BatchFailure>,
+}
+
+/// An order in a batch that couldn't be placed
+#[derive(Serialize, Deserialize, JsonSchema, Debug, PartialEq, Clone)]
+pub struct BatchFailure {
+    /// Position of the order in the batch, starting at 0
+    pub index: usize,
+
+    /// Status code the order would have been refused with on its own
+    pub status: u16,
+
+    pub message: String,
 }
 
 impl From<OrderRequest> for Order {
-    /// Create an Order from an OrderRequest by filling in the ID, status, and total fields
+    /// Create an Order from an OrderRequest, priced with the default pricing as of today.
+    /// Coupons can fail to redeem and addresses can be out of the delivery area, so coupons and
+    /// delivery addresses are only honoured through [`Pricing::order`]
     fn from(order_request: OrderRequest) -> Self {
-        Order {
-            id: None,
-            customer: order_request.customer,
-            status: OrderStatus::Pending,
-            total: order_request.food.iter().map(|item| item.price()).sum(),
-            food: order_request.food,
-        }
+        let order_request = OrderRequest {
+            coupon: None,
+            delivery: None,
+            ..order_request
+        };
+        Pricing::default()
+            .order(order_request, Local::now().date_naive())
+            .expect("pricing without a coupon or delivery cannot fail")
     }
 }
 
-/// Enum that represents the status of an order
-#[derive(Serialize, Deserialize, DisplayAsJson, FromStrAsJson, Debug, PartialEq, Clone)]
+/// Struct that represents a customer. Customers are created the first time they place an order
+#[derive(Serialize, Deserialize, JsonSchema, DisplayAsJson, Debug, PartialEq, Clone)]
+pub struct Customer {
+    /// Customer ID (unique). Should be generated by the SQL database
+    pub id: Option<i64>,
+
+    /// Customer Name, with surrounding whitespace trimmed
+    pub name: String,
+}
+
+/// Enum that represents the status of an order. Scheduled orders were placed ahead of time, and
+/// are kept from the kitchen until shortly before they're wanted
+#[derive(
+    Serialize, Deserialize, JsonSchema, DisplayAsJson, FromStrAsJson, Debug, PartialEq, Clone,
+)]
 pub enum OrderStatus {
+    Scheduled,
     Pending,
     Preparing,
     Transporting,
@@ -54,8 +140,24 @@ pub enum OrderStatus {
     Cancelled,
 }
 
+impl OrderStatus {
+    /// Status of a new order, which waits as Scheduled if it's wanted at a later time
+    pub fn placed(scheduled_for: Option<&DateTime<Local>>) -> Self {
+        match scheduled_for {
+            Some(_) => OrderStatus::Scheduled,
+            None => OrderStatus::Pending,
+        }
+    }
+
+    /// Whether the kitchen has yet to start on the order, so it can still be changed and its
+    /// ingredients haven't been used
+    pub fn is_waiting(&self) -> bool {
+        matches!(self, OrderStatus::Scheduled | OrderStatus::Pending)
+    }
+}
+
 /// Enum that represents a particular menu item
-#[derive(Serialize, Deserialize, DisplayAsJson, Debug, PartialEq, Clone)]
+#[derive(Serialize, Deserialize, JsonSchema, DisplayAsJson, Debug, PartialEq, Clone)]
 pub enum MenuItem {
     Burger(Burger),
     Fries,
@@ -63,17 +165,57 @@ pub enum MenuItem {
 }
 
 impl MenuItem {
-    fn price(&self) -> f64 {
+    /// Price of this item on its own
+    pub fn price(&self) -> f64 {
         match self {
             MenuItem::Burger(burger) => burger.price(),
             MenuItem::Fries => 5.0,
             MenuItem::Drink => 3.0,
         }
     }
+
+    /// Everything that goes into making this item, one entry per unit of stock used
+    pub fn ingredients(&self) -> Vec<Ingredient> {
+        match self {
+            MenuItem::Burger(burger) => {
+                let mut ingredients = vec![
+                    Ingredient::Bun(burger.bun.clone()),
+                    Ingredient::Patty(burger.patty.clone()),
+                ];
+                ingredients.extend(burger.toppings.iter().cloned().map(Ingredient::Topping));
+                ingredients
+            }
+            MenuItem::Fries => vec![Ingredient::Fries],
+            MenuItem::Drink => vec![Ingredient::Drink],
+        }
+    }
+}
+
+/// Enum that represents something we keep stock of
+#[derive(Serialize, Deserialize, JsonSchema, DisplayAsJson, Debug, PartialEq, Clone)]
+pub enum Ingredient {
+    Bun(Bun),
+    Patty(Patty),
+    Topping(Topping),
+    Fries,
+    Drink,
+}
+
+impl Ingredient {
+    /// Name of the ingredient for people, e.g. "GlutenFree bun"
+    pub fn name(&self) -> String {
+        match self {
+            Ingredient::Bun(bun) => format!("{bun:?} bun"),
+            Ingredient::Patty(patty) => format!("{patty:?} patty"),
+            Ingredient::Topping(topping) => format!("{topping:?}"),
+            Ingredient::Fries => "Fries".to_string(),
+            Ingredient::Drink => "Drink".to_string(),
+        }
+    }
 }
 
 /// Struct that represents a burger
-#[derive(Serialize, Deserialize, DisplayAsJson, Debug, PartialEq, Clone)]
+#[derive(Serialize, Deserialize, JsonSchema, DisplayAsJson, Debug, PartialEq, Clone)]
 pub struct Burger {
     bun: Bun,
     patty: Patty,
@@ -89,6 +231,14 @@ impl Burger {
         }
     }
 
+    pub fn patty(&self) -> &Patty {
+        &self.patty
+    }
+
+    pub fn toppings(&self) -> &[Topping] {
+        &self.toppings
+    }
+
     fn price(&self) -> f64 {
         self.bun.price()
             + self.patty.price()
@@ -101,7 +251,7 @@ impl Burger {
 }
 
 /// Enum that represents a type of bun
-#[derive(Serialize, Deserialize, DisplayAsJson, Debug, PartialEq, Clone)]
+#[derive(Serialize, Deserialize, JsonSchema, DisplayAsJson, Debug, PartialEq, Clone)]
 pub enum Bun {
     Sesame,
     Plain,
@@ -109,7 +259,11 @@ pub enum Bun {
 }
 
 impl Bun {
-    fn price(&self) -> f64 {
+    /// Every bun on the menu
+    pub const ALL: [Bun; 3] = [Bun::Plain, Bun::Sesame, Bun::GlutenFree];
+
+    /// Price of this bun
+    pub fn price(&self) -> f64 {
         match self {
             Bun::Sesame => 1.0,
             Bun::Plain => 0.0,
@@ -119,7 +273,7 @@ impl Bun {
 }
 
 /// Enum that represents a type of patty
-#[derive(Serialize, Deserialize, DisplayAsJson, Debug, PartialEq, Clone)]
+#[derive(Serialize, Deserialize, JsonSchema, DisplayAsJson, Debug, PartialEq, Clone)]
 pub enum Patty {
     Beef,
     Chicken,
@@ -127,7 +281,11 @@ pub enum Patty {
 }
 
 impl Patty {
-    fn price(&self) -> f64 {
+    /// Every patty on the menu
+    pub const ALL: [Patty; 3] = [Patty::Beef, Patty::Chicken, Patty::Veggie];
+
+    /// Price of this patty
+    pub fn price(&self) -> f64 {
         match self {
             Patty::Beef => 8.0,
             Patty::Chicken => 7.0,
@@ -137,7 +295,7 @@ impl Patty {
 }
 
 /// Enum that represents a type of topping
-#[derive(Serialize, Deserialize, DisplayAsJson, Debug, PartialEq, Clone)]
+#[derive(Serialize, Deserialize, JsonSchema, DisplayAsJson, Debug, PartialEq, Clone)]
 pub enum Topping {
     Lettuce,
     Tomato,
@@ -148,7 +306,18 @@ pub enum Topping {
 }
 
 impl Topping {
-    fn price(&self) -> f64 {
+    /// Every topping on the menu
+    pub const ALL: [Topping; 6] = [
+        Topping::Lettuce,
+        Topping::Tomato,
+        Topping::Onion,
+        Topping::Pickle,
+        Topping::Cheese,
+        Topping::Bacon,
+    ];
+
+    /// Price of this topping
+    pub fn price(&self) -> f64 {
         match self {
             Topping::Lettuce => 0.0,
             Topping::Tomato => 0.0,
@@ -187,17 +356,27 @@ mod tests {
                 MenuItem::Fries,
                 MenuItem::Drink,
             ],
+            coupon: None,
+            delivery: None,
+            scheduled_for: None,
         };
         let order = Order::from(order_request);
+        let breakdown = order.breakdown.clone().unwrap();
         assert_eq!(
             order,
             Order {
                 id: None,
                 customer: "Alice".to_string(),
                 status: OrderStatus::Pending,
-                total: 20.0,
+                total: 18.0,
                 food,
+                breakdown: Some(breakdown.clone()),
+                delivery: None,
+                driver: None,
+                scheduled_for: None,
             }
         );
+        assert_eq!(breakdown.subtotal, 20.0);
+        assert_eq!(breakdown.discounts[0].amount, 2.0);
     }
 }
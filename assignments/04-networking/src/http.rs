@@ -1,9 +1,18 @@
+use std::hash::{DefaultHasher, Hash, Hasher};
+use std::io::{BufRead, BufReader, ErrorKind, Read};
 use std::{fmt::Display, str::FromStr};
 
+use chrono::{DateTime, NaiveDateTime, Utc};
+use serde::Deserialize;
+
 use crate::error::AspirinEatsError;
+use crate::upstream::UpstreamError;
+
+/// How times are written in HTTP headers
+const HTTP_DATE_FORMAT: &str = "%a, %d %b %Y %H:%M:%S GMT";
 
 /// Simple wrapper for an HTTP Request
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct HttpRequest {
     /// The HTTP method used in the request (GET, POST, etc)
     pub method: Option<String>,
@@ -11,6 +20,9 @@ pub struct HttpRequest {
     /// The path requested by the client
     pub path: Option<String>,
 
+    /// Headers sent by the client, in the order they were sent
+    pub headers: Vec<(String, String)>,
+
     /// The body of the request
     pub body: Option<String>,
 }
@@ -20,13 +32,218 @@ impl FromStr for HttpRequest {
 
     // Parse a string into an HTTP Request
     fn from_str(s: &str) -> Result<Self, Self::Err> {
-        todo!()
+        let (head, body) = s.split_once("\r\n\r\n").unwrap_or((s, ""));
+        Ok(HttpRequest {
+            body: (!body.is_empty()).then(|| body.to_string()),
+            ..parse_head(head)?
+        })
+    }
+}
+
+/// Parse the request line and headers of a request, without its body. Lines may end in `\r\n` or
+/// a bare `\n`
+fn parse_head(head: &str) -> Result<HttpRequest, AspirinEatsError> {
+    let request_line = head
+        .lines()
+        .next()
+        .ok_or(AspirinEatsError::InvalidRequest)?;
+
+    let mut parts = request_line.split_whitespace();
+    let (Some(method), Some(path), Some(version), None) =
+        (parts.next(), parts.next(), parts.next(), parts.next())
+    else {
+        return Err(AspirinEatsError::InvalidRequest);
+    };
+    if !version.starts_with("HTTP/") || !path.starts_with('/') {
+        return Err(AspirinEatsError::InvalidRequest);
+    }
+
+    let headers = head
+        .lines()
+        .skip(1)
+        .map(|line| {
+            let (name, value) = line.split_once(':')?;
+            Some((name.trim().to_string(), value.trim().to_string()))
+        })
+        .collect::<Option<Vec<_>>>()
+        .ok_or(AspirinEatsError::InvalidRequest)?;
+
+    Ok(HttpRequest {
+        method: Some(method.to_string()),
+        path: Some(path.to_string()),
+        headers,
+        body: None,
+    })
+}
+
+impl Display for HttpRequest {
+    /// Convert an HttpRequest struct back into a valid HTTP Request. `Content-Length` is always
+    /// recomputed from the body
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let body = self.body.as_deref().unwrap_or("");
+        write!(
+            f,
+            "{} {} HTTP/1.1\r\n",
+            self.method.as_deref().unwrap_or("GET"),
+            self.path.as_deref().unwrap_or("/"),
+        )?;
+        for (name, value) in &self.headers {
+            if !name.eq_ignore_ascii_case("content-length") {
+                write!(f, "{name}: {value}\r\n")?;
+            }
+        }
+        write!(f, "Content-Length: {}\r\n\r\n{}", body.len(), body)
     }
 }
 
+impl HttpRequest {
+    /// Get the value of a header, ignoring case in the header name
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(header, _)| header.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.as_str())
+    }
+
+    /// Set a header, replacing any existing headers with the same name
+    pub fn set_header(&mut self, name: &str, value: &str) {
+        self.headers
+            .retain(|(header, _)| !header.eq_ignore_ascii_case(name));
+        self.headers.push((name.to_string(), value.to_string()));
+    }
+
+    /// The request path without any query string
+    pub fn route(&self) -> &str {
+        let path = self.path.as_deref().unwrap_or("/");
+        path.split_once('?').map_or(path, |(route, _query)| route)
+    }
+
+    /// Get the (percent-decoded) value of a parameter in the query string
+    pub fn query(&self, name: &str) -> Option<String> {
+        let (_route, query) = self.path.as_deref()?.split_once('?')?;
+        query
+            .split('&')
+            .filter_map(|pair| pair.split_once('=').or(Some((pair, ""))))
+            .find(|(key, _)| percent_decode(key) == name)
+            .map(|(_, value)| percent_decode(value))
+    }
+}
+
+/// Decode `%XX` escapes in a path segment. Unlike in a query string, `+` is just a plus
+pub fn percent_decode_path(s: &str) -> String {
+    percent_decode(&s.replace('+', "%2B"))
+}
+
+/// Decode `+` and `%XX` escapes in a query string component. Invalid escapes are left as-is
+fn percent_decode(s: &str) -> String {
+    let bytes = s.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        match bytes[i] {
+            b'+' => decoded.push(b' '),
+            b'%' => match s
+                .get(i + 1..i + 3)
+                .and_then(|hex| u8::from_str_radix(hex, 16).ok())
+            {
+                Some(byte) => {
+                    decoded.push(byte);
+                    i += 2;
+                }
+                None => decoded.push(b'%'),
+            },
+            byte => decoded.push(byte),
+        }
+        i += 1;
+    }
+    String::from_utf8_lossy(&decoded).into_owned()
+}
+
+/// Limits on the size of the requests a server is willing to read
+#[derive(Deserialize, Debug, PartialEq, Clone, Copy)]
+#[serde(default, deny_unknown_fields)]
+pub struct RequestLimits {
+    /// Longest request line and headers, in bytes
+    pub max_header_bytes: usize,
+
+    /// Longest body, in bytes
+    pub max_body_bytes: usize,
+}
+
+impl Default for RequestLimits {
+    fn default() -> Self {
+        RequestLimits {
+            max_header_bytes: 8 * 1024,
+            max_body_bytes: 1024 * 1024,
+        }
+    }
+}
+
+/// Read a single HTTP request from a stream. The body is read according to the `Content-Length`
+/// header, so the client doesn't need to close its end of the connection first
+pub fn read_request<R: Read>(stream: R) -> Result<HttpRequest, AspirinEatsError> {
+    read_request_with_limits(stream, RequestLimits::default())
+}
+
+/// Read a single HTTP request from a stream, refusing any request larger than `limits` allow
+pub fn read_request_with_limits<R: Read>(
+    stream: R,
+    limits: RequestLimits,
+) -> Result<HttpRequest, AspirinEatsError> {
+    let mut reader = BufReader::new(stream);
+    let mut head = String::new();
+    let mut content_length = 0;
+
+    loop {
+        let mut line = String::new();
+        // Read at most one byte past the limit, so an endless line can't use up all our memory
+        let remaining = limits.max_header_bytes.saturating_sub(head.len()) as u64;
+        let limit = remaining.saturating_add(1);
+        if (&mut reader).take(limit).read_line(&mut line)? == 0 {
+            return Err(AspirinEatsError::InvalidRequest);
+        }
+        if head.len() + line.len() > limits.max_header_bytes {
+            return Err(AspirinEatsError::RequestTooLarge);
+        }
+        if line == "\r\n" || line == "\n" {
+            break;
+        }
+        if let Some((name, value)) = line.split_once(':') {
+            if name.trim().eq_ignore_ascii_case("content-length") {
+                content_length = value
+                    .trim()
+                    .parse()
+                    .map_err(|_| AspirinEatsError::InvalidRequest)?;
+            }
+        }
+        head.push_str(&line);
+    }
+
+    if content_length > limits.max_body_bytes {
+        return Err(AspirinEatsError::RequestTooLarge);
+    }
+    // Only keep what actually arrives, rather than allocating whatever the client claims up front
+    let mut body = Vec::new();
+    (&mut reader)
+        .take(content_length as u64)
+        .read_to_end(&mut body)?;
+    if body.len() < content_length {
+        return Err(std::io::Error::from(ErrorKind::UnexpectedEof).into());
+    }
+    let body = String::from_utf8(body).map_err(|_| AspirinEatsError::InvalidRequest)?;
+
+    // The head is parsed by itself, so a blank line in the body can't be mistaken for its end
+    Ok(HttpRequest {
+        body: (!body.is_empty()).then_some(body),
+        ..parse_head(&head)?
+    })
+}
+
+#[derive(Debug, PartialEq)]
 pub struct HttpResponse {
     status_code: u16,
     status_text: String,
+    headers: Vec<(String, String)>,
     body: String,
 }
 
@@ -35,27 +252,324 @@ impl HttpResponse {
         HttpResponse {
             status_code,
             status_text: status_text.to_string(),
+            headers: Vec::new(),
             body: body.to_string(),
         }
     }
+
+    /// Add a header to the response
+    pub fn with_header(mut self, name: &str, value: &str) -> Self {
+        self.headers.push((name.to_string(), value.to_string()));
+        self
+    }
+
+    /// Get the value of a header, ignoring case in the header name
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(header, _)| header.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.as_str())
+    }
+
+    /// Drop the body, as in a response to `HEAD`. `Content-Length` still gives the length of the
+    /// body that was dropped. A `304 Not Modified` never had one, so is left as it is
+    pub fn without_body(mut self) -> Self {
+        if self.status_code == 304 {
+            return self;
+        }
+        let length = self.body.len();
+        self.body.clear();
+        self.with_header("Content-Length", &length.to_string())
+    }
+
+    /// Answer a `GET` or `HEAD` conditionally. A successful response is tagged with an `ETag`
+    /// computed from its body, unless it already has one, and becomes `304 Not Modified` if the
+    /// request's `If-None-Match` (or, without one, `If-Modified-Since`) shows the client already
+    /// has it
+    pub fn conditional(self, request: &HttpRequest) -> Self {
+        if self.status_code != 200 {
+            return self;
+        }
+        let response = match self.header("ETag") {
+            Some(_) => self,
+            None => {
+                let etag = content_etag(&self.body);
+                self.with_header("ETag", &etag)
+            }
+        };
+
+        let etag = response.header("ETag").unwrap_or_default();
+        let not_modified = match request.header("If-None-Match") {
+            Some(header) => if_none_match(header, etag),
+            None => {
+                let since = request
+                    .header("If-Modified-Since")
+                    .and_then(parse_http_date);
+                let modified = response.header("Last-Modified").and_then(parse_http_date);
+                matches!((since, modified), (Some(since), Some(modified)) if modified <= since)
+            }
+        };
+        if !not_modified {
+            return response;
+        }
+
+        // Headers describing the body don't apply to a response without one
+        let headers = response
+            .headers
+            .into_iter()
+            .filter(|(name, _)| {
+                !["Content-Type", "Content-Length", "Content-Encoding"]
+                    .iter()
+                    .any(|header| name.eq_ignore_ascii_case(header))
+            })
+            .collect();
+        HttpResponse {
+            status_code: 304,
+            status_text: "Not Modified".to_string(),
+            headers,
+            body: String::new(),
+        }
+    }
+
+    pub fn status_code(&self) -> u16 {
+        self.status_code
+    }
+
+    pub fn body(&self) -> &str {
+        &self.body
+    }
+}
+
+impl FromStr for HttpResponse {
+    type Err = AspirinEatsError;
+
+    /// Parse a raw HTTP Response, as read by a client
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (head, body) = s.split_once("\r\n\r\n").unwrap_or((s, ""));
+        let mut lines = head.split("\r\n");
+        let status_line = lines.next().ok_or(AspirinEatsError::InvalidResponse)?;
+
+        let mut parts = status_line.splitn(3, ' ');
+        let (Some(version), Some(status_code), status_text) =
+            (parts.next(), parts.next(), parts.next())
+        else {
+            return Err(AspirinEatsError::InvalidResponse);
+        };
+        let status_code = status_code
+            .parse()
+            .map_err(|_| AspirinEatsError::InvalidResponse)?;
+        if !version.starts_with("HTTP/") {
+            return Err(AspirinEatsError::InvalidResponse);
+        }
+
+        let headers = lines
+            .map(|line| {
+                let (name, value) = line.split_once(':')?;
+                Some((name.trim().to_string(), value.trim().to_string()))
+            })
+            .collect::<Option<Vec<_>>>()
+            .ok_or(AspirinEatsError::InvalidResponse)?;
+
+        Ok(HttpResponse {
+            status_code,
+            status_text: status_text.unwrap_or_default().to_string(),
+            headers,
+            body: body.to_string(),
+        })
+    }
 }
 
 impl Display for HttpResponse {
     /// Convert an HttpResponse struct to a valid HTTP Response
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        todo!()
+        write!(f, "HTTP/1.1 {} {}\r\n", self.status_code, self.status_text)?;
+        for (name, value) in &self.headers {
+            write!(f, "{name}: {value}\r\n")?;
+        }
+        write!(f, "\r\n{}", self.body)
+    }
+}
+
+/// Whether an `If-Match` header lets a change go ahead on something whose entity tag is `etag`:
+/// it has to be `*` or list `etag`. Weak tags never match
+pub fn if_match(header: &str, etag: &str) -> bool {
+    header
+        .split(',')
+        .map(str::trim)
+        .any(|tag| tag == "*" || tag == etag)
+}
+
+/// Whether an `If-None-Match` header says the client already has what's tagged `etag`: it's `*`
+/// or lists `etag`. Unlike `If-Match`, whether either tag is weak doesn't matter
+pub fn if_none_match(header: &str, etag: &str) -> bool {
+    let opaque = |tag: &str| tag.trim().trim_start_matches("W/").to_string();
+    header
+        .split(',')
+        .any(|tag| tag.trim() == "*" || opaque(tag) == opaque(etag))
+}
+
+/// Strong entity tag for a body, which changes whenever the body does
+pub fn content_etag(body: &str) -> String {
+    let mut hasher = DefaultHasher::new();
+    body.hash(&mut hasher);
+    format!("\"{:016x}\"", hasher.finish())
+}
+
+/// Format a time the way HTTP headers like `Last-Modified` want it, e.g.
+/// `Sun, 06 Nov 1994 08:49:37 GMT`
+pub fn http_date(time: DateTime<Utc>) -> String {
+    time.format(HTTP_DATE_FORMAT).to_string()
+}
+
+/// Parse a time from a header like `If-Modified-Since`. None if it isn't a valid HTTP date
+pub fn parse_http_date(value: &str) -> Option<DateTime<Utc>> {
+    NaiveDateTime::parse_from_str(value.trim(), HTTP_DATE_FORMAT)
+        .ok()
+        .map(|time| time.and_utc())
+}
+
+/// Value of the `Allow` header for a route that handles `methods`. `HEAD` is allowed wherever
+/// `GET` is, and `OPTIONS` everywhere
+pub fn allow_header(methods: &[&str]) -> String {
+    let mut allowed = methods.to_vec();
+    if methods.contains(&"GET") {
+        allowed.push("HEAD");
+    }
+    allowed.push("OPTIONS");
+    allowed.join(", ")
+}
+
+/// Which other sites' pages are allowed to call us from the browser
+#[derive(Deserialize, Debug, PartialEq, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub struct CorsPolicy {
+    /// Origins (e.g. `https://eats.example.com`) allowed to make requests, or `*` for any.
+    /// Empty turns CORS off
+    pub allowed_origins: Vec<String>,
+
+    /// Methods allowed in cross-origin requests
+    pub allowed_methods: Vec<String>,
+
+    /// Request headers allowed in cross-origin requests
+    pub allowed_headers: Vec<String>,
+
+    /// How long browsers may cache the answer to a preflight request
+    pub max_age_secs: u64,
+}
+
+impl Default for CorsPolicy {
+    fn default() -> Self {
+        CorsPolicy {
+            allowed_origins: vec![],
+            allowed_methods: ["GET", "POST", "DELETE"].map(String::from).to_vec(),
+            allowed_headers: ["Content-Type", "X-Request-Id"].map(String::from).to_vec(),
+            max_age_secs: 600,
+        }
+    }
+}
+
+impl CorsPolicy {
+    /// Whether `request` is a CORS preflight, i.e. a browser asking whether it may send the
+    /// real request
+    pub fn is_preflight(request: &HttpRequest) -> bool {
+        request.method.as_deref() == Some("OPTIONS")
+            && request.header("Origin").is_some()
+            && request.header("Access-Control-Request-Method").is_some()
+    }
+
+    fn allows_origin(&self, origin: &str) -> bool {
+        self.allowed_origins
+            .iter()
+            .any(|allowed| allowed == "*" || allowed.eq_ignore_ascii_case(origin))
+    }
+
+    /// Add the CORS headers for `request` to its `response`. Requests without an `Origin`, or
+    /// from an origin that isn't allowed, get no CORS headers, so browsers will block them
+    pub fn apply(&self, request: &HttpRequest, mut response: HttpResponse) -> HttpResponse {
+        let Some(origin) = request.header("Origin") else {
+            return response;
+        };
+        if !self.allows_origin(origin) {
+            return response;
+        }
+
+        if self.allowed_origins.iter().any(|allowed| allowed == "*") {
+            response = response.with_header("Access-Control-Allow-Origin", "*");
+        } else {
+            response = response
+                .with_header("Access-Control-Allow-Origin", origin)
+                .with_header("Vary", "Origin");
+        }
+        if Self::is_preflight(request) {
+            response = response
+                .with_header(
+                    "Access-Control-Allow-Methods",
+                    &self.allowed_methods.join(", "),
+                )
+                .with_header(
+                    "Access-Control-Allow-Headers",
+                    &self.allowed_headers.join(", "),
+                )
+                .with_header("Access-Control-Max-Age", &self.max_age_secs.to_string());
+        }
+        response
     }
 }
 
 impl From<AspirinEatsError> for HttpResponse {
     /// Given an error type, convert it to an appropriate HTTP Response
     fn from(value: AspirinEatsError) -> Self {
-        todo!()
+        match value {
+            AspirinEatsError::ParseError(_)
+            | AspirinEatsError::InvalidRequest
+            | AspirinEatsError::InvalidCoupon(_) => {
+                HttpResponse::new(400, "Bad Request", &value.to_string())
+            }
+            AspirinEatsError::NotFound => HttpResponse::new(404, "Not Found", &value.to_string()),
+            AspirinEatsError::MethodNotAllowed => {
+                HttpResponse::new(405, "Method Not Allowed", &value.to_string())
+            }
+            AspirinEatsError::OutOfStock(_) | AspirinEatsError::Conflict(_) => {
+                HttpResponse::new(409, "Conflict", &value.to_string())
+            }
+            AspirinEatsError::OutsideDeliveryArea(_) | AspirinEatsError::InvalidSchedule(_) => {
+                HttpResponse::new(422, "Unprocessable Content", &value.to_string())
+            }
+            AspirinEatsError::PreconditionFailed => {
+                HttpResponse::new(412, "Precondition Failed", &value.to_string())
+            }
+            AspirinEatsError::PreconditionRequired => {
+                HttpResponse::new(428, "Precondition Required", &value.to_string())
+            }
+            AspirinEatsError::RequestTooLarge => {
+                HttpResponse::new(413, "Payload Too Large", &value.to_string())
+            }
+            AspirinEatsError::Database(_)
+            | AspirinEatsError::Io(_)
+            | AspirinEatsError::Tls(_)
+            | AspirinEatsError::Config(_) => {
+                HttpResponse::new(500, "Internal Server Error", "Internal Server Error")
+            }
+            AspirinEatsError::InvalidResponse | AspirinEatsError::Response { .. } => {
+                HttpResponse::new(502, "Bad Gateway", &value.to_string())
+            }
+            AspirinEatsError::Upstream(UpstreamError::Timeout) => {
+                HttpResponse::new(504, "Gateway Timeout", &value.to_string())
+            }
+            AspirinEatsError::Upstream(UpstreamError::CircuitOpen) => {
+                HttpResponse::new(503, "Service Unavailable", &value.to_string())
+            }
+            AspirinEatsError::Upstream(_) => {
+                HttpResponse::new(502, "Bad Gateway", &value.to_string())
+            }
+        }
     }
 }
 
 #[cfg(test)]
 mod tests {
+    use proptest::prelude::*;
+
     use super::*;
 
     #[test]
@@ -67,6 +581,277 @@ mod tests {
         assert_eq!(http_request.body, Some("this is the body.".to_string()));
     }
 
+    #[test]
+    fn test_http_request_headers() {
+        let mut request = HttpRequest::from_str(
+            "GET / HTTP/1.1\r\nHost: localhost:8080\r\nX-Request-Id:  abc \r\n\r\n",
+        )
+        .unwrap();
+        assert_eq!(request.header("host"), Some("localhost:8080"));
+        assert_eq!(request.header("x-request-id"), Some("abc"));
+
+        request.set_header("x-request-id", "def");
+        assert_eq!(
+            request.to_string(),
+            "GET / HTTP/1.1\r\nHost: localhost:8080\r\nx-request-id: def\r\nContent-Length: 0\r\n\r\n"
+        );
+        assert!(HttpRequest::from_str("GET / HTTP/1.1\r\nno colon\r\n\r\n").is_err());
+    }
+
+    #[test]
+    fn test_http_request_from_str_rejects_malformed_request_line() {
+        assert!(HttpRequest::from_str("").is_err());
+        assert!(HttpRequest::from_str("GET\r\n\r\n").is_err());
+        assert!(HttpRequest::from_str("GET /orders\r\n\r\n").is_err());
+        assert!(HttpRequest::from_str("GET orders HTTP/1.1\r\n\r\n").is_err());
+    }
+
+    #[test]
+    fn test_read_request_uses_content_length() {
+        let raw = "POST /orders?x=1 HTTP/1.1\r\nContent-Length: 4\r\n\r\nbodyextra";
+        let request = read_request(raw.as_bytes()).unwrap();
+        assert_eq!(request.body, Some("body".to_string()));
+        assert_eq!(request.route(), "/orders");
+    }
+
+    #[test]
+    fn test_read_request_limits() {
+        let limits = RequestLimits {
+            max_header_bytes: 50,
+            max_body_bytes: 4,
+        };
+        let raw = "POST /orders HTTP/1.1\r\nContent-Length: 4\r\n\r\nbody";
+        assert!(read_request_with_limits(raw.as_bytes(), limits).is_ok());
+
+        let raw = "POST /orders HTTP/1.1\r\nContent-Length: 5\r\n\r\nbodys";
+        assert!(matches!(
+            read_request_with_limits(raw.as_bytes(), limits),
+            Err(AspirinEatsError::RequestTooLarge)
+        ));
+
+        let raw = format!("GET /{} HTTP/1.1\r\n\r\n", "a".repeat(100));
+        assert!(matches!(
+            read_request_with_limits(raw.as_bytes(), limits),
+            Err(AspirinEatsError::RequestTooLarge)
+        ));
+    }
+
+    #[test]
+    fn test_read_request_body_with_blank_line() {
+        // Bare newlines in the head, and a body that looks like the end of one
+        let raw = "POST /orders HTTP/1.1\nContent-Length: 8\n\na\r\n\r\nb:c";
+        let request = read_request(raw.as_bytes()).unwrap();
+        assert_eq!(request.headers.len(), 1);
+        assert_eq!(request.body, Some("a\r\n\r\nb:c".to_string()));
+    }
+
+    #[test]
+    fn test_read_request_huge_limits() {
+        let limits = RequestLimits {
+            max_header_bytes: usize::MAX,
+            max_body_bytes: usize::MAX,
+        };
+        let raw = "POST /orders HTTP/1.1\r\nContent-Length: 18446744073709551615\r\n\r\nbody";
+        assert!(matches!(
+            read_request_with_limits(raw.as_bytes(), limits),
+            Err(AspirinEatsError::Io(_))
+        ));
+    }
+
+    #[test]
+    fn test_without_body() {
+        let response = HttpResponse::new(200, "OK", "hello").without_body();
+        assert_eq!(
+            response.to_string(),
+            "HTTP/1.1 200 OK\r\nContent-Length: 5\r\n\r\n"
+        );
+    }
+
+    #[test]
+    fn test_allow_header() {
+        assert_eq!(allow_header(&["GET", "POST"]), "GET, POST, HEAD, OPTIONS");
+        assert_eq!(allow_header(&["DELETE"]), "DELETE, OPTIONS");
+    }
+
+    #[test]
+    fn test_if_match() {
+        assert!(if_match("\"3\"", "\"3\""));
+        assert!(if_match("\"1\", \"3\"", "\"3\""));
+        assert!(if_match("*", "\"3\""));
+        assert!(!if_match("\"2\"", "\"3\""));
+        assert!(!if_match("W/\"3\"", "\"3\""));
+    }
+
+    #[test]
+    fn test_if_none_match() {
+        assert!(if_none_match("\"3\"", "\"3\""));
+        assert!(if_none_match("\"1\", W/\"3\"", "\"3\""));
+        assert!(if_none_match("*", "\"3\""));
+        assert!(!if_none_match("\"2\"", "\"3\""));
+    }
+
+    #[test]
+    fn test_http_date() {
+        let time = DateTime::parse_from_rfc3339("1994-11-06T08:49:37Z")
+            .unwrap()
+            .to_utc();
+        assert_eq!(http_date(time), "Sun, 06 Nov 1994 08:49:37 GMT");
+        assert_eq!(parse_http_date("Sun, 06 Nov 1994 08:49:37 GMT"), Some(time));
+        assert_eq!(parse_http_date("yesterday"), None);
+    }
+
+    fn get_conditional_request(header: &str) -> HttpRequest {
+        format!("GET /orders HTTP/1.1\r\n{header}\r\n\r\n")
+            .parse()
+            .unwrap()
+    }
+
+    #[test]
+    fn test_conditional_etag() {
+        let response =
+            || HttpResponse::new(200, "OK", "[]").with_header("Content-Type", "application/json");
+        let etag = content_etag("[]");
+        assert_ne!(etag, content_etag("[{}]"));
+
+        let tagged = response().conditional(&get_conditional_request("Accept: */*"));
+        assert_eq!(tagged.status_code(), 200);
+        assert_eq!(tagged.header("ETag"), Some(etag.as_str()));
+
+        let request = get_conditional_request(&format!("If-None-Match: {etag}"));
+        let not_modified = response().conditional(&request);
+        assert_eq!(
+            not_modified.to_string(),
+            format!("HTTP/1.1 304 Not Modified\r\nETag: {etag}\r\n\r\n")
+        );
+
+        // An ETag that's already set, like an order's version, is kept
+        let versioned = response().with_header("ETag", "\"4\"");
+        let request = get_conditional_request("If-None-Match: \"4\"");
+        assert_eq!(versioned.conditional(&request).status_code(), 304);
+
+        // Errors are never conditional
+        let request = get_conditional_request("If-None-Match: *");
+        let error = HttpResponse::new(404, "Not Found", "");
+        assert_eq!(error.conditional(&request).status_code(), 404);
+    }
+
+    #[test]
+    fn test_conditional_last_modified() {
+        let response = || {
+            HttpResponse::new(200, "OK", "[]")
+                .with_header("Last-Modified", "Sun, 06 Nov 1994 08:49:37 GMT")
+        };
+        let status = |header: &str| {
+            response()
+                .conditional(&get_conditional_request(header))
+                .status_code()
+        };
+
+        assert_eq!(
+            status("If-Modified-Since: Sun, 06 Nov 1994 08:49:37 GMT"),
+            304
+        );
+        assert_eq!(
+            status("If-Modified-Since: Mon, 07 Nov 1994 00:00:00 GMT"),
+            304
+        );
+        assert_eq!(
+            status("If-Modified-Since: Sun, 06 Nov 1994 08:49:36 GMT"),
+            200
+        );
+        assert_eq!(status("If-Modified-Since: whenever"), 200);
+
+        // If-None-Match wins when both are sent
+        assert_eq!(
+            status("If-None-Match: \"nope\"\r\nIf-Modified-Since: Mon, 07 Nov 1994 00:00:00 GMT"),
+            200
+        );
+    }
+
+    #[test]
+    fn test_cors_policy() {
+        let policy = CorsPolicy {
+            allowed_origins: vec!["https://eats.example.com".to_string()],
+            ..CorsPolicy::default()
+        };
+        let respond = |request: &str| {
+            policy.apply(
+                &request.parse().unwrap(),
+                HttpResponse::new(204, "No Content", ""),
+            )
+        };
+
+        let response = respond("GET /orders HTTP/1.1\r\n\r\n");
+        assert_eq!(response.header("Access-Control-Allow-Origin"), None);
+        let response = respond("GET /orders HTTP/1.1\r\nOrigin: https://evil.example.com\r\n\r\n");
+        assert_eq!(response.header("Access-Control-Allow-Origin"), None);
+
+        let response = respond("GET /orders HTTP/1.1\r\nOrigin: https://eats.example.com\r\n\r\n");
+        assert_eq!(
+            response.header("Access-Control-Allow-Origin"),
+            Some("https://eats.example.com")
+        );
+        assert_eq!(response.header("Vary"), Some("Origin"));
+        assert_eq!(response.header("Access-Control-Allow-Methods"), None);
+
+        let response = respond(
+            "OPTIONS /orders HTTP/1.1\r\nOrigin: https://eats.example.com\r\n\
+             Access-Control-Request-Method: POST\r\n\r\n",
+        );
+        assert_eq!(
+            response.header("Access-Control-Allow-Methods"),
+            Some("GET, POST, DELETE")
+        );
+        assert_eq!(
+            response.header("Access-Control-Allow-Headers"),
+            Some("Content-Type, X-Request-Id")
+        );
+        assert_eq!(response.header("Access-Control-Max-Age"), Some("600"));
+    }
+
+    #[test]
+    fn test_cors_policy_wildcard() {
+        let policy = CorsPolicy {
+            allowed_origins: vec!["*".to_string()],
+            ..CorsPolicy::default()
+        };
+        let request = "GET / HTTP/1.1\r\nOrigin: https://anywhere.example\r\n\r\n";
+        let response = policy.apply(&request.parse().unwrap(), HttpResponse::new(200, "OK", ""));
+        assert_eq!(response.header("Access-Control-Allow-Origin"), Some("*"));
+        assert_eq!(response.header("Vary"), None);
+    }
+
+    #[test]
+    fn test_http_request_round_trip() {
+        let request = HttpRequest::from_str("POST /orders HTTP/1.1\r\n\r\n{}").unwrap();
+        let parsed = read_request(request.to_string().as_bytes()).unwrap();
+        assert_eq!(parsed.method, request.method);
+        assert_eq!(parsed.path, request.path);
+        assert_eq!(parsed.body, request.body);
+    }
+
+    #[test]
+    fn test_http_request_query() {
+        let request = HttpRequest::from_str(
+            "GET /reports?from=2024-01-01&name=a%20b+c&flag HTTP/1.1\r\n\r\n",
+        )
+        .unwrap();
+        assert_eq!(request.query("from"), Some("2024-01-01".to_string()));
+        assert_eq!(request.query("name"), Some("a b c".to_string()));
+        assert_eq!(request.query("flag"), Some("".to_string()));
+        assert_eq!(request.query("to"), None);
+    }
+
+    #[test]
+    fn test_http_response_headers_to_string() {
+        let response = HttpResponse::new(200, "OK", "a,b").with_header("Content-Type", "text/csv");
+        assert_eq!(response.header("content-type"), Some("text/csv"));
+        assert_eq!(
+            response.to_string(),
+            "HTTP/1.1 200 OK\r\nContent-Type: text/csv\r\n\r\na,b"
+        );
+    }
+
     #[test]
     fn test_http_response_to_string() {
         let response = HttpResponse::new(200, "OK", "Welcome to Aspirin Eats!");
@@ -77,6 +862,27 @@ mod tests {
     }
 
     #[test]
+    fn test_http_response_from_str() {
+        let response: HttpResponse =
+            "HTTP/1.1 404 Not Found\r\nX-Request-Id: abc\r\n\r\nResource not found"
+                .parse()
+                .unwrap();
+        assert_eq!(response.status_code(), 404);
+        assert_eq!(response.status_text, "Not Found");
+        assert_eq!(response.header("x-request-id"), Some("abc"));
+        assert_eq!(response.body(), "Resource not found");
+
+        let response = HttpResponse::new(201, "Created", "{}").with_header("Content-Length", "2");
+        let parsed: HttpResponse = response.to_string().parse().unwrap();
+        assert_eq!(parsed.to_string(), response.to_string());
+
+        assert!("HTTP/1.1 abc OK\r\n\r\n".parse::<HttpResponse>().is_err());
+        assert!("garbage".parse::<HttpResponse>().is_err());
+    }
+
+    #[test]
+    // Builds its io::Error the way it always has, from before io::Error::other was stable
+    #[allow(clippy::io_other_error)]
     fn test_http_response_from_aspirin_eats_error() {
         let error = AspirinEatsError::InvalidRequest;
         let response: HttpResponse = error.into();
@@ -96,10 +902,118 @@ mod tests {
         assert_eq!(response.status_text, "Method Not Allowed");
         assert_eq!(response.body, "Method not allowed");
 
+        let error = AspirinEatsError::OutsideDeliveryArea("1 Main St, Boston 02101".to_string());
+        let response: HttpResponse = error.into();
+        assert_eq!(response.status_code, 422);
+        assert_eq!(
+            response.body,
+            "Outside delivery area: 1 Main St, Boston 02101"
+        );
+
         let error = AspirinEatsError::Io(std::io::Error::new(std::io::ErrorKind::Other, "test"));
         let response: HttpResponse = error.into();
         assert_eq!(response.status_code, 500);
         assert_eq!(response.status_text, "Internal Server Error");
         assert_eq!(response.body, "Internal Server Error");
+
+        let error: AspirinEatsError = UpstreamError::Timeout.into();
+        let response: HttpResponse = error.into();
+        assert_eq!(response.status_code, 504);
+        assert_eq!(response.status_text, "Gateway Timeout");
+
+        let error: AspirinEatsError = UpstreamError::CircuitOpen.into();
+        let response: HttpResponse = error.into();
+        assert_eq!(response.status_code, 503);
+
+        let error: AspirinEatsError = UpstreamError::InvalidResponse.into();
+        let response: HttpResponse = error.into();
+        assert_eq!(response.status_code, 502);
+        assert_eq!(
+            response.body,
+            "Upstream error: origin sent an invalid response"
+        );
+    }
+
+    /// Header names, which can't hold a colon, and values, which lose surrounding whitespace
+    fn header_strategy() -> impl Strategy<Value = (String, String)> {
+        ("[A-Za-z0-9-]{1,20}", "([!-~]([ -~]{0,30}[!-~])?)?")
+    }
+
+    proptest! {
+        #[test]
+        fn prop_http_response_round_trip(
+            status_code in any::<u16>(),
+            status_text in "[ -~]{0,30}",
+            headers in proptest::collection::vec(header_strategy(), 0..8),
+            body in any::<String>(),
+        ) {
+            let response = HttpResponse {
+                status_code,
+                status_text,
+                headers,
+                body,
+            };
+            let parsed: HttpResponse = response.to_string().parse().unwrap();
+            prop_assert_eq!(parsed, response);
+        }
+
+        #[test]
+        fn prop_http_request_round_trip(
+            method in "[A-Z]{1,10}",
+            path in "/[!-~]{0,40}",
+            headers in proptest::collection::vec(header_strategy(), 0..8),
+            body in any::<String>(),
+        ) {
+            let request = HttpRequest {
+                method: Some(method),
+                path: Some(path),
+                headers,
+                body: (!body.is_empty()).then_some(body),
+            };
+            for parsed in [
+                request.to_string().parse::<HttpRequest>().unwrap(),
+                read_request(request.to_string().as_bytes()).unwrap(),
+            ] {
+                prop_assert_eq!(&parsed.method, &request.method);
+                prop_assert_eq!(&parsed.path, &request.path);
+                prop_assert_eq!(&parsed.body, &request.body);
+                prop_assert_eq!(parsed.to_string(), request.to_string());
+            }
+        }
+
+        #[test]
+        fn prop_parsers_never_panic(raw in any::<Vec<u8>>()) {
+            check_parsers(&raw);
+        }
+    }
+
+    /// Run `raw` through every parser. Bad input should only ever be an error
+    fn check_parsers(raw: &[u8]) {
+        let limits = RequestLimits {
+            max_header_bytes: 256,
+            max_body_bytes: 256,
+        };
+        let _ = read_request_with_limits(raw, limits);
+        let text = String::from_utf8_lossy(raw);
+        let _ = text.parse::<HttpResponse>();
+        if let Ok(request) = text.parse::<HttpRequest>() {
+            let _ = (request.route(), request.query("mode"));
+            let reparsed: HttpRequest = request.to_string().parse().unwrap();
+            assert_eq!(reparsed.to_string(), request.to_string());
+        }
+    }
+
+    /// Everything the fuzzers have been seeded with or turned up, kept as regression tests
+    #[test]
+    fn test_fuzz_corpus() {
+        let corpus = std::path::Path::new(env!("CARGO_MANIFEST_DIR")).join("fuzz/corpus");
+        let mut count = 0;
+        for target in std::fs::read_dir(corpus).unwrap() {
+            for input in std::fs::read_dir(target.unwrap().path()).unwrap() {
+                check_parsers(&std::fs::read(input.unwrap().path()).unwrap());
+                count += 1;
+            }
+        }
+        assert!(count > 0);
     }
 }
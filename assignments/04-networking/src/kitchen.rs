@@ -0,0 +1,730 @@
+use std::collections::{BTreeMap, VecDeque};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use serde::Deserialize;
+
+use crate::error::AspirinEatsError;
+use crate::food::{MenuItem, OrderStatus};
+use crate::store::OrderStore;
+
+/// Source of the current time for the kitchen, as time elapsed since some fixed starting point
+pub trait Clock {
+    fn now(&self) -> Duration;
+}
+
+/// The real time, counted from when the clock was created
+pub struct SystemClock {
+    start: Instant,
+}
+
+impl SystemClock {
+    pub fn new() -> Self {
+        SystemClock {
+            start: Instant::now(),
+        }
+    }
+}
+
+impl Default for SystemClock {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Clock for SystemClock {
+    fn now(&self) -> Duration {
+        self.start.elapsed()
+    }
+}
+
+/// A clock that only moves when it's told to, so the kitchen can be simulated without sleeping.
+/// Clones share the same time
+#[derive(Clone, Default)]
+pub struct ManualClock {
+    now: Arc<Mutex<Duration>>,
+}
+
+impl ManualClock {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Move the clock forward by `duration`
+    pub fn advance(&self, duration: Duration) {
+        *self.now.lock().expect("clock lock poisoned") += duration;
+    }
+}
+
+impl Clock for ManualClock {
+    fn now(&self) -> Duration {
+        *self.now.lock().expect("clock lock poisoned")
+    }
+}
+
+/// How the kitchen is staffed, and how long each item takes to make
+#[derive(Deserialize, Debug, PartialEq, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub struct KitchenPolicy {
+    /// Cook stations working through the queue at once. 0 turns the kitchen off
+    pub stations: usize,
+
+    /// How often the kitchen checks for new orders and moves finished ones along
+    pub tick_ms: u64,
+
+    /// Time to make a burger, before toppings
+    pub burger_secs: u64,
+
+    /// Extra time for each topping on a burger
+    pub topping_secs: u64,
+
+    pub fries_secs: u64,
+    pub drink_secs: u64,
+}
+
+impl Default for KitchenPolicy {
+    fn default() -> Self {
+        KitchenPolicy {
+            stations: 2,
+            tick_ms: 1000,
+            burger_secs: 240,
+            topping_secs: 15,
+            fries_secs: 180,
+            drink_secs: 30,
+        }
+    }
+}
+
+impl KitchenPolicy {
+    pub fn tick(&self) -> Duration {
+        Duration::from_millis(self.tick_ms)
+    }
+
+    /// How long a cook station takes to make `item`
+    pub fn prep_time(&self, item: &MenuItem) -> Duration {
+        Duration::from_secs(match item {
+            MenuItem::Burger(burger) => {
+                self.burger_secs + self.topping_secs * burger.toppings().len() as u64
+            }
+            MenuItem::Fries => self.fries_secs,
+            MenuItem::Drink => self.drink_secs,
+        })
+    }
+}
+
+/// An order's status changing, and when in the kitchen's time it did
+#[derive(Debug, PartialEq, Clone)]
+pub struct StatusChange {
+    pub order_id: i64,
+    pub status: OrderStatus,
+    pub at: Duration,
+}
+
+/// How the kitchen has been doing
+#[derive(Debug, PartialEq, Clone, Default)]
+pub struct KitchenStats {
+    /// Orders that have been made and sent out
+    pub completed: usize,
+
+    /// Items waiting for a cook station
+    pub queued_items: usize,
+
+    /// Time completed orders spent waiting before work on them started, on average
+    pub average_wait: Duration,
+
+    /// Longest time a completed order spent waiting before work on it started
+    pub longest_wait: Duration,
+}
+
+/// An item waiting in the queue
+struct QueuedItem {
+    order_id: i64,
+    prep_time: Duration,
+    queued_at: Duration,
+}
+
+/// Where an order the kitchen has taken on has got to
+struct Progress {
+    queued_at: Duration,
+
+    /// When the first of its items was started, if one has been
+    started: Option<Duration>,
+
+    /// Items not yet handed to a cook station
+    unassigned: usize,
+
+    /// When the last of the items handed out so far will be done
+    done_at: Duration,
+
+    /// Status the kitchen last gave the order
+    status: OrderStatus,
+
+    /// The food its queued items were made from
+    food: Vec<MenuItem>,
+}
+
+/// Works through pending orders: each item of each order joins a first-in first-out queue, and
+/// every cook station takes the next item as soon as it's free. An order is `Preparing` once
+/// work on any of its items starts, and `Transporting` once all of them are done.
+///
+/// Everything happens in the clock's time, worked out exactly however far apart ticks are, so
+/// advancing a [`ManualClock`] an hour and ticking once gives the same result as ticking every
+/// second for an hour
+pub struct Kitchen<C: Clock> {
+    clock: C,
+    policy: KitchenPolicy,
+
+    /// When each cook station is next free
+    stations: Vec<Duration>,
+    queue: VecDeque<QueuedItem>,
+    orders: BTreeMap<i64, Progress>,
+
+    completed: usize,
+    total_wait: Duration,
+    longest_wait: Duration,
+}
+
+impl<C: Clock> Kitchen<C> {
+    pub fn new(clock: C, policy: KitchenPolicy) -> Self {
+        let now = clock.now();
+        Kitchen {
+            stations: vec![now; policy.stations],
+            clock,
+            policy,
+            queue: VecDeque::new(),
+            orders: BTreeMap::new(),
+            completed: 0,
+            total_wait: Duration::ZERO,
+            longest_wait: Duration::ZERO,
+        }
+    }
+
+    pub fn stats(&self) -> KitchenStats {
+        KitchenStats {
+            completed: self.completed,
+            queued_items: self.queue.len(),
+            average_wait: match self.completed {
+                0 => Duration::ZERO,
+                completed => self.total_wait / completed as u32,
+            },
+            longest_wait: self.longest_wait,
+        }
+    }
+
+    /// Take on any new pending orders in `store`, do all the work that fits in the time since the
+    /// last tick, and update the statuses of the orders that moved along. Returns the changes
+    /// made, oldest first
+    pub fn tick<S: OrderStore>(
+        &mut self,
+        store: &S,
+    ) -> Result<Vec<StatusChange>, AspirinEatsError> {
+        let now = self.clock.now();
+        self.take_orders(store, now)?;
+        self.assign_work(now);
+        self.update_statuses(store, now)
+    }
+
+    /// Queue up the items of pending orders we haven't seen yet, queue again the items of orders
+    /// whose food changed before work on them started, and forget orders that were cancelled or
+    /// removed before work on them started
+    fn take_orders<S: OrderStore>(
+        &mut self,
+        store: &S,
+        now: Duration,
+    ) -> Result<(), AspirinEatsError> {
+        let orders = store.get_all_orders()?;
+        let pending: BTreeMap<i64, &[MenuItem]> = orders
+            .iter()
+            .filter(|order| order.status == OrderStatus::Pending)
+            .filter_map(|order| Some((order.id?, order.food.as_slice())))
+            .collect();
+
+        let gone: Vec<i64> = self
+            .orders
+            .iter()
+            .filter(|(id, progress)| progress.started.is_none() && !pending.contains_key(id))
+            .map(|(id, _)| *id)
+            .collect();
+        for id in gone {
+            self.orders.remove(&id);
+            self.queue.retain(|item| item.order_id != id);
+        }
+
+        for (id, food) in pending {
+            match self.orders.get(&id) {
+                Some(progress) if progress.started.is_none() && progress.food != food => {
+                    self.requeue(id, food, now)
+                }
+                Some(_) => {}
+                None => {
+                    self.queue.extend(food.iter().map(|item| QueuedItem {
+                        order_id: id,
+                        prep_time: self.policy.prep_time(item),
+                        queued_at: now,
+                    }));
+                    self.orders.insert(
+                        id,
+                        Progress {
+                            queued_at: now,
+                            started: food.is_empty().then_some(now),
+                            unassigned: food.len(),
+                            done_at: now,
+                            status: OrderStatus::Pending,
+                            food: food.to_vec(),
+                        },
+                    );
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Swap the queued items of an order that hasn't been started for `food`, keeping its place
+    /// in the queue
+    fn requeue(&mut self, id: i64, food: &[MenuItem], now: Duration) {
+        let progress = self.orders.get_mut(&id).expect("order is queued");
+        let at = self
+            .queue
+            .iter()
+            .position(|item| item.order_id == id)
+            .unwrap_or(self.queue.len());
+        let mut behind = self.queue.split_off(at);
+        behind.retain(|item| item.order_id != id);
+        self.queue.extend(food.iter().map(|item| QueuedItem {
+            order_id: id,
+            prep_time: self.policy.prep_time(item),
+            queued_at: progress.queued_at,
+        }));
+        self.queue.append(&mut behind);
+
+        progress.started = food.is_empty().then_some(now);
+        progress.unassigned = food.len();
+        progress.food = food.to_vec();
+    }
+
+    /// Hand queued items to cook stations, for every station that was free at some point before
+    /// `now` while there was something in the queue
+    fn assign_work(&mut self, now: Duration) {
+        while let Some(item) = self.queue.front() {
+            let Some((station, free_at)) = self
+                .stations
+                .iter()
+                .copied()
+                .enumerate()
+                .min_by_key(|(_, free_at)| *free_at)
+            else {
+                return;
+            };
+            let start = free_at.max(item.queued_at);
+            if start > now {
+                return;
+            }
+
+            let done_at = start + item.prep_time;
+            self.stations[station] = done_at;
+            let progress = self
+                .orders
+                .get_mut(&item.order_id)
+                .expect("queued items belong to a known order");
+            progress.started.get_or_insert(start);
+            progress.unassigned -= 1;
+            progress.done_at = progress.done_at.max(done_at);
+            self.queue.pop_front();
+        }
+    }
+
+    /// Move orders along whose work has started or finished by `now`
+    fn update_statuses<S: OrderStore>(
+        &mut self,
+        store: &S,
+        now: Duration,
+    ) -> Result<Vec<StatusChange>, AspirinEatsError> {
+        let mut changes = Vec::new();
+        let ids: Vec<i64> = self.orders.keys().copied().collect();
+        for id in ids {
+            let progress = &self.orders[&id];
+            let started = progress.started.filter(|started| *started <= now);
+            let done = progress.unassigned == 0 && progress.done_at <= now;
+
+            if progress.status == OrderStatus::Pending {
+                if let Some(started) = started {
+                    let preparing = store.set_status(
+                        id,
+                        OrderStatus::Pending,
+                        OrderStatus::Preparing,
+                        &progress.food,
+                    )?;
+                    if !preparing {
+                        // Cancelled, removed or given new food after all. The stations are
+                        // already busy, but an order that's still pending is queued again with
+                        // its new food on the next tick
+                        self.orders.remove(&id);
+                        continue;
+                    }
+                    changes.push(StatusChange {
+                        order_id: id,
+                        status: OrderStatus::Preparing,
+                        at: started,
+                    });
+                    if let Some(progress) = self.orders.get_mut(&id) {
+                        progress.status = OrderStatus::Preparing;
+                    }
+                }
+            }
+
+            let progress = &self.orders[&id];
+            if progress.status == OrderStatus::Preparing && done {
+                let Progress {
+                    queued_at,
+                    started,
+                    done_at,
+                    food,
+                    ..
+                } = self.orders.remove(&id).expect("order is in progress");
+                if store.set_status(id, OrderStatus::Preparing, OrderStatus::Transporting, &food)? {
+                    changes.push(StatusChange {
+                        order_id: id,
+                        status: OrderStatus::Transporting,
+                        at: done_at,
+                    });
+                    let wait = started.unwrap_or(queued_at) - queued_at;
+                    self.completed += 1;
+                    self.total_wait += wait;
+                    self.longest_wait = self.longest_wait.max(wait);
+                }
+            }
+        }
+        changes.sort_by_key(|change| change.at);
+        Ok(changes)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::Cell;
+
+    use chrono::{DateTime, Local, Utc};
+
+    use super::*;
+    use crate::db::{BatchMode, BatchOutcome};
+    use crate::food::{Bun, Burger, Order, Patty, Topping};
+    use crate::pricing::{PriceBreakdown, Pricing};
+    use crate::store::InMemoryStore;
+
+    /// A store where the food of an order is replaced just before the kitchen first changes a
+    /// status, i.e. after the kitchen read the order but before it acts on it
+    struct ReplacedMidTick {
+        store: InMemoryStore,
+        replace: Cell<Option<(i64, Vec<MenuItem>)>>,
+    }
+
+    impl OrderStore for ReplacedMidTick {
+        fn add_order(&self, order: Order) -> Result<i64, AspirinEatsError> {
+            self.store.add_order(order)
+        }
+
+        fn add_orders(
+            &self,
+            orders: Vec<Order>,
+            mode: BatchMode,
+        ) -> Result<BatchOutcome, AspirinEatsError> {
+            self.store.add_orders(orders, mode)
+        }
+
+        fn get_order(&self, id: i64) -> Result<Option<Order>, AspirinEatsError> {
+            self.store.get_order(id)
+        }
+
+        fn get_all_orders(&self) -> Result<Vec<Order>, AspirinEatsError> {
+            self.store.get_all_orders()
+        }
+
+        fn update_order(&self, order: &Order) -> Result<bool, AspirinEatsError> {
+            self.store.update_order(order)
+        }
+
+        fn set_status(
+            &self,
+            id: i64,
+            from: OrderStatus,
+            to: OrderStatus,
+            food: &[MenuItem],
+        ) -> Result<bool, AspirinEatsError> {
+            if let Some((replaced, new_food)) = self.replace.take() {
+                let version = self.store.get_order_version(replaced)?.unwrap();
+                let breakdown =
+                    Pricing::default().price(&new_food, None, Local::now().date_naive())?;
+                self.store
+                    .replace_items(replaced, version, &new_food, &breakdown)?;
+            }
+            self.store.set_status(id, from, to, food)
+        }
+
+        fn get_order_version(&self, id: i64) -> Result<Option<i64>, AspirinEatsError> {
+            self.store.get_order_version(id)
+        }
+
+        fn orders_last_modified(&self) -> Result<Option<DateTime<Utc>>, AspirinEatsError> {
+            self.store.orders_last_modified()
+        }
+
+        fn replace_items(
+            &self,
+            id: i64,
+            version: i64,
+            food: &[MenuItem],
+            breakdown: &PriceBreakdown,
+        ) -> Result<i64, AspirinEatsError> {
+            self.store.replace_items(id, version, food, breakdown)
+        }
+
+        fn remove_order(&self, id: i64) -> Result<(), AspirinEatsError> {
+            self.store.remove_order(id)
+        }
+
+        fn reset_orders(&self) -> Result<(), AspirinEatsError> {
+            self.store.reset_orders()
+        }
+    }
+
+    fn secs(secs: u64) -> Duration {
+        Duration::from_secs(secs)
+    }
+
+    fn add_order(store: &InMemoryStore, food: Vec<MenuItem>) -> i64 {
+        store
+            .add_order(Order {
+                id: None,
+                customer: "Amit".to_string(),
+                food,
+                status: OrderStatus::Pending,
+                total: 0.0,
+                breakdown: None,
+                delivery: None,
+                driver: None,
+                scheduled_for: None,
+            })
+            .unwrap()
+    }
+
+    fn change(order_id: i64, status: OrderStatus, at: u64) -> StatusChange {
+        StatusChange {
+            order_id,
+            status,
+            at: secs(at),
+        }
+    }
+
+    fn status(store: &InMemoryStore, id: i64) -> OrderStatus {
+        store.get_order(id).unwrap().unwrap().status
+    }
+
+    /// Two orders: a burger with two toppings (270s) and fries (180s), then a drink (30s)
+    fn get_test_store() -> InMemoryStore {
+        let store = InMemoryStore::new();
+        let burger = Burger::new(
+            Bun::Sesame,
+            Patty::Beef,
+            vec![Topping::Cheese, Topping::Bacon],
+        );
+        add_order(&store, vec![MenuItem::Burger(burger), MenuItem::Fries]);
+        add_order(&store, vec![MenuItem::Drink]);
+        store
+    }
+
+    fn get_test_kitchen(clock: &ManualClock, stations: usize) -> Kitchen<ManualClock> {
+        Kitchen::new(
+            clock.clone(),
+            KitchenPolicy {
+                stations,
+                ..KitchenPolicy::default()
+            },
+        )
+    }
+
+    #[test]
+    fn test_prep_time() {
+        let policy = KitchenPolicy::default();
+        let burger = Burger::new(Bun::Plain, Patty::Veggie, vec![Topping::Onion]);
+        assert_eq!(policy.prep_time(&MenuItem::Burger(burger)), secs(255));
+        assert_eq!(policy.prep_time(&MenuItem::Fries), secs(180));
+    }
+
+    #[test]
+    fn test_single_station_works_in_order() {
+        let store = get_test_store();
+        let clock = ManualClock::new();
+        let mut kitchen = get_test_kitchen(&clock, 1);
+
+        assert_eq!(
+            kitchen.tick(&store).unwrap(),
+            vec![change(1, OrderStatus::Preparing, 0)]
+        );
+        assert_eq!(status(&store, 2), OrderStatus::Pending);
+
+        // The burger is done, and the fries are started
+        clock.advance(secs(270));
+        assert_eq!(kitchen.tick(&store).unwrap(), vec![]);
+        assert_eq!(kitchen.stats().queued_items, 1);
+
+        // The fries were done at 450, when the drink was started
+        clock.advance(secs(200));
+        assert_eq!(
+            kitchen.tick(&store).unwrap(),
+            vec![
+                change(1, OrderStatus::Transporting, 450),
+                change(2, OrderStatus::Preparing, 450)
+            ]
+        );
+        assert_eq!(status(&store, 1), OrderStatus::Transporting);
+
+        clock.advance(secs(10));
+        assert_eq!(
+            kitchen.tick(&store).unwrap(),
+            vec![change(2, OrderStatus::Transporting, 480)]
+        );
+        assert_eq!(
+            kitchen.stats(),
+            KitchenStats {
+                completed: 2,
+                queued_items: 0,
+                average_wait: secs(225),
+                longest_wait: secs(450),
+            }
+        );
+    }
+
+    #[test]
+    fn test_stations_work_in_parallel() {
+        let store = get_test_store();
+        let clock = ManualClock::new();
+        let mut kitchen = get_test_kitchen(&clock, 2);
+
+        kitchen.tick(&store).unwrap();
+        // A third order arrives while both stations are busy
+        clock.advance(secs(100));
+        let third = add_order(&store, vec![MenuItem::Drink]);
+        assert_eq!(kitchen.tick(&store).unwrap(), vec![]);
+        clock.advance(secs(900));
+
+        assert_eq!(
+            kitchen.tick(&store).unwrap(),
+            vec![
+                // The drink was started when the fries' station freed up
+                change(2, OrderStatus::Preparing, 180),
+                change(2, OrderStatus::Transporting, 210),
+                change(third, OrderStatus::Preparing, 210),
+                change(third, OrderStatus::Transporting, 240),
+                change(1, OrderStatus::Transporting, 270),
+            ]
+        );
+        assert_eq!(kitchen.stats().average_wait, secs(180 + 110) / 3);
+        assert_eq!(kitchen.stats().longest_wait, secs(180));
+    }
+
+    #[test]
+    fn test_ticks_far_apart_give_the_same_result() {
+        let run = |step: u64| {
+            let store = get_test_store();
+            let clock = ManualClock::new();
+            let mut kitchen = get_test_kitchen(&clock, 1);
+            let mut changes = Vec::new();
+            for _ in 0..(600 / step) {
+                changes.extend(kitchen.tick(&store).unwrap());
+                clock.advance(secs(step));
+            }
+            changes.extend(kitchen.tick(&store).unwrap());
+            (changes, kitchen.stats())
+        };
+        assert_eq!(run(1), run(600));
+        assert_eq!(run(7), run(600));
+    }
+
+    #[test]
+    fn test_cancelled_orders_leave_the_queue() {
+        let store = get_test_store();
+        let clock = ManualClock::new();
+        let mut kitchen = get_test_kitchen(&clock, 1);
+        kitchen.tick(&store).unwrap();
+
+        let mut order = store.get_order(2).unwrap().unwrap();
+        order.status = OrderStatus::Cancelled;
+        store.update_order(&order).unwrap();
+        clock.advance(secs(1000));
+
+        assert_eq!(
+            kitchen.tick(&store).unwrap(),
+            vec![change(1, OrderStatus::Transporting, 450)]
+        );
+        assert_eq!(status(&store, 2), OrderStatus::Cancelled);
+        assert_eq!(kitchen.stats().queued_items, 0);
+    }
+
+    #[test]
+    fn test_changed_orders_are_requeued() {
+        let store = get_test_store();
+        let third = add_order(&store, vec![MenuItem::Drink]);
+        let clock = ManualClock::new();
+        let mut kitchen = get_test_kitchen(&clock, 1);
+        kitchen.tick(&store).unwrap();
+
+        // The drink becomes fries before the kitchen gets to it, without losing its place
+        let breakdown = Pricing::default()
+            .price(&[MenuItem::Fries], None, Local::now().date_naive())
+            .unwrap();
+        store
+            .replace_items(2, 1, &[MenuItem::Fries], &breakdown)
+            .unwrap();
+        clock.advance(secs(1000));
+
+        assert_eq!(
+            kitchen.tick(&store).unwrap(),
+            vec![
+                change(1, OrderStatus::Transporting, 450),
+                change(2, OrderStatus::Preparing, 450),
+                change(2, OrderStatus::Transporting, 630),
+                change(third, OrderStatus::Preparing, 630),
+                change(third, OrderStatus::Transporting, 660),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_food_replaced_mid_tick_is_not_started() {
+        let store = ReplacedMidTick {
+            store: InMemoryStore::new(),
+            replace: Cell::new(Some((1, vec![MenuItem::Fries]))),
+        };
+        add_order(&store.store, vec![MenuItem::Drink]);
+        let clock = ManualClock::new();
+        let mut kitchen = get_test_kitchen(&clock, 1);
+
+        // The drink became fries between the kitchen reading the order and starting it
+        assert_eq!(kitchen.tick(&store).unwrap(), vec![]);
+        let order = store.get_order(1).unwrap().unwrap();
+        assert_eq!(order.status, OrderStatus::Pending);
+        assert_eq!(order.food, vec![MenuItem::Fries]);
+
+        // So the fries are made once the station is done with the drink it had started
+        assert_eq!(kitchen.tick(&store).unwrap(), vec![]);
+        clock.advance(secs(1000));
+        assert_eq!(
+            kitchen.tick(&store).unwrap(),
+            vec![
+                change(1, OrderStatus::Preparing, 30),
+                change(1, OrderStatus::Transporting, 210),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_no_stations() {
+        let store = get_test_store();
+        let clock = ManualClock::new();
+        let mut kitchen = get_test_kitchen(&clock, 0);
+        clock.advance(secs(1000));
+
+        assert_eq!(kitchen.tick(&store).unwrap(), vec![]);
+        assert_eq!(status(&store, 1), OrderStatus::Pending);
+    }
+}
@@ -1,4 +1,22 @@
+pub mod api;
+pub mod client;
+pub mod compression;
+pub mod config;
 pub mod db;
+pub mod delivery;
+pub mod drivers;
 pub mod error;
 pub mod food;
 pub mod http;
+pub mod kitchen;
+pub mod logging;
+pub mod metrics;
+pub mod openapi;
+pub mod pricing;
+pub mod proxy;
+pub mod reports;
+pub mod schedule;
+pub mod store;
+pub mod tls;
+pub mod ui;
+pub mod upstream;
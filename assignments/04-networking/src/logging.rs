@@ -0,0 +1,265 @@
+use std::io::Write;
+use std::net::SocketAddr;
+use std::str::FromStr;
+use std::sync::Mutex;
+use std::time::Duration;
+
+use chrono::{DateTime, SecondsFormat, Utc};
+use serde::{Deserialize, Serialize, Serializer};
+
+use crate::error::AspirinEatsError;
+
+/// Header used to trace a single request through the proxy and the origin
+pub const REQUEST_ID_HEADER: &str = "X-Request-Id";
+
+/// How access log lines are written
+#[derive(Deserialize, Debug, PartialEq, Clone, Copy, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum LogFormat {
+    /// One space-separated line per request, for people to read
+    #[default]
+    Human,
+
+    /// One JSON object per line, for log collectors
+    Json,
+}
+
+impl FromStr for LogFormat {
+    type Err = AspirinEatsError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "human" | "text" => Ok(LogFormat::Human),
+            "json" => Ok(LogFormat::Json),
+            _ => Err(AspirinEatsError::Config(format!(
+                "unknown log format {s:?}"
+            ))),
+        }
+    }
+}
+
+/// How much a server logs. Each level includes everything logged at the levels before it
+#[derive(Deserialize, Debug, PartialEq, Eq, PartialOrd, Ord, Clone, Copy, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum LogLevel {
+    /// Only failures
+    Error,
+
+    /// Failures and anything suspicious, such as malformed requests
+    Warn,
+
+    /// Everything above plus a line in the access log for every request
+    #[default]
+    Info,
+
+    /// Everything above plus the configuration the server started with
+    Debug,
+}
+
+impl FromStr for LogLevel {
+    type Err = AspirinEatsError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "error" => Ok(LogLevel::Error),
+            "warn" | "warning" => Ok(LogLevel::Warn),
+            "info" => Ok(LogLevel::Info),
+            "debug" => Ok(LogLevel::Debug),
+            _ => Err(AspirinEatsError::Config(format!("unknown log level {s:?}"))),
+        }
+    }
+}
+
+/// Everything recorded about a single request
+#[derive(Serialize, Debug, PartialEq, Clone)]
+pub struct AccessLogEntry {
+    /// When the request finished
+    pub time: DateTime<Utc>,
+
+    /// Which process handled the request, e.g. "origin" or "proxy"
+    pub service: String,
+
+    pub request_id: Option<String>,
+    pub client: Option<SocketAddr>,
+    pub method: String,
+    pub path: String,
+    pub status: u16,
+
+    /// Size of the response sent back to the client, in bytes
+    pub bytes: usize,
+
+    #[serde(rename = "latency_ms", serialize_with = "serialize_millis")]
+    pub latency: Duration,
+}
+
+fn serialize_millis<S: Serializer>(latency: &Duration, serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.serialize_f64(latency.as_secs_f64() * 1000.0)
+}
+
+impl AccessLogEntry {
+    /// Render the entry as a single line (without a trailing newline)
+    pub fn render(&self, format: LogFormat) -> String {
+        match format {
+            LogFormat::Human => format!(
+                "{} {} {} \"{} {}\" {} {}B {:.1}ms id={}",
+                self.time.to_rfc3339_opts(SecondsFormat::Millis, true),
+                self.service,
+                self.client
+                    .map_or_else(|| "-".to_string(), |addr| addr.to_string()),
+                self.method,
+                self.path,
+                self.status,
+                self.bytes,
+                self.latency.as_secs_f64() * 1000.0,
+                self.request_id.as_deref().unwrap_or("-"),
+            ),
+            LogFormat::Json => serde_json::to_string(self).expect("Failed to serialize log entry"),
+        }
+    }
+}
+
+/// Writes an access log line for every request a server handles
+pub struct AccessLog {
+    service: String,
+    format: LogFormat,
+    writer: Mutex<Box<dyn Write + Send>>,
+}
+
+impl AccessLog {
+    /// Create an access log for `service` that writes to stdout
+    pub fn new(service: &str, format: LogFormat) -> Self {
+        Self::with_writer(service, format, std::io::stdout())
+    }
+
+    /// Create an access log for `service` that writes to `writer`
+    pub fn with_writer<W: Write + Send + 'static>(
+        service: &str,
+        format: LogFormat,
+        writer: W,
+    ) -> Self {
+        AccessLog {
+            service: service.to_string(),
+            format,
+            writer: Mutex::new(Box::new(writer)),
+        }
+    }
+
+    pub fn service(&self) -> &str {
+        &self.service
+    }
+
+    /// Write a line for `entry`. Logging is best-effort, so write errors are ignored
+    pub fn record(&self, entry: &AccessLogEntry) {
+        if let Ok(mut writer) = self.writer.lock() {
+            let _ = writeln!(writer, "{}", entry.render(self.format));
+            let _ = writer.flush();
+        }
+    }
+}
+
+/// Generate a new, unique request ID
+pub fn new_request_id() -> String {
+    uuid::Uuid::new_v4().to_string()
+}
+
+/// Writer that can be shared with an AccessLog and read back, for testing
+#[cfg(test)]
+#[derive(Clone, Default)]
+pub(crate) struct SharedBuffer(std::sync::Arc<Mutex<Vec<u8>>>);
+
+#[cfg(test)]
+impl SharedBuffer {
+    pub(crate) fn lines(&self) -> Vec<String> {
+        String::from_utf8(self.0.lock().unwrap().clone())
+            .unwrap()
+            .lines()
+            .map(str::to_string)
+            .collect()
+    }
+}
+
+#[cfg(test)]
+impl Write for SharedBuffer {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.0.lock().unwrap().write(buf)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn get_test_entry() -> AccessLogEntry {
+        AccessLogEntry {
+            time: DateTime::parse_from_rfc3339("2024-03-01T12:00:00Z")
+                .unwrap()
+                .to_utc(),
+            service: "origin".to_string(),
+            request_id: Some("abc".to_string()),
+            client: Some("127.0.0.1:5555".parse().unwrap()),
+            method: "GET".to_string(),
+            path: "/orders".to_string(),
+            status: 200,
+            bytes: 42,
+            latency: Duration::from_micros(1500),
+        }
+    }
+
+    #[test]
+    fn test_render_human() {
+        assert_eq!(
+            get_test_entry().render(LogFormat::Human),
+            "2024-03-01T12:00:00.000Z origin 127.0.0.1:5555 \"GET /orders\" 200 42B 1.5ms id=abc"
+        );
+    }
+
+    #[test]
+    fn test_render_json() {
+        let line = get_test_entry().render(LogFormat::Json);
+        let value: serde_json::Value = serde_json::from_str(&line).unwrap();
+        assert_eq!(value["request_id"], "abc");
+        assert_eq!(value["client"], "127.0.0.1:5555");
+        assert_eq!(value["status"], 200);
+        assert_eq!(value["latency_ms"], 1.5);
+    }
+
+    #[test]
+    fn test_record_writes_lines() {
+        let buffer = SharedBuffer::default();
+        let log = AccessLog::with_writer("origin", LogFormat::Human, buffer.clone());
+        let mut entry = get_test_entry();
+        log.record(&entry);
+        entry.request_id = None;
+        entry.client = None;
+        log.record(&entry);
+
+        let lines = buffer.lines();
+        assert_eq!(lines.len(), 2);
+        assert!(lines[1].ends_with("origin - \"GET /orders\" 200 42B 1.5ms id=-"));
+    }
+
+    #[test]
+    fn test_log_format_from_str() {
+        assert_eq!(LogFormat::from_str("JSON").unwrap(), LogFormat::Json);
+        assert_eq!(LogFormat::from_str("human").unwrap(), LogFormat::Human);
+        assert_eq!(
+            LogFormat::from_str("xml").unwrap_err().to_string(),
+            "Invalid configuration: unknown log format \"xml\""
+        );
+    }
+
+    #[test]
+    fn test_log_level() {
+        assert_eq!(LogLevel::from_str("WARN").unwrap(), LogLevel::Warn);
+        assert_eq!(
+            LogLevel::from_str("loud").unwrap_err().to_string(),
+            "Invalid configuration: unknown log level \"loud\""
+        );
+        assert!(LogLevel::Error < LogLevel::Info);
+        assert!(LogLevel::Debug > LogLevel::default());
+    }
+}
@@ -0,0 +1,395 @@
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::sync::atomic::{AtomicI64, Ordering};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use chrono::{DateTime, Utc};
+
+use crate::db::{BatchMode, BatchOutcome};
+use crate::error::AspirinEatsError;
+use crate::food::{MenuItem, Order, OrderStatus};
+use crate::pricing::PriceBreakdown;
+use crate::store::OrderStore;
+
+/// Path the origin serves its metrics on
+pub const METRICS_PATH: &str = "/metrics";
+
+/// Path the proxy serves its own metrics on, out of the way of the origin's, which it forwards
+pub const PROXY_METRICS_PATH: &str = "/proxy/metrics";
+
+/// Route label for requests that don't match any route, including ones that couldn't be parsed,
+/// so made-up paths can't create new time series
+pub const UNMATCHED_ROUTE: &str = "unmatched";
+
+/// Content type of the Prometheus text exposition format
+pub const METRICS_CONTENT_TYPE: &str = "text/plain; version=0.0.4";
+
+/// Upper bounds (in seconds) of the latency histogram buckets
+const BUCKETS: [f64; 11] = [
+    0.0005, 0.001, 0.0025, 0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0,
+];
+
+/// Latency histogram with fixed buckets
+#[derive(Debug, Default, Clone)]
+struct Histogram {
+    /// Number of observations that fell in each bucket (not cumulative)
+    buckets: [u64; BUCKETS.len()],
+    count: u64,
+    sum: f64,
+}
+
+impl Histogram {
+    fn observe(&mut self, duration: Duration) {
+        let seconds = duration.as_secs_f64();
+        if let Some(bucket) = BUCKETS.iter().position(|&bound| seconds <= bound) {
+            self.buckets[bucket] += 1;
+        }
+        self.count += 1;
+        self.sum += seconds;
+    }
+
+    fn render(&self, out: &mut String, name: &str, labels: &str) {
+        let mut cumulative = 0;
+        for (bound, count) in BUCKETS.iter().zip(self.buckets) {
+            cumulative += count;
+            let _ = writeln!(out, "{name}_bucket{{{labels},le=\"{bound}\"}} {cumulative}");
+        }
+        let _ = writeln!(out, "{name}_bucket{{{labels},le=\"+Inf\"}} {}", self.count);
+        let _ = writeln!(out, "{name}_sum{{{labels}}} {}", self.sum);
+        let _ = writeln!(out, "{name}_count{{{labels}}} {}", self.count);
+    }
+}
+
+#[derive(Debug, Default)]
+struct Recorded {
+    /// Request counts by (route, status)
+    requests: BTreeMap<(String, u16), u64>,
+
+    /// Request latency by route
+    request_latency: BTreeMap<String, Histogram>,
+
+    /// Database query latency by operation
+    db_latency: BTreeMap<String, Histogram>,
+
+    /// Failures talking to the origin, by kind
+    upstream_errors: BTreeMap<String, u64>,
+}
+
+/// Metrics for a single server, rendered in the Prometheus text format
+#[derive(Debug)]
+pub struct Metrics {
+    service: String,
+    in_flight: AtomicI64,
+    recorded: Mutex<Recorded>,
+}
+
+impl Metrics {
+    pub fn new(service: &str) -> Self {
+        Metrics {
+            service: service.to_string(),
+            in_flight: AtomicI64::new(0),
+            recorded: Mutex::new(Recorded::default()),
+        }
+    }
+
+    fn recorded(&self) -> std::sync::MutexGuard<'_, Recorded> {
+        self.recorded.lock().expect("metrics lock poisoned")
+    }
+
+    /// Count a connection as in flight until the returned guard is dropped
+    pub fn track_connection(&self) -> InFlightGuard<'_> {
+        self.in_flight.fetch_add(1, Ordering::SeqCst);
+        InFlightGuard { metrics: self }
+    }
+
+    /// Record a finished request to `route`, the template of the route it matched (e.g.
+    /// `/orders/{id}`) or [`UNMATCHED_ROUTE`]
+    pub fn record_request(&self, route: &str, status: u16, latency: Duration) {
+        let route = route.to_string();
+        let mut recorded = self.recorded();
+        *recorded
+            .requests
+            .entry((route.clone(), status))
+            .or_default() += 1;
+        recorded
+            .request_latency
+            .entry(route)
+            .or_default()
+            .observe(latency);
+    }
+
+    /// Run a database operation, recording how long it took
+    pub fn time_db<T>(&self, operation: &str, query: impl FnOnce() -> T) -> T {
+        let start = Instant::now();
+        let result = query();
+        self.recorded()
+            .db_latency
+            .entry(operation.to_string())
+            .or_default()
+            .observe(start.elapsed());
+        result
+    }
+
+    /// Count a failure talking to the origin, e.g. "connect" or "read"
+    pub fn record_upstream_error(&self, kind: &str) {
+        *self
+            .recorded()
+            .upstream_errors
+            .entry(kind.to_string())
+            .or_default() += 1;
+    }
+
+    /// Render every metric in the Prometheus text exposition format
+    pub fn render(&self) -> String {
+        let recorded = self.recorded();
+        let service = escape_label(&self.service);
+        let mut out = String::new();
+
+        out.push_str(
+            "# HELP aspirin_eats_http_requests_total Requests handled, by route and status\n",
+        );
+        out.push_str("# TYPE aspirin_eats_http_requests_total counter\n");
+        for ((route, status), count) in &recorded.requests {
+            let _ = writeln!(
+                out,
+                "aspirin_eats_http_requests_total{{service=\"{service}\",route=\"{}\",status=\"{status}\"}} {count}",
+                escape_label(route)
+            );
+        }
+
+        out.push_str(
+            "# HELP aspirin_eats_http_request_duration_seconds Time taken to handle requests\n",
+        );
+        out.push_str("# TYPE aspirin_eats_http_request_duration_seconds histogram\n");
+        for (route, histogram) in &recorded.request_latency {
+            histogram.render(
+                &mut out,
+                "aspirin_eats_http_request_duration_seconds",
+                &format!("service=\"{service}\",route=\"{}\"", escape_label(route)),
+            );
+        }
+
+        out.push_str(
+            "# HELP aspirin_eats_in_flight_connections Connections currently being handled\n",
+        );
+        out.push_str("# TYPE aspirin_eats_in_flight_connections gauge\n");
+        let _ = writeln!(
+            out,
+            "aspirin_eats_in_flight_connections{{service=\"{service}\"}} {}",
+            self.in_flight.load(Ordering::SeqCst)
+        );
+
+        out.push_str(
+            "# HELP aspirin_eats_db_query_duration_seconds Time taken by database operations\n",
+        );
+        out.push_str("# TYPE aspirin_eats_db_query_duration_seconds histogram\n");
+        for (operation, histogram) in &recorded.db_latency {
+            histogram.render(
+                &mut out,
+                "aspirin_eats_db_query_duration_seconds",
+                &format!(
+                    "service=\"{service}\",operation=\"{}\"",
+                    escape_label(operation)
+                ),
+            );
+        }
+
+        out.push_str(
+            "# HELP aspirin_eats_upstream_errors_total Failures talking to the origin server\n",
+        );
+        out.push_str("# TYPE aspirin_eats_upstream_errors_total counter\n");
+        for (kind, count) in &recorded.upstream_errors {
+            let _ = writeln!(
+                out,
+                "aspirin_eats_upstream_errors_total{{service=\"{service}\",kind=\"{}\"}} {count}",
+                escape_label(kind)
+            );
+        }
+
+        out
+    }
+}
+
+/// Marks a connection as in flight for as long as it is alive
+pub struct InFlightGuard<'a> {
+    metrics: &'a Metrics,
+}
+
+impl Drop for InFlightGuard<'_> {
+    fn drop(&mut self) {
+        self.metrics.in_flight.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+fn escape_label(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+/// An OrderStore that records how long each operation on the wrapped store takes
+pub struct InstrumentedStore<'a, S> {
+    store: &'a S,
+    metrics: &'a Metrics,
+}
+
+impl<'a, S: OrderStore> InstrumentedStore<'a, S> {
+    pub fn new(store: &'a S, metrics: &'a Metrics) -> Self {
+        InstrumentedStore { store, metrics }
+    }
+}
+
+impl<S: OrderStore> OrderStore for InstrumentedStore<'_, S> {
+    fn add_order(&self, order: Order) -> Result<i64, AspirinEatsError> {
+        self.metrics
+            .time_db("add_order", || self.store.add_order(order))
+    }
+
+    fn add_orders(
+        &self,
+        orders: Vec<Order>,
+        mode: BatchMode,
+    ) -> Result<BatchOutcome, AspirinEatsError> {
+        self.metrics
+            .time_db("add_orders", || self.store.add_orders(orders, mode))
+    }
+
+    fn get_order(&self, id: i64) -> Result<Option<Order>, AspirinEatsError> {
+        self.metrics
+            .time_db("get_order", || self.store.get_order(id))
+    }
+
+    fn get_all_orders(&self) -> Result<Vec<Order>, AspirinEatsError> {
+        self.metrics
+            .time_db("get_all_orders", || self.store.get_all_orders())
+    }
+
+    fn update_order(&self, order: &Order) -> Result<bool, AspirinEatsError> {
+        self.metrics
+            .time_db("update_order", || self.store.update_order(order))
+    }
+
+    fn set_status(
+        &self,
+        id: i64,
+        from: OrderStatus,
+        to: OrderStatus,
+        food: &[MenuItem],
+    ) -> Result<bool, AspirinEatsError> {
+        self.metrics
+            .time_db("set_status", || self.store.set_status(id, from, to, food))
+    }
+
+    fn get_order_version(&self, id: i64) -> Result<Option<i64>, AspirinEatsError> {
+        self.metrics
+            .time_db("get_order_version", || self.store.get_order_version(id))
+    }
+
+    fn orders_last_modified(&self) -> Result<Option<DateTime<Utc>>, AspirinEatsError> {
+        self.metrics
+            .time_db("orders_last_modified", || self.store.orders_last_modified())
+    }
+
+    fn replace_items(
+        &self,
+        id: i64,
+        version: i64,
+        food: &[MenuItem],
+        breakdown: &PriceBreakdown,
+    ) -> Result<i64, AspirinEatsError> {
+        self.metrics.time_db("replace_items", || {
+            self.store.replace_items(id, version, food, breakdown)
+        })
+    }
+
+    fn remove_order(&self, id: i64) -> Result<(), AspirinEatsError> {
+        self.metrics
+            .time_db("remove_order", || self.store.remove_order(id))
+    }
+
+    fn reset_orders(&self) -> Result<(), AspirinEatsError> {
+        self.metrics
+            .time_db("reset_orders", || self.store.reset_orders())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::store::InMemoryStore;
+
+    #[test]
+    fn test_request_counts_and_histogram() {
+        let metrics = Metrics::new("origin");
+        metrics.record_request("/orders/{id}", 200, Duration::from_micros(700));
+        metrics.record_request("/orders/{id}", 200, Duration::from_millis(30));
+        metrics.record_request("/orders/{id}", 404, Duration::from_secs(2));
+
+        let out = metrics.render();
+        assert!(out.contains(
+            "aspirin_eats_http_requests_total{service=\"origin\",route=\"/orders/{id}\",status=\"200\"} 2\n"
+        ));
+        assert!(out.contains(
+            "aspirin_eats_http_requests_total{service=\"origin\",route=\"/orders/{id}\",status=\"404\"} 1\n"
+        ));
+        let labels = "service=\"origin\",route=\"/orders/{id}\"";
+        assert!(out.contains(&format!(
+            "aspirin_eats_http_request_duration_seconds_bucket{{{labels},le=\"0.0005\"}} 0\n"
+        )));
+        assert!(out.contains(&format!(
+            "aspirin_eats_http_request_duration_seconds_bucket{{{labels},le=\"0.001\"}} 1\n"
+        )));
+        assert!(out.contains(&format!(
+            "aspirin_eats_http_request_duration_seconds_bucket{{{labels},le=\"0.05\"}} 2\n"
+        )));
+        assert!(out.contains(&format!(
+            "aspirin_eats_http_request_duration_seconds_bucket{{{labels},le=\"1\"}} 2\n"
+        )));
+        assert!(out.contains(&format!(
+            "aspirin_eats_http_request_duration_seconds_bucket{{{labels},le=\"+Inf\"}} 3\n"
+        )));
+        assert!(out.contains(&format!(
+            "aspirin_eats_http_request_duration_seconds_count{{{labels}}} 3\n"
+        )));
+    }
+
+    #[test]
+    fn test_in_flight_connections() {
+        let metrics = Metrics::new("proxy");
+        let gauge = |metrics: &Metrics| {
+            metrics
+                .render()
+                .lines()
+                .find(|line| line.starts_with("aspirin_eats_in_flight_connections{"))
+                .unwrap()
+                .to_string()
+        };
+
+        let first = metrics.track_connection();
+        let second = metrics.track_connection();
+        assert!(gauge(&metrics).ends_with(" 2"));
+        drop(first);
+        drop(second);
+        assert!(gauge(&metrics).ends_with(" 0"));
+    }
+
+    #[test]
+    fn test_instrumented_store_and_upstream_errors() {
+        let metrics = Metrics::new("origin");
+        let store = InMemoryStore::new();
+        let instrumented = InstrumentedStore::new(&store, &metrics);
+        instrumented.get_all_orders().unwrap();
+        instrumented.get_all_orders().unwrap();
+        metrics.record_upstream_error("connect");
+
+        let out = metrics.render();
+        assert!(out.contains(
+            "aspirin_eats_db_query_duration_seconds_count{service=\"origin\",operation=\"get_all_orders\"} 2\n"
+        ));
+        assert!(out.contains(
+            "aspirin_eats_upstream_errors_total{service=\"origin\",kind=\"connect\"} 1\n"
+        ));
+    }
+}
@@ -0,0 +1,331 @@
+use std::sync::OnceLock;
+
+use schemars::gen::SchemaSettings;
+use serde_json::{json, Map, Value};
+
+use crate::db::{StockChange, StockLevel};
+use crate::drivers::{ClaimRequest, Driver, DriverRequest, ShiftRequest};
+use crate::food::{BatchReport, Customer, ItemsRequest, Order, OrderRequest};
+use crate::pricing::Menu;
+use crate::reports::SalesReport;
+
+/// Path the OpenAPI document is served on
+pub const OPENAPI_PATH: &str = "/openapi.json";
+
+/// The OpenAPI document as checked in, which `document` has to match
+pub const CHECKED_IN: &str = include_str!("../openapi.json");
+
+/// OpenAPI 3 description of the orders API. The routes are described here, but every schema is
+/// derived from the types the API (de)serializes, so the two can't drift apart
+pub fn document() -> &'static str {
+    static DOCUMENT: OnceLock<String> = OnceLock::new();
+    DOCUMENT.get_or_init(|| {
+        let mut document = serde_json::to_string_pretty(&spec()).expect("spec is valid JSON");
+        document.push('\n');
+        document
+    })
+}
+
+fn spec() -> Value {
+    let mut generator = SchemaSettings::openapi3().into_generator();
+    let schema = |name: &str, schema: schemars::schema::Schema| {
+        (name.to_string(), serde_json::to_value(schema).unwrap())
+    };
+    let refs = Map::from_iter([
+        schema("Menu", generator.subschema_for::<Menu>()),
+        schema("Order", generator.subschema_for::<Order>()),
+        schema("OrderRequest", generator.subschema_for::<OrderRequest>()),
+        schema("BatchReport", generator.subschema_for::<BatchReport>()),
+        schema("ItemsRequest", generator.subschema_for::<ItemsRequest>()),
+        schema("Customer", generator.subschema_for::<Customer>()),
+        schema("SalesReport", generator.subschema_for::<SalesReport>()),
+        schema("StockLevel", generator.subschema_for::<StockLevel>()),
+        schema("StockChange", generator.subschema_for::<StockChange>()),
+        schema("Driver", generator.subschema_for::<Driver>()),
+        schema("DriverRequest", generator.subschema_for::<DriverRequest>()),
+        schema("ShiftRequest", generator.subschema_for::<ShiftRequest>()),
+        schema("ClaimRequest", generator.subschema_for::<ClaimRequest>()),
+    ]);
+    let list = |name: &str| json!({"type": "array", "items": refs[name]});
+    let id = json!({
+        "name": "id",
+        "in": "path",
+        "required": true,
+        "schema": {"type": "integer", "format": "int64"}
+    });
+    let mut start_shift = operation(
+        "Go on shift, optionally saying where from",
+        Some(refs["ShiftRequest"].clone()),
+        200,
+        Some(refs["Driver"].clone()),
+    );
+    start_shift["requestBody"]["required"] = json!(false);
+    let mut add_orders = operation(
+        "Place several orders in one transaction, saying which couldn't be placed",
+        Some(list("OrderRequest")),
+        201,
+        Some(refs["BatchReport"].clone()),
+    );
+    add_orders["responses"]["422"] = json!({
+        "description": "None of the orders were placed",
+        "content": {"application/json": {"schema": refs["BatchReport"]}}
+    });
+
+    json!({
+        "openapi": "3.0.3",
+        "info": {
+            "title": "Aspirin Eats",
+            "version": env!("CARGO_PKG_VERSION"),
+            "description": "Orders API served by the origin. Errors are answered with a plain \
+                text message and the matching status code. Every successful GET has an ETag, and \
+                is answered with 304 Not Modified when If-None-Match lists it"
+        },
+        "paths": {
+            "/menu": {
+                "get": operation(
+                    "What everything on the menu costs, and the combos on offer",
+                    None,
+                    200,
+                    Some(refs["Menu"].clone()),
+                ),
+            },
+            "/orders": {
+                "get": operation(
+                    "List every order. Last-Modified says when any order last changed, for \
+                        polling with If-Modified-Since",
+                    None,
+                    200,
+                    Some(list("Order")),
+                ),
+                "post": operation(
+                    "Place an order, priced by the server",
+                    Some(refs["OrderRequest"].clone()),
+                    201,
+                    Some(refs["Order"].clone()),
+                ),
+                "delete": operation("Remove every order", None, 200, None),
+            },
+            "/orders/batch": {
+                "parameters": [query(
+                    "mode",
+                    "Whether one order failing stops the rest being placed",
+                    json!({
+                        "type": "string",
+                        "enum": ["all_or_nothing", "best_effort"],
+                        "default": "all_or_nothing"
+                    }),
+                )],
+                "post": add_orders,
+            },
+            "/orders/{id}": {
+                "parameters": [id],
+                "get": operation("Get an order", None, 200, Some(refs["Order"].clone())),
+                "delete": operation("Remove an order", None, 200, None),
+            },
+            "/orders/{id}/items": {
+                "parameters": [
+                    id,
+                    {
+                        "name": "If-Match",
+                        "in": "header",
+                        "required": true,
+                        "description": "ETag the order was fetched with. 412 if it has changed \
+                            since, 428 if left out",
+                        "schema": {"type": "string"}
+                    }
+                ],
+                "put": operation(
+                    "Replace the food in a pending order and price it again. 409 once the order \
+                        has been started",
+                    Some(refs["ItemsRequest"].clone()),
+                    200,
+                    Some(refs["Order"].clone()),
+                ),
+            },
+            "/customers": {
+                "get": operation("List every customer", None, 200, Some(list("Customer"))),
+            },
+            "/customers/{id}": {
+                "parameters": [id],
+                "get": operation("Get a customer", None, 200, Some(refs["Customer"].clone())),
+            },
+            "/customers/{id}/orders": {
+                "parameters": [id],
+                "get": operation(
+                    "List a customer's orders, oldest first",
+                    None,
+                    200,
+                    Some(list("Order")),
+                ),
+            },
+            "/reports/sales": {
+                "parameters": [
+                    query("from", "First day to include", json!({"type": "string", "format": "date"})),
+                    query("to", "Last day to include", json!({"type": "string", "format": "date"})),
+                    query(
+                        "group_by",
+                        "What to break the report down by",
+                        json!({"type": "string", "enum": ["day", "item", "status"], "default": "day"}),
+                    ),
+                    query(
+                        "format",
+                        "json, or csv for just the groups",
+                        json!({"type": "string", "enum": ["json", "csv"], "default": "json"}),
+                    ),
+                ],
+                "get": operation("Sales report", None, 200, Some(refs["SalesReport"].clone())),
+            },
+            "/inventory": {
+                "get": operation(
+                    "Stock of every tracked ingredient. Untracked ingredients never run out",
+                    None,
+                    200,
+                    Some(list("StockLevel")),
+                ),
+                "put": operation(
+                    "Set the stock of some ingredients, tracking them if they weren't yet",
+                    Some(list("StockLevel")),
+                    200,
+                    Some(list("StockLevel")),
+                ),
+                "post": operation(
+                    "Add to or take from the stock of some ingredients",
+                    Some(list("StockChange")),
+                    200,
+                    Some(list("StockLevel")),
+                ),
+            },
+            "/drivers": {
+                "get": operation("List every driver", None, 200, Some(list("Driver"))),
+                "post": operation(
+                    "Sign up a driver, who starts off shift",
+                    Some(refs["DriverRequest"].clone()),
+                    201,
+                    Some(refs["Driver"].clone()),
+                ),
+            },
+            "/drivers/{id}": {
+                "parameters": [id],
+                "get": operation("Get a driver", None, 200, Some(refs["Driver"].clone())),
+            },
+            "/drivers/{id}/shift": {
+                "parameters": [id],
+                "post": start_shift,
+                "delete": operation(
+                    "Go off shift. Refused with 409 while out with an order",
+                    None,
+                    200,
+                    Some(refs["Driver"].clone()),
+                ),
+            },
+            "/drivers/{id}/claim": {
+                "parameters": [id],
+                "post": operation(
+                    "Take an order that's out of the kitchen to deliver. Refused with 409 if the \
+                     driver isn't free or the order isn't ready",
+                    Some(refs["ClaimRequest"].clone()),
+                    200,
+                    Some(refs["Order"].clone()),
+                ),
+            },
+            "/drivers/{id}/complete": {
+                "parameters": [id],
+                "post": operation(
+                    "Finish the driver's delivery, completing its order",
+                    None,
+                    200,
+                    Some(refs["Order"].clone()),
+                ),
+            },
+        },
+        "components": {
+            "schemas": generator.take_definitions(),
+        },
+    })
+}
+
+/// An operation that takes `request` as its JSON body (if any), and answers `status` with
+/// `response` as its JSON body (or a plain text message if there isn't one)
+fn operation(summary: &str, request: Option<Value>, status: u16, response: Option<Value>) -> Value {
+    let content = match response {
+        Some(schema) => json!({"application/json": {"schema": schema}}),
+        None => json!({"text/plain": {"schema": {"type": "string"}}}),
+    };
+    let mut operation = json!({
+        "summary": summary,
+        "responses": {
+            status.to_string(): {"description": summary, "content": content},
+            "default": {
+                "description": "Error",
+                "content": {"text/plain": {"schema": {"type": "string"}}}
+            }
+        }
+    });
+    if let Some(schema) = request {
+        operation["requestBody"] = json!({
+            "required": true,
+            "content": {"application/json": {"schema": schema}}
+        });
+    }
+    operation
+}
+
+fn query(name: &str, description: &str, schema: Value) -> Value {
+    json!({"name": name, "in": "query", "description": description, "schema": schema})
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Set to regenerate `openapi.json` instead of checking it
+    const UPDATE_ENV_VAR: &str = "UPDATE_OPENAPI";
+
+    #[test]
+    fn test_checked_in_document_is_up_to_date() {
+        if std::env::var_os(UPDATE_ENV_VAR).is_some() {
+            let path = concat!(env!("CARGO_MANIFEST_DIR"), "/openapi.json");
+            std::fs::write(path, document()).unwrap();
+            return;
+        }
+        assert!(
+            document() == CHECKED_IN,
+            "openapi.json is out of date with the API's types. Regenerate it with \
+             `{UPDATE_ENV_VAR}=1 cargo test openapi` and check in the result"
+        );
+    }
+
+    #[test]
+    fn test_schemas_match_serde() {
+        let spec: Value = serde_json::from_str(document()).unwrap();
+        let schemas = &spec["components"]["schemas"];
+
+        // Unit variants are plain strings, Burger is externally tagged
+        assert_eq!(
+            schemas["OrderStatus"]["enum"],
+            json!([
+                "Scheduled",
+                "Pending",
+                "Preparing",
+                "Transporting",
+                "Completed",
+                "Cancelled"
+            ])
+        );
+        let menu_item = schemas["MenuItem"]["oneOf"].as_array().unwrap();
+        assert_eq!(menu_item[0]["enum"], json!(["Fries", "Drink"]));
+        assert_eq!(
+            menu_item[1]["properties"]["Burger"]["$ref"],
+            "#/components/schemas/Burger"
+        );
+        assert_eq!(
+            schemas["Burger"]["required"],
+            json!(["bun", "patty", "toppings"])
+        );
+        assert_eq!(
+            spec["paths"]["/orders"]["post"]["requestBody"]["content"]["application/json"]
+                ["schema"]["$ref"],
+            "#/components/schemas/OrderRequest"
+        );
+    }
+}
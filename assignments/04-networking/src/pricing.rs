@@ -0,0 +1,651 @@
+use std::str::FromStr;
+
+use chrono::NaiveDate;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+
+use crate::delivery::{Address, DeliveryPolicy};
+use crate::error::AspirinEatsError;
+use crate::food::*;
+
+/// The kinds of menu item that a combo rule can ask for
+#[derive(Serialize, Deserialize, JsonSchema, Debug, PartialEq, Eq, Clone, Copy)]
+pub enum ItemKind {
+    Burger,
+    Fries,
+    Drink,
+}
+
+impl From<&MenuItem> for ItemKind {
+    fn from(item: &MenuItem) -> Self {
+        match item {
+            MenuItem::Burger(_) => ItemKind::Burger,
+            MenuItem::Fries => ItemKind::Fries,
+            MenuItem::Drink => ItemKind::Drink,
+        }
+    }
+}
+
+/// A discount that is applied once for every complete set of `items` found in an order
+#[derive(Serialize, Deserialize, JsonSchema, Debug, PartialEq, Clone)]
+pub struct ComboRule {
+    /// Name shown in the price breakdown
+    pub name: String,
+
+    /// Items that make up one combo. Each item in the order counts towards at most one combo
+    pub items: Vec<ItemKind>,
+
+    /// Amount taken off for each combo found
+    pub discount: f64,
+}
+
+impl ComboRule {
+    /// The burger + fries + drink meal deal
+    pub fn meal() -> Self {
+        ComboRule {
+            name: "Meal".to_string(),
+            items: vec![ItemKind::Burger, ItemKind::Fries, ItemKind::Drink],
+            discount: 2.0,
+        }
+    }
+}
+
+/// How much a coupon takes off the order
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone)]
+pub enum CouponKind {
+    /// Percentage (0-100) off the discounted subtotal
+    Percent(f64),
+
+    /// Fixed amount off the discounted subtotal, never taking it below zero
+    Fixed(f64),
+}
+
+/// A coupon code that can be redeemed up to and including its expiry date
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone)]
+pub struct Coupon {
+    pub code: String,
+    pub kind: CouponKind,
+    pub expires: NaiveDate,
+}
+
+impl FromStr for Coupon {
+    type Err = String;
+
+    /// Parse a coupon written as `CODE:AMOUNT:EXPIRES`, as coupons are given in environment
+    /// variables and flags. An amount ending in `%` is a percentage, e.g. `SAVE10:10%:2025-12-31`,
+    /// and anything else a fixed amount, e.g. `FIVE:5:2025-12-31`
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || format!("expected CODE:AMOUNT:YYYY-MM-DD, got {s:?}");
+        let mut parts = s.trim().split(':');
+        let (Some(code), Some(amount), Some(expires), None) =
+            (parts.next(), parts.next(), parts.next(), parts.next())
+        else {
+            return Err(invalid());
+        };
+        let kind = match amount.strip_suffix('%') {
+            Some(percent) => CouponKind::Percent(percent.parse().map_err(|_| invalid())?),
+            None => CouponKind::Fixed(amount.parse().map_err(|_| invalid())?),
+        };
+        Ok(Coupon {
+            code: code.to_string(),
+            kind,
+            expires: expires.parse().map_err(|_| invalid())?,
+        })
+    }
+}
+
+/// The `[pricing]` settings: what's charged on top of the menu, and what can be taken off it
+#[derive(Deserialize, Debug, PartialEq, Clone, Default)]
+#[serde(default, deny_unknown_fields)]
+pub struct PricingPolicy {
+    /// Tax rate as a fraction, e.g. 0.0625 for 6.25%
+    pub tax_rate: f64,
+
+    /// Coupons that customers can redeem
+    pub coupons: Vec<Coupon>,
+}
+
+impl PricingPolicy {
+    /// Problems with the policy, for config validation. Expired coupons aren't one, since
+    /// redeeming them already fails; see [`PricingPolicy::expired`]
+    pub fn problems(&self) -> Vec<String> {
+        let mut problems = Vec::new();
+        if !(0.0..=1.0).contains(&self.tax_rate) {
+            problems.push("pricing.tax_rate: must be between 0 and 1".to_string());
+        }
+        for (i, coupon) in self.coupons.iter().enumerate() {
+            let code = &coupon.code;
+            if code.trim().is_empty() {
+                problems.push("pricing.coupons: every coupon needs a code".to_string());
+            } else if self.coupons[..i]
+                .iter()
+                .any(|other| other.code.eq_ignore_ascii_case(code))
+            {
+                problems.push(format!("pricing.coupons: {code:?} is listed twice"));
+            }
+            match coupon.kind {
+                CouponKind::Percent(percent) if !(0.0..=100.0).contains(&percent) => {
+                    problems.push(format!(
+                        "pricing.coupons: {code:?} must take off between 0 and 100%"
+                    ));
+                }
+                CouponKind::Fixed(amount) if !amount.is_finite() || amount < 0.0 => {
+                    problems.push(format!(
+                        "pricing.coupons: {code:?} must not take off a negative amount"
+                    ));
+                }
+                _ => {}
+            }
+        }
+        problems
+    }
+
+    /// Coupons that have expired as of `today`, which customers can no longer redeem
+    pub fn expired(&self, today: NaiveDate) -> Vec<&Coupon> {
+        self.coupons
+            .iter()
+            .filter(|coupon| coupon.expires < today)
+            .collect()
+    }
+}
+
+/// A choice on the menu, e.g. a bun or a side, and what it costs
+#[derive(Serialize, Deserialize, JsonSchema, Debug, PartialEq, Clone)]
+pub struct MenuPrice {
+    pub name: String,
+    pub price: f64,
+}
+
+impl MenuPrice {
+    fn new<T: Serialize>(choice: &T, price: f64) -> Self {
+        MenuPrice {
+            name: serde_json::to_value(choice)
+                .ok()
+                .and_then(|value| value.as_str().map(str::to_string))
+                .unwrap_or_default(),
+            price: round_cents(price),
+        }
+    }
+}
+
+/// Everything on the menu and what it costs on its own, with the combos that take money off
+#[derive(Serialize, Deserialize, JsonSchema, Debug, PartialEq, Clone)]
+pub struct Menu {
+    pub buns: Vec<MenuPrice>,
+    pub patties: Vec<MenuPrice>,
+    pub toppings: Vec<MenuPrice>,
+    pub sides: Vec<MenuPrice>,
+    pub combos: Vec<ComboRule>,
+}
+
+/// A single priced item in an order
+#[derive(Serialize, Deserialize, JsonSchema, Debug, PartialEq, Clone)]
+pub struct LineItem {
+    pub item: MenuItem,
+    pub price: f64,
+}
+
+/// A single discount applied to an order
+#[derive(Serialize, Deserialize, JsonSchema, Debug, PartialEq, Clone)]
+pub struct Discount {
+    pub description: String,
+    pub amount: f64,
+}
+
+/// Itemized price of an order, from line items down to the total
+#[derive(Serialize, Deserialize, JsonSchema, Debug, PartialEq, Clone)]
+pub struct PriceBreakdown {
+    /// Price of every item in the order, in order
+    pub lines: Vec<LineItem>,
+
+    /// Sum of all of the line items
+    pub subtotal: f64,
+
+    /// Combo and coupon discounts, in the order they were applied
+    pub discounts: Vec<Discount>,
+
+    /// Tax charged on the subtotal after discounts
+    pub tax: f64,
+
+    /// Charged for delivering the order, on top of tax
+    #[serde(default)]
+    pub delivery_fee: f64,
+
+    /// Amount the customer pays
+    pub total: f64,
+}
+
+/// The pricing pipeline: line items, then combos, then a coupon, then tax, then delivery
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone)]
+pub struct Pricing {
+    /// Tax rate as a fraction, e.g. 0.0625 for 6.25%
+    pub tax_rate: f64,
+
+    /// Combo rules, applied in order
+    pub combos: Vec<ComboRule>,
+
+    /// Coupons that customers can redeem
+    pub coupons: Vec<Coupon>,
+
+    /// Where we deliver to, and what it costs
+    pub delivery: DeliveryPolicy,
+}
+
+impl Default for Pricing {
+    /// Standard menu pricing: the meal deal, no tax, no coupons and the default delivery area
+    fn default() -> Self {
+        Pricing {
+            tax_rate: 0.0,
+            combos: vec![ComboRule::meal()],
+            coupons: Vec::new(),
+            delivery: DeliveryPolicy::default(),
+        }
+    }
+}
+
+impl Pricing {
+    /// The menu as priced here, for showing to customers before they order
+    pub fn menu(&self) -> Menu {
+        Menu {
+            buns: Bun::ALL
+                .iter()
+                .map(|bun| MenuPrice::new(bun, bun.price()))
+                .collect(),
+            patties: Patty::ALL
+                .iter()
+                .map(|patty| MenuPrice::new(patty, patty.price()))
+                .collect(),
+            toppings: Topping::ALL
+                .iter()
+                .map(|topping| MenuPrice::new(topping, topping.price()))
+                .collect(),
+            sides: [MenuItem::Fries, MenuItem::Drink]
+                .iter()
+                .map(|side| MenuPrice::new(side, side.price()))
+                .collect(),
+            combos: self.combos.clone(),
+        }
+    }
+
+    /// Price a list of food, redeeming `coupon` if one is given. Fails if the coupon is unknown
+    /// or has expired as of `today`
+    pub fn price(
+        &self,
+        food: &[MenuItem],
+        coupon: Option<&str>,
+        today: NaiveDate,
+    ) -> Result<PriceBreakdown, AspirinEatsError> {
+        let coupon = coupon
+            .map(|code| self.find_coupon(code, today))
+            .transpose()?;
+
+        let lines: Vec<LineItem> = food
+            .iter()
+            .map(|item| LineItem {
+                item: item.clone(),
+                price: round_cents(item.price()),
+            })
+            .collect();
+        let subtotal = round_cents(lines.iter().map(|line| line.price).sum());
+
+        let mut discounts = self.combo_discounts(food);
+        let mut remaining = subtotal - discounts.iter().map(|d| d.amount).sum::<f64>();
+
+        if let Some(coupon) = coupon {
+            let amount = match coupon.kind {
+                CouponKind::Percent(percent) => remaining * percent / 100.0,
+                CouponKind::Fixed(amount) => amount,
+            };
+            let amount = round_cents(amount.clamp(0.0, remaining));
+            discounts.push(Discount {
+                description: format!("Coupon {}", coupon.code),
+                amount,
+            });
+            remaining -= amount;
+        }
+
+        let remaining = round_cents(remaining.max(0.0));
+        let tax = round_cents(remaining * self.tax_rate);
+        Ok(PriceBreakdown {
+            lines,
+            subtotal,
+            discounts,
+            tax,
+            delivery_fee: 0.0,
+            total: round_cents(remaining + tax),
+        })
+    }
+
+    /// Price a list of food like [`Pricing::price`], adding the fee for delivering it to
+    /// `delivery` if it's being delivered. Fails if we don't deliver there
+    pub fn price_delivered(
+        &self,
+        food: &[MenuItem],
+        coupon: Option<&str>,
+        delivery: Option<&Address>,
+        today: NaiveDate,
+    ) -> Result<PriceBreakdown, AspirinEatsError> {
+        let mut breakdown = self.price(food, coupon, today)?;
+        if let Some(address) = delivery {
+            breakdown.delivery_fee = round_cents(self.delivery.fee(address)?);
+            breakdown.total = round_cents(breakdown.total + breakdown.delivery_fee);
+        }
+        Ok(breakdown)
+    }
+
+    /// Create a new Order from an OrderRequest, pricing it as of `today`
+    pub fn order(
+        &self,
+        order_request: OrderRequest,
+        today: NaiveDate,
+    ) -> Result<Order, AspirinEatsError> {
+        let breakdown = self.price_delivered(
+            &order_request.food,
+            order_request.coupon.as_deref(),
+            order_request.delivery.as_ref(),
+            today,
+        )?;
+        Ok(Order {
+            id: None,
+            customer: order_request.customer,
+            food: order_request.food,
+            status: OrderStatus::placed(order_request.scheduled_for.as_ref()),
+            total: breakdown.total,
+            breakdown: Some(breakdown),
+            delivery: order_request.delivery,
+            driver: None,
+            scheduled_for: order_request.scheduled_for,
+        })
+    }
+
+    fn find_coupon(&self, code: &str, today: NaiveDate) -> Result<&Coupon, AspirinEatsError> {
+        self.coupons
+            .iter()
+            .find(|coupon| coupon.code.eq_ignore_ascii_case(code.trim()))
+            .filter(|coupon| today <= coupon.expires)
+            .ok_or_else(|| AspirinEatsError::InvalidCoupon(code.to_string()))
+    }
+
+    fn combo_discounts(&self, food: &[MenuItem]) -> Vec<Discount> {
+        let mut available: Vec<ItemKind> = food.iter().map(ItemKind::from).collect();
+        let mut discounts = Vec::new();
+
+        for combo in &self.combos {
+            if combo.items.is_empty() {
+                continue;
+            }
+            let mut count = 0;
+            while let Some(rest) = take_combo(&available, &combo.items) {
+                available = rest;
+                count += 1;
+            }
+            if count > 0 {
+                discounts.push(Discount {
+                    description: format!("{} x{}", combo.name, count),
+                    amount: round_cents(combo.discount * count as f64),
+                });
+            }
+        }
+
+        discounts
+    }
+}
+
+/// Remove one of each of `wanted` from `available`, or return None if they aren't all there
+fn take_combo(available: &[ItemKind], wanted: &[ItemKind]) -> Option<Vec<ItemKind>> {
+    let mut rest = available.to_vec();
+    for kind in wanted {
+        let index = rest.iter().position(|k| k == kind)?;
+        rest.remove(index);
+    }
+    Some(rest)
+}
+
+fn round_cents(amount: f64) -> f64 {
+    (amount * 100.0).round() / 100.0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::delivery::{DeliveryZone, Location};
+
+    fn burger() -> MenuItem {
+        MenuItem::Burger(Burger::new(
+            Bun::Sesame,
+            Patty::Beef,
+            vec![Topping::Cheese, Topping::Bacon],
+        ))
+    }
+
+    fn date(year: i32, month: u32, day: u32) -> NaiveDate {
+        NaiveDate::from_ymd_opt(year, month, day).unwrap()
+    }
+
+    fn get_test_pricing() -> Pricing {
+        Pricing {
+            tax_rate: 0.1,
+            combos: vec![ComboRule::meal()],
+            coupons: vec![
+                Coupon {
+                    code: "HALF".to_string(),
+                    kind: CouponKind::Percent(50.0),
+                    expires: date(2024, 12, 31),
+                },
+                Coupon {
+                    code: "FIVE".to_string(),
+                    kind: CouponKind::Fixed(5.0),
+                    expires: date(2024, 12, 31),
+                },
+            ],
+            delivery: DeliveryPolicy {
+                restaurant: Location { lat: 0.0, lon: 0.0 },
+                zones: vec![DeliveryZone::Radius {
+                    name: "Nearby".to_string(),
+                    km: 10.0,
+                }],
+                base_fee: 2.0,
+                fee_per_km: 1.0,
+            },
+        }
+    }
+
+    fn address(lon: f64) -> Address {
+        Address {
+            street: "1 Main St".to_string(),
+            city: "Springfield".to_string(),
+            postcode: "12345".to_string(),
+            location: Location { lat: 0.0, lon },
+        }
+    }
+
+    #[test]
+    fn test_line_items_and_subtotal() {
+        let pricing = Pricing {
+            tax_rate: 0.0,
+            combos: vec![],
+            coupons: vec![],
+            ..Pricing::default()
+        };
+        let got = pricing
+            .price(&[burger(), MenuItem::Fries], None, date(2024, 1, 1))
+            .unwrap();
+        assert_eq!(
+            got.lines.iter().map(|l| l.price).collect::<Vec<_>>(),
+            vec![12.0, 5.0]
+        );
+        assert_eq!(got.subtotal, 17.0);
+        assert!(got.discounts.is_empty());
+        assert_eq!(got.total, 17.0);
+    }
+
+    #[test]
+    fn test_menu() {
+        let menu = Pricing::default().menu();
+        assert_eq!(
+            menu.buns[2],
+            MenuPrice {
+                name: "GlutenFree".to_string(),
+                price: 2.0
+            }
+        );
+        assert_eq!(menu.patties.len(), 3);
+        assert_eq!(menu.toppings.len(), 6);
+        assert_eq!(
+            serde_json::to_value(&menu.sides).unwrap(),
+            serde_json::json!([{"name": "Fries", "price": 5.0}, {"name": "Drink", "price": 3.0}])
+        );
+        assert_eq!(menu.combos, vec![ComboRule::meal()]);
+
+        // Every listed price is what an order is charged for it
+        let burger = Burger::new(Bun::GlutenFree, Patty::Veggie, vec![Topping::Bacon]);
+        assert_eq!(
+            MenuItem::Burger(burger).price(),
+            menu.buns[2].price + menu.patties[2].price + menu.toppings[5].price
+        );
+    }
+
+    #[test]
+    fn test_meal_combo() {
+        let food = vec![burger(), MenuItem::Fries, MenuItem::Drink, MenuItem::Drink];
+        let got = Pricing::default()
+            .price(&food, None, date(2024, 1, 1))
+            .unwrap();
+        assert_eq!(got.subtotal, 23.0);
+        assert_eq!(
+            got.discounts,
+            vec![Discount {
+                description: "Meal x1".to_string(),
+                amount: 2.0
+            }]
+        );
+        assert_eq!(got.total, 21.0);
+    }
+
+    #[test]
+    fn test_each_item_counts_towards_one_combo() {
+        let food = vec![
+            burger(),
+            burger(),
+            MenuItem::Fries,
+            MenuItem::Fries,
+            MenuItem::Drink,
+        ];
+        let got = Pricing::default()
+            .price(&food, None, date(2024, 1, 1))
+            .unwrap();
+        assert_eq!(got.discounts[0].description, "Meal x1");
+        assert_eq!(got.discounts[0].amount, 2.0);
+    }
+
+    #[test]
+    fn test_percent_coupon_applies_after_combo_and_before_tax() {
+        let food = vec![burger(), MenuItem::Fries, MenuItem::Drink];
+        let got = get_test_pricing()
+            .price(&food, Some("half"), date(2024, 6, 1))
+            .unwrap();
+        // 20 - 2 (meal) = 18, half off = 9, 10% tax = 0.9
+        assert_eq!(got.discounts[1].amount, 9.0);
+        assert_eq!(got.tax, 0.9);
+        assert_eq!(got.total, 9.9);
+    }
+
+    #[test]
+    fn test_fixed_coupon_never_goes_below_zero() {
+        let got = get_test_pricing()
+            .price(&[MenuItem::Drink], Some("FIVE"), date(2024, 6, 1))
+            .unwrap();
+        assert_eq!(got.discounts[0].amount, 3.0);
+        assert_eq!(got.tax, 0.0);
+        assert_eq!(got.total, 0.0);
+    }
+
+    #[test]
+    fn test_coupon_expiry() {
+        let pricing = get_test_pricing();
+        assert!(pricing
+            .price(&[MenuItem::Fries], Some("FIVE"), date(2024, 12, 31))
+            .is_ok());
+        assert!(matches!(
+            pricing.price(&[MenuItem::Fries], Some("FIVE"), date(2025, 1, 1)),
+            Err(AspirinEatsError::InvalidCoupon(_))
+        ));
+        assert!(matches!(
+            pricing.price(&[MenuItem::Fries], Some("BOGUS"), date(2024, 1, 1)),
+            Err(AspirinEatsError::InvalidCoupon(_))
+        ));
+    }
+
+    #[test]
+    fn test_tax_rounding() {
+        let pricing = Pricing {
+            tax_rate: 0.0625,
+            combos: vec![],
+            coupons: vec![],
+            ..Pricing::default()
+        };
+        let got = pricing
+            .price(&[MenuItem::Drink], None, date(2024, 1, 1))
+            .unwrap();
+        assert_eq!(got.tax, 0.19);
+        assert_eq!(got.total, 3.19);
+    }
+
+    #[test]
+    fn test_order_from_pricing() {
+        let order_request = OrderRequest {
+            customer: "Alice".to_string(),
+            food: vec![MenuItem::Fries],
+            coupon: Some("FIVE".to_string()),
+            delivery: None,
+            scheduled_for: None,
+        };
+        let order = get_test_pricing()
+            .order(order_request, date(2024, 1, 1))
+            .unwrap();
+        assert_eq!(order.total, 0.0);
+        assert_eq!(order.breakdown.unwrap().discounts.len(), 1);
+    }
+
+    #[test]
+    fn test_delivery_fee() {
+        let pricing = get_test_pricing();
+        let food = [MenuItem::Fries];
+        let today = date(2024, 1, 1);
+
+        let got = pricing.price_delivered(&food, None, None, today).unwrap();
+        assert_eq!(got.delivery_fee, 0.0);
+        assert_eq!(got.total, 5.5);
+
+        // 3.34km away, with the fee going on after tax
+        let got = pricing
+            .price_delivered(&food, None, Some(&address(0.03)), today)
+            .unwrap();
+        assert_eq!(got.tax, 0.5);
+        assert_eq!(got.delivery_fee, 5.34);
+        assert_eq!(got.total, 10.84);
+
+        // 11.1km away
+        assert!(matches!(
+            pricing.price_delivered(&food, None, Some(&address(0.1)), today),
+            Err(AspirinEatsError::OutsideDeliveryArea(_))
+        ));
+    }
+
+    #[test]
+    fn test_delivered_order() {
+        let order_request = OrderRequest {
+            customer: "Alice".to_string(),
+            food: vec![MenuItem::Drink],
+            coupon: None,
+            delivery: Some(address(0.0)),
+            scheduled_for: None,
+        };
+        let order = get_test_pricing()
+            .order(order_request, date(2024, 1, 1))
+            .unwrap();
+        assert_eq!(order.total, 5.3);
+        assert_eq!(order.breakdown.unwrap().delivery_fee, 2.0);
+        assert_eq!(order.delivery, Some(address(0.0)));
+    }
+}
@@ -0,0 +1,687 @@
+use std::io::{Read, Write};
+use std::net::{Shutdown, SocketAddr, TcpListener, TcpStream};
+use std::sync::Arc;
+use std::time::Instant;
+
+use chrono::Utc;
+use rustls::ServerConfig;
+
+use crate::api::route_template;
+use crate::compression::{strip_encoded_etags, CompressionPolicy};
+use crate::config::TimeoutConfig;
+use crate::error::AspirinEatsError;
+use crate::http::{read_request_with_limits, HttpRequest, HttpResponse, RequestLimits};
+use crate::logging::{new_request_id, AccessLog, AccessLogEntry, REQUEST_ID_HEADER};
+use crate::metrics::{Metrics, METRICS_CONTENT_TYPE, PROXY_METRICS_PATH, UNMATCHED_ROUTE};
+use crate::tls;
+use crate::upstream::{CircuitBreaker, UpstreamError, UpstreamPolicy};
+
+/// Reverse proxy that forwards each connection to a single origin server
+pub struct Proxy {
+    origin_addr: String,
+    access_log: Option<AccessLog>,
+    metrics: Metrics,
+    limits: RequestLimits,
+    compression: CompressionPolicy,
+    tls: Option<Arc<ServerConfig>>,
+    upstream: UpstreamPolicy,
+    breaker: CircuitBreaker,
+}
+
+impl Proxy {
+    pub fn new(origin_addr: &str) -> Self {
+        Proxy {
+            origin_addr: origin_addr.to_string(),
+            access_log: None,
+            metrics: Metrics::new("proxy"),
+            limits: RequestLimits::default(),
+            compression: CompressionPolicy::default(),
+            tls: None,
+            breaker: CircuitBreaker::new(&UpstreamPolicy::default()),
+            upstream: UpstreamPolicy::default(),
+        }
+    }
+
+    /// Time out, retry and stop calling the origin as `upstream` says
+    pub fn with_upstream(mut self, upstream: UpstreamPolicy) -> Self {
+        self.breaker = CircuitBreaker::new(&upstream);
+        self.upstream = upstream;
+        self
+    }
+
+    /// Terminate TLS on connections served through `serve_stream`. The origin is still spoken to
+    /// in plaintext
+    pub fn with_tls(mut self, config: Arc<ServerConfig>) -> Self {
+        self.tls = Some(config);
+        self
+    }
+
+    /// Refuse requests bigger than `limits`
+    pub fn with_limits(mut self, limits: RequestLimits) -> Self {
+        self.limits = limits;
+        self
+    }
+
+    /// Compress responses for clients that accept it, as `compression` allows
+    pub fn with_compression(mut self, compression: CompressionPolicy) -> Self {
+        self.compression = compression;
+        self
+    }
+
+    /// Metrics recorded by `serve_connection`, as served on `GET /proxy/metrics`
+    pub fn metrics(&self) -> &Metrics {
+        &self.metrics
+    }
+
+    /// Log every request served through `serve_connection`
+    pub fn with_access_log(mut self, access_log: AccessLog) -> Self {
+        self.access_log = Some(access_log);
+        self
+    }
+
+    /// Serve connections from `listener` on `workers` threads, never returning. A connection
+    /// holds on to its worker while the origin is retried, so at most `workers` slow requests are
+    /// served at once and the rest wait to be accepted. Connections that fail are passed to
+    /// `on_error`
+    pub fn serve<F>(
+        &self,
+        listener: &TcpListener,
+        workers: usize,
+        timeouts: &TimeoutConfig,
+        on_error: F,
+    ) where
+        F: Fn(AspirinEatsError) + Sync,
+    {
+        std::thread::scope(|scope| {
+            for _ in 0..workers {
+                scope.spawn(|| {
+                    for stream in listener.incoming() {
+                        let result = stream.map_err(Into::into).and_then(|stream| {
+                            stream.set_read_timeout(Some(timeouts.read()))?;
+                            stream.set_write_timeout(Some(timeouts.write()))?;
+                            self.serve_stream(stream)
+                        });
+                        if let Err(err) = result {
+                            on_error(err);
+                        }
+                    }
+                });
+            }
+        });
+    }
+
+    /// Serve a connection accepted by the proxy's listener, over TLS if it's enabled
+    pub fn serve_stream(&self, stream: TcpStream) -> Result<(), AspirinEatsError> {
+        let client_addr = stream.peer_addr().ok();
+        match &self.tls {
+            Some(config) => {
+                let mut stream = tls::accept(config, stream)?;
+                self.serve_connection(&mut stream, client_addr)?;
+                stream.conn.send_close_notify();
+                stream.flush()?;
+                Ok(())
+            }
+            None => self.serve_connection(stream, client_addr),
+        }
+    }
+
+    /// Read a single request from `client`, tag it with a new `X-Request-Id`, forward it to the
+    /// origin and relay the origin's response back, compressed if the client accepts it.
+    /// Malformed requests and `GET /proxy/metrics` are answered by the proxy without reaching the
+    /// origin, and an origin that can't be reached is answered with a 502, 503 or 504
+    pub fn serve_connection<C: Read + Write>(
+        &self,
+        mut client: C,
+        client_addr: Option<SocketAddr>,
+    ) -> Result<(), AspirinEatsError> {
+        let start = Instant::now();
+        let _in_flight = self.metrics.track_connection();
+        let request_id = new_request_id();
+
+        let (request, response) = match read_request_with_limits(&mut client, self.limits) {
+            Ok(request) if is_metrics_request(&request) => {
+                let response = HttpResponse::new(200, "OK", &self.metrics.render())
+                    .with_header("Content-Type", METRICS_CONTENT_TYPE)
+                    .with_header(REQUEST_ID_HEADER, &request_id);
+                (Some(request), response.to_string().into_bytes())
+            }
+            Ok(mut request) => {
+                request.set_header(REQUEST_ID_HEADER, &request_id);
+                for name in ["If-None-Match", "If-Match"] {
+                    if let Some(tags) = request.header(name).map(strip_encoded_etags) {
+                        request.set_header(name, &tags);
+                    }
+                }
+                let response = match self.forward(&request) {
+                    Ok(response) => response,
+                    Err(err @ AspirinEatsError::Upstream(_)) => {
+                        let response: HttpResponse = err.into();
+                        let response = response.with_header(REQUEST_ID_HEADER, &request_id);
+                        response.to_string().into_bytes()
+                    }
+                    Err(err) => return Err(err),
+                };
+                (Some(request), response)
+            }
+            Err(err) => {
+                let response: HttpResponse = err.into();
+                let response = response.with_header(REQUEST_ID_HEADER, &request_id);
+                (None, response.to_string().into_bytes())
+            }
+        };
+        let response = match &request {
+            Some(request) => self.compression.apply(request, response),
+            None => response,
+        };
+        client.write_all(&response)?;
+        client.flush()?;
+
+        let status = response_status(&response).unwrap_or_default();
+        let route = match &request {
+            Some(request) if is_metrics_request(request) => PROXY_METRICS_PATH,
+            Some(request) => route_template(request),
+            None => UNMATCHED_ROUTE,
+        };
+        self.metrics.record_request(route, status, start.elapsed());
+        if let Some(access_log) = &self.access_log {
+            access_log.record(&AccessLogEntry {
+                time: Utc::now(),
+                service: access_log.service().to_string(),
+                request_id: Some(request_id),
+                client: client_addr,
+                method: request
+                    .as_ref()
+                    .and_then(|r| r.method.clone())
+                    .unwrap_or_else(|| "-".to_string()),
+                path: request
+                    .as_ref()
+                    .and_then(|r| r.path.clone())
+                    .unwrap_or_else(|| "-".to_string()),
+                status,
+                bytes: response.len(),
+                latency: start.elapsed(),
+            });
+        }
+        Ok(())
+    }
+
+    /// Send `request` to the origin, retrying idempotent requests that fail and counting anything
+    /// that goes wrong as an upstream error. Fails fast while the circuit breaker is open
+    fn forward(&self, request: &HttpRequest) -> Result<Vec<u8>, AspirinEatsError> {
+        let attempts = self
+            .upstream
+            .attempts(request.method.as_deref().unwrap_or_default());
+        let mut retry = 0;
+        loop {
+            let result = self.breaker.check().and_then(|_| {
+                let result = self.try_forward(request);
+                match &result {
+                    Ok(_) => self.breaker.record_success(),
+                    Err(_) => self.breaker.record_failure(),
+                }
+                result
+            });
+            match result {
+                Ok(response) => return Ok(response),
+                Err(err) => {
+                    self.metrics.record_upstream_error(err.kind());
+                    retry += 1;
+                    if retry >= attempts || matches!(err, UpstreamError::CircuitOpen) {
+                        return Err(err.into());
+                    }
+                    std::thread::sleep(self.upstream.backoff(retry));
+                }
+            }
+        }
+    }
+
+    /// Make a single attempt at sending `request` to the origin
+    fn try_forward(&self, request: &HttpRequest) -> Result<Vec<u8>, UpstreamError> {
+        let origin = self.upstream.connect(&self.origin_addr)?;
+        let response = exchange(request, &origin).map_err(|err| match err {
+            AspirinEatsError::Io(err) => UpstreamError::from_io(err),
+            _ => UpstreamError::InvalidResponse,
+        })?;
+        if response_status(&response).is_none() {
+            return Err(UpstreamError::InvalidResponse);
+        }
+        // The response is complete, so failing to shut down cleanly doesn't matter
+        let _ = origin.shutdown(Shutdown::Both);
+        Ok(response)
+    }
+}
+
+/// Send `request` to `origin` and read back everything it responds with
+pub fn exchange<O: Read + Write>(
+    request: &HttpRequest,
+    mut origin: O,
+) -> Result<Vec<u8>, AspirinEatsError> {
+    origin.write_all(request.to_string().as_bytes())?;
+    origin.flush()?;
+
+    let mut response = Vec::new();
+    origin.read_to_end(&mut response)?;
+    Ok(response)
+}
+
+/// Whether `request` is asking for the proxy's own metrics
+fn is_metrics_request(request: &HttpRequest) -> bool {
+    request.method.as_deref() == Some("GET") && request.route() == PROXY_METRICS_PATH
+}
+
+/// Status code from the first line of a raw HTTP response
+fn response_status(response: &[u8]) -> Option<u16> {
+    let status_line = response.split(|&b| b == b'\n').next()?;
+    let status_line = std::str::from_utf8(status_line).ok()?;
+    status_line.split_whitespace().nth(1)?.parse().ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use super::*;
+    use crate::http::read_request;
+    use crate::logging::{LogFormat, SharedBuffer};
+
+    /// Stream that reads from a fixed input and records everything written to it
+    struct MockStream {
+        input: Cursor<Vec<u8>>,
+        output: Vec<u8>,
+    }
+
+    impl MockStream {
+        fn new(input: &str) -> Self {
+            MockStream {
+                input: Cursor::new(input.as_bytes().to_vec()),
+                output: Vec::new(),
+            }
+        }
+
+        fn written(&self) -> String {
+            String::from_utf8(self.output.clone()).unwrap()
+        }
+    }
+
+    impl Read for MockStream {
+        fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+            self.input.read(buf)
+        }
+    }
+
+    impl Write for MockStream {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.output.write(buf)
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    /// Start an origin on an ephemeral port that answers one connection with `response`, and
+    /// sends back the request it received
+    fn spawn_origin(response: &str) -> (String, std::thread::JoinHandle<String>) {
+        let response = response.to_string();
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap().to_string();
+        let handle = std::thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let request = read_request(&mut stream).unwrap();
+            stream.write_all(response.as_bytes()).unwrap();
+            request.to_string()
+        });
+        (addr, handle)
+    }
+
+    #[test]
+    fn test_exchange() {
+        let request = "GET /orders HTTP/1.1\r\nHost: proxy\r\n\r\n"
+            .parse()
+            .unwrap();
+        let mut origin = MockStream::new("HTTP/1.1 200 OK\r\n\r\n[]");
+
+        let response = exchange(&request, &mut origin).unwrap();
+
+        assert_eq!(
+            origin.written(),
+            "GET /orders HTTP/1.1\r\nHost: proxy\r\nContent-Length: 0\r\n\r\n"
+        );
+        assert_eq!(response, b"HTTP/1.1 200 OK\r\n\r\n[]");
+    }
+
+    #[test]
+    fn test_serve_connection_adds_request_id_and_logs() {
+        let (origin_addr, origin) = spawn_origin("HTTP/1.1 404 Not Found\r\n\r\nnope");
+        let buffer = SharedBuffer::default();
+        let proxy = Proxy::new(&origin_addr).with_access_log(AccessLog::with_writer(
+            "proxy",
+            LogFormat::Json,
+            buffer.clone(),
+        ));
+        let mut client = MockStream::new("GET /orders/9 HTTP/1.1\r\nX-Request-Id: spoofed\r\n\r\n");
+
+        proxy
+            .serve_connection(&mut client, "10.0.0.1:4000".parse().ok())
+            .unwrap();
+
+        assert_eq!(client.written(), "HTTP/1.1 404 Not Found\r\n\r\nnope");
+        let forwarded: HttpRequest = origin.join().unwrap().parse().unwrap();
+        let request_id = forwarded.header(REQUEST_ID_HEADER).unwrap().to_string();
+        assert_ne!(request_id, "spoofed");
+
+        let entry: serde_json::Value = serde_json::from_str(&buffer.lines()[0]).unwrap();
+        assert_eq!(entry["service"], "proxy");
+        assert_eq!(entry["request_id"], request_id.as_str());
+        assert_eq!(entry["path"], "/orders/9");
+        assert_eq!(entry["status"], 404);
+        assert_eq!(entry["bytes"], 30);
+    }
+
+    #[test]
+    fn test_serve_connection_rejects_malformed_request() {
+        // Nothing is listening on port 9 (discard), so reaching the origin would fail the test
+        let proxy = Proxy::new("127.0.0.1:9");
+        let mut client = MockStream::new("nonsense\r\n\r\n");
+
+        proxy.serve_connection(&mut client, None).unwrap();
+
+        assert!(client.written().starts_with("HTTP/1.1 400 Bad Request"));
+    }
+
+    #[test]
+    fn test_metrics() {
+        let (origin_addr, origin) = spawn_origin("HTTP/1.1 200 OK\r\n\r\n[]");
+        let proxy = Proxy::new(&origin_addr).with_upstream(get_test_policy(0));
+        proxy
+            .serve_connection(MockStream::new("GET /orders/3 HTTP/1.1\r\n\r\n"), None)
+            .unwrap();
+        origin.join().unwrap();
+
+        // The origin is gone now, so this one can't be forwarded
+        let mut client = MockStream::new("GET /orders HTTP/1.1\r\n\r\n");
+        proxy.serve_connection(&mut client, None).unwrap();
+        assert!(client.written().starts_with("HTTP/1.1 502 Bad Gateway"));
+
+        let mut client = MockStream::new("GET /proxy/metrics HTTP/1.1\r\n\r\n");
+        proxy.serve_connection(&mut client, None).unwrap();
+        let body = client.written();
+        assert!(body.contains(&format!("Content-Type: {METRICS_CONTENT_TYPE}\r\n")));
+        assert!(body.contains(
+            "aspirin_eats_http_requests_total{service=\"proxy\",route=\"/orders/{id}\",status=\"200\"} 1\n"
+        ));
+        assert!(body.contains(
+            "aspirin_eats_upstream_errors_total{service=\"proxy\",kind=\"connect\"} 1\n"
+        ));
+        assert!(body.contains("aspirin_eats_in_flight_connections{service=\"proxy\"} 1\n"));
+        assert!(proxy.metrics().render().contains(
+            "aspirin_eats_http_requests_total{service=\"proxy\",route=\"/proxy/metrics\",status=\"200\"} 1\n"
+        ));
+    }
+
+    #[test]
+    fn test_forwards_origin_metrics() {
+        let (origin_addr, origin) = spawn_origin("HTTP/1.1 200 OK\r\n\r\norigin metrics");
+        let proxy = Proxy::new(&origin_addr).with_upstream(get_test_policy(0));
+        let mut client = MockStream::new("GET /metrics HTTP/1.1\r\n\r\n");
+
+        proxy.serve_connection(&mut client, None).unwrap();
+
+        assert!(origin.join().unwrap().starts_with("GET /metrics HTTP/1.1"));
+        assert!(client.written().ends_with("origin metrics"));
+    }
+
+    /// Start `proxy` on an ephemeral port, serving a single connection
+    fn spawn_proxy(
+        proxy: Proxy,
+    ) -> (
+        String,
+        std::thread::JoinHandle<Result<(), AspirinEatsError>>,
+    ) {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap().to_string();
+        let handle = std::thread::spawn(move || {
+            let (stream, _) = listener.accept().unwrap();
+            proxy.serve_stream(stream)
+        });
+        (addr, handle)
+    }
+
+    #[test]
+    fn test_tls_termination() {
+        let cert = tls::TestCert::generate();
+        let (origin_addr, origin) = spawn_origin("HTTP/1.1 200 OK\r\n\r\n[]");
+        let config = tls::load_server_config(&cert.cert_path, &cert.key_path).unwrap();
+        let (proxy_addr, proxy) = spawn_proxy(Proxy::new(&origin_addr).with_tls(config));
+
+        let connection =
+            rustls::ClientConnection::new(cert.client_config(), "localhost".try_into().unwrap())
+                .unwrap();
+        let mut client =
+            rustls::StreamOwned::new(connection, TcpStream::connect(proxy_addr).unwrap());
+        client
+            .write_all(b"GET /orders HTTP/1.1\r\nHost: localhost\r\n\r\n")
+            .unwrap();
+        let mut response = String::new();
+        client.read_to_string(&mut response).unwrap();
+
+        assert_eq!(response, "HTTP/1.1 200 OK\r\n\r\n[]");
+        proxy.join().unwrap().unwrap();
+        // The origin still gets plain HTTP
+        let forwarded: HttpRequest = origin.join().unwrap().parse().unwrap();
+        assert_eq!(forwarded.route(), "/orders");
+    }
+
+    #[test]
+    fn test_tls_rejects_plaintext() {
+        let cert = tls::TestCert::generate();
+        let config = tls::load_server_config(&cert.cert_path, &cert.key_path).unwrap();
+        // Nothing is listening on port 9 (discard), so reaching the origin would fail the test
+        let (proxy_addr, proxy) = spawn_proxy(Proxy::new("127.0.0.1:9").with_tls(config));
+
+        let mut client = TcpStream::connect(proxy_addr).unwrap();
+        client.write_all(b"GET / HTTP/1.1\r\n\r\n").unwrap();
+
+        assert!(matches!(
+            proxy.join().unwrap(),
+            Err(AspirinEatsError::Io(_) | AspirinEatsError::Tls(_))
+        ));
+    }
+
+    #[test]
+    fn test_compresses_responses() {
+        let body = format!("[{}]", vec![r#"{"customer":"Amit"}"#; 200].join(","));
+        let (origin_addr, origin) = spawn_origin(&format!("HTTP/1.1 200 OK\r\n\r\n{body}"));
+        let proxy = Proxy::new(&origin_addr);
+        let mut client =
+            MockStream::new("GET /orders HTTP/1.1\r\nAccept-Encoding: gzip, deflate\r\n\r\n");
+
+        proxy.serve_connection(&mut client, None).unwrap();
+        origin.join().unwrap();
+
+        let split = client
+            .output
+            .windows(4)
+            .position(|w| w == b"\r\n\r\n")
+            .unwrap();
+        let head = String::from_utf8(client.output[..split].to_vec()).unwrap();
+        assert!(head.contains("Content-Encoding: gzip"));
+        let mut decompressed = String::new();
+        flate2::read::GzDecoder::new(&client.output[split + 4..])
+            .read_to_string(&mut decompressed)
+            .unwrap();
+        assert_eq!(decompressed, body);
+    }
+
+    /// Upstream policy with quick timeouts and `max_retries` retries
+    fn get_test_policy(max_retries: u32) -> UpstreamPolicy {
+        UpstreamPolicy {
+            connect_timeout_ms: 500,
+            read_timeout_ms: 200,
+            max_retries,
+            retry_backoff_ms: 1,
+            ..UpstreamPolicy::default()
+        }
+    }
+
+    /// Address of a port that nothing is listening on
+    fn closed_port() -> String {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        listener.local_addr().unwrap().to_string()
+    }
+
+    #[test]
+    fn test_retries_idempotent_requests() {
+        let proxy = Proxy::new(&closed_port()).with_upstream(get_test_policy(2));
+
+        let mut client = MockStream::new("GET /orders HTTP/1.1\r\n\r\n");
+        proxy.serve_connection(&mut client, None).unwrap();
+        assert!(client.written().starts_with("HTTP/1.1 502 Bad Gateway"));
+        assert!(client.written().contains(REQUEST_ID_HEADER));
+        assert!(proxy.metrics().render().contains(
+            "aspirin_eats_upstream_errors_total{service=\"proxy\",kind=\"connect\"} 3\n"
+        ));
+
+        // Placing an order twice would be worse than failing, so it's only tried once
+        let mut client = MockStream::new("POST /orders HTTP/1.1\r\nContent-Length: 2\r\n\r\n{}");
+        proxy.serve_connection(&mut client, None).unwrap();
+        assert!(proxy.metrics().render().contains(
+            "aspirin_eats_upstream_errors_total{service=\"proxy\",kind=\"connect\"} 4\n"
+        ));
+    }
+
+    #[test]
+    fn test_retry_recovers() {
+        // The first connection is dropped without a response, the second one is answered
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let origin_addr = listener.local_addr().unwrap().to_string();
+        let origin = std::thread::spawn(move || {
+            drop(listener.accept().unwrap());
+            let (mut stream, _) = listener.accept().unwrap();
+            read_request(&mut stream).unwrap();
+            stream.write_all(b"HTTP/1.1 200 OK\r\n\r\n[]").unwrap();
+        });
+        let proxy = Proxy::new(&origin_addr).with_upstream(get_test_policy(1));
+        let mut client = MockStream::new("DELETE /orders/1 HTTP/1.1\r\n\r\n");
+
+        proxy.serve_connection(&mut client, None).unwrap();
+        origin.join().unwrap();
+
+        assert_eq!(client.written(), "HTTP/1.1 200 OK\r\n\r\n[]");
+    }
+
+    #[test]
+    fn test_origin_timeout() {
+        // Accepts the connection but never answers
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let origin_addr = listener.local_addr().unwrap().to_string();
+        let origin = std::thread::spawn(move || {
+            let (stream, _) = listener.accept().unwrap();
+            std::thread::sleep(std::time::Duration::from_millis(500));
+            drop(stream);
+        });
+        let proxy = Proxy::new(&origin_addr).with_upstream(get_test_policy(0));
+        let mut client = MockStream::new("GET /orders HTTP/1.1\r\n\r\n");
+
+        proxy.serve_connection(&mut client, None).unwrap();
+        origin.join().unwrap();
+
+        assert!(client.written().starts_with("HTTP/1.1 504 Gateway Timeout"));
+    }
+
+    #[test]
+    fn test_circuit_breaker_fails_fast() {
+        let policy = UpstreamPolicy {
+            failure_threshold: 2,
+            open_ms: 60_000,
+            ..get_test_policy(0)
+        };
+        let proxy = Proxy::new(&closed_port()).with_upstream(policy);
+        for _ in 0..2 {
+            let mut client = MockStream::new("GET /orders HTTP/1.1\r\n\r\n");
+            proxy.serve_connection(&mut client, None).unwrap();
+            assert!(client.written().starts_with("HTTP/1.1 502 Bad Gateway"));
+        }
+
+        // The circuit is open now, so the origin isn't tried again
+        let mut client = MockStream::new("GET /orders HTTP/1.1\r\n\r\n");
+        proxy.serve_connection(&mut client, None).unwrap();
+        assert!(client
+            .written()
+            .starts_with("HTTP/1.1 503 Service Unavailable"));
+        assert!(proxy.metrics().render().contains(
+            "aspirin_eats_upstream_errors_total{service=\"proxy\",kind=\"circuit_open\"} 1\n"
+        ));
+        assert!(proxy.metrics().render().contains(
+            "aspirin_eats_upstream_errors_total{service=\"proxy\",kind=\"connect\"} 2\n"
+        ));
+    }
+
+    /// How long it takes the proxy to answer `GET /fast` while another client waits on `GET /slow`,
+    /// with `workers` connections served at once
+    fn time_fast_behind_slow(workers: usize) -> std::time::Duration {
+        // Answers every connection on its own thread, taking 500ms over /slow
+        let origin = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let origin_addr = origin.local_addr().unwrap().to_string();
+        std::thread::spawn(move || {
+            for stream in origin.incoming() {
+                std::thread::spawn(move || {
+                    let mut stream = stream.unwrap();
+                    let request = read_request(&mut stream).unwrap();
+                    if request.path.as_deref() == Some("/slow") {
+                        std::thread::sleep(std::time::Duration::from_millis(500));
+                    }
+                    stream.write_all(b"HTTP/1.1 200 OK\r\n\r\n[]").unwrap();
+                });
+            }
+        });
+        let proxy = Proxy::new(&origin_addr).with_upstream(UpstreamPolicy {
+            read_timeout_ms: 2000,
+            ..get_test_policy(0)
+        });
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let proxy_addr = listener.local_addr().unwrap();
+        std::thread::spawn(move || {
+            proxy.serve(&listener, workers, &TimeoutConfig::default(), |err| {
+                panic!("{err}")
+            })
+        });
+
+        let send = |path: &str| {
+            let mut stream = TcpStream::connect(proxy_addr).unwrap();
+            write!(stream, "GET {path} HTTP/1.1\r\n\r\n").unwrap();
+            stream
+        };
+        let mut slow = send("/slow");
+        std::thread::sleep(std::time::Duration::from_millis(100));
+        let start = Instant::now();
+        let mut fast = send("/fast");
+        let mut response = String::new();
+        fast.read_to_string(&mut response).unwrap();
+        assert!(response.starts_with("HTTP/1.1 200 OK"));
+        let elapsed = start.elapsed();
+        slow.read_to_string(&mut response).unwrap();
+        elapsed
+    }
+
+    #[test]
+    fn test_serve_doesnt_block_behind_slow_requests() {
+        assert!(time_fast_behind_slow(2) < std::time::Duration::from_millis(300));
+    }
+
+    #[test]
+    fn test_serve_blocks_when_every_worker_is_busy() {
+        // This is the limit of a fixed pool: with the only worker waiting on the origin, the next
+        // client isn't even accepted until it's done
+        assert!(time_fast_behind_slow(1) >= std::time::Duration::from_millis(300));
+    }
+
+    #[test]
+    fn test_response_status() {
+        assert_eq!(
+            response_status(b"HTTP/1.1 503 Service Unavailable\r\n"),
+            Some(503)
+        );
+        assert_eq!(response_status(b"garbage"), None);
+    }
+}
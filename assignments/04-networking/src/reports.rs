@@ -0,0 +1,191 @@
+use std::str::FromStr;
+
+use chrono::NaiveDate;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+
+use crate::error::AspirinEatsError;
+
+/// What to break a sales report down by
+#[derive(Serialize, Deserialize, JsonSchema, Debug, PartialEq, Clone, Copy)]
+#[serde(rename_all = "lowercase")]
+pub enum ReportGrouping {
+    /// One group per calendar day the orders were placed on
+    Day,
+
+    /// One group per kind of menu item (Burger, Fries, Drink)
+    Item,
+
+    /// One group per order status
+    Status,
+}
+
+impl FromStr for ReportGrouping {
+    type Err = AspirinEatsError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "day" => Ok(ReportGrouping::Day),
+            "item" => Ok(ReportGrouping::Item),
+            "status" => Ok(ReportGrouping::Status),
+            _ => Err(AspirinEatsError::InvalidRequest),
+        }
+    }
+}
+
+/// Which orders a sales report covers, and how to group them
+#[derive(Debug, PartialEq, Clone)]
+pub struct SalesQuery {
+    /// First day to include, if any
+    pub from: Option<NaiveDate>,
+
+    /// Last day to include, if any
+    pub to: Option<NaiveDate>,
+
+    pub group_by: ReportGrouping,
+}
+
+/// Aggregated sales over a set of orders
+#[derive(Serialize, Deserialize, JsonSchema, Debug, PartialEq, Clone)]
+pub struct SalesSummary {
+    /// Number of orders
+    pub orders: i64,
+
+    /// Number of menu items across those orders
+    pub items: i64,
+
+    /// Money taken. For item groups this is the line item price, before any discounts or tax
+    pub revenue: f64,
+
+    /// Revenue divided by the number of orders
+    pub average_order_value: f64,
+}
+
+impl SalesSummary {
+    /// Summarize `orders` orders of `items` items that took `revenue`, to the cent
+    pub fn new(orders: i64, items: i64, revenue: f64) -> Self {
+        let revenue = (revenue * 100.0).round() / 100.0;
+        SalesSummary {
+            orders,
+            items,
+            revenue,
+            average_order_value: if orders > 0 {
+                (revenue / orders as f64 * 100.0).round() / 100.0
+            } else {
+                0.0
+            },
+        }
+    }
+}
+
+/// Sales for a single group of a report, e.g. a single day
+#[derive(Serialize, Deserialize, JsonSchema, Debug, PartialEq, Clone)]
+pub struct SalesGroup {
+    pub key: String,
+
+    #[serde(flatten)]
+    pub sales: SalesSummary,
+}
+
+/// Number of times something was ordered
+#[derive(Serialize, Deserialize, JsonSchema, Debug, PartialEq, Clone)]
+pub struct Tally {
+    pub name: String,
+    pub count: i64,
+}
+
+/// Sales report returned by `GET /reports/sales`. Cancelled orders only show up when grouping by
+/// status; everything else leaves them out
+#[derive(Serialize, Deserialize, JsonSchema, Debug, PartialEq, Clone)]
+pub struct SalesReport {
+    pub from: Option<NaiveDate>,
+    pub to: Option<NaiveDate>,
+    pub group_by: ReportGrouping,
+
+    /// Totals across the whole report
+    pub summary: SalesSummary,
+
+    /// Totals for each group, in key order
+    pub groups: Vec<SalesGroup>,
+
+    /// Toppings, most ordered first
+    pub popular_toppings: Vec<Tally>,
+
+    /// How many burgers were ordered with each kind of patty, most ordered first
+    pub patty_mix: Vec<Tally>,
+}
+
+impl SalesReport {
+    /// Render the report's groups as CSV, one row per group
+    pub fn to_csv(&self) -> String {
+        let mut csv = String::from("key,orders,items,revenue,average_order_value\n");
+        for group in &self.groups {
+            csv.push_str(&format!(
+                "{},{},{},{:.2},{:.2}\n",
+                csv_field(&group.key),
+                group.sales.orders,
+                group.sales.items,
+                group.sales.revenue,
+                group.sales.average_order_value
+            ));
+        }
+        csv
+    }
+}
+
+/// Quote a CSV field if it contains anything that would otherwise break the row
+fn csv_field(field: &str) -> String {
+    if field.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", field.replace('"', "\"\""))
+    } else {
+        field.to_string()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_grouping_from_str() {
+        assert_eq!(
+            ReportGrouping::from_str("day").unwrap(),
+            ReportGrouping::Day
+        );
+        assert_eq!(
+            ReportGrouping::from_str("item").unwrap(),
+            ReportGrouping::Item
+        );
+        assert_eq!(
+            ReportGrouping::from_str("status").unwrap(),
+            ReportGrouping::Status
+        );
+        assert!(ReportGrouping::from_str("week").is_err());
+    }
+
+    #[test]
+    fn test_to_csv() {
+        let sales = SalesSummary {
+            orders: 2,
+            items: 3,
+            revenue: 15.5,
+            average_order_value: 7.75,
+        };
+        let report = SalesReport {
+            from: None,
+            to: None,
+            group_by: ReportGrouping::Day,
+            summary: sales.clone(),
+            groups: vec![SalesGroup {
+                key: "a,\"b\"".to_string(),
+                sales,
+            }],
+            popular_toppings: vec![],
+            patty_mix: vec![],
+        };
+        assert_eq!(
+            report.to_csv(),
+            "key,orders,items,revenue,average_order_value\n\"a,\"\"b\"\"\",2,3,15.50,7.75\n"
+        );
+    }
+}
@@ -0,0 +1,142 @@
+use std::time::Duration;
+
+use chrono::{DateTime, Local, NaiveTime, TimeDelta};
+use serde::Deserialize;
+
+use crate::error::AspirinEatsError;
+
+/// When orders can be placed ahead of time for, and when they go to the kitchen
+#[derive(Deserialize, Debug, PartialEq, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub struct SchedulePolicy {
+    /// When we open, in the restaurant's local time
+    pub opens: NaiveTime,
+
+    /// When we close. Earlier than `opens` if we're open past midnight, and the same as `opens`
+    /// if we never close
+    pub closes: NaiveTime,
+
+    /// How long before its time a scheduled order goes to the kitchen, which should be about how
+    /// long it takes to make
+    pub release_mins: u32,
+
+    /// How often scheduled orders are checked for ones that are due
+    pub tick_ms: u64,
+}
+
+impl Default for SchedulePolicy {
+    /// Open from 11am to 10pm, releasing orders 20 minutes before they're wanted
+    fn default() -> Self {
+        SchedulePolicy {
+            opens: NaiveTime::from_hms_opt(11, 0, 0).expect("valid time"),
+            closes: NaiveTime::from_hms_opt(22, 0, 0).expect("valid time"),
+            release_mins: 20,
+            tick_ms: 1000,
+        }
+    }
+}
+
+impl SchedulePolicy {
+    /// Whether we're open at `time`
+    pub fn is_open_at(&self, time: NaiveTime) -> bool {
+        match self.opens.cmp(&self.closes) {
+            std::cmp::Ordering::Less => self.opens <= time && time < self.closes,
+            std::cmp::Ordering::Greater => self.opens <= time || time < self.closes,
+            std::cmp::Ordering::Equal => true,
+        }
+    }
+
+    /// Check that an order can be scheduled for `scheduled_for` as of `now`: it has to be in the
+    /// future and while we're open
+    pub fn check(
+        &self,
+        scheduled_for: &DateTime<Local>,
+        now: &DateTime<Local>,
+    ) -> Result<(), AspirinEatsError> {
+        if scheduled_for <= now {
+            return Err(AspirinEatsError::InvalidSchedule(format!(
+                "{} has already passed",
+                scheduled_for.to_rfc3339()
+            )));
+        }
+        if !self.is_open_at(scheduled_for.time()) {
+            return Err(AspirinEatsError::InvalidSchedule(format!(
+                "we're only open from {} to {}",
+                self.opens.format("%H:%M"),
+                self.closes.format("%H:%M")
+            )));
+        }
+        Ok(())
+    }
+
+    /// Latest time an order can be scheduled for and still be released at `now`
+    pub fn release_until(&self, now: DateTime<Local>) -> DateTime<Local> {
+        now + TimeDelta::minutes(self.release_mins.into())
+    }
+
+    pub fn tick(&self) -> Duration {
+        Duration::from_millis(self.tick_ms)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::TimeZone;
+
+    use super::*;
+
+    fn time(hour: u32, min: u32) -> NaiveTime {
+        NaiveTime::from_hms_opt(hour, min, 0).unwrap()
+    }
+
+    fn at(day: u32, hour: u32, min: u32) -> DateTime<Local> {
+        Local.with_ymd_and_hms(2024, 3, day, hour, min, 0).unwrap()
+    }
+
+    #[test]
+    fn test_is_open_at() {
+        let policy = SchedulePolicy::default();
+        assert!(policy.is_open_at(time(11, 0)));
+        assert!(policy.is_open_at(time(21, 59)));
+        assert!(!policy.is_open_at(time(22, 0)));
+        assert!(!policy.is_open_at(time(3, 0)));
+
+        let late = SchedulePolicy {
+            opens: time(17, 0),
+            closes: time(2, 0),
+            ..policy.clone()
+        };
+        assert!(late.is_open_at(time(23, 30)));
+        assert!(late.is_open_at(time(1, 0)));
+        assert!(!late.is_open_at(time(12, 0)));
+
+        let always = SchedulePolicy {
+            closes: policy.opens,
+            ..policy
+        };
+        assert!(always.is_open_at(time(3, 0)));
+    }
+
+    #[test]
+    fn test_check() {
+        let policy = SchedulePolicy::default();
+        let now = at(10, 12, 0);
+        assert!(policy.check(&at(10, 18, 30), &now).is_ok());
+        assert!(policy.check(&at(11, 11, 0), &now).is_ok());
+
+        for scheduled_for in [now, at(10, 11, 59), at(9, 18, 0)] {
+            let err = policy.check(&scheduled_for, &now).unwrap_err();
+            assert!(err.to_string().ends_with("has already passed"), "{err}");
+        }
+        assert_eq!(
+            policy.check(&at(10, 23, 0), &now).unwrap_err().to_string(),
+            "Can't schedule order: we're only open from 11:00 to 22:00"
+        );
+    }
+
+    #[test]
+    fn test_release_until() {
+        let policy = SchedulePolicy::default();
+        assert_eq!(policy.release_until(at(10, 12, 0)), at(10, 12, 20));
+    }
+}
@@ -0,0 +1,1463 @@
+use std::cmp::Reverse;
+use std::collections::{BTreeMap, BTreeSet};
+use std::sync::Mutex;
+
+use chrono::{DateTime, Local, NaiveDateTime, SubsecRound, Utc};
+use serde::Serialize;
+
+use crate::db::{
+    ingredient_counts, ingredient_key, normalize_name, AspirinEatsDb, BatchMode, BatchOutcome,
+    StockChange, StockLevel,
+};
+use crate::delivery::Location;
+use crate::drivers::Driver;
+use crate::error::AspirinEatsError;
+use crate::food::{Customer, MenuItem, Order, OrderStatus};
+use crate::pricing::PriceBreakdown;
+use crate::reports::{ReportGrouping, SalesGroup, SalesQuery, SalesReport, SalesSummary, Tally};
+
+/// Somewhere orders can be kept. IDs are handed out in increasing order starting at 1, are never
+/// reused after an order is removed, and start again from 1 after `reset_orders`. Customer names
+/// are matched ignoring case and extra whitespace, and orders come back with the name the
+/// customer first ordered under.
+///
+/// Every change to an order moves it on to its next version. Adding an order takes its
+/// ingredients out of stock, and cancelling or removing it before the kitchen starts on it puts
+/// them back
+pub trait OrderStore {
+    /// Add a new order, ignoring any ID it already has, and return its ID. Fails without adding
+    /// the order if any of its ingredients are out of stock
+    fn add_order(&self, order: Order) -> Result<i64, AspirinEatsError>;
+
+    /// Add a batch of orders, reporting what happened to each of them in the order they were
+    /// given. Each order takes its own stock, so one that fails leaves the others as they were.
+    /// In [`BatchMode::AllOrNothing`], any failure leaves the store as it was
+    fn add_orders(
+        &self,
+        orders: Vec<Order>,
+        mode: BatchMode,
+    ) -> Result<BatchOutcome, AspirinEatsError>;
+
+    /// Get an order by ID
+    fn get_order(&self, id: i64) -> Result<Option<Order>, AspirinEatsError>;
+
+    /// Get all orders, in ID order
+    fn get_all_orders(&self) -> Result<Vec<Order>, AspirinEatsError>;
+
+    /// Replace the order with `order.id`. Returns false if there is no such order
+    fn update_order(&self, order: &Order) -> Result<bool, AspirinEatsError>;
+
+    /// Move an order from status `from` to `to` as long as its food is still `food`. It's a
+    /// single step, so two writers can't both make the same change, and nobody acts on food that
+    /// has since been replaced. Returns false, changing nothing, if there is no such order, or
+    /// its status isn't `from` or its food isn't `food`
+    fn set_status(
+        &self,
+        id: i64,
+        from: OrderStatus,
+        to: OrderStatus,
+        food: &[MenuItem],
+    ) -> Result<bool, AspirinEatsError>;
+
+    /// Get the version of an order, starting at 1 when it's added
+    fn get_order_version(&self, id: i64) -> Result<Option<i64>, AspirinEatsError>;
+
+    /// When any order was last added, changed or removed, to the second. None if none ever has
+    fn orders_last_modified(&self) -> Result<Option<DateTime<Utc>>, AspirinEatsError>;
+
+    /// Replace the food of an order the kitchen hasn't started, priced at `breakdown`, as long as
+    /// it's still at `version`. Fails without changing anything if there isn't enough stock for
+    /// the new food. Returns the order's new version
+    fn replace_items(
+        &self,
+        id: i64,
+        version: i64,
+        food: &[MenuItem],
+        breakdown: &PriceBreakdown,
+    ) -> Result<i64, AspirinEatsError>;
+
+    /// Remove an order by ID. Removing an order that doesn't exist does nothing
+    fn remove_order(&self, id: i64) -> Result<(), AspirinEatsError>;
+
+    /// Remove all orders. Stock is left as it is
+    fn reset_orders(&self) -> Result<(), AspirinEatsError>;
+}
+
+/// Somewhere customers are kept. Customers are created by ordering, and are kept when their
+/// orders are removed
+pub trait CustomerStore {
+    /// Get a customer by ID
+    fn get_customer(&self, id: i64) -> Result<Option<Customer>, AspirinEatsError>;
+
+    /// Get all customers, in the order they first ordered
+    fn get_all_customers(&self) -> Result<Vec<Customer>, AspirinEatsError>;
+
+    /// Get all of a customer's orders, oldest first
+    fn get_customer_orders(&self, customer_id: i64) -> Result<Vec<Order>, AspirinEatsError>;
+}
+
+/// Somewhere stock is kept. Ingredients that aren't tracked never run out
+pub trait InventoryStore {
+    /// Stock of every tracked ingredient
+    fn get_inventory(&self) -> Result<Vec<StockLevel>, AspirinEatsError>;
+
+    /// Set how much of each ingredient is in stock, starting to track any that weren't yet
+    fn set_stock(&self, levels: &[StockLevel]) -> Result<(), AspirinEatsError>;
+
+    /// Add to (or take from) the stock of each ingredient, starting from zero for any that
+    /// weren't tracked yet. Fails without changing anything if stock would go below zero
+    fn adjust_stock(&self, changes: &[StockChange]) -> Result<(), AspirinEatsError>;
+}
+
+/// Somewhere drivers are kept, along with which orders they're delivering
+pub trait DriverStore {
+    /// Sign up a new driver, who starts off shift. Returns their ID
+    fn add_driver(&self, name: &str) -> Result<i64, AspirinEatsError>;
+
+    /// Get a driver by ID
+    fn get_driver(&self, id: i64) -> Result<Option<Driver>, AspirinEatsError>;
+
+    /// Get all drivers, in the order they signed up
+    fn get_all_drivers(&self) -> Result<Vec<Driver>, AspirinEatsError>;
+
+    /// Put a driver on shift, moving them to `location` if they say where they are
+    fn start_shift(&self, id: i64, location: Option<Location>) -> Result<Driver, AspirinEatsError>;
+
+    /// Take a driver off shift. Fails if they're still out with an order
+    fn end_shift(&self, id: i64) -> Result<Driver, AspirinEatsError>;
+
+    /// Give an order to a driver to deliver. The driver has to be on shift and not already out
+    /// with an order, and the order has to be ready: out of the kitchen, with no driver yet
+    fn claim_delivery(&self, driver_id: i64, order_id: i64) -> Result<Order, AspirinEatsError>;
+
+    /// Mark a driver's delivery as done, completing the order and leaving the driver at the
+    /// address it went to
+    fn complete_delivery(&self, driver_id: i64) -> Result<Order, AspirinEatsError>;
+}
+
+/// Somewhere sales can be reported on
+pub trait ReportStore {
+    /// Aggregate sales over the orders matching `query`
+    fn sales_report(&self, query: &SalesQuery) -> Result<SalesReport, AspirinEatsError>;
+}
+
+/// Everything the API needs to keep
+pub trait Store: OrderStore + CustomerStore + InventoryStore + DriverStore + ReportStore {}
+
+impl<S: OrderStore + CustomerStore + InventoryStore + DriverStore + ReportStore> Store for S {}
+
+impl OrderStore for AspirinEatsDb {
+    fn add_order(&self, order: Order) -> Result<i64, AspirinEatsError> {
+        AspirinEatsDb::add_order(self, order)
+    }
+
+    fn add_orders(
+        &self,
+        orders: Vec<Order>,
+        mode: BatchMode,
+    ) -> Result<BatchOutcome, AspirinEatsError> {
+        AspirinEatsDb::add_orders(self, orders, mode)
+    }
+
+    fn get_order(&self, id: i64) -> Result<Option<Order>, AspirinEatsError> {
+        Ok(AspirinEatsDb::get_order(self, id)?)
+    }
+
+    fn get_all_orders(&self) -> Result<Vec<Order>, AspirinEatsError> {
+        Ok(AspirinEatsDb::get_all_orders(self)?)
+    }
+
+    fn update_order(&self, order: &Order) -> Result<bool, AspirinEatsError> {
+        Ok(AspirinEatsDb::update_order(self, order)?)
+    }
+
+    fn set_status(
+        &self,
+        id: i64,
+        from: OrderStatus,
+        to: OrderStatus,
+        food: &[MenuItem],
+    ) -> Result<bool, AspirinEatsError> {
+        Ok(AspirinEatsDb::set_status(self, id, from, to, food)?)
+    }
+
+    fn get_order_version(&self, id: i64) -> Result<Option<i64>, AspirinEatsError> {
+        Ok(AspirinEatsDb::get_order_version(self, id)?)
+    }
+
+    fn orders_last_modified(&self) -> Result<Option<DateTime<Utc>>, AspirinEatsError> {
+        Ok(AspirinEatsDb::orders_last_modified(self)?)
+    }
+
+    fn replace_items(
+        &self,
+        id: i64,
+        version: i64,
+        food: &[MenuItem],
+        breakdown: &PriceBreakdown,
+    ) -> Result<i64, AspirinEatsError> {
+        AspirinEatsDb::replace_items(self, id, version, food, breakdown)
+    }
+
+    fn remove_order(&self, id: i64) -> Result<(), AspirinEatsError> {
+        Ok(AspirinEatsDb::remove_order(self, id)?)
+    }
+
+    fn reset_orders(&self) -> Result<(), AspirinEatsError> {
+        Ok(AspirinEatsDb::reset_orders(self)?)
+    }
+}
+
+impl CustomerStore for AspirinEatsDb {
+    fn get_customer(&self, id: i64) -> Result<Option<Customer>, AspirinEatsError> {
+        Ok(AspirinEatsDb::get_customer(self, id)?)
+    }
+
+    fn get_all_customers(&self) -> Result<Vec<Customer>, AspirinEatsError> {
+        Ok(AspirinEatsDb::get_all_customers(self)?)
+    }
+
+    fn get_customer_orders(&self, customer_id: i64) -> Result<Vec<Order>, AspirinEatsError> {
+        Ok(AspirinEatsDb::get_customer_orders(self, customer_id)?)
+    }
+}
+
+impl InventoryStore for AspirinEatsDb {
+    fn get_inventory(&self) -> Result<Vec<StockLevel>, AspirinEatsError> {
+        Ok(AspirinEatsDb::get_inventory(self)?)
+    }
+
+    fn set_stock(&self, levels: &[StockLevel]) -> Result<(), AspirinEatsError> {
+        AspirinEatsDb::set_stock(self, levels)
+    }
+
+    fn adjust_stock(&self, changes: &[StockChange]) -> Result<(), AspirinEatsError> {
+        AspirinEatsDb::adjust_stock(self, changes)
+    }
+}
+
+impl DriverStore for AspirinEatsDb {
+    fn add_driver(&self, name: &str) -> Result<i64, AspirinEatsError> {
+        Ok(AspirinEatsDb::add_driver(self, name)?)
+    }
+
+    fn get_driver(&self, id: i64) -> Result<Option<Driver>, AspirinEatsError> {
+        Ok(AspirinEatsDb::get_driver(self, id)?)
+    }
+
+    fn get_all_drivers(&self) -> Result<Vec<Driver>, AspirinEatsError> {
+        Ok(AspirinEatsDb::get_all_drivers(self)?)
+    }
+
+    fn start_shift(&self, id: i64, location: Option<Location>) -> Result<Driver, AspirinEatsError> {
+        AspirinEatsDb::start_shift(self, id, location)
+    }
+
+    fn end_shift(&self, id: i64) -> Result<Driver, AspirinEatsError> {
+        AspirinEatsDb::end_shift(self, id)
+    }
+
+    fn claim_delivery(&self, driver_id: i64, order_id: i64) -> Result<Order, AspirinEatsError> {
+        AspirinEatsDb::claim_delivery(self, driver_id, order_id)
+    }
+
+    fn complete_delivery(&self, driver_id: i64) -> Result<Order, AspirinEatsError> {
+        AspirinEatsDb::complete_delivery(self, driver_id)
+    }
+}
+
+impl ReportStore for AspirinEatsDb {
+    fn sales_report(&self, query: &SalesQuery) -> Result<SalesReport, AspirinEatsError> {
+        Ok(AspirinEatsDb::sales_report(self, query)?)
+    }
+}
+
+/// A Store that only lives in memory, behaving just like the database. Useful for testing
+/// without SQLite
+#[derive(Default)]
+pub struct InMemoryStore {
+    inner: Mutex<InMemoryState>,
+}
+
+#[derive(Default, Clone)]
+struct InMemoryState {
+    orders: BTreeMap<i64, StoredOrder>,
+
+    /// Last ID handed out, like SQLite's AUTOINCREMENT sequence
+    last_id: i64,
+
+    /// Canonical customer names, in the order the customers were created. A customer's ID is
+    /// their position in the list plus one
+    customers: Vec<String>,
+
+    /// Tracked ingredients, keyed like the inventory table so they come back in the same order
+    stock: BTreeMap<String, StockLevel>,
+
+    /// Drivers in the order they signed up, with `delivering` left to be worked out from the
+    /// orders. A driver's ID is their position in the list plus one
+    drivers: Vec<Driver>,
+
+    orders_last_modified: Option<DateTime<Utc>>,
+}
+
+#[derive(Clone)]
+struct StoredOrder {
+    order: Order,
+    customer_id: i64,
+    version: i64,
+    created_at: NaiveDateTime,
+}
+
+/// Position in a list of something whose IDs count up from 1
+fn index(id: i64) -> Option<usize> {
+    usize::try_from(id).ok()?.checked_sub(1)
+}
+
+/// Name of a unit enum variant, as it's serialized
+fn variant_name<T: Serialize>(value: &T) -> String {
+    serde_json::to_value(value)
+        .ok()
+        .and_then(|value| value.as_str().map(str::to_string))
+        .unwrap_or_default()
+}
+
+impl InMemoryState {
+    /// ID of the customer with the given name, creating them if they don't exist yet. Names are
+    /// compared like the customers table does, ignoring ASCII case
+    fn customer_id(&mut self, name: &str) -> i64 {
+        let name = normalize_name(name);
+        let position = match self
+            .customers
+            .iter()
+            .position(|customer| customer.eq_ignore_ascii_case(&name))
+        {
+            Some(position) => position,
+            None => {
+                self.customers.push(name);
+                self.customers.len() - 1
+            }
+        };
+        position as i64 + 1
+    }
+
+    fn insert_order(&mut self, mut order: Order, created_at: NaiveDateTime) -> i64 {
+        let customer_id = self.customer_id(&order.customer);
+        self.last_id += 1;
+        order.id = Some(self.last_id);
+        order.customer = self.customers[customer_id as usize - 1].clone();
+        self.orders.insert(
+            self.last_id,
+            StoredOrder {
+                order,
+                customer_id,
+                version: 1,
+                created_at,
+            },
+        );
+        self.touch_orders();
+        self.last_id
+    }
+
+    fn touch_orders(&mut self) {
+        self.orders_last_modified = Some(Utc::now().trunc_subsecs(0));
+    }
+
+    /// Take the ingredients for `food` out of stock, or nothing if there isn't enough of one
+    fn take_stock(&mut self, food: &[MenuItem]) -> Result<(), AspirinEatsError> {
+        let counts = ingredient_counts(food);
+        for (ingredient, needed) in &counts {
+            if let Some(level) = self.stock.get(&ingredient_key(ingredient)) {
+                if level.quantity < *needed {
+                    return Err(AspirinEatsError::OutOfStock(ingredient.clone()));
+                }
+            }
+        }
+        for (ingredient, needed) in counts {
+            if let Some(level) = self.stock.get_mut(&ingredient_key(&ingredient)) {
+                level.quantity -= needed;
+            }
+        }
+        Ok(())
+    }
+
+    /// Put the ingredients for `food` back into stock
+    fn restock(&mut self, food: &[MenuItem]) {
+        for (ingredient, count) in ingredient_counts(food) {
+            if let Some(level) = self.stock.get_mut(&ingredient_key(&ingredient)) {
+                level.quantity += count;
+            }
+        }
+    }
+
+    fn driver(&self, id: i64) -> Option<Driver> {
+        let mut driver = self.drivers.get(index(id)?)?.clone();
+        driver.delivering = self
+            .orders
+            .values()
+            .map(|stored| &stored.order)
+            .find(|order| order.driver == Some(id) && order.status == OrderStatus::Transporting)
+            .and_then(|order| order.id);
+        Some(driver)
+    }
+}
+
+impl InMemoryStore {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, InMemoryState> {
+        self.inner.lock().expect("in-memory store lock poisoned")
+    }
+
+    /// Make a change to a copy of the store, keeping it only if it succeeds, like a transaction
+    fn transaction<T>(
+        &self,
+        change: impl FnOnce(&mut InMemoryState) -> Result<T, AspirinEatsError>,
+    ) -> Result<T, AspirinEatsError> {
+        let mut state = self.lock();
+        let mut copy = state.clone();
+        let result = change(&mut copy)?;
+        *state = copy;
+        Ok(result)
+    }
+}
+
+impl OrderStore for InMemoryStore {
+    fn add_order(&self, order: Order) -> Result<i64, AspirinEatsError> {
+        self.transaction(|state| {
+            state.take_stock(&order.food)?;
+            Ok(state.insert_order(order, Local::now().naive_local()))
+        })
+    }
+
+    fn add_orders(
+        &self,
+        orders: Vec<Order>,
+        mode: BatchMode,
+    ) -> Result<BatchOutcome, AspirinEatsError> {
+        let mut state = self.lock();
+        let mut batch = state.clone();
+        let created_at = Local::now().naive_local();
+        let mut results = Vec::with_capacity(orders.len());
+        for order in orders {
+            let before = batch.clone();
+            let result = batch
+                .take_stock(&order.food)
+                .map(|_| batch.insert_order(order, created_at));
+            if result.is_err() {
+                batch = before;
+            }
+            results.push(result);
+        }
+
+        let committed = mode == BatchMode::BestEffort || results.iter().all(Result::is_ok);
+        if committed {
+            *state = batch;
+        }
+        Ok(BatchOutcome { results, committed })
+    }
+
+    fn get_order(&self, id: i64) -> Result<Option<Order>, AspirinEatsError> {
+        Ok(self
+            .lock()
+            .orders
+            .get(&id)
+            .map(|stored| stored.order.clone()))
+    }
+
+    fn get_all_orders(&self) -> Result<Vec<Order>, AspirinEatsError> {
+        Ok(self
+            .lock()
+            .orders
+            .values()
+            .map(|stored| stored.order.clone())
+            .collect())
+    }
+
+    fn update_order(&self, order: &Order) -> Result<bool, AspirinEatsError> {
+        let mut state = self.lock();
+        let Some(id) = order.id.filter(|id| state.orders.contains_key(id)) else {
+            return Ok(false);
+        };
+        let old = state.orders[&id].order.clone();
+        if old.status.is_waiting() && order.status == OrderStatus::Cancelled {
+            state.restock(&old.food);
+        }
+        let customer_id = state.customer_id(&order.customer);
+        let customer = state.customers[customer_id as usize - 1].clone();
+        let stored = state.orders.get_mut(&id).expect("order exists");
+        stored.order = Order {
+            customer,
+            ..order.clone()
+        };
+        stored.customer_id = customer_id;
+        stored.version += 1;
+        state.touch_orders();
+        Ok(true)
+    }
+
+    fn set_status(
+        &self,
+        id: i64,
+        from: OrderStatus,
+        to: OrderStatus,
+        food: &[MenuItem],
+    ) -> Result<bool, AspirinEatsError> {
+        let mut state = self.lock();
+        let Some(stored) = state
+            .orders
+            .get_mut(&id)
+            .filter(|stored| stored.order.status == from && stored.order.food == food)
+        else {
+            return Ok(false);
+        };
+        stored.order.status = to.clone();
+        stored.version += 1;
+        let food = stored.order.food.clone();
+        if from.is_waiting() && to == OrderStatus::Cancelled {
+            state.restock(&food);
+        }
+        state.touch_orders();
+        Ok(true)
+    }
+
+    fn get_order_version(&self, id: i64) -> Result<Option<i64>, AspirinEatsError> {
+        Ok(self.lock().orders.get(&id).map(|stored| stored.version))
+    }
+
+    fn orders_last_modified(&self) -> Result<Option<DateTime<Utc>>, AspirinEatsError> {
+        Ok(self.lock().orders_last_modified)
+    }
+
+    fn replace_items(
+        &self,
+        id: i64,
+        version: i64,
+        food: &[MenuItem],
+        breakdown: &PriceBreakdown,
+    ) -> Result<i64, AspirinEatsError> {
+        self.transaction(|state| {
+            let old = state
+                .orders
+                .get(&id)
+                .ok_or(AspirinEatsError::NotFound)?
+                .clone();
+            if !old.order.status.is_waiting() {
+                return Err(AspirinEatsError::Conflict(format!(
+                    "Order {id} is {:?}, so it can't be changed any more",
+                    old.order.status
+                )));
+            }
+            state.restock(&old.order.food);
+            state.take_stock(food)?;
+            if old.version != version {
+                return Err(AspirinEatsError::PreconditionFailed);
+            }
+
+            let stored = state.orders.get_mut(&id).expect("order exists");
+            stored.order.food = food.to_vec();
+            stored.order.total = breakdown.total;
+            stored.order.breakdown = Some(breakdown.clone());
+            stored.version += 1;
+            state.touch_orders();
+            Ok(version + 1)
+        })
+    }
+
+    fn remove_order(&self, id: i64) -> Result<(), AspirinEatsError> {
+        let mut state = self.lock();
+        if let Some(stored) = state.orders.remove(&id) {
+            if stored.order.status.is_waiting() {
+                state.restock(&stored.order.food);
+            }
+            state.touch_orders();
+        }
+        Ok(())
+    }
+
+    fn reset_orders(&self) -> Result<(), AspirinEatsError> {
+        let mut state = self.lock();
+        if !state.orders.is_empty() {
+            state.orders.clear();
+            state.touch_orders();
+        }
+        state.last_id = 0;
+        Ok(())
+    }
+}
+
+impl CustomerStore for InMemoryStore {
+    fn get_customer(&self, id: i64) -> Result<Option<Customer>, AspirinEatsError> {
+        let state = self.lock();
+        Ok(index(id)
+            .and_then(|index| state.customers.get(index))
+            .map(|name| Customer {
+                id: Some(id),
+                name: name.clone(),
+            }))
+    }
+
+    fn get_all_customers(&self) -> Result<Vec<Customer>, AspirinEatsError> {
+        Ok(self
+            .lock()
+            .customers
+            .iter()
+            .zip(1..)
+            .map(|(name, id)| Customer {
+                id: Some(id),
+                name: name.clone(),
+            })
+            .collect())
+    }
+
+    fn get_customer_orders(&self, customer_id: i64) -> Result<Vec<Order>, AspirinEatsError> {
+        Ok(self
+            .lock()
+            .orders
+            .values()
+            .filter(|stored| stored.customer_id == customer_id)
+            .map(|stored| stored.order.clone())
+            .collect())
+    }
+}
+
+impl InventoryStore for InMemoryStore {
+    fn get_inventory(&self) -> Result<Vec<StockLevel>, AspirinEatsError> {
+        Ok(self.lock().stock.values().cloned().collect())
+    }
+
+    fn set_stock(&self, levels: &[StockLevel]) -> Result<(), AspirinEatsError> {
+        if levels.iter().any(|level| level.quantity < 0) {
+            return Err(AspirinEatsError::InvalidRequest);
+        }
+        let mut state = self.lock();
+        for level in levels {
+            state
+                .stock
+                .insert(ingredient_key(&level.ingredient), level.clone());
+        }
+        Ok(())
+    }
+
+    fn adjust_stock(&self, changes: &[StockChange]) -> Result<(), AspirinEatsError> {
+        self.transaction(|state| {
+            for change in changes {
+                let level = state
+                    .stock
+                    .entry(ingredient_key(&change.ingredient))
+                    .or_insert_with(|| StockLevel {
+                        ingredient: change.ingredient.clone(),
+                        quantity: 0,
+                    });
+                if level.quantity + change.change < 0 {
+                    return Err(AspirinEatsError::OutOfStock(change.ingredient.clone()));
+                }
+                level.quantity += change.change;
+            }
+            Ok(())
+        })
+    }
+}
+
+impl DriverStore for InMemoryStore {
+    fn add_driver(&self, name: &str) -> Result<i64, AspirinEatsError> {
+        let mut state = self.lock();
+        let id = state.drivers.len() as i64 + 1;
+        state.drivers.push(Driver {
+            id: Some(id),
+            name: normalize_name(name),
+            on_shift: false,
+            location: None,
+            delivering: None,
+        });
+        Ok(id)
+    }
+
+    fn get_driver(&self, id: i64) -> Result<Option<Driver>, AspirinEatsError> {
+        Ok(self.lock().driver(id))
+    }
+
+    fn get_all_drivers(&self) -> Result<Vec<Driver>, AspirinEatsError> {
+        let state = self.lock();
+        Ok((1..=state.drivers.len() as i64)
+            .filter_map(|id| state.driver(id))
+            .collect())
+    }
+
+    fn start_shift(&self, id: i64, location: Option<Location>) -> Result<Driver, AspirinEatsError> {
+        let mut state = self.lock();
+        let driver = index(id)
+            .and_then(|index| state.drivers.get_mut(index))
+            .ok_or(AspirinEatsError::NotFound)?;
+        driver.on_shift = true;
+        if location.is_some() {
+            driver.location = location;
+        }
+        state.driver(id).ok_or(AspirinEatsError::NotFound)
+    }
+
+    fn end_shift(&self, id: i64) -> Result<Driver, AspirinEatsError> {
+        let mut state = self.lock();
+        let driver = state.driver(id).ok_or(AspirinEatsError::NotFound)?;
+        if let Some(order_id) = driver.delivering {
+            return Err(AspirinEatsError::Conflict(format!(
+                "Driver {id} is still delivering order {order_id}"
+            )));
+        }
+        state.drivers[index(id).expect("driver exists")].on_shift = false;
+        state.driver(id).ok_or(AspirinEatsError::NotFound)
+    }
+
+    fn claim_delivery(&self, driver_id: i64, order_id: i64) -> Result<Order, AspirinEatsError> {
+        let mut state = self.lock();
+        let driver = state.driver(driver_id).ok_or(AspirinEatsError::NotFound)?;
+        if !driver.on_shift {
+            return Err(AspirinEatsError::Conflict(format!(
+                "Driver {driver_id} is off shift"
+            )));
+        }
+        if let Some(delivering) = driver.delivering {
+            return Err(AspirinEatsError::Conflict(format!(
+                "Driver {driver_id} is already delivering order {delivering}"
+            )));
+        }
+        let stored = state
+            .orders
+            .get_mut(&order_id)
+            .ok_or(AspirinEatsError::NotFound)?;
+        if stored.order.status != OrderStatus::Transporting || stored.order.driver.is_some() {
+            return Err(AspirinEatsError::Conflict(format!(
+                "Order {order_id} isn't ready for a driver"
+            )));
+        }
+        stored.order.driver = Some(driver_id);
+        stored.version += 1;
+        let order = stored.order.clone();
+        state.touch_orders();
+        Ok(order)
+    }
+
+    fn complete_delivery(&self, driver_id: i64) -> Result<Order, AspirinEatsError> {
+        let mut state = self.lock();
+        let driver = state.driver(driver_id).ok_or(AspirinEatsError::NotFound)?;
+        let order_id = driver.delivering.ok_or_else(|| {
+            AspirinEatsError::Conflict(format!("Driver {driver_id} isn't delivering anything"))
+        })?;
+        let stored = state.orders.get_mut(&order_id).expect("order exists");
+        stored.order.status = OrderStatus::Completed;
+        stored.version += 1;
+        let order = stored.order.clone();
+        if let Some(address) = &order.delivery {
+            state.drivers[index(driver_id).expect("driver exists")].location =
+                Some(address.location);
+        }
+        state.touch_orders();
+        Ok(order)
+    }
+}
+
+impl ReportStore for InMemoryStore {
+    fn sales_report(&self, query: &SalesQuery) -> Result<SalesReport, AspirinEatsError> {
+        let state = self.lock();
+        let matching: Vec<&StoredOrder> = state
+            .orders
+            .values()
+            .filter(|stored| {
+                let date = stored.created_at.date();
+                query.from.is_none_or(|from| date >= from) && query.to.is_none_or(|to| date <= to)
+            })
+            .collect();
+        let sold: Vec<&StoredOrder> = matching
+            .iter()
+            .copied()
+            .filter(|stored| stored.order.status != OrderStatus::Cancelled)
+            .collect();
+
+        let groups = match query.group_by {
+            ReportGrouping::Day => {
+                summarize_by(&sold, |stored| stored.created_at.date().to_string())
+            }
+            ReportGrouping::Status => {
+                summarize_by(&matching, |stored| variant_name(&stored.order.status))
+            }
+            ReportGrouping::Item => {
+                // Line items in the breakdown are in the same order as the food
+                let mut items: BTreeMap<String, (BTreeSet<i64>, i64, f64)> = BTreeMap::new();
+                for stored in &sold {
+                    for (position, item) in stored.order.food.iter().enumerate() {
+                        let key = match item {
+                            MenuItem::Burger(_) => "Burger".to_string(),
+                            item => variant_name(item),
+                        };
+                        let (orders, count, revenue) = items.entry(key).or_default();
+                        orders.extend(stored.order.id);
+                        *count += 1;
+                        *revenue += stored
+                            .order
+                            .breakdown
+                            .as_ref()
+                            .and_then(|breakdown| breakdown.lines.get(position))
+                            .map_or(0.0, |line| line.price);
+                    }
+                }
+                items
+                    .into_iter()
+                    .map(|(key, (orders, count, revenue))| SalesGroup {
+                        key,
+                        sales: SalesSummary::new(orders.len() as i64, count, revenue),
+                    })
+                    .collect()
+            }
+        };
+
+        let burgers = || {
+            sold.iter()
+                .flat_map(|stored| &stored.order.food)
+                .filter_map(|item| match item {
+                    MenuItem::Burger(burger) => Some(burger),
+                    _ => None,
+                })
+        };
+        Ok(SalesReport {
+            from: query.from,
+            to: query.to,
+            group_by: query.group_by,
+            summary: summarize(&sold),
+            groups,
+            popular_toppings: tally(
+                burgers().flat_map(|burger| burger.toppings().iter().map(variant_name)),
+            ),
+            patty_mix: tally(burgers().map(|burger| variant_name(burger.patty()))),
+        })
+    }
+}
+
+fn summarize(orders: &[&StoredOrder]) -> SalesSummary {
+    SalesSummary::new(
+        orders.len() as i64,
+        orders
+            .iter()
+            .map(|stored| stored.order.food.len() as i64)
+            .sum(),
+        orders.iter().map(|stored| stored.order.total).sum(),
+    )
+}
+
+/// Summarize `orders` in groups, in key order
+fn summarize_by(orders: &[&StoredOrder], key: impl Fn(&StoredOrder) -> String) -> Vec<SalesGroup> {
+    let mut groups: BTreeMap<String, Vec<&StoredOrder>> = BTreeMap::new();
+    for stored in orders {
+        groups.entry(key(stored)).or_default().push(stored);
+    }
+    groups
+        .into_iter()
+        .map(|(key, orders)| SalesGroup {
+            key,
+            sales: summarize(&orders),
+        })
+        .collect()
+}
+
+/// Count how many times each name comes up, most common first
+fn tally(names: impl Iterator<Item = String>) -> Vec<Tally> {
+    let mut counts: BTreeMap<String, i64> = BTreeMap::new();
+    for name in names {
+        *counts.entry(name).or_default() += 1;
+    }
+    let mut tally: Vec<Tally> = counts
+        .into_iter()
+        .map(|(name, count)| Tally { name, count })
+        .collect();
+    tally.sort_by_key(|tally| Reverse(tally.count));
+    tally
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::delivery::Address;
+    use crate::food::*;
+    use crate::pricing::Pricing;
+
+    /// Fries left in stock, or None if they aren't tracked
+    fn fries_in_stock<S: InventoryStore>(store: &S) -> Option<i64> {
+        store
+            .get_inventory()
+            .unwrap()
+            .into_iter()
+            .find(|level| level.ingredient == Ingredient::Fries)
+            .map(|level| level.quantity)
+    }
+
+    fn set_status<S: OrderStore>(store: &S, id: i64, status: OrderStatus) {
+        let mut order = store.get_order(id).unwrap().unwrap();
+        order.status = status;
+        assert!(store.update_order(&order).unwrap());
+    }
+
+    /// A priced order for a burger with `toppings` and fries
+    fn get_priced_order(patty: Patty, toppings: Vec<Topping>) -> Order {
+        Pricing::default()
+            .order(
+                OrderRequest {
+                    customer: "Amit".to_string(),
+                    food: vec![
+                        MenuItem::Burger(Burger::new(Bun::Plain, patty, toppings)),
+                        MenuItem::Fries,
+                    ],
+                    coupon: None,
+                    delivery: None,
+                    scheduled_for: None,
+                },
+                Local::now().date_naive(),
+            )
+            .unwrap()
+    }
+
+    fn get_test_order(customer: &str) -> Order {
+        Order {
+            id: None,
+            customer: customer.to_string(),
+            food: vec![MenuItem::Fries, MenuItem::Drink],
+            status: OrderStatus::Pending,
+            total: 8.0,
+            breakdown: None,
+            delivery: None,
+            driver: None,
+            scheduled_for: None,
+        }
+    }
+
+    /// Tests that every OrderStore must pass, run once for each implementation
+    macro_rules! conformance_tests {
+        ($name:ident, $store:expr) => {
+            mod $name {
+                use super::*;
+
+                #[test]
+                fn test_add_get_order() {
+                    let store = $store;
+                    let mut order = get_test_order("Amit");
+                    order.id = Some(store.add_order(order.clone()).unwrap());
+
+                    assert_eq!(store.get_order(order.id.unwrap()).unwrap(), Some(order));
+                    assert_eq!(store.get_order(42).unwrap(), None);
+                }
+
+                #[test]
+                fn test_ids_are_sequential_and_not_reused() {
+                    let store = $store;
+                    assert_eq!(store.add_order(get_test_order("Amit")).unwrap(), 1);
+                    assert_eq!(store.add_order(get_test_order("Amit")).unwrap(), 2);
+                    store.remove_order(2).unwrap();
+                    assert_eq!(store.add_order(get_test_order("Amit")).unwrap(), 3);
+                }
+
+                #[test]
+                fn test_add_ignores_existing_id() {
+                    let store = $store;
+                    let mut order = get_test_order("Amit");
+                    order.id = Some(99);
+                    assert_eq!(store.add_order(order).unwrap(), 1);
+                }
+
+                #[test]
+                fn test_get_all_orders_in_id_order() {
+                    let store = $store;
+                    for customer in ["Amit", "Bea", "Cal"] {
+                        store.add_order(get_test_order(customer)).unwrap();
+                    }
+                    store.remove_order(2).unwrap();
+
+                    let got = store.get_all_orders().unwrap();
+                    let ids: Vec<i64> = got.iter().map(|o| o.id.unwrap()).collect();
+                    assert_eq!(ids, vec![1, 3]);
+                }
+
+                #[test]
+                fn test_customer_names_are_canonical() {
+                    let store = $store;
+                    store.add_order(get_test_order("Amit")).unwrap();
+                    let id = store.add_order(get_test_order("  AMIT ")).unwrap();
+
+                    assert_eq!(store.get_order(id).unwrap().unwrap().customer, "Amit");
+                }
+
+                #[test]
+                fn test_update_order() {
+                    let store = $store;
+                    let id = store.add_order(get_test_order("Amit")).unwrap();
+
+                    let mut order = store.get_order(id).unwrap().unwrap();
+                    order.status = OrderStatus::Preparing;
+                    order.food.push(MenuItem::Fries);
+                    order.total = 13.0;
+                    assert!(store.update_order(&order).unwrap());
+                    assert_eq!(store.get_order(id).unwrap(), Some(order.clone()));
+
+                    order.id = Some(42);
+                    assert!(!store.update_order(&order).unwrap());
+                    order.id = None;
+                    assert!(!store.update_order(&order).unwrap());
+                    assert_eq!(store.get_all_orders().unwrap().len(), 1);
+                }
+
+                #[test]
+                fn test_remove_missing_order() {
+                    let store = $store;
+                    store.remove_order(7).unwrap();
+                    assert!(store.get_all_orders().unwrap().is_empty());
+                }
+
+                #[test]
+                fn test_reset_restarts_ids() {
+                    let store = $store;
+                    for _ in 0..3 {
+                        store.add_order(get_test_order("Amit")).unwrap();
+                    }
+                    store.reset_orders().unwrap();
+
+                    assert!(store.get_all_orders().unwrap().is_empty());
+                    assert_eq!(store.add_order(get_test_order("Amit")).unwrap(), 1);
+                }
+
+                #[test]
+                fn test_versions() {
+                    let store = $store;
+                    let id = store.add_order(get_test_order("Amit")).unwrap();
+                    assert_eq!(store.get_order_version(id).unwrap(), Some(1));
+
+                    set_status(&store, id, OrderStatus::Pending);
+                    assert_eq!(store.get_order_version(id).unwrap(), Some(2));
+                    let breakdown = Pricing::default()
+                        .price(&[MenuItem::Drink], None, Local::now().date_naive())
+                        .unwrap();
+                    assert_eq!(
+                        store
+                            .replace_items(id, 2, &[MenuItem::Drink], &breakdown)
+                            .unwrap(),
+                        3
+                    );
+                    assert_eq!(store.get_order_version(id).unwrap(), Some(3));
+                    assert_eq!(store.get_order_version(42).unwrap(), None);
+                }
+
+                #[test]
+                fn test_set_status() {
+                    let store = $store;
+                    let food = get_test_order("Amit").food;
+                    let id = store.add_order(get_test_order("Amit")).unwrap();
+                    assert!(store
+                        .set_status(id, OrderStatus::Pending, OrderStatus::Preparing, &food)
+                        .unwrap());
+                    assert_eq!(store.get_order_version(id).unwrap(), Some(2));
+
+                    // A second writer making the same change finds it already made
+                    assert!(!store
+                        .set_status(id, OrderStatus::Pending, OrderStatus::Preparing, &food)
+                        .unwrap());
+                    assert!(!store
+                        .set_status(42, OrderStatus::Pending, OrderStatus::Preparing, &food)
+                        .unwrap());
+                    let order = store.get_order(id).unwrap().unwrap();
+                    assert_eq!(order.status, OrderStatus::Preparing);
+                    assert_eq!(store.get_order_version(id).unwrap(), Some(2));
+
+                    // Nor can anyone act on food that has been replaced since they read it
+                    let id = store.add_order(get_test_order("Amit")).unwrap();
+                    let breakdown = Pricing::default()
+                        .price(&[MenuItem::Drink], None, Local::now().date_naive())
+                        .unwrap();
+                    store
+                        .replace_items(id, 1, &[MenuItem::Drink], &breakdown)
+                        .unwrap();
+                    assert!(!store
+                        .set_status(id, OrderStatus::Pending, OrderStatus::Preparing, &food)
+                        .unwrap());
+                    assert!(store
+                        .set_status(
+                            id,
+                            OrderStatus::Pending,
+                            OrderStatus::Preparing,
+                            &[MenuItem::Drink]
+                        )
+                        .unwrap());
+
+                    // Cancelling a waiting order restocks it, like update_order
+                    store
+                        .set_stock(&[StockLevel {
+                            ingredient: Ingredient::Fries,
+                            quantity: 1,
+                        }])
+                        .unwrap();
+                    let id = store.add_order(get_test_order("Amit")).unwrap();
+                    assert!(store
+                        .set_status(id, OrderStatus::Pending, OrderStatus::Cancelled, &food)
+                        .unwrap());
+                    assert_eq!(fries_in_stock(&store), Some(1));
+                }
+
+                #[test]
+                fn test_orders_last_modified() {
+                    let store = $store;
+                    assert_eq!(store.orders_last_modified().unwrap(), None);
+                    store.add_order(get_test_order("Amit")).unwrap();
+
+                    let modified = store.orders_last_modified().unwrap().unwrap();
+                    assert!((Utc::now() - modified).num_seconds() < 5);
+                }
+
+                #[test]
+                fn test_stock_follows_orders() {
+                    let store = $store;
+                    store
+                        .set_stock(&[StockLevel {
+                            ingredient: Ingredient::Fries,
+                            quantity: 1,
+                        }])
+                        .unwrap();
+
+                    // Drinks aren't tracked, so only the fries run out
+                    let first = store.add_order(get_test_order("Amit")).unwrap();
+                    assert_eq!(fries_in_stock(&store), Some(0));
+                    assert!(matches!(
+                        store.add_order(get_test_order("Amit")),
+                        Err(AspirinEatsError::OutOfStock(Ingredient::Fries))
+                    ));
+                    assert_eq!(store.get_all_orders().unwrap().len(), 1);
+
+                    // Cancelling gives the fries back, but only once
+                    set_status(&store, first, OrderStatus::Cancelled);
+                    set_status(&store, first, OrderStatus::Cancelled);
+                    assert_eq!(fries_in_stock(&store), Some(1));
+
+                    let second = store.add_order(get_test_order("Amit")).unwrap();
+                    store.remove_order(second).unwrap();
+                    assert_eq!(fries_in_stock(&store), Some(1));
+
+                    // Fries the kitchen has started on are gone
+                    let third = store.add_order(get_test_order("Amit")).unwrap();
+                    set_status(&store, third, OrderStatus::Preparing);
+                    store.remove_order(third).unwrap();
+                    assert_eq!(fries_in_stock(&store), Some(0));
+                }
+
+                #[test]
+                fn test_replace_items() {
+                    let store = $store;
+                    store
+                        .set_stock(&[StockLevel {
+                            ingredient: Ingredient::Fries,
+                            quantity: 2,
+                        }])
+                        .unwrap();
+                    let id = store.add_order(get_test_order("Amit")).unwrap();
+                    let price = |food: &[MenuItem]| {
+                        Pricing::default()
+                            .price(food, None, Local::now().date_naive())
+                            .unwrap()
+                    };
+
+                    let food = vec![MenuItem::Fries, MenuItem::Fries];
+                    assert_eq!(store.replace_items(id, 1, &food, &price(&food)).unwrap(), 2);
+                    let order = store.get_order(id).unwrap().unwrap();
+                    assert_eq!(order.food, food);
+                    assert_eq!(order.total, 10.0);
+                    assert_eq!(order.breakdown, Some(price(&food)));
+                    assert_eq!(fries_in_stock(&store), Some(0));
+
+                    let more = vec![MenuItem::Fries; 3];
+                    assert!(matches!(
+                        store.replace_items(id, 2, &more, &price(&more)),
+                        Err(AspirinEatsError::OutOfStock(Ingredient::Fries))
+                    ));
+                    assert!(matches!(
+                        store.replace_items(id, 1, &food, &price(&food)),
+                        Err(AspirinEatsError::PreconditionFailed)
+                    ));
+                    assert!(matches!(
+                        store.replace_items(42, 1, &food, &price(&food)),
+                        Err(AspirinEatsError::NotFound)
+                    ));
+                    assert_eq!(store.get_order(id).unwrap(), Some(order));
+                    assert_eq!(fries_in_stock(&store), Some(0));
+
+                    set_status(&store, id, OrderStatus::Preparing);
+                    assert!(matches!(
+                        store.replace_items(id, 3, &food, &price(&food)),
+                        Err(AspirinEatsError::Conflict(_))
+                    ));
+                }
+
+                #[test]
+                fn test_add_orders() {
+                    let store = $store;
+                    store
+                        .set_stock(&[StockLevel {
+                            ingredient: Ingredient::Fries,
+                            quantity: 1,
+                        }])
+                        .unwrap();
+                    let batch = vec![get_test_order("Amit"), get_test_order("Bea")];
+
+                    let outcome = store
+                        .add_orders(batch.clone(), BatchMode::AllOrNothing)
+                        .unwrap();
+                    assert!(!outcome.committed);
+                    assert!(matches!(outcome.results[..], [Ok(1), Err(_)]));
+                    assert!(store.get_all_orders().unwrap().is_empty());
+                    assert!(store.get_all_customers().unwrap().is_empty());
+                    assert_eq!(fries_in_stock(&store), Some(1));
+
+                    let outcome = store.add_orders(batch, BatchMode::BestEffort).unwrap();
+                    assert!(outcome.committed);
+                    assert!(matches!(
+                        outcome.results[..],
+                        [Ok(1), Err(AspirinEatsError::OutOfStock(Ingredient::Fries))]
+                    ));
+                    assert_eq!(store.get_all_orders().unwrap().len(), 1);
+                    assert_eq!(fries_in_stock(&store), Some(0));
+                }
+
+                #[test]
+                fn test_customers() {
+                    let store = $store;
+                    store.add_order(get_test_order("Amit")).unwrap();
+                    store.add_order(get_test_order("  amit ")).unwrap();
+                    let id = store.add_order(get_test_order("Bea")).unwrap();
+
+                    let names: Vec<String> = store
+                        .get_all_customers()
+                        .unwrap()
+                        .into_iter()
+                        .map(|customer| customer.name)
+                        .collect();
+                    assert_eq!(names, vec!["Amit", "Bea"]);
+                    assert_eq!(
+                        store.get_customer(2).unwrap(),
+                        Some(Customer {
+                            id: Some(2),
+                            name: "Bea".to_string()
+                        })
+                    );
+                    assert_eq!(store.get_customer(9).unwrap(), None);
+
+                    // Moving an order to another customer goes by their name too
+                    let mut order = store.get_order(id).unwrap().unwrap();
+                    order.customer = "AMIT".to_string();
+                    store.update_order(&order).unwrap();
+                    assert_eq!(store.get_order(id).unwrap().unwrap().customer, "Amit");
+                    let ids: Vec<i64> = store
+                        .get_customer_orders(1)
+                        .unwrap()
+                        .iter()
+                        .map(|order| order.id.unwrap())
+                        .collect();
+                    assert_eq!(ids, vec![1, 2, 3]);
+                    assert!(store.get_customer_orders(2).unwrap().is_empty());
+                }
+
+                #[test]
+                fn test_inventory() {
+                    let store = $store;
+                    store
+                        .set_stock(&[
+                            StockLevel {
+                                ingredient: Ingredient::Patty(Patty::Beef),
+                                quantity: 3,
+                            },
+                            StockLevel {
+                                ingredient: Ingredient::Fries,
+                                quantity: 1,
+                            },
+                        ])
+                        .unwrap();
+                    assert!(matches!(
+                        store.set_stock(&[StockLevel {
+                            ingredient: Ingredient::Drink,
+                            quantity: -1,
+                        }]),
+                        Err(AspirinEatsError::InvalidRequest)
+                    ));
+
+                    // Nothing changes unless every change can be made
+                    let change = |ingredient, change| StockChange { ingredient, change };
+                    assert!(matches!(
+                        store.adjust_stock(&[
+                            change(Ingredient::Fries, 5),
+                            change(Ingredient::Patty(Patty::Beef), -4),
+                        ]),
+                        Err(AspirinEatsError::OutOfStock(Ingredient::Patty(Patty::Beef)))
+                    ));
+                    store
+                        .adjust_stock(&[
+                            change(Ingredient::Drink, 2),
+                            change(Ingredient::Fries, -1),
+                        ])
+                        .unwrap();
+
+                    let levels: Vec<(Ingredient, i64)> = store
+                        .get_inventory()
+                        .unwrap()
+                        .into_iter()
+                        .map(|level| (level.ingredient, level.quantity))
+                        .collect();
+                    assert_eq!(
+                        levels,
+                        vec![
+                            (Ingredient::Drink, 2),
+                            (Ingredient::Fries, 0),
+                            (Ingredient::Patty(Patty::Beef), 3),
+                        ]
+                    );
+                }
+
+                #[test]
+                fn test_drivers() {
+                    let store = $store;
+                    let address = Address {
+                        street: "1 Main St".to_string(),
+                        city: "Boston".to_string(),
+                        postcode: "02134".to_string(),
+                        location: Location {
+                            lat: 42.36,
+                            lon: -71.06,
+                        },
+                    };
+                    let id = store
+                        .add_order(Order {
+                            delivery: Some(address.clone()),
+                            ..get_test_order("Amit")
+                        })
+                        .unwrap();
+                    assert_eq!(store.add_driver("  Sam  Smith ").unwrap(), 1);
+                    assert_eq!(store.get_driver(1).unwrap().unwrap().name, "Sam Smith");
+                    assert!(matches!(
+                        store.claim_delivery(1, id),
+                        Err(AspirinEatsError::Conflict(_))
+                    ));
+
+                    let start = Location {
+                        lat: 42.0,
+                        lon: -71.0,
+                    };
+                    let driver = store.start_shift(1, Some(start)).unwrap();
+                    assert!(driver.on_shift);
+                    assert_eq!(driver.location, Some(start));
+                    assert!(matches!(
+                        store.claim_delivery(1, id),
+                        Err(AspirinEatsError::Conflict(_))
+                    ));
+
+                    set_status(&store, id, OrderStatus::Transporting);
+                    let order = store.claim_delivery(1, id).unwrap();
+                    assert_eq!(order.driver, Some(1));
+                    assert_eq!(store.get_order_version(id).unwrap(), Some(3));
+                    assert_eq!(store.get_driver(1).unwrap().unwrap().delivering, Some(id));
+                    assert!(matches!(
+                        store.end_shift(1),
+                        Err(AspirinEatsError::Conflict(_))
+                    ));
+
+                    let order = store.complete_delivery(1).unwrap();
+                    assert_eq!(order.status, OrderStatus::Completed);
+                    assert_eq!(store.get_order(id).unwrap(), Some(order));
+                    let driver = store.end_shift(1).unwrap();
+                    assert_eq!(driver.location, Some(address.location));
+                    assert_eq!(driver.delivering, None);
+                    assert!(matches!(
+                        store.complete_delivery(1),
+                        Err(AspirinEatsError::Conflict(_))
+                    ));
+
+                    assert_eq!(store.get_all_drivers().unwrap(), vec![driver]);
+                    assert_eq!(store.get_driver(2).unwrap(), None);
+                    assert!(matches!(
+                        store.start_shift(2, None),
+                        Err(AspirinEatsError::NotFound)
+                    ));
+                }
+
+                #[test]
+                fn test_sales_report() {
+                    let store = $store;
+                    store
+                        .add_order(get_priced_order(
+                            Patty::Beef,
+                            vec![Topping::Cheese, Topping::Bacon],
+                        ))
+                        .unwrap();
+                    store
+                        .add_order(get_priced_order(Patty::Chicken, vec![Topping::Bacon]))
+                        .unwrap();
+                    let cancelled = store
+                        .add_order(get_priced_order(Patty::Veggie, vec![Topping::Onion]))
+                        .unwrap();
+                    set_status(&store, cancelled, OrderStatus::Cancelled);
+
+                    let today = Local::now().date_naive();
+                    let report = |group_by| {
+                        store
+                            .sales_report(&SalesQuery {
+                                from: Some(today),
+                                to: Some(today),
+                                group_by,
+                            })
+                            .unwrap()
+                    };
+                    let tally = |name: &str, count| Tally {
+                        name: name.to_string(),
+                        count,
+                    };
+
+                    let by_item = report(ReportGrouping::Item);
+                    assert_eq!(by_item.summary, SalesSummary::new(2, 4, 30.0));
+                    assert_eq!(
+                        by_item.groups,
+                        vec![
+                            SalesGroup {
+                                key: "Burger".to_string(),
+                                sales: SalesSummary::new(2, 2, 20.0),
+                            },
+                            SalesGroup {
+                                key: "Fries".to_string(),
+                                sales: SalesSummary::new(2, 2, 10.0),
+                            },
+                        ]
+                    );
+                    assert_eq!(
+                        by_item.popular_toppings,
+                        vec![tally("Bacon", 2), tally("Cheese", 1)]
+                    );
+                    assert_eq!(
+                        by_item.patty_mix,
+                        vec![tally("Beef", 1), tally("Chicken", 1)]
+                    );
+
+                    let keys = |report: SalesReport| -> Vec<String> {
+                        report.groups.into_iter().map(|group| group.key).collect()
+                    };
+                    assert_eq!(keys(report(ReportGrouping::Day)), vec![today.to_string()]);
+                    assert_eq!(
+                        keys(report(ReportGrouping::Status)),
+                        vec!["Cancelled", "Pending"]
+                    );
+
+                    let tomorrow = store
+                        .sales_report(&SalesQuery {
+                            from: today.succ_opt(),
+                            to: None,
+                            group_by: ReportGrouping::Day,
+                        })
+                        .unwrap();
+                    assert_eq!(tomorrow.summary, SalesSummary::new(0, 0, 0.0));
+                    assert!(tomorrow.groups.is_empty());
+                }
+            }
+        };
+    }
+
+    conformance_tests!(sqlite, AspirinEatsDb::in_memory().unwrap());
+    conformance_tests!(in_memory, InMemoryStore::new());
+}
@@ -0,0 +1,183 @@
+use std::fs::File;
+use std::io::BufReader;
+use std::net::TcpStream;
+use std::path::Path;
+use std::sync::Arc;
+
+use rustls::crypto::{ring, CryptoProvider};
+use rustls::pki_types::{CertificateDer, PrivateKeyDer, ServerName};
+use rustls::{
+    ClientConfig, ClientConnection, RootCertStore, ServerConfig, ServerConnection, StreamOwned,
+};
+
+use crate::error::AspirinEatsError;
+
+/// A client connection with TLS terminated by us
+pub type TlsStream = StreamOwned<ServerConnection, TcpStream>;
+
+/// A connection to a server, with TLS started by us
+pub type TlsClientStream = StreamOwned<ClientConnection, TcpStream>;
+
+/// Crypto used for every TLS connection
+pub fn crypto_provider() -> Arc<CryptoProvider> {
+    Arc::new(ring::default_provider())
+}
+
+/// Load a PEM certificate chain and private key into a server config
+pub fn load_server_config(
+    cert_path: &Path,
+    key_path: &Path,
+) -> Result<Arc<ServerConfig>, AspirinEatsError> {
+    let certs = load_certs(cert_path)?;
+    let key = load_key(key_path)?;
+    let config = ServerConfig::builder_with_provider(crypto_provider())
+        .with_safe_default_protocol_versions()?
+        .with_no_client_auth()
+        .with_single_cert(certs, key)?;
+    Ok(Arc::new(config))
+}
+
+/// Wrap an accepted connection in TLS, completing the handshake before returning
+pub fn accept(
+    config: &Arc<ServerConfig>,
+    mut stream: TcpStream,
+) -> Result<TlsStream, AspirinEatsError> {
+    let mut connection = ServerConnection::new(config.clone())?;
+    while connection.is_handshaking() {
+        if let Err(err) = connection.complete_io(&mut stream) {
+            // Let the client know why we hung up, if we can
+            let _ = connection.write_tls(&mut stream);
+            return Err(err.into());
+        }
+    }
+    Ok(StreamOwned::new(connection, stream))
+}
+
+/// Load a client config that trusts only the PEM certificates in `ca_path`
+pub fn load_client_config(ca_path: &Path) -> Result<Arc<ClientConfig>, AspirinEatsError> {
+    let mut roots = RootCertStore::empty();
+    for cert in load_certs(ca_path)? {
+        roots.add(cert)?;
+    }
+    let config = ClientConfig::builder_with_provider(crypto_provider())
+        .with_safe_default_protocol_versions()?
+        .with_root_certificates(roots)
+        .with_no_client_auth();
+    Ok(Arc::new(config))
+}
+
+/// Wrap a connection to `host` in TLS, completing the handshake before returning
+pub fn connect(
+    config: &Arc<ClientConfig>,
+    host: &str,
+    mut stream: TcpStream,
+) -> Result<TlsClientStream, AspirinEatsError> {
+    let name = ServerName::try_from(host.to_string())
+        .map_err(|_| rustls::Error::General(format!("invalid server name {host:?}")))?;
+    let mut connection = ClientConnection::new(config.clone(), name)?;
+    while connection.is_handshaking() {
+        connection.complete_io(&mut stream)?;
+    }
+    Ok(StreamOwned::new(connection, stream))
+}
+
+fn load_certs(path: &Path) -> Result<Vec<CertificateDer<'static>>, AspirinEatsError> {
+    let mut reader = open_pem(path)?;
+    let certs = rustls_pemfile::certs(&mut reader)
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|err| pem_error(path, err))?;
+    if certs.is_empty() {
+        return Err(pem_error(path, "no certificates found"));
+    }
+    Ok(certs)
+}
+
+fn load_key(path: &Path) -> Result<PrivateKeyDer<'static>, AspirinEatsError> {
+    let mut reader = open_pem(path)?;
+    rustls_pemfile::private_key(&mut reader)
+        .map_err(|err| pem_error(path, err))?
+        .ok_or_else(|| pem_error(path, "no private key found"))
+}
+
+fn open_pem(path: &Path) -> Result<BufReader<File>, AspirinEatsError> {
+    File::open(path)
+        .map(BufReader::new)
+        .map_err(|err| pem_error(path, err))
+}
+
+fn pem_error(path: &Path, err: impl std::fmt::Display) -> AspirinEatsError {
+    rustls::Error::General(format!("{}: {err}", path.display())).into()
+}
+
+/// Self-signed certificates for tests, written to temporary files
+#[cfg(test)]
+pub(crate) struct TestCert {
+    pub(crate) cert_path: std::path::PathBuf,
+    pub(crate) key_path: std::path::PathBuf,
+}
+
+#[cfg(test)]
+impl TestCert {
+    /// Generate a certificate for `localhost`
+    pub(crate) fn generate() -> Self {
+        let rcgen::CertifiedKey { cert, key_pair } =
+            rcgen::generate_simple_self_signed(vec!["localhost".to_string()]).unwrap();
+        let id = uuid::Uuid::new_v4();
+        let cert_path = std::env::temp_dir().join(format!("aspirin-eats-{id}.crt"));
+        let key_path = std::env::temp_dir().join(format!("aspirin-eats-{id}.key"));
+        std::fs::write(&cert_path, cert.pem()).unwrap();
+        std::fs::write(&key_path, key_pair.serialize_pem()).unwrap();
+        TestCert {
+            cert_path,
+            key_path,
+        }
+    }
+
+    /// Client config that only trusts this certificate
+    pub(crate) fn client_config(&self) -> Arc<ClientConfig> {
+        load_client_config(&self.cert_path).unwrap()
+    }
+}
+
+#[cfg(test)]
+impl Drop for TestCert {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_file(&self.cert_path);
+        let _ = std::fs::remove_file(&self.key_path);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_load_server_config() {
+        let cert = TestCert::generate();
+        assert!(load_server_config(&cert.cert_path, &cert.key_path).is_ok());
+    }
+
+    #[test]
+    fn test_load_server_config_errors() {
+        let cert = TestCert::generate();
+        let missing = Path::new("/nonexistent/aspirin-eats.key");
+
+        let err = load_server_config(&cert.cert_path, missing).unwrap_err();
+        assert!(err.to_string().contains("/nonexistent/aspirin-eats.key"));
+
+        // The key file has no certificates in it, and the certificate no key
+        let err = load_server_config(&cert.key_path, &cert.key_path).unwrap_err();
+        assert!(err.to_string().contains("no certificates found"));
+        let err = load_server_config(&cert.cert_path, &cert.cert_path).unwrap_err();
+        assert!(err.to_string().contains("no private key found"));
+    }
+
+    #[test]
+    fn test_load_client_config_errors() {
+        let cert = TestCert::generate();
+        let err = load_client_config(&cert.key_path).unwrap_err();
+        assert!(err.to_string().contains("no certificates found"));
+        let err = load_client_config(Path::new("/nonexistent/aspirin-eats.crt")).unwrap_err();
+        assert!(err.to_string().contains("/nonexistent/aspirin-eats.crt"));
+    }
+}
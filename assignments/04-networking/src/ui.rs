@@ -0,0 +1,276 @@
+use std::fs;
+use std::path::{Component, Path, PathBuf};
+
+use serde::Deserialize;
+
+use crate::error::AspirinEatsError;
+use crate::http::{http_date, percent_decode_path, HttpResponse};
+
+/// Path the web UI is served under
+pub const UI_PREFIX: &str = "/ui";
+
+/// The web UI: a directory of static files served under `/ui/`
+#[derive(Deserialize, Debug, PartialEq, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub struct UiPolicy {
+    /// Whether the UI is served at all
+    pub enabled: bool,
+
+    /// Directory holding the UI. Relative to where the origin is started from
+    pub dir: PathBuf,
+
+    /// How long browsers may use a stylesheet, script or image without checking for a new one.
+    /// Pages are always checked, so they pick up new versions of everything straight away
+    pub max_age_secs: u64,
+}
+
+impl Default for UiPolicy {
+    fn default() -> Self {
+        UiPolicy {
+            enabled: true,
+            dir: PathBuf::from("ui"),
+            max_age_secs: 3600,
+        }
+    }
+}
+
+impl UiPolicy {
+    /// `GET /ui/...`: the file `route` names, or the `index.html` of the directory it names.
+    /// Directories are redirected to their path with a trailing slash, so relative links in
+    /// their pages work. Responses are text, so only files of the text types `content_type`
+    /// knows are served. Anything else, or a file that isn't valid UTF-8, is not found
+    pub fn serve(&self, route: &str) -> Result<HttpResponse, AspirinEatsError> {
+        let rest = route
+            .strip_prefix(UI_PREFIX)
+            .filter(|rest| self.enabled && (rest.is_empty() || rest.starts_with('/')))
+            .ok_or(AspirinEatsError::NotFound)?;
+
+        let mut path = self.dir.clone();
+        for segment in rest.split('/').filter(|segment| !segment.is_empty()) {
+            path.push(safe_segment(segment)?);
+        }
+        if path.is_dir() {
+            if !route.ends_with('/') {
+                return Ok(HttpResponse::new(301, "Moved Permanently", "")
+                    .with_header("Location", &format!("{route}/")));
+            }
+            path.push("index.html");
+        }
+
+        // A symlink could still lead out of the directory
+        let root = self
+            .dir
+            .canonicalize()
+            .map_err(|_| AspirinEatsError::NotFound)?;
+        let path = path
+            .canonicalize()
+            .map_err(|_| AspirinEatsError::NotFound)?;
+        if !path.starts_with(&root) || !path.is_file() {
+            return Err(AspirinEatsError::NotFound);
+        }
+        let content_type = content_type(&path).ok_or(AspirinEatsError::NotFound)?;
+
+        let body = String::from_utf8(fs::read(&path)?).map_err(|_| AspirinEatsError::NotFound)?;
+        let modified = fs::metadata(&path)?.modified()?;
+        let cache_control = match content_type.starts_with("text/html") {
+            true => "no-cache".to_string(),
+            false => format!("public, max-age={}", self.max_age_secs),
+        };
+        Ok(HttpResponse::new(200, "OK", &body)
+            .with_header("Content-Type", content_type)
+            .with_header("Cache-Control", &cache_control)
+            .with_header("Last-Modified", &http_date(modified.into())))
+    }
+}
+
+/// A decoded path segment, as long as it names something inside its directory. `..`, `.`,
+/// hidden files and anything with a separator in it are treated as not being there
+fn safe_segment(segment: &str) -> Result<String, AspirinEatsError> {
+    let segment = percent_decode_path(segment);
+    let mut components = Path::new(&segment).components();
+    match (components.next(), components.next()) {
+        (Some(Component::Normal(_)), None)
+            if !segment.starts_with('.') && !segment.contains(['/', '\\', '\0']) =>
+        {
+            Ok(segment)
+        }
+        _ => Err(AspirinEatsError::NotFound),
+    }
+}
+
+/// The `Content-Type` of a text file, going by its extension. None for anything else, which
+/// can't be served as text
+fn content_type(path: &Path) -> Option<&'static str> {
+    let extension = path
+        .extension()
+        .and_then(|extension| extension.to_str())
+        .unwrap_or_default()
+        .to_ascii_lowercase();
+    Some(match extension.as_str() {
+        "html" | "htm" => "text/html; charset=utf-8",
+        "css" => "text/css; charset=utf-8",
+        "js" | "mjs" => "text/javascript; charset=utf-8",
+        "json" | "map" => "application/json",
+        "svg" => "image/svg+xml",
+        "txt" => "text/plain; charset=utf-8",
+        "xml" => "application/xml",
+        "webmanifest" => "application/manifest+json",
+        _ => return None,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A UI directory with a page, a stylesheet and a subdirectory, next to a file that
+    /// shouldn't be reachable from it. Deleted when dropped
+    struct TestUi {
+        root: PathBuf,
+        policy: UiPolicy,
+    }
+
+    impl TestUi {
+        fn create() -> Self {
+            let root = std::env::temp_dir().join(format!("aspirin-eats-{}", uuid::Uuid::new_v4()));
+            let dir = root.join("ui");
+            fs::create_dir_all(dir.join("orders")).unwrap();
+            fs::write(dir.join("index.html"), "<h1>Menu</h1>").unwrap();
+            fs::write(dir.join("app.css"), "body {}").unwrap();
+            fs::write(dir.join("c++ notes.txt"), "plus").unwrap();
+            fs::write(dir.join(".env"), "SECRET=1").unwrap();
+            fs::write(dir.join("logo.png"), b"\x89PNG\r\n\x1a\n\xff").unwrap();
+            fs::write(dir.join("latin1.txt"), b"caf\xe9").unwrap();
+            fs::write(dir.join("orders").join("index.html"), "<h1>Orders</h1>").unwrap();
+            fs::write(root.join("secret.txt"), "secret").unwrap();
+            TestUi {
+                root,
+                policy: UiPolicy {
+                    dir,
+                    ..UiPolicy::default()
+                },
+            }
+        }
+    }
+
+    impl Drop for TestUi {
+        fn drop(&mut self) {
+            let _ = fs::remove_dir_all(&self.root);
+        }
+    }
+
+    #[test]
+    fn test_serve() {
+        let ui = TestUi::create();
+        let policy = &ui.policy;
+
+        let response = policy.serve("/ui/app.css").unwrap();
+        assert_eq!(response.status_code(), 200);
+        assert_eq!(response.body(), "body {}");
+        assert_eq!(
+            response.header("Content-Type"),
+            Some("text/css; charset=utf-8")
+        );
+        assert_eq!(
+            response.header("Cache-Control"),
+            Some("public, max-age=3600")
+        );
+        assert!(response.header("Last-Modified").is_some());
+
+        // Escapes are decoded, but a plus is just a plus
+        assert_eq!(
+            policy.serve("/ui/c++%20notes%2Etxt").unwrap().body(),
+            "plus"
+        );
+        assert!(matches!(
+            policy.serve("/ui/c++notes.txt"),
+            Err(AspirinEatsError::NotFound)
+        ));
+    }
+
+    #[test]
+    fn test_serve_index() {
+        let ui = TestUi::create();
+        let policy = &ui.policy;
+
+        for route in ["/ui/", "/ui/index.html"] {
+            let response = policy.serve(route).unwrap();
+            assert_eq!(response.body(), "<h1>Menu</h1>");
+            assert_eq!(
+                response.header("Content-Type"),
+                Some("text/html; charset=utf-8")
+            );
+            assert_eq!(response.header("Cache-Control"), Some("no-cache"));
+        }
+        assert_eq!(
+            policy.serve("/ui/orders/").unwrap().body(),
+            "<h1>Orders</h1>"
+        );
+
+        for (route, location) in [("/ui", "/ui/"), ("/ui/orders", "/ui/orders/")] {
+            let response = policy.serve(route).unwrap();
+            assert_eq!(response.status_code(), 301);
+            assert_eq!(response.header("Location"), Some(location));
+        }
+    }
+
+    #[test]
+    fn test_serve_refuses_traversal() {
+        let ui = TestUi::create();
+        let policy = &ui.policy;
+        for route in [
+            "/ui/../secret.txt",
+            "/ui/%2e%2e/secret.txt",
+            "/ui/orders/..%2F..%2Fsecret.txt",
+            "/ui/..%5Csecret.txt",
+            "/ui/%2Fetc%2Fpasswd",
+            "/ui/.env",
+            "/ui/./app.css",
+            "/ui/missing.js",
+            "/uiapp.css",
+        ] {
+            assert!(
+                matches!(policy.serve(route), Err(AspirinEatsError::NotFound)),
+                "{route}"
+            );
+        }
+    }
+
+    #[test]
+    fn test_serve_only_text() {
+        let ui = TestUi::create();
+        let policy = &ui.policy;
+        for route in ["/ui/logo.png", "/ui/latin1.txt"] {
+            assert!(
+                matches!(policy.serve(route), Err(AspirinEatsError::NotFound)),
+                "{route}"
+            );
+        }
+    }
+
+    #[test]
+    fn test_serve_disabled() {
+        let ui = TestUi::create();
+        let policy = UiPolicy {
+            enabled: false,
+            ..ui.policy.clone()
+        };
+        assert!(matches!(
+            policy.serve("/ui/"),
+            Err(AspirinEatsError::NotFound)
+        ));
+    }
+
+    #[test]
+    fn test_content_type() {
+        for (file, expected) in [
+            ("index.HTML", Some("text/html; charset=utf-8")),
+            ("app.js", Some("text/javascript; charset=utf-8")),
+            ("logo.svg", Some("image/svg+xml")),
+            ("logo.png", None),
+            ("README", None),
+        ] {
+            assert_eq!(content_type(Path::new(file)), expected, "{file}");
+        }
+    }
+}
@@ -0,0 +1,269 @@
+use std::io::ErrorKind;
+use std::net::{TcpStream, ToSocketAddrs};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use serde::Deserialize;
+
+/// Ways talking to the origin can go wrong
+#[derive(thiserror::Error, Debug)]
+pub enum UpstreamError {
+    #[error("could not connect to origin: {0}")]
+    Connect(std::io::Error),
+
+    #[error("origin did not respond in time")]
+    Timeout,
+
+    #[error("lost connection to origin: {0}")]
+    Io(std::io::Error),
+
+    #[error("origin sent an invalid response")]
+    InvalidResponse,
+
+    /// The origin has failed too often recently, so we didn't try it
+    #[error("origin is unavailable")]
+    CircuitOpen,
+}
+
+impl UpstreamError {
+    /// Turn an error reading from or writing to the origin into a timeout, if it was one
+    pub fn from_io(err: std::io::Error) -> Self {
+        match err.kind() {
+            ErrorKind::WouldBlock | ErrorKind::TimedOut => UpstreamError::Timeout,
+            _ => UpstreamError::Io(err),
+        }
+    }
+
+    /// Short name used to label metrics
+    pub fn kind(&self) -> &'static str {
+        match self {
+            UpstreamError::Connect(_) => "connect",
+            UpstreamError::Timeout => "timeout",
+            UpstreamError::Io(_) => "exchange",
+            UpstreamError::InvalidResponse => "invalid_response",
+            UpstreamError::CircuitOpen => "circuit_open",
+        }
+    }
+}
+
+/// How the proxy talks to the origin, and when it gives up on it
+#[derive(Deserialize, Debug, PartialEq, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub struct UpstreamPolicy {
+    /// How long to wait for a connection to the origin
+    pub connect_timeout_ms: u64,
+
+    /// How long to wait for the origin to respond
+    pub read_timeout_ms: u64,
+
+    /// How many times to retry an idempotent request (GET, HEAD, OPTIONS or DELETE)
+    pub max_retries: u32,
+
+    /// Wait before the first retry. Each retry after that waits twice as long as the last
+    pub retry_backoff_ms: u64,
+
+    /// Failures in a row that open the circuit
+    pub failure_threshold: u32,
+
+    /// How long the circuit stays open before a request is let through to probe the origin
+    pub open_ms: u64,
+}
+
+impl Default for UpstreamPolicy {
+    fn default() -> Self {
+        UpstreamPolicy {
+            connect_timeout_ms: 1000,
+            read_timeout_ms: 10_000,
+            max_retries: 2,
+            retry_backoff_ms: 50,
+            failure_threshold: 5,
+            open_ms: 10_000,
+        }
+    }
+}
+
+impl UpstreamPolicy {
+    pub fn connect_timeout(&self) -> Duration {
+        Duration::from_millis(self.connect_timeout_ms)
+    }
+
+    pub fn read_timeout(&self) -> Duration {
+        Duration::from_millis(self.read_timeout_ms)
+    }
+
+    /// How many times to try `method` before giving up
+    pub fn attempts(&self, method: &str) -> u32 {
+        match method {
+            "GET" | "HEAD" | "OPTIONS" | "DELETE" => 1 + self.max_retries,
+            _ => 1,
+        }
+    }
+
+    /// How long to wait before retry number `retry` (starting from 1)
+    pub fn backoff(&self, retry: u32) -> Duration {
+        Duration::from_millis(self.retry_backoff_ms << retry.saturating_sub(1).min(16))
+    }
+
+    /// Connect to the origin at `addr`, trying each address it resolves to
+    pub fn connect(&self, addr: &str) -> Result<TcpStream, UpstreamError> {
+        let mut last_err = None;
+        for addr in addr.to_socket_addrs().map_err(UpstreamError::Connect)? {
+            match TcpStream::connect_timeout(&addr, self.connect_timeout()) {
+                Ok(stream) => {
+                    let timeout = Some(self.read_timeout());
+                    stream
+                        .set_read_timeout(timeout)
+                        .and_then(|_| stream.set_write_timeout(timeout))
+                        .map_err(UpstreamError::Connect)?;
+                    return Ok(stream);
+                }
+                Err(err) => last_err = Some(err),
+            }
+        }
+        Err(match last_err {
+            Some(err) if err.kind() == ErrorKind::TimedOut => UpstreamError::Timeout,
+            Some(err) => UpstreamError::Connect(err),
+            None => UpstreamError::Connect(ErrorKind::NotFound.into()),
+        })
+    }
+}
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+enum CircuitState {
+    /// Requests go through. Counts failures in a row
+    Closed(u32),
+
+    /// Requests fail fast until the given time
+    Open(Instant),
+
+    /// A single request is probing whether the origin has recovered
+    HalfOpen,
+}
+
+/// Stops sending requests to an origin that keeps failing, so clients get an answer straight away
+/// instead of waiting on timeouts
+#[derive(Debug)]
+pub struct CircuitBreaker {
+    failure_threshold: u32,
+    open_for: Duration,
+    state: Mutex<CircuitState>,
+}
+
+impl CircuitBreaker {
+    pub fn new(policy: &UpstreamPolicy) -> Self {
+        CircuitBreaker {
+            failure_threshold: policy.failure_threshold.max(1),
+            open_for: Duration::from_millis(policy.open_ms),
+            state: Mutex::new(CircuitState::Closed(0)),
+        }
+    }
+
+    fn state(&self) -> std::sync::MutexGuard<'_, CircuitState> {
+        self.state.lock().expect("circuit breaker lock poisoned")
+    }
+
+    /// Check whether a request may go to the origin now. Once the circuit has been open long
+    /// enough, a single request is let through as a probe
+    pub fn check(&self) -> Result<(), UpstreamError> {
+        let mut state = self.state();
+        match *state {
+            CircuitState::Closed(_) => Ok(()),
+            CircuitState::Open(until) if Instant::now() >= until => {
+                *state = CircuitState::HalfOpen;
+                Ok(())
+            }
+            CircuitState::Open(_) | CircuitState::HalfOpen => Err(UpstreamError::CircuitOpen),
+        }
+    }
+
+    /// The origin answered, so close the circuit
+    pub fn record_success(&self) {
+        *self.state() = CircuitState::Closed(0);
+    }
+
+    /// The origin failed. Opens the circuit if it has failed too often, or if it was being probed
+    pub fn record_failure(&self) {
+        let mut state = self.state();
+        *state = match *state {
+            CircuitState::Closed(failures) if failures + 1 < self.failure_threshold => {
+                CircuitState::Closed(failures + 1)
+            }
+            _ => CircuitState::Open(Instant::now() + self.open_for),
+        };
+    }
+
+    pub fn is_open(&self) -> bool {
+        !matches!(*self.state(), CircuitState::Closed(_))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn get_test_breaker(open_ms: u64) -> CircuitBreaker {
+        CircuitBreaker::new(&UpstreamPolicy {
+            failure_threshold: 3,
+            open_ms,
+            ..UpstreamPolicy::default()
+        })
+    }
+
+    #[test]
+    fn test_circuit_opens_after_threshold() {
+        let breaker = get_test_breaker(60_000);
+        breaker.record_failure();
+        breaker.record_failure();
+        breaker.record_success();
+        breaker.record_failure();
+        breaker.record_failure();
+        assert!(breaker.check().is_ok());
+
+        breaker.record_failure();
+        assert!(breaker.is_open());
+        assert!(matches!(breaker.check(), Err(UpstreamError::CircuitOpen)));
+    }
+
+    #[test]
+    fn test_circuit_probes_for_recovery() {
+        let breaker = get_test_breaker(0);
+        for _ in 0..3 {
+            breaker.record_failure();
+        }
+
+        // The first request is the probe, everything else waits for it
+        assert!(breaker.check().is_ok());
+        assert!(matches!(breaker.check(), Err(UpstreamError::CircuitOpen)));
+        breaker.record_failure();
+
+        assert!(breaker.check().is_ok());
+        breaker.record_success();
+        assert!(!breaker.is_open());
+        assert!(breaker.check().is_ok());
+    }
+
+    #[test]
+    fn test_attempts_and_backoff() {
+        let policy = UpstreamPolicy {
+            max_retries: 2,
+            retry_backoff_ms: 10,
+            ..UpstreamPolicy::default()
+        };
+        assert_eq!(policy.attempts("GET"), 3);
+        assert_eq!(policy.attempts("DELETE"), 3);
+        assert_eq!(policy.attempts("POST"), 1);
+        assert_eq!(policy.backoff(1), Duration::from_millis(10));
+        assert_eq!(policy.backoff(3), Duration::from_millis(40));
+    }
+
+    #[test]
+    fn test_from_io() {
+        let timeout = std::io::Error::from(ErrorKind::WouldBlock);
+        assert!(matches!(
+            UpstreamError::from_io(timeout),
+            UpstreamError::Timeout
+        ));
+        let reset = std::io::Error::from(ErrorKind::ConnectionReset);
+        assert_eq!(UpstreamError::from_io(reset).kind(), "exchange");
+    }
+}
@@ -0,0 +1,225 @@
+//! Harness for end-to-end tests: runs an origin and a proxy in front of it on ephemeral ports,
+//! and sends real HTTP requests through the proxy
+
+use std::net::{SocketAddr, TcpListener, TcpStream};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread::JoinHandle;
+use std::time::Duration;
+
+use aspirin_eats::api::Api;
+use aspirin_eats::client;
+use aspirin_eats::db::AspirinEatsDb;
+use aspirin_eats::http::{HttpRequest, HttpResponse};
+use aspirin_eats::pricing::Pricing;
+use aspirin_eats::proxy::Proxy;
+use serde::de::DeserializeOwned;
+
+/// How long a test waits on a server before failing, rather than hanging
+const TIMEOUT: Duration = Duration::from_secs(10);
+
+/// A server accepting connections on its own thread until it's dropped
+pub struct Server {
+    addr: SocketAddr,
+    stop: Arc<AtomicBool>,
+    handle: Option<JoinHandle<()>>,
+}
+
+impl Server {
+    /// Listen on an ephemeral port, handing each connection to `serve` in turn
+    pub fn spawn<F>(mut serve: F) -> Self
+    where
+        F: FnMut(TcpStream) + Send + 'static,
+    {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let stop = Arc::new(AtomicBool::new(false));
+        let stopped = stop.clone();
+        let handle = std::thread::spawn(move || {
+            for stream in listener.incoming() {
+                if stopped.load(Ordering::SeqCst) {
+                    break;
+                }
+                let Ok(stream) = stream else { continue };
+                stream.set_read_timeout(Some(TIMEOUT)).unwrap();
+                stream.set_write_timeout(Some(TIMEOUT)).unwrap();
+                serve(stream);
+            }
+        });
+        Server {
+            addr,
+            stop,
+            handle: Some(handle),
+        }
+    }
+
+    pub fn addr(&self) -> SocketAddr {
+        self.addr
+    }
+}
+
+impl Drop for Server {
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::SeqCst);
+        // Wake the listener up so it notices it should stop
+        let _ = TcpStream::connect(self.addr);
+        if let Some(handle) = self.handle.take() {
+            // Don't hide the failure that made the test panic behind another one
+            if handle.join().is_err() && !std::thread::panicking() {
+                panic!("server thread panicked");
+            }
+        }
+    }
+}
+
+/// API backed by an in-memory database, with default pricing
+pub fn test_api() -> Api {
+    Api::new(AspirinEatsDb::in_memory().unwrap(), Pricing::default())
+}
+
+/// An origin backed by an in-memory database, with a proxy in front of it
+pub struct TestServers {
+    // Dropped in this order, so the proxy stops before its origin
+    proxy: Server,
+    origin: Server,
+}
+
+impl TestServers {
+    /// Start an origin with default pricing and a proxy with default settings
+    pub fn start() -> Self {
+        Self::start_with(test_api(), |proxy| proxy)
+    }
+
+    /// Start an origin serving `api`, and a proxy set up by `configure`
+    pub fn start_with(api: Api, configure: impl FnOnce(Proxy) -> Proxy) -> Self {
+        let origin = Server::spawn(move |stream| {
+            let client = stream.peer_addr().ok();
+            api.serve_connection(stream, client).unwrap();
+        });
+
+        let proxy = configure(Proxy::new(&origin.addr().to_string()));
+        let proxy = Server::spawn(move |stream| {
+            proxy.serve_stream(stream).unwrap();
+        });
+        TestServers { proxy, origin }
+    }
+
+    pub fn proxy_addr(&self) -> SocketAddr {
+        self.proxy.addr()
+    }
+
+    pub fn origin_addr(&self) -> SocketAddr {
+        self.origin.addr()
+    }
+
+    /// Send a request through the proxy
+    pub fn send(&self, method: &str, path: &str, body: &str) -> TestResponse {
+        self.send_with_headers(method, path, &[], body)
+    }
+
+    /// Send a request with extra headers through the proxy
+    pub fn send_with_headers(
+        &self,
+        method: &str,
+        path: &str,
+        headers: &[(&str, &str)],
+        body: &str,
+    ) -> TestResponse {
+        let mut request = HttpRequest {
+            method: Some(method.to_string()),
+            path: Some(path.to_string()),
+            headers: vec![("Host".to_string(), self.proxy_addr().to_string())],
+            body: (!body.is_empty()).then(|| body.to_string()),
+        };
+        for (name, value) in headers {
+            request.set_header(name, value);
+        }
+        self.send_request(&request)
+    }
+
+    /// Send a raw request through the proxy
+    pub fn send_request(&self, request: &HttpRequest) -> TestResponse {
+        let stream = TcpStream::connect(self.proxy_addr()).unwrap();
+        stream.set_read_timeout(Some(TIMEOUT)).unwrap();
+        let response = client::exchange(request, stream).unwrap();
+        TestResponse {
+            description: format!(
+                "{} {}",
+                request.method.as_deref().unwrap_or_default(),
+                request.path.as_deref().unwrap_or_default()
+            ),
+            response,
+        }
+    }
+}
+
+/// A response read back through the proxy, with assertions that say which request they were for
+pub struct TestResponse {
+    description: String,
+    pub response: HttpResponse,
+}
+
+impl TestResponse {
+    pub fn assert_status(&self, status: u16) -> &Self {
+        assert_eq!(
+            self.response.status_code(),
+            status,
+            "{}: unexpected status, body was {:?}",
+            self.description,
+            self.response.body()
+        );
+        self
+    }
+
+    pub fn assert_header(&self, name: &str, value: &str) -> &Self {
+        assert_eq!(
+            self.response.header(name),
+            Some(value),
+            "{}: unexpected {name} header",
+            self.description
+        );
+        self
+    }
+
+    pub fn assert_no_header(&self, name: &str) -> &Self {
+        assert_eq!(
+            self.response.header(name),
+            None,
+            "{}: unexpected {name} header",
+            self.description
+        );
+        self
+    }
+
+    pub fn assert_body(&self, body: &str) -> &Self {
+        assert_eq!(
+            self.response.body(),
+            body,
+            "{}: unexpected body",
+            self.description
+        );
+        self
+    }
+
+    /// Check the body is JSON equal to `expected`
+    pub fn assert_json(&self, expected: serde_json::Value) -> &Self {
+        assert_eq!(
+            self.json::<serde_json::Value>(),
+            expected,
+            "{}: unexpected body",
+            self.description
+        );
+        self
+    }
+
+    /// Parse the body as JSON
+    pub fn json<T: DeserializeOwned>(&self) -> T {
+        serde_json::from_str(self.response.body()).unwrap_or_else(|err| {
+            panic!(
+                "{}: body isn't the expected JSON ({err}): {:?}",
+                self.description,
+                self.response.body()
+            )
+        })
+    }
+}
@@ -0,0 +1,507 @@
+//! Every endpoint, exercised over real HTTP through the proxy
+
+mod common;
+
+use aspirin_eats::compression::{encoded_etag, Encoding};
+use aspirin_eats::food::{BatchReport, Customer, Order, OrderStatus};
+use aspirin_eats::http::{CorsPolicy, RequestLimits};
+use aspirin_eats::reports::SalesReport;
+use aspirin_eats::ui::UiPolicy;
+use common::{test_api, TestServers};
+use serde_json::json;
+
+const ORDER: &str = r#"{"customer":"Amit","food":[{"Burger":{"bun":"Plain","patty":"Beef","toppings":["Lettuce","Tomato","Bacon"]}},"Fries"]}"#;
+const OTHER_ORDER: &str = r#"{"customer":"Sam","food":["Drink"]}"#;
+
+#[test]
+fn test_welcome() {
+    let servers = TestServers::start();
+    servers
+        .send("GET", "/", "")
+        .assert_status(200)
+        .assert_body("Welcome to Aspirin Eats!");
+}
+
+#[test]
+fn test_orders() {
+    let servers = TestServers::start();
+    servers.send("GET", "/orders", "").assert_json(json!([]));
+
+    let order: Order = servers
+        .send("POST", "/orders", ORDER)
+        .assert_status(201)
+        .json();
+    assert_eq!(order.id, Some(1));
+    assert_eq!(order.customer, "Amit");
+    assert_eq!(order.status, OrderStatus::Pending);
+    assert_eq!(order.total, 15.0);
+
+    let response = servers.send("GET", "/orders/1", "");
+    response.assert_status(200);
+    assert_eq!(response.json::<Order>(), order);
+
+    servers
+        .send("POST", "/orders", OTHER_ORDER)
+        .assert_status(201);
+    let orders: Vec<Order> = servers.send("GET", "/orders", "").assert_status(200).json();
+    assert_eq!(orders.len(), 2);
+    assert_eq!(orders[0], order);
+
+    servers
+        .send("DELETE", "/orders/1", "")
+        .assert_status(200)
+        .assert_body("Order 1 removed");
+    servers.send("GET", "/orders/1", "").assert_status(404);
+    servers.send("DELETE", "/orders/1", "").assert_status(404);
+
+    servers
+        .send("DELETE", "/orders", "")
+        .assert_status(200)
+        .assert_body("All orders removed");
+    servers.send("GET", "/orders", "").assert_json(json!([]));
+}
+
+#[test]
+fn test_customers() {
+    let servers = TestServers::start();
+    servers.send("POST", "/orders", ORDER).assert_status(201);
+    servers
+        .send("POST", "/orders", OTHER_ORDER)
+        .assert_status(201);
+    servers.send("POST", "/orders", ORDER).assert_status(201);
+
+    servers
+        .send("GET", "/customers", "")
+        .assert_status(200)
+        .assert_json(json!([{"id": 1, "name": "Amit"}, {"id": 2, "name": "Sam"}]));
+    let customer: Customer = servers.send("GET", "/customers/2", "").json();
+    assert_eq!(customer.name, "Sam");
+    servers.send("GET", "/customers/3", "").assert_status(404);
+
+    let orders: Vec<Order> = servers
+        .send("GET", "/customers/1/orders", "")
+        .assert_status(200)
+        .json();
+    assert_eq!(
+        orders.iter().map(|order| order.id).collect::<Vec<_>>(),
+        vec![Some(1), Some(3)]
+    );
+    servers
+        .send("GET", "/customers/3/orders", "")
+        .assert_status(404);
+}
+
+#[test]
+fn test_sales_report() {
+    let servers = TestServers::start();
+    servers.send("POST", "/orders", ORDER).assert_status(201);
+    servers
+        .send("POST", "/orders", OTHER_ORDER)
+        .assert_status(201);
+
+    let report: SalesReport = servers
+        .send("GET", "/reports/sales?group_by=status", "")
+        .assert_status(200)
+        .json();
+    assert_eq!(report.summary.orders, 2);
+    assert_eq!(report.summary.revenue, 18.0);
+    assert_eq!(report.groups.len(), 1);
+    assert_eq!(report.groups[0].key, "Pending");
+
+    servers
+        .send("GET", "/reports/sales?group_by=status&format=csv", "")
+        .assert_status(200)
+        .assert_header("Content-Type", "text/csv")
+        .assert_body("key,orders,items,revenue,average_order_value\nPending,2,3,18.00,9.00\n");
+
+    servers
+        .send("GET", "/reports/sales?group_by=weekday", "")
+        .assert_status(400);
+    servers
+        .send("GET", "/reports/sales?from=yesterday", "")
+        .assert_status(400);
+}
+
+#[test]
+fn test_errors() {
+    let servers = TestServers::start();
+    servers
+        .send("POST", "/orders", "not json")
+        .assert_status(400);
+    servers.send("POST", "/orders", "").assert_status(400);
+    servers
+        .send("POST", "/orders", r#"{"customer":"Amit","food":[]}"#)
+        .assert_status(400);
+    servers
+        .send(
+            "POST",
+            "/orders",
+            r#"{"customer":"Amit","food":["Fries"],"coupon":"NOPE"}"#,
+        )
+        .assert_status(400)
+        .assert_body("Invalid coupon: NOPE");
+    servers.send("GET", "/orders/abc", "").assert_status(400);
+    servers.send("GET", "/burgers", "").assert_status(404);
+    servers
+        .send("PUT", "/orders/1", "")
+        .assert_status(405)
+        .assert_header("Allow", "GET, DELETE, HEAD, OPTIONS");
+}
+
+#[test]
+fn test_head_and_options() {
+    let servers = TestServers::start();
+    servers.send("POST", "/orders", ORDER).assert_status(201);
+    let length = servers.send("GET", "/orders/1", "").response.body().len();
+
+    servers
+        .send("HEAD", "/orders/1", "")
+        .assert_status(200)
+        .assert_header("Content-Length", &length.to_string())
+        .assert_body("");
+    servers
+        .send("OPTIONS", "/orders", "")
+        .assert_status(204)
+        .assert_header("Allow", "GET, POST, DELETE, HEAD, OPTIONS");
+    servers.send("OPTIONS", "/burgers", "").assert_status(404);
+}
+
+#[test]
+fn test_cors() {
+    let cors = CorsPolicy {
+        allowed_origins: vec!["https://eats.example.com".to_string()],
+        ..CorsPolicy::default()
+    };
+    let servers = TestServers::start_with(test_api().with_cors(cors), |proxy| proxy);
+
+    servers
+        .send_with_headers(
+            "OPTIONS",
+            "/orders",
+            &[
+                ("Origin", "https://eats.example.com"),
+                ("Access-Control-Request-Method", "POST"),
+            ],
+            "",
+        )
+        .assert_status(204)
+        .assert_header("Access-Control-Allow-Origin", "https://eats.example.com")
+        .assert_header("Access-Control-Allow-Methods", "GET, POST, DELETE");
+    servers
+        .send_with_headers(
+            "GET",
+            "/orders",
+            &[("Origin", "https://evil.example.com")],
+            "",
+        )
+        .assert_status(200)
+        .assert_no_header("Access-Control-Allow-Origin");
+}
+
+#[test]
+fn test_proxy_behaviour() {
+    let limits = RequestLimits {
+        max_body_bytes: 64,
+        ..RequestLimits::default()
+    };
+    let servers = TestServers::start_with(test_api(), |proxy| proxy.with_limits(limits));
+
+    // The proxy tags every request, ignoring ids sent by clients
+    let response = servers.send_with_headers("GET", "/", &[("X-Request-Id", "spoofed")], "");
+    let request_id = response.response.header("X-Request-Id").unwrap();
+    assert_ne!(request_id, "spoofed");
+
+    servers
+        .send("POST", "/orders", &format!("{ORDER}{}", " ".repeat(64)))
+        .assert_status(413);
+
+    for _ in 0..10 {
+        servers
+            .send("POST", "/orders", OTHER_ORDER)
+            .assert_status(201);
+    }
+
+    // Served by the proxy itself, never reaching the origin
+    let metrics = servers.send("GET", "/proxy/metrics", "");
+    metrics.assert_status(200);
+    assert!(metrics.response.body().contains(
+        "aspirin_eats_http_requests_total{service=\"proxy\",route=\"/orders\",status=\"201\"} 10\n"
+    ));
+
+    // While the origin's are forwarded like any other request
+    let metrics = servers.send("GET", "/metrics", "");
+    metrics.assert_status(200);
+    assert!(metrics.response.body().contains(
+        "aspirin_eats_http_requests_total{service=\"origin\",route=\"/orders\",status=\"201\"} 10\n"
+    ));
+}
+
+#[test]
+fn test_origin_directly() {
+    let servers = TestServers::start();
+    servers.send("GET", "/", "").assert_status(200);
+
+    // The origin's own metrics are still there for anyone who can reach it
+    let response = aspirin_eats::client::Client::new(&servers.origin_addr().to_string())
+        .send("GET", "/metrics", None)
+        .unwrap();
+    assert!(response.body().contains(
+        "aspirin_eats_http_requests_total{service=\"origin\",route=\"/\",status=\"200\"} 1\n"
+    ));
+}
+
+#[test]
+fn test_openapi() {
+    let servers = TestServers::start();
+    let spec: serde_json::Value = servers
+        .send("GET", "/openapi.json", "")
+        .assert_status(200)
+        .assert_header("Content-Type", "application/json")
+        .json();
+    assert_eq!(spec["openapi"], "3.0.3");
+    assert!(spec["paths"]["/orders/{id}"]["get"].is_object());
+}
+
+#[test]
+fn test_inventory() {
+    let servers = TestServers::start();
+    servers
+        .send(
+            "PUT",
+            "/inventory",
+            r#"[{"ingredient":{"Bun":"Plain"},"quantity":1}]"#,
+        )
+        .assert_status(200)
+        .assert_json(json!([{"ingredient": {"Bun": "Plain"}, "quantity": 1}]));
+
+    servers.send("POST", "/orders", ORDER).assert_status(201);
+    servers
+        .send("POST", "/orders", ORDER)
+        .assert_status(409)
+        .assert_body("Out of stock: Plain bun");
+    servers.send("GET", "/orders", "").assert_status(200);
+
+    // Cancelling the pending order puts its bun back
+    servers.send("DELETE", "/orders/1", "").assert_status(200);
+    servers
+        .send(
+            "POST",
+            "/inventory",
+            r#"[{"ingredient":{"Bun":"Plain"},"change":2}]"#,
+        )
+        .assert_status(200)
+        .assert_json(json!([{"ingredient": {"Bun": "Plain"}, "quantity": 3}]));
+    servers
+        .send("GET", "/inventory", "")
+        .assert_json(json!([{"ingredient": {"Bun": "Plain"}, "quantity": 3}]));
+}
+
+#[test]
+fn test_delivery() {
+    let servers = TestServers::start();
+    let order = |lat: f64| {
+        json!({
+            "customer": "Amit",
+            "food": ["Fries"],
+            "delivery": {
+                "street": "1000 Olin Way",
+                "city": "Needham",
+                "postcode": "02492",
+                "location": {"lat": lat, "lon": -71.2615}
+            }
+        })
+        .to_string()
+    };
+
+    // At the restaurant's door, so only the base fee
+    let placed: Order = servers
+        .send("POST", "/orders", &order(42.2929))
+        .assert_status(201)
+        .json();
+    assert_eq!(placed.total, 7.0);
+    assert_eq!(placed.breakdown.unwrap().delivery_fee, 2.0);
+    let got: Order = servers.send("GET", "/orders/1", "").json();
+    assert_eq!(got.delivery.unwrap().city, "Needham");
+
+    // A degree of latitude is more than 100km
+    servers
+        .send("POST", "/orders", &order(43.2929))
+        .assert_status(422)
+        .assert_body("Outside delivery area: 1000 Olin Way, Needham 02492");
+    servers
+        .send("POST", "/orders", &order(91.0))
+        .assert_status(400);
+}
+
+#[test]
+fn test_drivers() {
+    let servers = TestServers::start();
+    servers
+        .send("POST", "/drivers", r#"{"name":"Sam"}"#)
+        .assert_status(201);
+    servers
+        .send("POST", "/drivers/1/shift", "")
+        .assert_status(200)
+        .assert_json(json!({"id": 1, "name": "Sam", "on_shift": true}));
+
+    servers.send("POST", "/orders", ORDER).assert_status(201);
+    servers
+        .send("POST", "/drivers/1/claim", r#"{"order":1}"#)
+        .assert_status(409)
+        .assert_body("Order 1 isn't ready for a driver");
+    servers
+        .send("POST", "/drivers/1/complete", "")
+        .assert_status(409)
+        .assert_body("Driver 1 isn't delivering anything");
+
+    servers
+        .send("DELETE", "/drivers/1/shift", "")
+        .assert_status(200);
+    servers
+        .send("GET", "/drivers", "")
+        .assert_json(json!([{"id": 1, "name": "Sam", "on_shift": false}]));
+}
+
+#[test]
+fn test_replace_items() {
+    let servers = TestServers::start();
+    servers.send("POST", "/orders", ORDER).assert_status(201);
+    let response = servers.send("GET", "/orders/1", "");
+    let etag = response.response.header("ETag").unwrap().to_string();
+
+    let items = r#"{"food":["Fries","Drink"]}"#;
+    servers
+        .send("PUT", "/orders/1/items", items)
+        .assert_status(428);
+    let order: Order = servers
+        .send_with_headers("PUT", "/orders/1/items", &[("If-Match", &etag)], items)
+        .assert_status(200)
+        .json();
+    assert_eq!(order.id, Some(1));
+    assert_eq!(order.total, 8.0);
+    servers
+        .send_with_headers("PUT", "/orders/1/items", &[("If-Match", &etag)], items)
+        .assert_status(412);
+}
+
+#[test]
+fn test_batch_orders() {
+    let servers = TestServers::start();
+    let batch = format!(r#"[{ORDER},{OTHER_ORDER},{{"customer":"Amit","food":[]}}]"#);
+
+    servers
+        .send("POST", "/orders/batch", &batch)
+        .assert_status(422)
+        .assert_json(json!({
+            "created": [],
+            "failures": [{"index": 2, "status": 400, "message": "Invalid Request"}]
+        }));
+    servers.send("GET", "/orders", "").assert_json(json!([]));
+
+    let report: BatchReport = servers
+        .send("POST", "/orders/batch?mode=best_effort", &batch)
+        .assert_status(201)
+        .json();
+    assert_eq!(report.created, vec![1, 2]);
+    let orders: Vec<Order> = servers.send("GET", "/orders", "").json();
+    assert_eq!(orders.len(), 2);
+}
+
+#[test]
+fn test_polling_orders() {
+    let servers = TestServers::start();
+    servers.send("POST", "/orders", ORDER).assert_status(201);
+    let response = servers.send("GET", "/orders", "");
+    let etag = response.response.header("ETag").unwrap().to_string();
+    let modified = response
+        .response
+        .header("Last-Modified")
+        .unwrap()
+        .to_string();
+
+    servers
+        .send_with_headers("GET", "/orders", &[("If-None-Match", &etag)], "")
+        .assert_status(304)
+        .assert_header("ETag", &etag)
+        .assert_body("");
+    servers
+        .send_with_headers("GET", "/orders", &[("If-Modified-Since", &modified)], "")
+        .assert_status(304);
+
+    // A client that was sent the list compressed has the compressed body's tag
+    let gzip_etag = encoded_etag(&etag, Encoding::Gzip);
+    servers
+        .send_with_headers(
+            "GET",
+            "/orders",
+            &[("If-None-Match", &gzip_etag), ("Accept-Encoding", "gzip")],
+            "",
+        )
+        .assert_status(304);
+
+    servers
+        .send("POST", "/orders", OTHER_ORDER)
+        .assert_status(201);
+    servers
+        .send_with_headers("GET", "/orders", &[("If-None-Match", &etag)], "")
+        .assert_status(200);
+}
+
+#[test]
+fn test_ui() {
+    let servers = TestServers::start_with(
+        test_api().with_ui(UiPolicy {
+            dir: concat!(env!("CARGO_MANIFEST_DIR"), "/ui").into(),
+            ..UiPolicy::default()
+        }),
+        |proxy| proxy,
+    );
+    servers
+        .send("GET", "/ui", "")
+        .assert_status(301)
+        .assert_header("Location", "/ui/");
+    servers
+        .send("GET", "/ui/status.html?id=1", "")
+        .assert_status(200)
+        .assert_header("Content-Type", "text/html; charset=utf-8")
+        .assert_header("Cache-Control", "no-cache");
+    servers
+        .send("GET", "/ui/app.js", "")
+        .assert_status(200)
+        .assert_header("Content-Type", "text/javascript; charset=utf-8")
+        .assert_header("Cache-Control", "public, max-age=3600");
+    servers
+        .send("GET", "/ui/%2e%2e/Cargo.toml", "")
+        .assert_status(404);
+}
+
+#[test]
+fn test_ui_places_and_tracks_an_order() {
+    let servers = TestServers::start();
+
+    // app.js only parses bodies that say they're JSON, and builds its menu from /menu
+    let menu = servers.send("GET", "/menu", "");
+    menu.assert_status(200)
+        .assert_header("Content-Type", "application/json");
+    assert_eq!(menu.json::<serde_json::Value>()["sides"][0]["price"], 5.0);
+
+    let placed = servers.send_with_headers(
+        "POST",
+        "/orders",
+        &[("Content-Type", "application/json")],
+        ORDER,
+    );
+    placed
+        .assert_status(201)
+        .assert_header("Content-Type", "application/json");
+    let id = placed.json::<serde_json::Value>()["id"].as_i64().unwrap();
+
+    // The status page it redirects to then tracks the order by that ID
+    let tracked = servers.send("GET", &format!("/orders/{id}"), "");
+    tracked
+        .assert_status(200)
+        .assert_header("Content-Type", "application/json");
+    let order: Order = tracked.json();
+    assert_eq!(order.id, Some(id));
+    assert_eq!(order.status, OrderStatus::Pending);
+}
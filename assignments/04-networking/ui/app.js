@@ -0,0 +1,305 @@
+// The Aspirin Eats ordering UI. Every page loads this script, and `data-page` on <body> picks
+// what it sets up. Orders go through the same API as everyone else's, at /orders
+
+"use strict";
+
+// The order goes through Scheduled (if placed ahead of time) and Pending to Completed
+const PROGRESS = ["Scheduled", "Pending", "Preparing", "Transporting", "Completed"];
+
+// How often the status page checks on an order that isn't finished yet
+const POLL_MS = 5000;
+
+const money = (amount) => `$${amount.toFixed(2)}`;
+
+// Spell out names like "GlutenFree" for people
+const label = (name) => name.replace(/([a-z])([A-Z])/g, "$1 $2");
+
+function describe(item) {
+  if (typeof item === "string") {
+    return item;
+  }
+  const { bun, patty, toppings } = item.Burger;
+  const extras = toppings.length ? ` with ${toppings.map(label).join(", ")}` : "";
+  return `${label(patty)} burger on a ${label(bun).toLowerCase()} bun${extras}`;
+}
+
+// Call the API, turning error responses (which have plain text bodies) into exceptions
+async function api(method, path, body) {
+  const options = { method, headers: {} };
+  if (body !== undefined) {
+    options.headers["Content-Type"] = "application/json";
+    options.body = JSON.stringify(body);
+  }
+  const response = await fetch(path, options);
+  const text = await response.text();
+  if (!response.ok) {
+    throw new Error(text || `${response.status} ${response.statusText}`);
+  }
+  return response.headers.get("Content-Type")?.startsWith("application/json")
+    ? JSON.parse(text)
+    : text;
+}
+
+function element(tag, text, attributes = {}) {
+  const node = document.createElement(tag);
+  if (text !== undefined) {
+    node.textContent = text;
+  }
+  Object.assign(node, attributes);
+  return node;
+}
+
+function row(...cells) {
+  const tr = element("tr");
+  cells.forEach((cell) => tr.append(element("td", cell)));
+  return tr;
+}
+
+function showError(err) {
+  const error = document.getElementById("error");
+  error.textContent = err ? err.message : "";
+  error.hidden = !err;
+}
+
+// The menu as the API prices it, listing what each choice costs on its own. The API works out
+// the real total, including deals, coupons, tax and delivery
+const loadMenu = () => api("GET", "/menu");
+
+// "Burger, Fries and Drink"
+const list = (names) =>
+  names.length > 1 ? `${names.slice(0, -1).join(", ")} and ${names.at(-1)}` : names.join("");
+
+async function setUpMenu() {
+  let menu;
+  try {
+    menu = await loadMenu();
+  } catch (err) {
+    showError(err);
+    return;
+  }
+  for (const id of ["buns", "patties", "toppings", "sides"]) {
+    const table = document.getElementById(id);
+    for (const { name, price } of menu[id]) {
+      table.append(row(label(name), price ? money(price) : "free"));
+    }
+  }
+  const combos = document.getElementById("combos");
+  for (const combo of menu.combos) {
+    const items = list(combo.items.map((item) => item.toLowerCase()));
+    combos.append(element("li", `${combo.name}: get ${items} together and save ${money(combo.discount)}`));
+  }
+}
+
+async function setUpOrder() {
+  const form = document.getElementById("order-form");
+  const basket = [];
+
+  let menu;
+  try {
+    menu = await loadMenu();
+  } catch (err) {
+    showError(err);
+    return;
+  }
+  for (const [select, prices] of [
+    [form.bun, menu.buns],
+    [form.patty, menu.patties],
+  ]) {
+    for (const { name, price } of prices) {
+      select.append(element("option", `${label(name)} (${money(price)})`, { value: name }));
+    }
+  }
+  const toppings = document.getElementById("topping-choices");
+  for (const { name, price } of menu.toppings) {
+    const choice = element("label", ` ${label(name)}${price ? ` (+${money(price)})` : ""}`);
+    choice.prepend(element("input", undefined, { type: "checkbox", name: "topping", value: name }));
+    toppings.append(choice);
+  }
+
+  const renderBasket = () => {
+    const list = document.getElementById("basket");
+    list.replaceChildren();
+    basket.forEach((burger, index) => {
+      const remove = element("button", "Remove", { type: "button" });
+      remove.addEventListener("click", () => {
+        basket.splice(index, 1);
+        renderBasket();
+      });
+      const item = element("li", `${describe(burger)} `);
+      item.append(remove);
+      list.append(item);
+    });
+    if (!basket.length) {
+      list.append(element("li", "No burgers yet", { className: "empty" }));
+    }
+  };
+
+  document.getElementById("add-burger").addEventListener("click", () => {
+    const chosen = [...form.querySelectorAll("input[name=topping]:checked")];
+    basket.push({
+      Burger: {
+        bun: form.bun.value,
+        patty: form.patty.value,
+        toppings: chosen.map((input) => input.value),
+      },
+    });
+    chosen.forEach((input) => (input.checked = false));
+    renderBasket();
+  });
+
+  form.deliver.addEventListener("change", () => {
+    document.getElementById("address").hidden = !form.deliver.checked;
+    for (const name of ["street", "city", "postcode", "lat", "lon"]) {
+      form[name].required = form.deliver.checked;
+    }
+  });
+
+  form.addEventListener("submit", async (event) => {
+    event.preventDefault();
+    const food = [
+      ...basket,
+      ...Array(Number(form.fries.value) || 0).fill("Fries"),
+      ...Array(Number(form.drinks.value) || 0).fill("Drink"),
+    ];
+    if (!food.length) {
+      showError(new Error("Add something to your order first"));
+      return;
+    }
+
+    const order = { customer: form.customer.value, food };
+    if (form.coupon.value.trim()) {
+      order.coupon = form.coupon.value.trim();
+    }
+    if (form.deliver.checked) {
+      order.delivery = {
+        street: form.street.value,
+        city: form.city.value,
+        postcode: form.postcode.value,
+        location: { lat: Number(form.lat.value), lon: Number(form.lon.value) },
+      };
+    }
+    if (form.scheduled_for.value) {
+      order.scheduled_for = new Date(form.scheduled_for.value).toISOString();
+    }
+
+    try {
+      showError(null);
+      const placed = await api("POST", "/orders", order);
+      window.location.href = `status.html?id=${placed.id}`;
+    } catch (err) {
+      showError(err);
+    }
+  });
+
+  renderBasket();
+}
+
+function setUpStatus() {
+  const lookup = document.getElementById("lookup");
+  const cancel = document.getElementById("cancel");
+  let timer;
+  let current;
+
+  const render = (order) => {
+    current = order;
+    document.getElementById("order").hidden = false;
+    document.getElementById("order-id").textContent = `#${order.id}`;
+    document.getElementById("order-customer").textContent = order.customer;
+
+    const progress = document.getElementById("progress");
+    progress.replaceChildren();
+    if (order.status === "Cancelled") {
+      progress.append(element("li", "Cancelled", { className: "current" }));
+    } else {
+      const reached = PROGRESS.indexOf(order.status);
+      PROGRESS.forEach((status, index) => {
+        if (status === "Scheduled" && !order.scheduled_for) {
+          return;
+        }
+        const className = index < reached ? "done" : index === reached ? "current" : "";
+        progress.append(element("li", status === "Transporting" ? "On its way" : status, { className }));
+      });
+    }
+
+    const scheduled = document.getElementById("order-scheduled");
+    scheduled.hidden = !order.scheduled_for;
+    if (order.scheduled_for) {
+      scheduled.textContent = `Wanted for ${new Date(order.scheduled_for).toLocaleString()}`;
+    }
+    const delivery = document.getElementById("order-delivery");
+    delivery.hidden = !order.delivery;
+    if (order.delivery) {
+      const { street, city, postcode } = order.delivery;
+      delivery.textContent = `Delivering to ${street}, ${city} ${postcode}`;
+    }
+
+    const lines = document.getElementById("order-lines");
+    lines.replaceChildren();
+    const breakdown = order.breakdown;
+    if (breakdown) {
+      breakdown.lines.forEach((line) => lines.append(row(describe(line.item), money(line.price))));
+      breakdown.discounts.forEach((discount) =>
+        lines.append(row(discount.description, `-${money(discount.amount)}`)),
+      );
+      if (breakdown.tax) {
+        lines.append(row("Tax", money(breakdown.tax)));
+      }
+      if (breakdown.delivery_fee) {
+        lines.append(row("Delivery", money(breakdown.delivery_fee)));
+      }
+    } else {
+      order.food.forEach((item) => lines.append(row(describe(item), "")));
+    }
+    const total = row("Total", money(order.total));
+    total.className = "total";
+    lines.append(total);
+
+    cancel.hidden = !["Scheduled", "Pending"].includes(order.status);
+  };
+
+  // Keep checking until the order is finished. The API answers unchanged orders with
+  // 304 Not Modified, which fetch hands back as the cached order
+  const track = async (id) => {
+    clearTimeout(timer);
+    try {
+      showError(null);
+      const order = await api("GET", `/orders/${encodeURIComponent(id)}`);
+      render(order);
+      if (!["Completed", "Cancelled"].includes(order.status)) {
+        timer = setTimeout(() => track(id), POLL_MS);
+      }
+    } catch (err) {
+      document.getElementById("order").hidden = true;
+      showError(err);
+    }
+  };
+
+  lookup.addEventListener("submit", (event) => {
+    event.preventDefault();
+    history.replaceState(null, "", `?id=${encodeURIComponent(lookup.id.value)}`);
+    track(lookup.id.value);
+  });
+
+  cancel.addEventListener("click", async () => {
+    if (!current || !confirm(`Cancel order #${current.id}?`)) {
+      return;
+    }
+    clearTimeout(timer);
+    try {
+      await api("DELETE", `/orders/${current.id}`);
+      document.getElementById("order").hidden = true;
+      showError(new Error(`Order #${current.id} has been cancelled`));
+    } catch (err) {
+      showError(err);
+    }
+  });
+
+  const id = new URLSearchParams(window.location.search).get("id");
+  if (id) {
+    lookup.id.value = id;
+    track(id);
+  }
+}
+
+const PAGES = { menu: setUpMenu, order: setUpOrder, status: setUpStatus };
+PAGES[document.body.dataset.page]?.();